@@ -0,0 +1,762 @@
+//! HLS 分片的解密.
+//!
+//! 支持 RFC 8216 中的 `METHOD=NONE`、`AES-128` 和 `SAMPLE-AES`。`SAMPLE-AES` 只加密 MPEG-TS
+//! 中 H.264 和 AAC 基本流的部分数据 (见 Apple 的 *MPEG-2 Stream Encryption Format for HTTP Live
+//! Streaming*)，解密时重组 PES、解密后重新打包，并把 PMT 中加密流的类型改回普通的 H.264/AAC，
+//! 得到的分片与未加密的分片无异。其他加密方式和密钥格式返回 [`UnsupportedEncryption`]。
+
+use aes::cipher::{
+    BlockDecryptMut, KeyIvInit,
+    block_padding::{NoPadding, Pkcs7},
+};
+use anyhow::Context as _;
+use std::collections::HashMap;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_AAC: u8 = 0x0f;
+const STREAM_TYPE_SAMPLE_AES_H264: u8 = 0xdb;
+const STREAM_TYPE_SAMPLE_AES_AAC: u8 = 0xcf;
+const STREAM_TYPE_SAMPLE_AES_AC3: u8 = 0xc1;
+
+/// 回放使用了无法解密的加密方式或密钥格式 (如 FairPlay).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedEncryption {
+    pub method: String,
+    pub keyformat: Option<String>,
+}
+
+impl std::fmt::Display for UnsupportedEncryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unsupported HLS encryption: METHOD={}", self.method)?;
+        if let Some(k) = &self.keyformat {
+            write!(f, ", KEYFORMAT={k}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnsupportedEncryption {}
+
+/// 分片的加密方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Cipher {
+    None,
+    Aes128,
+    SampleAes,
+}
+
+impl Cipher {
+    pub(super) fn of(key: &m3u8_rs::Key) -> Result<Self, UnsupportedEncryption> {
+        let unsupported = || UnsupportedEncryption {
+            method: key.method.to_string(),
+            keyformat: key.keyformat.clone(),
+        };
+        if key
+            .keyformat
+            .as_deref()
+            .is_some_and(|f| !f.eq_ignore_ascii_case("identity"))
+            && key.method != m3u8_rs::KeyMethod::None
+        {
+            return Err(unsupported());
+        }
+        match key.method {
+            m3u8_rs::KeyMethod::None => Ok(Self::None),
+            m3u8_rs::KeyMethod::AES128 => Ok(Self::Aes128),
+            m3u8_rs::KeyMethod::SampleAES => Ok(Self::SampleAes),
+            m3u8_rs::KeyMethod::Other(_) => Err(unsupported()),
+        }
+    }
+}
+
+/// m3u8-rs 拒绝没有 `IV` 的 `METHOD=NONE` (条件写反了)，这样的标签会被忽略，之后的分片
+/// 仍然使用之前的密钥。解析前为它们补上一个不会被使用的 `IV`。
+pub(super) fn fix_method_none(raw: &[u8]) -> std::borrow::Cow<'_, [u8]> {
+    const TAG: &[u8] = b"#EXT-X-KEY:METHOD=NONE";
+    if memchr::memmem::find(raw, TAG).is_none() {
+        return raw.into();
+    }
+    let mut out = Vec::with_capacity(raw.len() + 16);
+    for line in raw.split_inclusive(|&b| b == b'\n') {
+        let body = line.trim_ascii_end();
+        if body.starts_with(TAG) && memchr::memmem::find(body, b"IV=").is_none() {
+            out.extend_from_slice(body);
+            out.extend_from_slice(b",IV=0x0");
+            out.extend_from_slice(&line[body.len()..]);
+        } else {
+            out.extend_from_slice(line);
+        }
+    }
+    out.into()
+}
+
+/// 分片的 IV：`IV` 属性给出时使用它 (所有分片相同)，否则为分片的媒体序列号 (RFC 8216 5.2)
+pub(super) fn segment_iv(key: &m3u8_rs::Key, seq: u64) -> anyhow::Result<[u8; 16]> {
+    let Some(iv) = &key.iv else {
+        return Ok((seq as u128).to_be_bytes());
+    };
+    let hex = iv
+        .strip_prefix("0x")
+        .or_else(|| iv.strip_prefix("0X"))
+        .context("iv not start with 0x")?;
+    anyhow::ensure!(hex.len() <= 32, "iv longer than 128 bits: {iv}");
+    let iv = u128::from_str_radix(hex, 16).context("parse iv failed")?;
+    Ok(iv.to_be_bytes())
+}
+
+/// 解密整个分片 (`AES-128`，CBC 在每个分片处重新开始，PKCS7 填充)
+pub(super) fn decrypt_aes128(
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    data: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    cbc::Decryptor::<aes::Aes128>::new(key.into(), iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .ok()
+        .context("decrypt failed")
+}
+
+/// 解密 `SAMPLE-AES` 分片，返回未加密的 MPEG-TS
+pub(super) fn decrypt_sample_aes(
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    data: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(
+        data.len().is_multiple_of(PACKET_SIZE),
+        "segment length {} is not a multiple of {PACKET_SIZE}",
+        data.len()
+    );
+    let mut pmt_pid = None;
+    let mut streams = HashMap::new();
+    let mut pending: HashMap<u16, PendingPes> = HashMap::new();
+    // encrypted PES are written back into the slot of their first packet
+    let mut out: Vec<Vec<u8>> = Vec::new();
+
+    for (i, p) in data.chunks_exact(PACKET_SIZE).enumerate() {
+        anyhow::ensure!(p[0] == SYNC_BYTE, "lost sync at packet {i}");
+        let pid = u16::from_be_bytes([p[1] & 0x1f, p[2]]);
+        let pusi = p[1] & 0x40 != 0;
+        let (af, payload) = split_packet(p).with_context(|| format!("packet {i}"))?;
+
+        if pid == 0 && pusi {
+            pmt_pid = parse_pat(payload).or(pmt_pid);
+        } else if Some(pid) == pmt_pid && pusi {
+            let mut p = p.to_vec();
+            let off = PACKET_SIZE - payload.len();
+            streams = rewrite_pmt(&mut p[off..])?;
+            out.push(p);
+            continue;
+        }
+
+        let Some(&kind) = streams.get(&pid) else {
+            out.push(p.to_vec());
+            continue;
+        };
+        if pusi {
+            if let Some(pes) = pending.remove(&pid) {
+                let slot = pes.slot;
+                out[slot] = pes.finish(pid, kind, key, iv)?;
+            }
+            pending.insert(
+                pid,
+                PendingPes {
+                    slot: out.len(),
+                    cc: p[3] & 0x0f,
+                    af: keep_adaptation(af),
+                    data: Vec::new(),
+                },
+            );
+            out.push(Vec::new());
+        }
+        // payload without a starting packet is dropped
+        if let Some(pes) = pending.get_mut(&pid) {
+            pes.data.extend_from_slice(payload);
+        }
+    }
+    for (pid, pes) in pending {
+        let kind = streams[&pid];
+        let slot = pes.slot;
+        out[slot] = pes.finish(pid, kind, key, iv)?;
+    }
+    Ok(out.concat())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EsKind {
+    H264,
+    Aac,
+}
+
+struct PendingPes {
+    slot: usize,
+    cc: u8,
+    /// 第一个包的适配域 (不含长度字节和填充)
+    af: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl PendingPes {
+    /// 解密 PES 中的基本流并重新打包为 TS 包
+    fn finish(
+        self,
+        pid: u16,
+        kind: EsKind,
+        key: &[u8; 16],
+        iv: &[u8; 16],
+    ) -> anyhow::Result<Vec<u8>> {
+        let pes = &self.data;
+        anyhow::ensure!(
+            pes.len() >= 9 && pes[..3] == [0, 0, 1],
+            "invalid PES on pid {pid:#x}"
+        );
+        let header_len = 9 + pes[8] as usize;
+        anyhow::ensure!(
+            pes.len() >= header_len,
+            "truncated PES header on pid {pid:#x}"
+        );
+        let es = match kind {
+            EsKind::H264 => decrypt_h264(key, iv, &pes[header_len..]),
+            EsKind::Aac => decrypt_adts(key, iv, &pes[header_len..]),
+        };
+
+        let mut pes = [&pes[..header_len], &es].concat();
+        if pes[4..6] != [0, 0] {
+            let len = pes.len() - 6;
+            let len = if len > 0xffff { 0 } else { len as u16 };
+            pes[4..6].copy_from_slice(&len.to_be_bytes());
+        }
+        Ok(packetize(pid, self.cc, &self.af, &pes))
+    }
+}
+
+/// 拆分出 TS 包的适配域 (不含长度字节) 和负载
+fn split_packet(p: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    let rest = &p[4..];
+    match (p[3] >> 4) & 0x3 {
+        0b01 => Ok((&[], rest)),
+        0b10 | 0b11 => {
+            let n = rest[0] as usize;
+            anyhow::ensure!(n < rest.len(), "invalid adaptation field length {n}");
+            let payload = if p[3] & 0x10 != 0 {
+                &rest[1 + n..]
+            } else {
+                &[][..]
+            };
+            Ok((&rest[1..1 + n], payload))
+        }
+        _ => Ok((&[], &[])),
+    }
+}
+
+/// 只保留适配域中的标志位和 PCR，其余可选字段和填充丢弃
+fn keep_adaptation(af: &[u8]) -> Vec<u8> {
+    let Some(&flags) = af.first() else {
+        return Vec::new();
+    };
+    let flags = flags & 0xf0;
+    if flags & 0x10 != 0 && af.len() >= 7 {
+        [&[flags][..], &af[1..7]].concat()
+    } else if flags & !0x10 != 0 {
+        vec![flags & !0x10]
+    } else {
+        Vec::new()
+    }
+}
+
+/// 把 PES 打包为 TS 包，`af` 放在第一个包中，最后一个包用适配域填充
+fn packetize(pid: u16, mut cc: u8, af: &[u8], mut pes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity((pes.len() / 184 + 2) * PACKET_SIZE);
+    let mut af = af;
+    let mut first = true;
+    while first || !pes.is_empty() {
+        let mut p = [0xff; PACKET_SIZE];
+        p[0] = SYNC_BYTE;
+        p[1] = ((first as u8) << 6) | (pid >> 8) as u8 & 0x1f;
+        p[2] = pid as u8;
+        let room = 184 - if af.is_empty() { 0 } else { 1 + af.len() };
+        let n = pes.len().min(room);
+        let stuffing = room - n;
+        let af_total = if af.is_empty() {
+            stuffing
+        } else {
+            1 + af.len() + stuffing
+        };
+        if af_total > 0 {
+            p[3] = 0x30 | cc;
+            p[4] = (af_total - 1) as u8;
+            if !af.is_empty() {
+                p[5..5 + af.len()].copy_from_slice(af);
+            } else if af_total > 1 {
+                p[5] = 0;
+            }
+        } else {
+            p[3] = 0x10 | cc;
+        }
+        p[4 + af_total..].copy_from_slice(&pes[..n]);
+        out.extend_from_slice(&p);
+
+        pes = &pes[n..];
+        af = &[];
+        first = false;
+        cc = (cc + 1) & 0x0f;
+    }
+    out
+}
+
+fn psi_section(payload: &[u8]) -> Option<std::ops::Range<usize>> {
+    let start = 1 + *payload.first()? as usize;
+    let len = 3
+        + (u16::from_be_bytes([*payload.get(start + 1)?, *payload.get(start + 2)?]) & 0x0fff)
+            as usize;
+    (start + len <= payload.len() && len >= 12).then_some(start..start + len)
+}
+
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    let s = &payload[psi_section(payload)?];
+    // the first program after the table header, before the CRC
+    s[8..s.len() - 4]
+        .chunks_exact(4)
+        .find(|p| p[..2] != [0, 0])
+        .map(|p| u16::from_be_bytes([p[2] & 0x1f, p[3]]))
+}
+
+/// 把 PMT 中的加密流类型改回普通类型并重新计算 CRC，返回加密流的 PID
+fn rewrite_pmt(payload: &mut [u8]) -> anyhow::Result<HashMap<u16, EsKind>> {
+    let range = psi_section(payload).context("invalid PMT")?;
+    let s = &mut payload[range];
+    let end = s.len() - 4;
+    let info_len = (u16::from_be_bytes([s[10], s[11]]) & 0x0fff) as usize;
+    let mut pos = 12 + info_len;
+    let mut streams = HashMap::new();
+    while pos + 5 <= end {
+        let pid = u16::from_be_bytes([s[pos + 1] & 0x1f, s[pos + 2]]);
+        let (kind, plain) = match s[pos] {
+            STREAM_TYPE_SAMPLE_AES_H264 => (EsKind::H264, STREAM_TYPE_H264),
+            STREAM_TYPE_SAMPLE_AES_AAC => (EsKind::Aac, STREAM_TYPE_AAC),
+            STREAM_TYPE_SAMPLE_AES_AC3 => {
+                return Err(UnsupportedEncryption {
+                    method: "SAMPLE-AES (AC-3)".to_owned(),
+                    keyformat: None,
+                }
+                .into());
+            }
+            _ => {
+                pos += 5 + (u16::from_be_bytes([s[pos + 3], s[pos + 4]]) & 0x0fff) as usize;
+                continue;
+            }
+        };
+        s[pos] = plain;
+        streams.insert(pid, kind);
+        pos += 5 + (u16::from_be_bytes([s[pos + 3], s[pos + 4]]) & 0x0fff) as usize;
+    }
+    let crc = crc32_mpeg(&s[..end]);
+    s[end..].copy_from_slice(&crc.to_be_bytes());
+    Ok(streams)
+}
+
+fn crc32_mpeg(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// 以 CBC 解密 `blocks` 指向的若干 16 字节块，这些块在 `data` 中不必相邻
+fn decrypt_blocks(key: &[u8; 16], iv: &[u8; 16], data: &mut [u8], blocks: &[usize]) {
+    if blocks.is_empty() {
+        return;
+    }
+    let mut buf = blocks
+        .iter()
+        .flat_map(|&i| data[i..i + 16].iter().copied())
+        .collect::<Vec<_>>();
+    cbc::Decryptor::<aes::Aes128>::new(key.into(), iv.into())
+        .decrypt_padded_mut::<NoPadding>(&mut buf)
+        .expect("whole blocks");
+    for (&i, b) in blocks.iter().zip(buf.chunks_exact(16)) {
+        data[i..i + 16].copy_from_slice(b);
+    }
+}
+
+/// 解密 H.264 基本流：长于 48 字节的 slice NAL 单元在去掉防竞争字节后，前 32 字节为明文，
+/// 之后每 160 字节中的前 16 字节被加密，末尾不足 16 字节的部分为明文。每个 NAL 单元重新使用 IV。
+fn decrypt_h264(key: &[u8; 16], iv: &[u8; 16], es: &[u8]) -> Vec<u8> {
+    let starts = memchr::memmem::find_iter(es, &[0, 0, 1])
+        .map(|i| i + 3)
+        .collect::<Vec<_>>();
+    let Some(&first) = starts.first() else {
+        return es.to_vec();
+    };
+    let mut out = es[..first].to_vec();
+    for (k, &start) in starts.iter().enumerate() {
+        let next = starts.get(k + 1).map_or(es.len(), |&n| n - 3);
+        // a zero before the next start code belongs to a 4-byte start code
+        let end = if next < es.len() && next > start && es[next - 1] == 0 {
+            next - 1
+        } else {
+            next
+        };
+        let nal = &es[start..end];
+        if nal.len() > 48 && matches!(nal[0] & 0x1f, 1 | 5) {
+            let mut nal = unescape(nal);
+            let mut blocks = Vec::new();
+            let mut pos = 32;
+            while pos < nal.len() {
+                if nal.len() - pos > 16 {
+                    blocks.push(pos);
+                    pos += 16;
+                }
+                pos += 144.min(nal.len() - pos);
+            }
+            decrypt_blocks(key, iv, &mut nal, &blocks);
+            out.extend_from_slice(&nal);
+        } else {
+            out.extend_from_slice(nal);
+        }
+        let after = starts.get(k + 1).copied().unwrap_or(es.len());
+        out.extend_from_slice(&es[end..after]);
+    }
+    out
+}
+
+/// 去掉 NAL 单元中的防竞争字节 (`00 00 03` 中的 `03`)
+fn unescape(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+/// 解密 ADTS 基本流：每帧头部之后的 16 字节为明文，其后的完整 16 字节块被加密，
+/// 末尾不足 16 字节的部分为明文。每帧重新使用 IV。
+fn decrypt_adts(key: &[u8; 16], iv: &[u8; 16], es: &[u8]) -> Vec<u8> {
+    let mut out = es.to_vec();
+    let mut pos = 0;
+    while pos + 7 <= out.len() {
+        let h = &out[pos..pos + 7];
+        if h[0] != 0xff || h[1] & 0xf0 != 0xf0 {
+            log::warn!("lost ADTS sync in SAMPLE-AES audio at byte {pos}");
+            break;
+        }
+        let header_len = if h[1] & 1 == 0 { 9 } else { 7 };
+        let frame_len =
+            ((h[3] as usize & 0x3) << 11) | ((h[4] as usize) << 3) | (h[5] as usize >> 5);
+        if frame_len < header_len || pos + frame_len > out.len() {
+            break;
+        }
+        let clear = pos + header_len + 16;
+        let n = (pos + frame_len).saturating_sub(clear) / 16;
+        let blocks = (0..n).map(|b| clear + b * 16).collect::<Vec<_>>();
+        decrypt_blocks(key, iv, &mut out, &blocks);
+        pos += frame_len;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncryptMut;
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+    const IV: [u8; 16] = *b"fedcba9876543210";
+
+    fn key(method: m3u8_rs::KeyMethod, iv: Option<&str>, keyformat: Option<&str>) -> m3u8_rs::Key {
+        m3u8_rs::Key {
+            method,
+            uri: Some("key".to_owned()),
+            iv: iv.map(str::to_owned),
+            keyformat: keyformat.map(str::to_owned),
+            keyformatversions: None,
+        }
+    }
+
+    /// 以 CBC 加密若干不相邻的块，与 `decrypt_blocks` 互逆
+    fn encrypt_blocks(data: &mut [u8], blocks: &[usize]) {
+        let mut buf = blocks
+            .iter()
+            .flat_map(|&i| data[i..i + 16].iter().copied())
+            .collect::<Vec<_>>();
+        let len = buf.len();
+        cbc::Encryptor::<aes::Aes128>::new(&KEY.into(), &IV.into())
+            .encrypt_padded_mut::<NoPadding>(&mut buf, len)
+            .unwrap();
+        for (&i, b) in blocks.iter().zip(buf.chunks_exact(16)) {
+            data[i..i + 16].copy_from_slice(b);
+        }
+    }
+
+    fn encrypt_nal(nal: &[u8]) -> Vec<u8> {
+        let mut nal = nal.to_vec();
+        let mut blocks = Vec::new();
+        let mut pos = 32;
+        while pos < nal.len() {
+            if nal.len() - pos > 16 {
+                blocks.push(pos);
+                pos += 16;
+            }
+            pos += 144.min(nal.len() - pos);
+        }
+        encrypt_blocks(&mut nal, &blocks);
+        // emulation prevention is applied after encryption
+        let mut out = Vec::new();
+        let mut zeros = 0;
+        for b in nal {
+            if zeros >= 2 && b <= 3 {
+                out.push(3);
+                zeros = 0;
+            }
+            zeros = if b == 0 { zeros + 1 } else { 0 };
+            out.push(b);
+        }
+        out
+    }
+
+    fn adts(payload_len: usize, seed: u8) -> Vec<u8> {
+        let len = 7 + payload_len;
+        let mut f = vec![
+            0xff,
+            0xf1,
+            0x4c,
+            0x80 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 7) << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        f.extend((0..payload_len).map(|i| (i as u8).wrapping_mul(seed)));
+        f
+    }
+
+    fn pes(stream_id: u8, es: &[u8]) -> Vec<u8> {
+        let mut p = vec![0, 0, 1, stream_id, 0, 0, 0x80, 0x80, 5, 0x21, 0, 1, 0, 1];
+        if stream_id == 0xc0 {
+            let len = (p.len() - 6 + es.len()) as u16;
+            p[4..6].copy_from_slice(&len.to_be_bytes());
+        }
+        p.extend_from_slice(es);
+        p
+    }
+
+    fn psi(pid: u16, section: &[u8]) -> Vec<u8> {
+        let mut s = section.to_vec();
+        let crc = crc32_mpeg(&s);
+        s.extend_from_slice(&crc.to_be_bytes());
+        packetize(pid, 0, &[], &[&[0][..], &s].concat())
+    }
+
+    fn segment(video_type: u8, audio_type: u8, video: &[u8], audio: &[u8]) -> Vec<u8> {
+        let pat = [0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00];
+        let pmt = [
+            0x02, 0xb0, 23, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0x00, video_type, 0xe1, 0x00, 0xf0,
+            0x00, audio_type, 0xe1, 0x01, 0xf0, 0x00,
+        ];
+        let pcr = [0x10, 0, 0, 0, 0, 0x7e, 0];
+        [
+            psi(0, &pat),
+            psi(0x1000, &pmt),
+            packetize(0x100, 3, &pcr, &pes(0xe0, video)),
+            packetize(0x101, 7, &[], &pes(0xc0, audio)),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_cipher() {
+        use m3u8_rs::KeyMethod::*;
+        assert_eq!(
+            Cipher::of(&key(None, Option::None, Option::None)),
+            Ok(Cipher::None)
+        );
+        assert_eq!(
+            Cipher::of(&key(AES128, Option::None, Some("identity"))),
+            Ok(Cipher::Aes128)
+        );
+        assert_eq!(
+            Cipher::of(&key(SampleAES, Option::None, Option::None)),
+            Ok(Cipher::SampleAes)
+        );
+
+        let e = Cipher::of(&key(
+            SampleAES,
+            Option::None,
+            Some("com.apple.streamingkeydelivery"),
+        ))
+        .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "unsupported HLS encryption: METHOD=SAMPLE-AES, KEYFORMAT=com.apple.streamingkeydelivery"
+        );
+        let e = Cipher::of(&key(
+            Other("SAMPLE-AES-CTR".to_owned()),
+            Option::None,
+            Option::None,
+        ))
+        .unwrap_err();
+        assert_eq!(e.method, "SAMPLE-AES-CTR");
+    }
+
+    #[test]
+    fn test_fix_method_none() {
+        let raw = b"#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"k\"\n#EXTINF:10,\na.ts\n\
+            #EXT-X-KEY:METHOD=NONE\r\n#EXTINF:10,\nb.ts\n#EXT-X-ENDLIST\n";
+        let fixed = fix_method_none(raw);
+        assert!(fixed.windows(9).any(|w| w == b",IV=0x0\r\n"));
+        let m3u8_rs::Playlist::MediaPlaylist(pl) = m3u8_rs::parse_playlist_res(&fixed).unwrap()
+        else {
+            panic!("not a media playlist");
+        };
+        let method = |i: usize| pl.segments[i].key.as_ref().map(|k| k.method.clone());
+        assert_eq!(method(0), Some(m3u8_rs::KeyMethod::AES128));
+        assert_eq!(method(1), Some(m3u8_rs::KeyMethod::None));
+
+        let plain = b"#EXTM3U\n#EXTINF:10,\na.ts\n";
+        assert!(matches!(
+            fix_method_none(plain),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_segment_iv() {
+        use m3u8_rs::KeyMethod::AES128;
+        // the media sequence number as a big-endian 128-bit integer
+        let iv = segment_iv(&key(AES128, None, None), 0x0102).unwrap();
+        assert_eq!(iv[..14], [0; 14]);
+        assert_eq!(iv[14..], [1, 2]);
+        let iv = segment_iv(&key(AES128, None, None), 0x0103).unwrap();
+        assert_eq!(iv[15], 3);
+
+        // an explicit IV is used for every segment, in either case
+        let explicit = key(AES128, Some("0X000102030405060708090A0B0C0D0E0F"), None);
+        let expected = std::array::from_fn::<u8, 16, _>(|i| i as u8);
+        assert_eq!(segment_iv(&explicit, 7).unwrap(), expected);
+        assert_eq!(segment_iv(&explicit, 8).unwrap(), expected);
+        let lower = key(AES128, Some("0x0a"), None);
+        assert_eq!(segment_iv(&lower, 7).unwrap()[15], 10);
+
+        assert!(segment_iv(&key(AES128, Some("0a"), None), 0).is_err());
+        let long = format!("0x{}", "1".repeat(33));
+        assert!(segment_iv(&key(AES128, Some(&long), None), 0).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_aes128() {
+        // cbc restarts at every segment with the segment's own iv
+        let segments = [vec![1u8; 100], vec![2u8; 188]];
+        for (seq, plain) in segments.iter().enumerate() {
+            let iv = segment_iv(
+                &key(m3u8_rs::KeyMethod::AES128, None, None),
+                100 + seq as u64,
+            )
+            .unwrap();
+            let enc = cbc::Encryptor::<aes::Aes128>::new(&KEY.into(), &iv.into())
+                .encrypt_padded_vec_mut::<Pkcs7>(plain);
+            assert_eq!(&decrypt_aes128(&KEY, &iv, &enc).unwrap(), plain);
+            assert!(decrypt_aes128(&KEY, &IV, &enc[..enc.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decrypt_sample_aes() {
+        // sps, a short slice kept in clear and an idr slice with start code emulation
+        let sps = [0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02];
+        let short = [[0x41].as_slice(), &[0x9a; 40]].concat();
+        let mut idr = vec![0x65];
+        idr.extend((0..600u32).map(|i| if i % 50 < 3 { 0 } else { (i * 7) as u8 }));
+        let video = |idr: &[u8]| {
+            [
+                &[0, 0, 0, 1][..],
+                &sps,
+                &[0, 0, 1],
+                &short,
+                &[0, 0, 0, 1],
+                idr,
+            ]
+            .concat()
+        };
+        let clear_video = video(&idr);
+        let enc_video = video(&encrypt_nal(&idr));
+        assert_ne!(enc_video.len(), clear_video.len());
+
+        let frames = [adts(100, 3), adts(20, 5), adts(39, 7)];
+        let clear_audio = frames.concat();
+        let enc_audio = frames
+            .iter()
+            .map(|f| {
+                let mut f = f.clone();
+                let n = (f.len() - 7 - 16) / 16;
+                encrypt_blocks(&mut f, &(0..n).map(|b| 23 + b * 16).collect::<Vec<_>>());
+                f
+            })
+            .collect::<Vec<_>>()
+            .concat();
+        assert_ne!(enc_audio, clear_audio);
+
+        let encrypted = segment(
+            STREAM_TYPE_SAMPLE_AES_H264,
+            STREAM_TYPE_SAMPLE_AES_AAC,
+            &enc_video,
+            &enc_audio,
+        );
+        let expected = segment(
+            STREAM_TYPE_H264,
+            STREAM_TYPE_AAC,
+            &clear_video,
+            &clear_audio,
+        );
+        let decrypted = decrypt_sample_aes(&KEY, &IV, &encrypted).unwrap();
+        assert_eq!(decrypted, expected);
+    }
+
+    #[test]
+    fn test_sample_aes_unsupported() {
+        let ts = segment(
+            STREAM_TYPE_SAMPLE_AES_H264,
+            STREAM_TYPE_SAMPLE_AES_AC3,
+            &[],
+            &[],
+        );
+        let e = decrypt_sample_aes(&KEY, &IV, &ts).unwrap_err();
+        assert!(e.is::<UnsupportedEncryption>());
+        assert!(decrypt_sample_aes(&KEY, &IV, &ts[1..]).is_err());
+    }
+
+    #[test]
+    fn test_packetize() {
+        for len in [0, 1, 170, 176, 177, 183, 184, 185, 400] {
+            let pes = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+            let af = [0x50, 1, 2, 3, 4, 5, 6];
+            let ts = packetize(0x100, 15, &af, &pes);
+            assert_eq!(ts.len() % PACKET_SIZE, 0);
+            let mut payload = Vec::new();
+            for (i, p) in ts.chunks_exact(PACKET_SIZE).enumerate() {
+                assert_eq!(p[3] & 0x0f, (15 + i as u8) & 0x0f);
+                assert_eq!(p[1] & 0x40 != 0, i == 0);
+                let (a, data) = split_packet(p).unwrap();
+                if i == 0 {
+                    assert_eq!(keep_adaptation(a), af);
+                }
+                payload.extend_from_slice(data);
+            }
+            assert_eq!(payload, pes);
+        }
+    }
+}
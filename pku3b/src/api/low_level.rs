@@ -5,7 +5,12 @@ use rand::Rng as _;
 use scraper::Html;
 use std::str::FromStr as _;
 
-use crate::multipart;
+use crate::{
+    cache::{CacheKey, CacheStore},
+    cassette::{Cassette, Interaction},
+    multipart,
+};
+use std::{sync::Arc, time::Duration};
 
 pub const OAUTH_LOGIN: &str = "https://iaaa.pku.edu.cn/iaaa/oauthlogin.do";
 pub const OAUTH_REDIR: &str =
@@ -19,6 +24,8 @@ pub const LIST_CONTENT: &str =
     "https://course.pku.edu.cn/webapps/blackboard/content/listContent.jsp";
 pub const VIDEO_LIST: &str =
     "https://course.pku.edu.cn/webapps/bb-streammedia-hqy-BBLEARN/videoList.action";
+/// 回放列表每页的条数
+pub const VIDEO_LIST_PAGE_SIZE: usize = 100;
 pub const VIDEO_SUB_INFO: &str =
     "https://yjapise.pku.edu.cn/courseapi/v2/schedule/get-sub-info-by-auth-data";
 
@@ -26,13 +33,336 @@ pub const VIDEO_SUB_INFO: &str =
 #[derive(Clone)]
 pub struct LowLevelClient {
     http_client: cyper::Client,
+    offline: bool,
+    /// 页面响应缓存的有效期，为 `None` 时总是重新请求
+    cache_ttl: Option<Duration>,
+    store: &'static CacheStore,
+    cassette: Option<Arc<Cassette>>,
+    base_url: Option<url::Url>,
 }
 
+/// 一次请求的响应，响应体已被完整读取
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: http::StatusCode,
+    headers: http::HeaderMap,
+    url: url::Url,
+    body: bytes::Bytes,
+}
+
+impl Response {
+    async fn read(res: cyper::Response) -> anyhow::Result<Self> {
+        Ok(Self {
+            status: res.status(),
+            headers: res.headers().clone(),
+            url: res.url().clone(),
+            body: res.bytes().await?,
+        })
+    }
+
+    fn replayed(i: Interaction, body: Vec<u8>, url: url::Url) -> anyhow::Result<Self> {
+        let mut headers = http::HeaderMap::new();
+        for (k, v) in i.headers {
+            headers.append(http::HeaderName::try_from(k)?, v.parse()?);
+        }
+        Ok(Self {
+            status: http::StatusCode::from_u16(i.status)?,
+            headers,
+            url,
+            body: body.into(),
+        })
+    }
+
+    pub fn status(&self) -> http::StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &http::HeaderMap {
+        &self.headers
+    }
+
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    pub fn bytes(self) -> bytes::Bytes {
+        self.body
+    }
+
+    pub fn text(self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// 缓存的页面响应
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+fn check_base_url(base: &url::Url) -> anyhow::Result<()> {
+    let loopback = match base.host() {
+        Some(url::Host::Domain(d)) => d == "localhost",
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => anyhow::bail!("base url {base} has no host"),
+    };
+    match base.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        "http" => anyhow::bail!("base url {base} must use https unless it points to this machine"),
+        s => anyhow::bail!("unsupported scheme {s} in base url {base}"),
+    }
+}
+
+/// 登录失效时，教学网会以 200 返回登录页面 (而不是重定向)，其中有指向 IAAA 的登录链接
+fn is_login_page(body: &str) -> bool {
+    body.contains("iaaa.pku.edu.cn/iaaa/oauth.jsp")
+}
+
+/// 页面的响应文本，是登录页面时返回错误
+fn page_text(url: &url::Url, res: Response) -> anyhow::Result<String> {
+    let body = res.text();
+    anyhow::ensure!(
+        !is_login_page(&body),
+        "got the login page for {url}, the session may have expired"
+    );
+    Ok(body)
+}
+
+/// 离线模式下尝试发送网络请求时返回的错误，说明所需的数据从未被缓存过.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotCachedError {
+    pub method: String,
+    pub url: String,
+}
+
+impl std::fmt::Display for NotCachedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "offline: {} {} has never been cached, run the command once online first",
+            self.method, self.url
+        )
+    }
+}
+
+impl std::error::Error for NotCachedError {}
+
 impl LowLevelClient {
     pub fn from_cyper_client(client: cyper::Client) -> Self {
         Self {
             http_client: client,
+            offline: false,
+            cache_ttl: None,
+            store: CacheStore::global(),
+            cassette: None,
+            base_url: None,
+        }
+    }
+
+    /// 将发往 `*.pku.edu.cn` 的请求转发到 `base`（只使用其中的协议、域名和端口，路径不变），
+    /// 用于在本地的模拟服务器上测试。录制、缓存和返回的 [`Response::url`] 仍使用原始地址，
+    /// 因此使用全局缓存时改用 [`CacheStore::for_base_url`]，不与真实教学网的缓存混在一起。
+    ///
+    /// 转发的请求带有账号密码和 cookie，因此 `http` 地址只能指向本机。
+    pub fn with_base_url(mut self, base: url::Url) -> anyhow::Result<Self> {
+        check_base_url(&base)?;
+        if std::ptr::eq(self.store, CacheStore::global()) {
+            self.store = CacheStore::for_base_url(&base);
+        }
+        self.base_url = Some(base);
+        Ok(self)
+    }
+
+    pub fn cache_store(&self) -> &'static CacheStore {
+        self.store
+    }
+
+    pub fn base_url(&self) -> Option<&url::Url> {
+        self.base_url.as_ref()
+    }
+
+    fn rewrite_url(&self, url: &mut url::Url) -> anyhow::Result<()> {
+        let Some(base) = &self.base_url else {
+            return Ok(());
+        };
+        if !url
+            .host_str()
+            .is_some_and(|h| h == "pku.edu.cn" || h.ends_with(".pku.edu.cn"))
+        {
+            return Ok(());
         }
+        let orig = url.to_string();
+        url.set_scheme(base.scheme())
+            .and_then(|_| url.set_host(base.host_str()).map_err(|_| ()))
+            .and_then(|_| url.set_port(base.port()))
+            .map_err(|_| anyhow::anyhow!("cannot rewrite {orig} to {base}"))
+    }
+
+    /// 录制或回放所有请求，见 [`crate::cassette`]
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// 使用指定的缓存（默认为 [`CacheStore::global`]）
+    pub fn with_cache_store(mut self, store: &'static CacheStore) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// 设置页面响应缓存的有效期
+    pub fn with_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// 不使用缓存中的页面（仍会更新缓存），用于需要最新数据的场合，例如提交作业
+    pub fn nocache(&self) -> Self {
+        self.clone().with_cache_ttl(None)
+    }
+
+    /// 设置离线模式。离线模式下不会发出任何请求，而是返回 [`NotCachedError`].
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// 所有请求都经由此处发送
+    async fn send(&self, req: cyper::RequestBuilder) -> anyhow::Result<Response> {
+        let (client, req) = req.build_split();
+        self.execute(client, req, None).await
+    }
+
+    /// `full_body_limit` 用于 Range 请求：服务器忽略 Range 并返回 200 时，响应体只有不超过该长度
+    /// 才会被读入内存，否则返回错误
+    async fn execute(
+        &self,
+        client: cyper::Client,
+        mut req: cyper::Request,
+        full_body_limit: Option<u64>,
+    ) -> anyhow::Result<Response> {
+        if let Some(c) = &self.cassette
+            && c.is_replay()
+        {
+            let (i, body) = c.find(&req)?;
+            return Response::replayed(i, body, req.url().clone());
+        }
+        if self.offline {
+            return Err(NotCachedError {
+                method: req.method().to_string(),
+                url: req.url().to_string(),
+            }
+            .into());
+        }
+
+        let interaction = self.cassette.as_ref().map(|c| c.interaction(&req));
+        let url = req.url().clone();
+        self.rewrite_url(req.url_mut())?;
+        let res = client.execute(req).await?;
+        if let Some(limit) = full_body_limit
+            && res.status() == http::StatusCode::OK
+            && res.content_length().is_none_or(|n| n > limit)
+        {
+            // the body cannot be streamed, so do not hold the whole file in memory
+            anyhow::bail!(
+                "server ignored the range request and sent the whole file ({} bytes)",
+                res.content_length()
+                    .map_or_else(|| "unknown".to_owned(), |n| n.to_string())
+            );
+        }
+        let mut res = Response::read(res).await?;
+        res.url = url;
+        if let (Some(c), Some(i)) = (&self.cassette, interaction)
+            && let Err(e) = c.save(i, res.status, &res.headers, &res.body)
+        {
+            log::warn!("record {}: {e:#}", res.url);
+        }
+        Ok(res)
+    }
+
+    /// 发送 GET 请求并返回响应文本，响应会保存在缓存中.
+    ///
+    /// 缓存未过期时直接返回缓存的内容；过期后带上 `If-None-Match`/`If-Modified-Since`
+    /// 重新验证，服务器返回 304 时继续使用缓存。登录失效时返回的登录页面不会被缓存，而是返回错误。
+    async fn get_text(&self, req: cyper::RequestBuilder) -> anyhow::Result<String> {
+        let (client, mut req) = req.build_split();
+        let url = req.url().clone();
+
+        if self.cassette.is_some() {
+            // recording and replaying should see every request
+            let res = self.execute(client, req, None).await?;
+            anyhow::ensure!(res.status().is_success(), "status not success");
+            return page_text(&url, res);
+        }
+
+        let mut key = CacheKey::new("http").id(url.as_str());
+        if let Some((_, id)) = url.query_pairs().find(|(k, _)| k == "course_id") {
+            key = key.course_id(id);
+        }
+        let store = self.store;
+
+        let cached = store.get_with_age(&key).await.and_then(|(age, buf)| {
+            Some((age, serde_json::from_slice::<CachedResponse>(&buf).ok()?))
+        });
+        if let Some((age, c)) = &cached {
+            if self.cache_ttl.is_some_and(|ttl| *age < ttl) {
+                return Ok(c.body.clone());
+            }
+
+            let headers = req.headers_mut();
+            if let Some(v) = &c.etag {
+                headers.insert(http::header::IF_NONE_MATCH, v.parse()?);
+            }
+            if let Some(v) = &c.last_modified {
+                headers.insert(http::header::IF_MODIFIED_SINCE, v.parse()?);
+            }
+        }
+
+        let res = self.execute(client, req, None).await?;
+        if res.status() == http::StatusCode::NOT_MODIFIED
+            && let Some((_, c)) = cached
+        {
+            log::debug!("not modified: {url}");
+            store
+                .put(&key, self.cache_ttl.as_ref(), serde_json::to_vec(&c)?)
+                .await?;
+            return Ok(c.body);
+        }
+        anyhow::ensure!(res.status().is_success(), "status not success");
+
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        let etag = header(http::header::ETAG);
+        let last_modified = header(http::header::LAST_MODIFIED);
+        let body = page_text(&url, res)?;
+
+        let c = CachedResponse {
+            etag,
+            last_modified,
+            body,
+        };
+        store
+            .put(&key, self.cache_ttl.as_ref(), serde_json::to_vec(&c)?)
+            .await?;
+        Ok(c.body)
+    }
+
+    /// 同 [`Self::get_text`]，返回解析后的 HTML 文档
+    async fn get_page(&self, req: cyper::RequestBuilder) -> anyhow::Result<Html> {
+        let body = self.get_text(req).await?;
+        Ok(scraper::Html::parse_document(&body))
     }
 
     /// 向 [`OAUTH_LOGIN`] 发送登录请求，并返回 JSON (形如 { token: "..." })
@@ -41,20 +371,16 @@ impl LowLevelClient {
         username: &str,
         password: &str,
     ) -> anyhow::Result<serde_json::Value> {
-        let res = self
-            .http_client
-            .post(OAUTH_LOGIN)?
-            .form(&[
-                ("appid", "blackboard"),
-                ("userName", username),
-                ("password", password),
-                ("randCode", ""),
-                ("smsCode", ""),
-                ("otpCode", ""),
-                ("redirUrl", OAUTH_REDIR),
-            ])?
-            .send()
-            .await?;
+        let req = self.http_client.post(OAUTH_LOGIN)?.form(&[
+            ("appid", "blackboard"),
+            ("userName", username),
+            ("password", password),
+            ("randCode", ""),
+            ("smsCode", ""),
+            ("otpCode", ""),
+            ("redirUrl", OAUTH_REDIR),
+        ])?;
+        let res = self.send(req).await?;
 
         anyhow::ensure!(
             res.status().is_success(),
@@ -62,7 +388,7 @@ impl LowLevelClient {
             res.status()
         );
 
-        let rbody = res.text().await?;
+        let rbody = res.text();
         let value = serde_json::Value::from_str(&rbody).context("fail to parse response json")?;
         Ok(value)
     }
@@ -74,12 +400,11 @@ impl LowLevelClient {
         let _rand: f64 = rng.sample(rand::distr::Open01);
         let _rand = format!("{_rand:.20}");
 
-        let res = self
+        let req = self
             .http_client
             .get(SSO_LOGIN)?
-            .query(&[("_rand", _rand.as_str()), ("token", token)])?
-            .send()
-            .await?;
+            .query(&[("_rand", _rand.as_str()), ("token", token)])?;
+        let res = self.send(req).await?;
 
         anyhow::ensure!(res.status().is_success(), "status not success");
 
@@ -88,39 +413,23 @@ impl LowLevelClient {
 
     /// 获取教学网主页内容 ([`BLACKBOARD_HOME`]), 返回 HTML 文档
     pub async fn bb_homepage(&self) -> anyhow::Result<Html> {
-        let res = self
+        let req = self
             .http_client
             .get(BLACKBOARD_HOME)?
-            .query(&[("tab_tab_group_id", "_1_1")])?
-            .send()
-            .await?;
-        anyhow::ensure!(res.status().is_success(), "status not success");
-
-        let rbody = res.text().await?;
-        let dom = scraper::Html::parse_document(&rbody);
-        Ok(dom)
+            .query(&[("tab_tab_group_id", "_1_1")])?;
+        self.get_page(req).await
     }
 
     /// 根据课程的 key 获取课程主页内容 ([`COURSE_INFO`])
     pub async fn bb_coursepage(&self, key: &str) -> anyhow::Result<Html> {
-        let res = self
-            .http_client
-            .get(COURSE_INFO)?
-            .query(&[
-                ("method", "search"),
-                ("context", "course_entry"),
-                ("course_id", key),
-                ("handle", "announcements_entry"),
-                ("mode", "view"),
-            ])?
-            .send()
-            .await?;
-
-        anyhow::ensure!(res.status().is_success(), "status not success");
-
-        let rbody = res.text().await?;
-        let dom = scraper::Html::parse_document(&rbody);
-        Ok(dom)
+        let req = self.http_client.get(COURSE_INFO)?.query(&[
+            ("method", "search"),
+            ("context", "course_entry"),
+            ("course_id", key),
+            ("handle", "announcements_entry"),
+            ("mode", "view"),
+        ])?;
+        self.get_page(req).await
     }
 
     /// 根据 content_id 和 course_id 获取课程内容列表页面（包含作业、公告和一些其他东西）
@@ -129,18 +438,11 @@ impl LowLevelClient {
         course_id: &str,
         content_id: &str,
     ) -> anyhow::Result<Html> {
-        let res = self
+        let req = self
             .http_client
             .get(LIST_CONTENT)?
-            .query(&[("content_id", content_id), ("course_id", course_id)])?
-            .send()
-            .await?;
-
-        anyhow::ensure!(res.status().is_success(), "status not success");
-
-        let rbody = res.text().await?;
-        let dom = scraper::Html::parse_document(&rbody);
-        Ok(dom)
+            .query(&[("content_id", content_id), ("course_id", course_id)])?;
+        self.get_page(req).await
     }
 
     /// 根据 content_id 和 course_id 获取作业上传页面的信息.
@@ -149,22 +451,12 @@ impl LowLevelClient {
         course_id: &str,
         content_id: &str,
     ) -> anyhow::Result<Html> {
-        let res = self
-            .http_client
-            .get(UPLOAD_ASSIGNMENT)?
-            .query(&[
-                ("action", "newAttempt"),
-                ("content_id", content_id),
-                ("course_id", course_id),
-            ])?
-            .send()
-            .await?;
-
-        anyhow::ensure!(res.status().is_success(), "status not success");
-
-        let rbody = res.text().await?;
-        let dom = scraper::Html::parse_document(&rbody);
-        Ok(dom)
+        let req = self.http_client.get(UPLOAD_ASSIGNMENT)?.query(&[
+            ("action", "newAttempt"),
+            ("content_id", content_id),
+            ("course_id", course_id),
+        ])?;
+        self.get_page(req).await
     }
 
     /// 根据 content_id 和 course_id 获取作业的历史提交页面.
@@ -173,35 +465,25 @@ impl LowLevelClient {
         course_id: &str,
         content_id: &str,
     ) -> anyhow::Result<Html> {
-        let res = self
-            .http_client
-            .get(UPLOAD_ASSIGNMENT)?
-            .query(&[
-                ("mode", "view"),
-                ("content_id", content_id),
-                ("course_id", course_id),
-            ])?
-            .send()
-            .await?;
-
-        anyhow::ensure!(res.status().is_success(), "status not success");
-
-        let rbody = res.text().await?;
-        let dom = scraper::Html::parse_document(&rbody);
-        Ok(dom)
+        let req = self.http_client.get(UPLOAD_ASSIGNMENT)?.query(&[
+            ("mode", "view"),
+            ("content_id", content_id),
+            ("course_id", course_id),
+        ])?;
+        self.get_page(req).await
     }
 
     /// 向 [`UPLOAD_ASSIGNMENT`] 发送提交作业的请求
     pub async fn bb_course_assignment_uploaddata(
         &self,
         body: multipart::MultipartBuilder<'_>,
-    ) -> anyhow::Result<cyper::Response> {
+    ) -> anyhow::Result<Response> {
         let boundary = body.boundary().to_owned();
         let body = body.build().context("build multipart form body")?;
 
         log::debug!("body built: {}", body.len());
 
-        let res = self
+        let req = self
             .http_client
             .post(UPLOAD_ASSIGNMENT)?
             .header("origin", "https://course.pku.edu.cn")?
@@ -211,34 +493,27 @@ impl LowLevelClient {
                 format!("multipart/form-data; boundary={}", boundary),
             )?
             .query(&[("action", "submit")])?
-            .body(body)
-            .send()
-            .await?;
+            .body(body);
+        let res = self.send(req).await?;
 
         Ok(res)
     }
 
-    /// 根据 course_id 获取回放列表页面内容.
-    pub async fn bb_course_video_list(&self, course_id: &str) -> anyhow::Result<Html> {
-        let res = self
-            .http_client
-            .get(VIDEO_LIST)?
-            .query(&[
-                ("sortDir", "ASCENDING"),
-                ("numResults", "100"), // 一门课一般不会有超过 100 条回放
-                ("editPaging", "false"),
-                ("course_id", course_id),
-                ("mode", "view"),
-                ("startIndex", "0"),
-            ])?
-            .send()
-            .await?;
-
-        anyhow::ensure!(res.status().is_success(), "status not success");
-
-        let rbody = res.text().await?;
-        let dom = scraper::Html::parse_document(&rbody);
-        Ok(dom)
+    /// 根据 course_id 获取回放列表中从 `start_index` 开始的一页 (至多 [`VIDEO_LIST_PAGE_SIZE`] 条).
+    pub async fn bb_course_video_list(
+        &self,
+        course_id: &str,
+        start_index: usize,
+    ) -> anyhow::Result<Html> {
+        let req = self.http_client.get(VIDEO_LIST)?.query(&[
+            ("sortDir", "ASCENDING"),
+            ("numResults", &VIDEO_LIST_PAGE_SIZE.to_string()),
+            ("editPaging", "false"),
+            ("course_id", course_id),
+            ("mode", "view"),
+            ("startIndex", &start_index.to_string()),
+        ])?;
+        self.get_page(req).await
     }
 
     /// 获取视频回放的 sub_info（用于下载 m3u8 playlist）, 返回 JSON 信息
@@ -249,38 +524,50 @@ impl LowLevelClient {
         app_id: &str,
         auth_data: &str,
     ) -> anyhow::Result<serde_json::Value> {
-        let res = self
-            .http_client
-            .get(VIDEO_SUB_INFO)?
-            .query(&[
-                ("all", "1"),
-                ("course_id", course_id),
-                ("sub_id", sub_id),
-                ("with_sub_data", "1"),
-                ("app_id", app_id),
-                ("auth_data", auth_data),
-            ])?
-            .send()
-            .await?;
+        let req = self.http_client.get(VIDEO_SUB_INFO)?.query(&[
+            ("all", "1"),
+            ("course_id", course_id),
+            ("sub_id", sub_id),
+            ("with_sub_data", "1"),
+            ("app_id", app_id),
+            ("auth_data", auth_data),
+        ])?;
+        let res = self.send(req).await?;
 
         anyhow::ensure!(res.status().is_success(), "status not success");
 
-        let rbody = res.text().await?;
+        let rbody = res.text();
         let value = serde_json::Value::from_str(&rbody)?;
         Ok(value)
     }
 
     /// 利用 [`convert_uri`] 将 uri 自动补全，然后发送请求.
-    pub async fn get_by_uri(&self, uri: &str) -> anyhow::Result<cyper::Response> {
+    pub async fn get_by_uri(&self, uri: &str) -> anyhow::Result<Response> {
         let url = convert_uri(uri)?;
         log::trace!("GET {}", url);
-        let res = self
+        let req = self.http_client.get(url).context("create request failed")?;
+        let res = self.send(req).await?;
+        Ok(res)
+    }
+
+    /// 利用 [`convert_uri`] 将 uri 自动补全，请求从 `start` 开始的至多 `len` 字节.
+    ///
+    /// 服务器忽略 Range 请求时，只接受不超过 `len` 字节的完整响应，更大的文件返回错误而不会读入内存。
+    pub async fn get_range_by_uri(
+        &self,
+        uri: &str,
+        start: u64,
+        len: u64,
+    ) -> anyhow::Result<Response> {
+        let url = convert_uri(uri)?;
+        log::trace!("GET {} (bytes {}..{})", url, start, start + len);
+        let req = self
             .http_client
             .get(url)
             .context("create request failed")?
-            .send()
-            .await?;
-        Ok(res)
+            .header("range", format!("bytes={}-{}", start, start + len - 1))?;
+        let (client, req) = req.build_split();
+        self.execute(client, req, Some(len)).await
     }
 
     /// 利用 [`convert_uri`] 将 uri 自动补全，然后发送请求, 返回页面 HTML
@@ -290,12 +577,27 @@ impl LowLevelClient {
 
         anyhow::ensure!(res.status().is_success(), "status not success");
 
-        let rbody = res.text().await?;
+        let rbody = res.text();
         let dom = scraper::Html::parse_document(&rbody);
         Ok(dom)
     }
 }
 
+/// 解析 `Content-Range` 响应头，返回 (起始位置, 总长度)。
+/// 形如 `bytes 0-99/1000`，或在 416 响应中形如 `bytes */1000`。
+pub fn parse_content_range(v: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let (range, total) = v.strip_prefix("bytes ")?.split_once('/')?;
+    let start = match range {
+        "*" => None,
+        r => Some(r.split_once('-')?.0.parse().ok()?),
+    };
+    let total = match total {
+        "*" => None,
+        t => Some(t.parse().ok()?),
+    };
+    Some((start, total))
+}
+
 /// 将 uri 转换为完整的 url。协议默认为 `https`，域名默认为 `course.pku.edu.cn`。
 pub fn convert_uri(uri: &str) -> anyhow::Result<String> {
     let uri = http::Uri::from_str(uri).context("parse uri string")?;
@@ -323,6 +625,21 @@ pub fn convert_uri(uri: &str) -> anyhow::Result<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/1000"),
+            Some((Some(100), Some(1000)))
+        );
+        assert_eq!(parse_content_range("bytes 0-99/*"), Some((Some(0), None)));
+        assert_eq!(
+            parse_content_range("bytes */1000"),
+            Some((None, Some(1000)))
+        );
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+        assert_eq!(parse_content_range("bytes x-1/2"), None);
+    }
+
     #[test]
     fn test_convert_uri() {
         let uri = "/path/to/resource";
@@ -340,4 +657,115 @@ mod tests {
         let result = convert_uri(uri).unwrap();
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_rewrite_url() {
+        let c = LowLevelClient::from_cyper_client(cyper::Client::new())
+            .with_base_url("http://127.0.0.1:8080".parse().unwrap())
+            .unwrap();
+        // pages of the fake server never go to the global cache
+        assert!(!std::ptr::eq(c.cache_store(), CacheStore::global()));
+        assert!(c.cache_store().dir().ends_with("base-url/127.0.0.1-8080"));
+
+        let mut url: url::Url = "https://course.pku.edu.cn/webapps/login/?a=1"
+            .parse()
+            .unwrap();
+        c.rewrite_url(&mut url).unwrap();
+        assert_eq!(url.as_str(), "http://127.0.0.1:8080/webapps/login/?a=1");
+
+        for other in ["https://example.com/x", "https://notpku.edu.cn/x"] {
+            let mut url: url::Url = other.parse().unwrap();
+            c.rewrite_url(&mut url).unwrap();
+            assert_eq!(url.as_str(), other);
+        }
+        let mut url: url::Url = "https://pku.edu.cn/x".parse().unwrap();
+        c.rewrite_url(&mut url).unwrap();
+        assert_eq!(url.as_str(), "http://127.0.0.1:8080/x");
+    }
+
+    #[test]
+    fn test_check_base_url() {
+        let check = |s: &str| check_base_url(&s.parse().unwrap()).is_ok();
+        assert!(check("http://127.0.0.1:8080"));
+        assert!(check("http://localhost:8080"));
+        assert!(check("http://[::1]:8080"));
+        assert!(check("https://bb.example.com"));
+        assert!(!check("http://192.168.1.2:8080"));
+        assert!(!check("http://bb.example.com"));
+        assert!(!check("ftp://127.0.0.1"));
+    }
+
+    #[compio::test]
+    async fn test_offline_page_cache() {
+        let dir = std::env::temp_dir().join(format!("pku3b-http-cache-{}", std::process::id()));
+        let store: &'static CacheStore = Box::leak(Box::new(CacheStore::new(
+            &dir,
+            crate::cache::DEFAULT_SIZE_LIMIT,
+        )));
+        let c = LowLevelClient::from_cyper_client(cyper::Client::new())
+            .with_offline(true)
+            .with_cache_ttl(Some(Duration::MAX))
+            .with_cache_store(store);
+
+        assert!(c.bb_coursepage("_1_1").await.is_err());
+
+        let url = format!(
+            "{COURSE_INFO}?method=search&context=course_entry&course_id=_1_1&handle=announcements_entry&mode=view"
+        );
+        let body = "<html><body><h1 id=t>课程主页</h1></body></html>".to_owned();
+        let cached = CachedResponse {
+            etag: None,
+            last_modified: None,
+            body,
+        };
+        let key = CacheKey::new("http").course_id("_1_1").id(url);
+        store
+            .put(&key, None, serde_json::to_vec(&cached).unwrap())
+            .await
+            .unwrap();
+
+        let dom = c.bb_coursepage("_1_1").await.unwrap();
+        let h1 = dom.select(&scraper::Selector::parse("#t").unwrap()).next();
+        assert_eq!(h1.unwrap().text().collect::<String>(), "课程主页");
+        assert_eq!(store.entries()[0].course_id.as_deref(), Some("_1_1"));
+
+        store.clear().unwrap();
+    }
+
+    #[compio::test]
+    async fn test_replay() {
+        let dir = std::env::temp_dir().join(format!("pku3b-replay-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let recorder = Cassette::record(&dir).unwrap();
+        let url = url::Url::parse(&format!("{BLACKBOARD_HOME}?tab_tab_group_id=_1_1")).unwrap();
+        let i = recorder.interaction(&cyper::Request::new(http::Method::GET, url));
+        let body = "<html><body><h1 id=t>教学网</h1></body></html>";
+        recorder
+            .save(
+                i,
+                http::StatusCode::OK,
+                &http::HeaderMap::new(),
+                body.as_bytes(),
+            )
+            .unwrap();
+
+        let c = LowLevelClient::from_cyper_client(cyper::Client::new())
+            .with_cassette(Arc::new(Cassette::replay(&dir).unwrap()));
+        let dom = c.bb_homepage().await.unwrap();
+        let h1 = dom.select(&scraper::Selector::parse("#t").unwrap()).next();
+        assert_eq!(h1.unwrap().text().collect::<String>(), "教学网");
+        assert!(c.get_by_uri("/webapps/login/").await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[compio::test]
+    async fn test_offline_send() {
+        let c = LowLevelClient::from_cyper_client(cyper::Client::new()).with_offline(true);
+        let e = c.get_by_uri("/webapps/login/").await.unwrap_err();
+        let e = e.downcast_ref::<NotCachedError>().unwrap();
+        assert_eq!(e.method, "GET");
+        assert_eq!(e.url, "https://course.pku.edu.cn/webapps/login/");
+    }
 }
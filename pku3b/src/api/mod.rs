@@ -1,14 +1,21 @@
+mod hls;
 mod low_level;
+pub mod parse;
 mod tree;
+mod variant;
+mod view;
+pub use hls::UnsupportedEncryption;
+pub use low_level::{NotCachedError, Response};
 pub use tree::*;
+pub use variant::{PlaybackKind, Quality, VideoSelector, VideoVariant};
+pub use view::*;
 
 use crate::{
-    multipart, qs,
-    utils::{with_cache, with_cache_bytes},
+    cache::{CacheKey, with_cache_bytes},
+    datetime, multipart, qs,
 };
 use anyhow::Context;
-use anyhow::{Result, anyhow}; // 正确导入anyhow宏
-use chrono::TimeZone;
+use anyhow::Result;
 use cyper::IntoUrl;
 use futures_util::future::join_all;
 use itertools::Itertools;
@@ -57,6 +64,14 @@ impl Client {
     pub fn new(
         cache_ttl: Option<std::time::Duration>,
         download_artifact_ttl: Option<std::time::Duration>,
+    ) -> Self {
+        Self::build(cache_ttl, download_artifact_ttl, false)
+    }
+
+    fn build(
+        cache_ttl: Option<std::time::Duration>,
+        download_artifact_ttl: Option<std::time::Duration>,
+        offline: bool,
     ) -> Self {
         let mut default_headers = http::HeaderMap::new();
         default_headers.insert(http::header::USER_AGENT, AGENT.parse().unwrap());
@@ -68,9 +83,13 @@ impl Client {
         log::info!("Cache TTL: {:?}", cache_ttl);
         log::info!("Download Artifact TTL: {:?}", download_artifact_ttl);
 
+        let http_client = low_level::LowLevelClient::from_cyper_client(http_client)
+            .with_offline(offline)
+            .with_cache_ttl(cache_ttl);
+
         Self(
             ClientInner {
-                http_client: low_level::LowLevelClient::from_cyper_client(http_client),
+                http_client,
                 cache_ttl,
                 download_artifact_ttl,
             }
@@ -82,8 +101,57 @@ impl Client {
         Self::new(None, None)
     }
 
+    /// 离线模式：跳过登录，所有数据都从本地缓存中读取（无论缓存是否过期）。
+    /// 需要的数据从未被缓存过时返回 [`NotCachedError`].
+    pub fn offline() -> Self {
+        Self::build(
+            Some(std::time::Duration::MAX),
+            Some(std::time::Duration::MAX),
+            true,
+        )
+    }
+
+    /// 录制或回放该 client 发出的所有请求，见 [`crate::cassette`]
+    pub fn with_cassette(&self, cassette: Arc<crate::cassette::Cassette>) -> Self {
+        self.map_http_client(|c| c.with_cassette(cassette))
+    }
+
+    /// 将发往 `*.pku.edu.cn` 的请求转发到 `base`（只使用其中的协议、域名和端口），用于在本地的
+    /// 模拟服务器上测试，见 [`low_level::LowLevelClient::with_base_url`]
+    pub fn with_base_url(&self, base: url::Url) -> anyhow::Result<Self> {
+        let http_client = self.0.http_client.clone().with_base_url(base)?;
+        Ok(self.map_http_client(|_| http_client))
+    }
+
+    /// 使用指定的缓存（默认为 [`crate::cache::CacheStore::global`]）
+    pub fn with_cache_store(&self, store: &'static crate::cache::CacheStore) -> Self {
+        self.map_http_client(|c| c.with_cache_store(store))
+    }
+
+    fn map_http_client(
+        &self,
+        f: impl FnOnce(low_level::LowLevelClient) -> low_level::LowLevelClient,
+    ) -> Self {
+        let inner = &self.0;
+        Self(
+            ClientInner {
+                http_client: f(inner.http_client.clone()),
+                cache_ttl: inner.cache_ttl,
+                download_artifact_ttl: inner.download_artifact_ttl,
+            }
+            .into(),
+        )
+    }
+
+    /// 登录教学网。离线模式下不会登录，用户名和密码会被忽略.
     pub async fn blackboard(&self, username: &str, password: &str) -> anyhow::Result<Blackboard> {
         let c = &self.0.http_client;
+        if c.is_offline() {
+            return Ok(Blackboard {
+                client: self.clone(),
+            });
+        }
+
         let value = c.oauth_login(username, password).await?;
         let token = value
             .as_object()
@@ -141,50 +209,11 @@ impl Blackboard {
     }
     async fn _get_courses(&self) -> anyhow::Result<Vec<(String, String, bool)>> {
         let dom = self.client.bb_homepage().await?;
-        let re = regex::Regex::new(r"key=([\d_]+),").unwrap();
-        let ul_sel = Selector::parse("ul.courseListing").unwrap();
-        let sel = Selector::parse("li a").unwrap();
-
-        let f = |a: scraper::ElementRef<'_>| {
-            let href = a.value().attr("href").unwrap();
-            let text = a.text().collect::<String>();
-            // use regex to extract course key (of form key=_80052_1)
-
-            let key = re
-                .captures(href)
-                .and_then(|s| s.get(1))
-                .context("course key not found")?
-                .as_str()
-                .to_owned();
-
-            Ok((key, text))
-        };
-
-        // the first one contains the courses in the current semester
-        let ul = dom.select(&ul_sel).nth(0).context("courses not found")?;
-        let courses = ul.select(&sel).map(f).collect::<anyhow::Result<Vec<_>>>()?;
-
-        // the second one contains the courses in the previous semester
-        let ul_history = dom.select(&ul_sel).nth(1).context("courses not found")?;
-        let courses_history = ul_history
-            .select(&sel)
-            .map(f)
-            .collect::<anyhow::Result<Vec<_>>>()?;
-
-        Ok(courses
-            .into_iter()
-            .map(|(k, t)| (k, t, true))
-            .chain(courses_history.into_iter().map(|(k, t)| (k, t, false)))
-            .collect())
+        parse::courses(&dom)
     }
     pub async fn get_courses(&self, only_current: bool) -> anyhow::Result<Vec<CourseHandle>> {
         log::info!("fetching courses...");
-        let courses = with_cache(
-            "Blackboard::_get_courses",
-            self.client.cache_ttl(),
-            self._get_courses(),
-        )
-        .await?;
+        let courses = self._get_courses().await?;
 
         let mut courses = courses
             .into_iter()
@@ -230,14 +259,14 @@ impl CourseMeta {
     /// Cousre Name
     pub fn name(&self) -> &str {
         let s = self.title();
-        let i = s
-            .char_indices()
-            .filter(|(_, c)| *c == '(')
-            .next_back()
-            .unwrap()
-            .0;
+        let i = s.char_indices().rfind(|(_, c)| *c == '(').unwrap().0;
         s.split_at(i).0.trim()
     }
+
+    /// 属于该课程的缓存条目的键
+    pub fn cache_key(&self, kind: &'static str) -> CacheKey {
+        CacheKey::new(kind).course(&self.id, self.name())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -249,17 +278,7 @@ pub struct CourseHandle {
 impl CourseHandle {
     pub async fn _get(&self) -> anyhow::Result<HashMap<String, String>> {
         let dom = self.client.bb_coursepage(&self.meta.id).await?;
-
-        let entries = dom
-            .select(&Selector::parse("#courseMenuPalette_contents > li > a").unwrap())
-            .map(|a| {
-                let text = a.text().collect::<String>();
-                let href = a.value().attr("href").unwrap();
-                Ok((text, href.to_owned()))
-            })
-            .collect::<anyhow::Result<HashMap<_, _>>>()?;
-
-        Ok(entries)
+        Ok(parse::course_entries(&dom))
     }
     pub fn title(&self) -> &str {
         self.meta.title()
@@ -271,12 +290,7 @@ impl CourseHandle {
     pub async fn get(&self) -> anyhow::Result<Course> {
         log::info!("fetching course {}", self.meta.title());
 
-        let entries = with_cache(
-            &format!("CourseHandle::_get_{}", self.meta.id),
-            self.client.cache_ttl(),
-            self._get(),
-        )
-        .await?;
+        let entries = self._get().await?;
 
         Ok(Course {
             client: self.client.clone(),
@@ -313,21 +327,20 @@ impl Course {
         // 为每个栏目创建初始探针
         for (section_name, uri) in &self.entries {
             // 修正：使用 Url::parse 代替 into_url
-            if let Ok(uri_str) = low_level::convert_uri(uri) {
-                if let Ok(url) = Url::parse(&uri_str) {
-                    if low_level::LIST_CONTENT.ends_with(url.path()) {
-                        for (key, value) in url.query_pairs() {
-                            if key == "content_id" {
-                                let probe = ContentProbe {
-                                    parent_id: None,
-                                    parent_title: None,
-                                    depth: 0,
-                                    id: value.to_string(),
-                                    section_name: Some(section_name.clone()),
-                                };
-                                initial_probes.push(probe);
-                            }
-                        }
+            if let Ok(uri_str) = low_level::convert_uri(uri)
+                && let Ok(url) = Url::parse(&uri_str)
+                && low_level::LIST_CONTENT.ends_with(url.path())
+            {
+                for (key, value) in url.query_pairs() {
+                    if key == "content_id" {
+                        let probe = ContentProbe {
+                            parent_id: None,
+                            parent_title: None,
+                            depth: 0,
+                            id: value.to_string(),
+                            section_name: Some(section_name.clone()),
+                        };
+                        initial_probes.push(probe);
                     }
                 }
             }
@@ -347,6 +360,35 @@ impl Course {
     pub fn entries(&self) -> &HashMap<String, String> {
         &self.entries
     }
+
+    /// 下载本课程中任意内容的附件（跟随一次重定向）
+    pub async fn download_attachment(&self, uri: &str, dest: &std::path::Path) -> Result<()> {
+        log::debug!("downloading attachment from https://course.pku.edu.cn{uri}");
+
+        let mut res = self.client.get_by_uri(uri).await?;
+        if res.status().is_redirection() {
+            let loc = res
+                .headers()
+                .get("location")
+                .context("location header not found")?
+                .to_str()
+                .context("location header not str")?
+                .to_owned();
+            log::debug!("redirected to https://course.pku.edu.cn{loc}");
+            res = self.client.get_by_uri(&loc).await?;
+        }
+
+        anyhow::ensure!(
+            res.status().is_success(),
+            "status not success: {}",
+            res.status()
+        );
+        let body = res.bytes();
+        let r = compio::fs::write(dest, body).await;
+        compio::buf::buf_try!(@try r);
+
+        Ok(())
+    }
     #[allow(dead_code)]
     pub async fn query_launch_link(&self, uri: &str) -> anyhow::Result<String> {
         let res = self.client.get_by_uri(uri).await?;
@@ -371,26 +413,21 @@ impl Course {
             .iter()
             .find(|(_, url)| url.contains("tool_id=_1761_1"))
             .map(|(k, _)| k.clone());
-        if video_entry_title.is_none() {
-            log::warn!(
-                "课程 {} 未找到对应的视频栏目 entry（videoList）",
-                self.meta.title()
-            );
-        } else {
+        if let Some(title) = &video_entry_title {
             log::debug!(
                 "课程 {} 的视频 entry 被识别为 [{}]",
                 self.meta.title(),
-                video_entry_title.as_ref().unwrap()
+                title
+            );
+        } else {
+            log::warn!(
+                "课程 {} 未找到对应的视频栏目 entry（videoList）",
+                self.meta.title()
             );
         }
 
         // ② 拉取 meta 列表
-        let metas = with_cache(
-            &format!("Course::get_video_list_{}", self.meta.id),
-            self.client.cache_ttl(),
-            self._get_video_list(),
-        )
-        .await?;
+        let metas = self._get_video_list().await?;
 
         // ③ 为每个视频绑定统一的 section_name（即 entry）
         let videos = metas
@@ -423,45 +460,31 @@ impl Course {
     }
     async fn _get_video_list(&self) -> anyhow::Result<Vec<CourseVideoMeta>> {
         let u = low_level::VIDEO_LIST.into_url()?;
-        let dom = self.client.bb_course_video_list(&self.meta.id).await?;
-
-        let videos = dom
-            .select(&Selector::parse("tbody#listContainer_databody > tr").unwrap())
-            .map(|tr| {
-                let title = tr
-                    .child_elements()
-                    .nth(0)
-                    .unwrap()
-                    .text()
-                    .collect::<String>();
-                let s = Selector::parse("span.table-data-cell-value").unwrap();
-                let mut values = tr.select(&s);
-                let time = values
-                    .next()
-                    .context("time not found")?
-                    .text()
-                    .collect::<String>();
-                let _ = values.next().context("teacher not found")?;
-                let link = values.next().context("video link not found")?;
-                let a = link
-                    .child_elements()
-                    .next()
-                    .context("video link anchor not found")?;
-                let link = a
-                    .value()
-                    .attr("href")
-                    .context("video link not found")?
-                    .to_owned();
-
-                Ok(CourseVideoMeta {
-                    title,
-                    time,
-                    url: u.join(&link)?.to_string(),
-                })
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?;
-
-        Ok(videos)
+        let mut metas: Vec<CourseVideoMeta> = Vec::new();
+        let mut start = 0;
+        loop {
+            let dom = self
+                .client
+                .bb_course_video_list(&self.meta.id, start)
+                .await?;
+            // rows without a link are skipped by the parser, so page by the rows on the page
+            let rows = parse::video_list_rows(&dom);
+            let page = parse::video_list(&dom, &u)?;
+            // stop if the server ignores startIndex and returns the first page again
+            if page
+                .first()
+                .is_some_and(|v| metas.iter().any(|m| m.url == v.url))
+            {
+                log::warn!("video list of {} is not paginated", self.meta.title());
+                break;
+            }
+            metas.extend(page);
+            start += rows;
+            if rows < low_level::VIDEO_LIST_PAGE_SIZE {
+                break;
+            }
+        }
+        Ok(metas)
     }
     /// 列出本课程全部作业句柄（AssignmentHandle）
     pub async fn list_assignments(&self) -> anyhow::Result<Vec<CourseAssignmentHandle>> {
@@ -552,7 +575,7 @@ impl Course {
 
         Ok((handles, depths, parent_ids))
     }
-    pub async fn list_announcements(&self) -> Result<Vec<CourseAnnouncementHandle>> {
+    async fn _list_announcements(&self) -> Result<Vec<CourseContentData>> {
         let dom = self
             .client
             .0
@@ -560,21 +583,19 @@ impl Course {
             .bb_coursepage(&self.meta.id)
             .await?;
 
-        let list_selector = Selector::parse("ul.announcementList > li")
-            .map_err(|e| anyhow!("选择器错误: {}", e))?;
+        parse::announcements(&dom)
+    }
+    pub async fn list_announcements(&self) -> Result<Vec<CourseAnnouncementHandle>> {
+        let announcements = self._list_announcements().await?;
 
-        let mut announcements = Vec::new();
-        for li in dom.select(&list_selector) {
-            // 统一调用 from_element
-            let content = Arc::new(CourseContentData::from_announcement_element(li)?);
-            announcements.push(CourseAnnouncementHandle {
+        Ok(announcements
+            .into_iter()
+            .map(|content| CourseAnnouncementHandle {
                 client: self.client.clone(),
                 course: self.meta.clone(),
-                content,
-            });
-        }
-
-        Ok(announcements)
+                content: Arc::new(content),
+            })
+            .collect())
     }
     pub async fn build_tree(&self) -> anyhow::Result<CourseTreeNode> {
         // 1. 创建根节点 - 课程本身
@@ -766,44 +787,39 @@ impl CourseContentStream {
         let doms = join_all(futs).await;
 
         // 处理结果
-        for (probe, dom_result) in to_process.into_iter().zip(doms.into_iter()) {
+        for (probe, dom_result) in to_process.into_iter().zip(doms) {
             match dom_result {
                 Ok(dom) => {
-                    let selector = Selector::parse("#content_listContainer > li").unwrap();
-                    for li in dom.select(&selector) {
-                        match CourseContentData::from_element(
-                            li,
-                            probe.parent_id.as_deref(),
-                            probe.parent_title.as_deref(),
-                            probe.depth,
-                            probe.section_name.as_deref(),
-                        ) {
-                            Ok(data) => {
-                                // 更新层级映射
-                                self.parent_map
-                                    .insert(data.id.clone(), Some(probe.id.clone()));
-                                self.depth_map.insert(data.id.clone(), data.depth);
-
-                                // 添加到批次
-                                batch.push(data.clone());
-
-                                // 如果是文件夹且有链接，添加到探测队列
-                                if data.is_folder && data.has_link {
-                                    let child_probe = ContentProbe {
-                                        parent_id: Some(data.id.clone()),
-                                        parent_title: Some(data.title.clone()), // 使用文件夹标题作为子节点的父标题
-                                        depth: data.depth,
-                                        id: data.id.clone(),
-                                        section_name: probe.section_name.clone(), // 保持同一栏目
-                                    };
-
-                                    self.probe_queue.push_back(child_probe);
-                                }
-                            }
-                            Err(e) => log::warn!("解析元素错误: {}", e),
+                    for data in parse::contents(
+                        &dom,
+                        probe.parent_id.as_deref(),
+                        probe.parent_title.as_deref(),
+                        probe.depth,
+                        probe.section_name.as_deref(),
+                    ) {
+                        // 更新层级映射
+                        self.parent_map
+                            .insert(data.id.clone(), Some(probe.id.clone()));
+                        self.depth_map.insert(data.id.clone(), data.depth);
+
+                        // 如果是文件夹且有链接，添加到探测队列
+                        if data.is_folder && data.has_link {
+                            let child_probe = ContentProbe {
+                                parent_id: Some(data.id.clone()),
+                                parent_title: Some(data.title.clone()), // 使用文件夹标题作为子节点的父标题
+                                depth: data.depth,
+                                id: data.id.clone(),
+                                section_name: probe.section_name.clone(), // 保持同一栏目
+                            };
+
+                            self.probe_queue.push_back(child_probe);
                         }
+
+                        // 添加到批次
+                        batch.push(data);
                     }
                 }
+                Err(e) if e.is::<NotCachedError>() => return Err(e),
                 Err(e) => {
                     log::warn!("内容页面获取失败 {}: {}", probe.id, e);
                     // 重新加入队列重试
@@ -843,6 +859,9 @@ impl CourseContentStream {
     pub fn len(&self) -> usize {
         self.visited_ids.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.visited_ids.is_empty()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -864,10 +883,22 @@ impl CourseContent {
             None
         }
     }
+
+    pub fn into_document_opt(self) -> Option<CourseDocumentHandle> {
+        if let CourseContentKind::Document = self.data.kind {
+            Some(CourseDocumentHandle {
+                client: self.client,
+                course: self.course,
+                content: self.data,
+            })
+        } else {
+            None
+        }
+    }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
-enum CourseContentKind {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum CourseContentKind {
     Document,
     Assignment,
     Folder,       // 添加 Folder 变体
@@ -893,6 +924,25 @@ pub struct CourseContentData {
 }
 
 impl CourseContentData {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+    pub fn kind(&self) -> CourseContentKind {
+        self.kind
+    }
+    /// 所在文件夹的 ID，位于栏目顶层时为 `None`
+    pub fn parent_id(&self) -> Option<&str> {
+        self.parent_id.as_deref()
+    }
+    pub fn descriptions(&self) -> &[String] {
+        &self.descriptions
+    }
+    pub fn attachments(&self) -> &[(String, String)] {
+        &self.attachments
+    }
     pub fn is_folder(&self) -> bool {
         self.is_folder
     }
@@ -915,11 +965,11 @@ impl CourseContentData {
         // ── ② 内容类型判定 ───────────────────────────────────────
         let alt = img.attr("alt");
         let (kind, is_folder) = match alt {
-            Some("作业") => (CourseContentKind::Assignment, false),
-            Some("内容文件夹") | Some("文件夹") | Some("目录") => {
+            Some("作业" | "Assignment") => (CourseContentKind::Assignment, false),
+            Some("内容文件夹" | "文件夹" | "目录" | "Content Folder" | "Folder") => {
                 (CourseContentKind::Folder, true)
             } // 使用 Folder 变体
-            Some("项目") | Some("文件") => (CourseContentKind::Document, false),
+            Some("项目" | "文件" | "Item" | "File") => (CourseContentKind::Document, false),
             Some(alt) => {
                 log::warn!("unknown content kind: {alt:?}");
                 (CourseContentKind::Unknown, false)
@@ -941,6 +991,7 @@ impl CourseContentData {
         // ── ④ 描述正文（纯文本）─────────────────────────────────
         let descriptions = detail_div
             .select(&Selector::parse("div.vtbegenerated > *").unwrap())
+            .filter(|p| !matches!(p.value().name(), "script" | "style"))
             .map(|p| Self::collect_text(p).trim().to_owned())
             .collect::<Vec<_>>();
 
@@ -953,7 +1004,11 @@ impl CourseContentData {
                     .collect::<String>()
                     .trim_start_matches('\u{a0}')
                     .to_owned();
-                let href = a.value().attr("href").unwrap().to_owned();
+                let href = a
+                    .value()
+                    .attr("href")
+                    .context("attachment link not found")?
+                    .to_owned();
                 Ok((text, href))
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
@@ -1123,32 +1178,14 @@ impl CourseAssignmentHandle {
             .bb_course_assignment_uploadpage(&self.course.id, &self.content.id)
             .await?;
 
-        let deadline = dom
-            .select(&Selector::parse("#assignMeta2 + div").unwrap())
-            .next()
-            .map(|e| {
-                // replace consecutive whitespaces with a single space
-                e.text()
-                    .collect::<String>()
-                    .split_whitespace()
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            });
+        let deadline = parse::assignment_deadline(&dom);
 
         let attempt = self._get_current_attempt().await?;
 
         Ok(CourseAssignmentData { deadline, attempt })
     }
     pub async fn get(&self) -> anyhow::Result<CourseAssignment> {
-        let data = with_cache(
-            &format!(
-                "CourseAssignmentHandle::_get_{}_{}",
-                self.content.id, self.course.id
-            ),
-            self.client.cache_ttl(),
-            self._get(),
-        )
-        .await?;
+        let data = self._get().await?;
 
         Ok(CourseAssignment {
             client: self.client.clone(),
@@ -1164,21 +1201,7 @@ impl CourseAssignmentHandle {
             .bb_course_assignment_viewpage(&self.course.id, &self.content.id)
             .await?;
 
-        let attempt_label = if let Some(e) = dom
-            .select(&Selector::parse("h3#currentAttempt_label").unwrap())
-            .next()
-        {
-            e.text().collect::<String>()
-        } else {
-            return Ok(None);
-        };
-
-        let attempt_label = attempt_label
-            .split_whitespace()
-            .collect::<Vec<_>>()
-            .join(" ");
-
-        Ok(Some(attempt_label))
+        Ok(parse::current_attempt(&dom))
     }
 }
 
@@ -1196,11 +1219,61 @@ pub struct CourseAssignment {
     data: CourseAssignmentData,
 }
 
+#[cfg(test)]
+impl CourseAssignment {
+    /// 不经过网络构造一个作业，用于其他模块的测试
+    pub(crate) fn for_test(title: &str, deadline: &str, attempt: Option<&str>) -> Self {
+        Self {
+            client: Client::default(),
+            course: Arc::new(CourseMeta {
+                id: "_80167_1".to_owned(),
+                long_title: "24-25学年第1学期: 数据结构与算法(24-25学年第1学期)".to_owned(),
+                is_current: true,
+            }),
+            content: Arc::new(CourseContentData {
+                id: "_1500201_1".to_owned(),
+                title: title.to_owned(),
+                kind: CourseContentKind::Assignment,
+                has_link: true,
+                descriptions: vec!["截止前提交 PDF 文件。".to_owned()],
+                attachments: Vec::new(),
+                parent_id: None,
+                parent_title: None,
+                depth: 0,
+                section_name: None,
+                is_folder: false,
+            }),
+            data: CourseAssignmentData {
+                deadline: Some(deadline.to_owned()),
+                attempt: attempt.map(str::to_owned),
+            },
+        }
+    }
+}
+
 impl CourseAssignment {
     pub fn title(&self) -> &str {
         &self.content.title
     }
 
+    pub fn course(&self) -> &CourseMeta {
+        &self.course
+    }
+
+    pub fn content_id(&self) -> &str {
+        &self.content.id
+    }
+
+    /// 作业在教学网上的页面地址
+    pub fn url(&self) -> String {
+        format!(
+            "{}?action=newAttempt&content_id={}&course_id={}",
+            low_level::UPLOAD_ASSIGNMENT,
+            self.content.id,
+            self.course.id
+        )
+    }
+
     pub fn descriptions(&self) -> &[String] {
         &self.content.descriptions
     }
@@ -1213,29 +1286,20 @@ impl CourseAssignment {
         self.data.attempt.as_deref()
     }
 
+    /// Try to parse the time of the last attempt from its label.
+    pub fn last_attempt_time(&self) -> Option<chrono::DateTime<chrono::Local>> {
+        datetime::parse_local(self.data.attempt.as_deref()?)
+    }
+
     pub async fn get_submit_formfields(&self) -> anyhow::Result<HashMap<String, String>> {
+        // the form carries a one-time nonce, so never use a cached page
         let dom = self
             .client
+            .nocache()
             .bb_course_assignment_uploadpage(&self.course.id, &self.content.id)
             .await?;
 
-        let extract_field = |input: scraper::ElementRef<'_>| {
-            let name = input.value().attr("name")?.to_owned();
-            let value = input.value().attr("value")?.to_owned();
-            Some((name, value))
-        };
-
-        let submitformfields = dom
-            .select(&Selector::parse("form#uploadAssignmentFormId input").unwrap())
-            .map(extract_field)
-            .chain(
-                dom.select(&Selector::parse("div.field input").unwrap())
-                    .map(extract_field),
-            )
-            .flatten()
-            .collect::<HashMap<_, _>>();
-
-        Ok(submitformfields)
+        Ok(parse::submit_formfields(&dom))
     }
 
     pub async fn submit_file(&self, path: &std::path::Path) -> anyhow::Result<()> {
@@ -1310,7 +1374,7 @@ impl CourseAssignment {
 
         if !res.status().is_success() {
             let st = res.status();
-            let rbody = res.text().await?;
+            let rbody = res.text();
             if rbody.contains("尝试呈现错误页面时发生严重的内部错误") {
                 anyhow::bail!("invalid status {} (caused by unknown server error)", st);
             }
@@ -1322,38 +1386,9 @@ impl CourseAssignment {
         Ok(())
     }
 
-    /// Try to parse the deadline string into a NaiveDateTime.
+    /// Try to parse the deadline string. The result is converted to the local timezone.
     pub fn deadline(&self) -> Option<chrono::DateTime<chrono::Local>> {
-        let d = self.data.deadline.as_deref()?;
-        let re = regex::Regex::new(
-            r"(\d{4})年(\d{1,2})月(\d{1,2})日 星期. (上午|下午)(\d{1,2}):(\d{1,2})",
-        )
-        .unwrap();
-
-        if let Some(caps) = re.captures(d) {
-            let year: i32 = caps[1].parse().ok()?;
-            let month: u32 = caps[2].parse().ok()?;
-            let day: u32 = caps[3].parse().ok()?;
-            let mut hour: u32 = caps[5].parse().ok()?;
-            let minute: u32 = caps[6].parse().ok()?;
-
-            // Adjust for PM times
-            if &caps[4] == "下午" && hour < 12 {
-                hour += 12;
-            }
-
-            // Create NaiveDateTime
-            let naive_dt = chrono::NaiveDateTime::new(
-                chrono::NaiveDate::from_ymd_opt(year, month, day)?,
-                chrono::NaiveTime::from_hms_opt(hour, minute, 0)?,
-            );
-
-            let r = chrono::Local.from_local_datetime(&naive_dt).unwrap();
-
-            Some(r)
-        } else {
-            None
-        }
+        datetime::parse_local(self.data.deadline.as_deref()?)
     }
 
     pub fn deadline_raw(&self) -> Option<&str> {
@@ -1391,7 +1426,7 @@ impl CourseAssignment {
             "status not success: {}",
             res.status()
         );
-        let body = res.bytes().await?;
+        let body = res.bytes();
 
         // compio::fs::write 返回 BufResult，仍需用宏展开成 Result
         let r = compio::fs::write(dest, body).await;
@@ -1440,6 +1475,10 @@ impl CourseDocumentHandle {
         &self.content.title
     }
 
+    pub fn attachments(&self) -> &[(String, String)] {
+        &self.content.attachments
+    }
+
     pub async fn get(&self) -> anyhow::Result<CourseDocument> {
         Ok(CourseDocument {
             client: self.client.clone(),
@@ -1499,14 +1538,14 @@ impl CourseDocument {
                 let res2 = self.client.get_by_uri(&loc).await?;
                 anyhow::ensure!(res2.status().is_success(), "status not success");
 
-                let body = res2.bytes().await?;
+                let body = res2.bytes();
                 let r = compio::fs::write(dest, body).await;
                 compio::buf::buf_try!(@try r);
             }
 
             /* ---------- ② 直接 200 OK ---------- */
             200 => {
-                let body = res.bytes().await?;
+                let body = res.bytes();
                 let r = compio::fs::write(dest, body).await;
                 compio::buf::buf_try!(@try r);
             }
@@ -1548,6 +1587,10 @@ pub struct CourseAnnouncement {
     pub content: Arc<CourseContentData>,
 }
 impl CourseAnnouncement {
+    pub fn id(&self) -> &str {
+        &self.content.id
+    }
+
     pub fn title(&self) -> String {
         self.content.title.clone()
     }
@@ -1556,6 +1599,16 @@ impl CourseAnnouncement {
         &self.content.descriptions
     }
 
+    /// 发布时间（原始字符串）
+    pub fn created_time_raw(&self) -> Option<&str> {
+        self.content.descriptions.get(1).map(String::as_str)
+    }
+
+    /// 发布时间，转换为本机时区
+    pub fn created_time(&self) -> Option<chrono::DateTime<chrono::Local>> {
+        datetime::parse_local(self.created_time_raw()?)
+    }
+
     pub fn attachments(&self) -> &Vec<(String, String)> {
         &self.content.attachments
     }
@@ -1582,13 +1635,13 @@ impl CourseAnnouncement {
                 let res2 = self.client.get_by_uri(&loc).await?;
                 anyhow::ensure!(res2.status().is_success(), "status not success");
 
-                let body = res2.bytes().await?;
+                let body = res2.bytes();
                 let r = compio::fs::write(dest, body).await;
                 compio::buf::buf_try!(@try r);
             }
 
             200 => {
-                let body = res.bytes().await?;
+                let body = res.bytes();
                 let r = compio::fs::write(dest, body).await;
                 compio::buf::buf_try!(@try r);
             }
@@ -1600,11 +1653,16 @@ impl CourseAnnouncement {
         Ok(())
     }
 }
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CourseVideoMeta {
     title: String,
     time: String,
     url: String,
+    #[serde(default)]
+    teacher: String,
+    /// 回放时长 (秒)，获取回放后由播放列表中各分片的 `EXTINF` 累加得到
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
 }
 impl CourseVideoMeta {
     pub fn title(&self) -> &str {
@@ -1613,6 +1671,26 @@ impl CourseVideoMeta {
     pub fn time(&self) -> &str {
         &self.time
     }
+    /// 授课教师，旧版本的缓存中可能为空
+    pub fn teacher(&self) -> &str {
+        &self.teacher
+    }
+    /// 回放开始时间，转换为本机时区
+    pub fn start_time(&self) -> Option<chrono::DateTime<chrono::Local>> {
+        datetime::parse_local(&self.time)
+    }
+    /// 回放时长。回放列表中没有时长，只有通过 [`CourseVideo::meta`] 获取的 HLS 回放才有
+    pub fn duration(&self) -> Option<std::time::Duration> {
+        self.duration.map(std::time::Duration::from_secs_f64)
+    }
+    /// 回放结束时间，即开始时间加上时长
+    pub fn end_time(&self) -> Option<chrono::DateTime<chrono::Local>> {
+        Some(self.start_time()? + self.duration()?)
+    }
+    /// 回放播放页面地址
+    pub fn url(&self) -> &str {
+        &self.url
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1656,10 +1734,17 @@ impl CourseVideoHandle {
     pub fn meta(&self) -> &CourseVideoMeta {
         &self.meta
     }
+    pub fn course(&self) -> &CourseMeta {
+        &self.course
+    }
+    /// 所用客户端的缓存，回放的中间文件也应放在其目录下
+    pub fn cache_store(&self) -> &'static crate::cache::CacheStore {
+        self.client.cache_store()
+    }
     async fn get_iframe_url(&self) -> anyhow::Result<String> {
         let res = self.client.get_by_uri(&self.meta.url).await?;
         anyhow::ensure!(res.status().is_success(), "status not success");
-        let rbody = res.text().await?;
+        let rbody = res.text();
         let dom = scraper::Html::parse_document(&rbody);
         let iframe = dom
             .select(&Selector::parse("#content iframe").unwrap())
@@ -1705,104 +1790,177 @@ impl CourseVideoHandle {
         Ok(value)
     }
 
-    fn get_m3u8_path(&self, sub_info: serde_json::Value) -> anyhow::Result<String> {
-        let sub_content = sub_info
-            .as_object()
-            .context("sub_info not object")?
-            .get("list")
-            .context("sub_info.list not found")?
-            .as_array()
-            .context("sub_info.list not array")?
-            .first()
-            .context("sub_info.list empty")?
-            .as_object()
-            .context("sub_info.list[0] not object")?
-            .get("sub_content")
-            .context("sub_info.list[0].sub_content not found")?
-            .as_str()
-            .context("sub_info.list[0].sub_content not string")?;
-
-        let sub_content = serde_json::Value::from_str(sub_content)?;
-
-        let save_playback = sub_content
-            .as_object()
-            .context("sub_content not object")?
-            .get("save_playback")
-            .context("sub_content.save_playback not found")?
-            .as_object()
-            .context("sub_content.save_playback not object")?;
-
-        let is_m3u8 = save_playback
-            .get("is_m3u8")
-            .context("sub_content.save_playback.is_m3u8 not found")?
-            .as_str()
-            .context("sub_content.save_playback.is_m3u8 not string")?;
-
-        anyhow::ensure!(is_m3u8 == "yes", "not m3u8");
-
-        let url = save_playback
-            .get("contents")
-            .context("save_playback.contents not found")?
-            .as_str()
-            .context("save_playback.contents not string")?;
-
-        Ok(url.to_owned())
-    }
-
     async fn get_m3u8_playlist(&self, url: &str) -> anyhow::Result<bytes::Bytes> {
         let res = self.client.get_by_uri(url).await?;
         anyhow::ensure!(res.status().is_success(), "status not success");
-        let rbody = res.bytes().await?;
+        let rbody = res.bytes();
         Ok(rbody)
     }
 
-    async fn _get(&self) -> anyhow::Result<(String, bytes::Bytes)> {
+    /// 列出所有变体。sub_info 直接给出媒体播放列表时一并返回其内容，避免重复请求。
+    async fn _variants(&self) -> anyhow::Result<Vec<(VideoVariant, Option<bytes::Bytes>)>> {
         let loc = self.get_iframe_url().await?;
         let info = self.get_sub_info(&loc).await?;
-        let pl_url = self.get_m3u8_path(info)?;
-        let pl_raw = self.get_m3u8_playlist(&pl_url).await?;
-        Ok((pl_url, pl_raw))
+
+        let mut r = Vec::new();
+        for (stream, kind, url) in variant::sub_info_streams(&info)? {
+            if kind == PlaybackKind::File {
+                let v = VideoVariant {
+                    stream,
+                    kind,
+                    bandwidth: None,
+                    resolution: None,
+                    url,
+                };
+                r.push((v, None));
+                continue;
+            }
+            let raw = self.get_m3u8_playlist(&url).await?;
+            let (_, pl) = m3u8_rs::parse_playlist(&raw)
+                .map_err(|e| anyhow::anyhow!("{:#}", e))
+                .context("parse m3u8 failed")?;
+            match pl {
+                m3u8_rs::Playlist::MasterPlaylist(pl) => {
+                    let base = Url::parse(&url).context("parse playlist url failed")?;
+                    let vs = variant::master_variants(&pl, &base, &stream)?;
+                    r.extend(vs.into_iter().map(|v| (v, None)));
+                }
+                m3u8_rs::Playlist::MediaPlaylist(_) => {
+                    let v = VideoVariant {
+                        stream,
+                        kind,
+                        bandwidth: None,
+                        resolution: None,
+                        url,
+                    };
+                    r.push((v, Some(raw)));
+                }
+            }
+        }
+        Ok(r)
+    }
+
+    fn context_msg(&self) -> String {
+        format!(
+            "get course video for {} {}",
+            self.course.title(),
+            self.meta().title()
+        )
     }
 
+    /// 列出回放的所有视频流和清晰度
+    pub async fn variants(&self) -> anyhow::Result<Vec<VideoVariant>> {
+        let vs = self._variants().await.with_context(|| self.context_msg())?;
+        Ok(vs.into_iter().map(|(v, _)| v).collect())
+    }
+
+    /// 获取第一路视频流中码率最高的变体
     pub async fn get(&self) -> anyhow::Result<CourseVideo> {
-        let (pl_url, pl_raw) = self._get().await.with_context(|| {
-            format!(
-                "get course video for {} {}",
-                self.course.title(),
-                self.meta().title()
-            )
-        })?;
+        self.get_with(&VideoSelector::default()).await
+    }
+
+    /// 获取按 `selector` 选出的变体
+    pub async fn get_with(&self, selector: &VideoSelector) -> anyhow::Result<CourseVideo> {
+        let mut vs = self._variants().await.with_context(|| self.context_msg())?;
+        let i = selector
+            .select(&vs.iter().map(|(v, _)| v.clone()).collect::<Vec<_>>())
+            .with_context(|| self.context_msg())?;
+        let (variant, raw) = vs.swap_remove(i);
+        log::debug!("selected video variant {variant}: {}", variant.url);
 
-        let pl_raw = pl_raw.to_vec();
-        let (_, pl) = m3u8_rs::parse_playlist(&pl_raw)
+        let url = variant
+            .url
+            .as_str()
+            .into_url()
+            .context("parse playback url failed")?;
+        let video = |playback| CourseVideo {
+            client: self.client.clone(),
+            course: self.course.clone(),
+            meta: self.meta.clone(),
+            playback,
+            variant: variant.clone(),
+        };
+        if variant.kind == PlaybackKind::File {
+            return Ok(video(Playback::File { url }));
+        }
+
+        let pl_raw = match raw {
+            Some(raw) => raw,
+            None => self
+                .get_m3u8_playlist(&variant.url)
+                .await
+                .with_context(|| self.context_msg())?,
+        };
+        let (_, pl) = m3u8_rs::parse_playlist(&hls::fix_method_none(&pl_raw))
             .map_err(|e| anyhow::anyhow!("{:#}", e))
             .context("parse m3u8 failed")?;
 
         match pl {
-            m3u8_rs::Playlist::MasterPlaylist(_) => anyhow::bail!("master playlist not supported"),
-            m3u8_rs::Playlist::MediaPlaylist(pl) => Ok(CourseVideo {
-                client: self.client.clone(),
-                course: self.course.clone(),
-                meta: self.meta.clone(),
-                pl_url: pl_url.into_url().context("parse pl_url failed")?,
-                pl_raw: pl_raw.into(),
-                pl,
-            }),
+            m3u8_rs::Playlist::MasterPlaylist(_) => {
+                anyhow::bail!("nested master playlist not supported")
+            }
+            m3u8_rs::Playlist::MediaPlaylist(pl) => {
+                let duration = pl.segments.iter().map(|s| s.duration as f64).sum();
+                Ok(CourseVideo {
+                    meta: Arc::new(CourseVideoMeta {
+                        duration: Some(duration),
+                        ..(*self.meta).clone()
+                    }),
+                    ..video(Playback::Hls {
+                        pl_url: url,
+                        pl_raw,
+                        pl: Box::new(pl),
+                    })
+                })
+            }
         }
     }
 }
 
+#[derive(Debug)]
+enum Playback {
+    Hls {
+        pl_raw: bytes::Bytes,
+        pl_url: url::Url,
+        pl: Box<m3u8_rs::MediaPlaylist>,
+    },
+    File {
+        url: url::Url,
+    },
+}
+
+/// 回放视频。HLS 回放按分片下载，其余回放是单个视频文件，见 [`CourseVideo::file_url`]。
 #[derive(Debug)]
 pub struct CourseVideo {
     client: Client,
     course: Arc<CourseMeta>,
     meta: Arc<CourseVideoMeta>,
-    pl_raw: bytes::Bytes,
-    pl_url: url::Url,
-    pl: m3u8_rs::MediaPlaylist,
+    playback: Playback,
+    variant: VideoVariant,
+}
+
+/// 回放视频文件的一段数据
+#[derive(Debug)]
+pub struct FileChunk {
+    /// 这段数据在文件中的起始位置
+    pub offset: u64,
+    pub data: bytes::Bytes,
+    /// 文件总长度，服务器未给出时为 `None`
+    pub total: Option<u64>,
+    /// 文件的 `ETag`，没有时为 `Last-Modified`，用于在续传前确认文件没有变化
+    pub validator: Option<String>,
 }
 
 impl CourseVideo {
+    pub fn course(&self) -> &CourseMeta {
+        &self.course
+    }
+
+    /// 见 [`CourseVideoHandle::cache_store`]
+    pub fn cache_store(&self) -> &'static crate::cache::CacheStore {
+        self.client.cache_store()
+    }
+
     pub fn course_name(&self) -> &str {
         self.course.name()
     }
@@ -1811,12 +1969,94 @@ impl CourseVideo {
         &self.meta
     }
 
+    /// 下载的变体
+    pub fn variant(&self) -> &VideoVariant {
+        &self.variant
+    }
+
+    /// HLS 回放的媒体播放列表，视频文件回放为空
     pub fn m3u8_raw(&self) -> bytes::Bytes {
-        self.pl_raw.clone()
+        match &self.playback {
+            Playback::Hls { pl_raw, .. } => pl_raw.clone(),
+            Playback::File { .. } => bytes::Bytes::new(),
+        }
     }
 
+    fn segments(&self) -> &[m3u8_rs::MediaSegment] {
+        match &self.playback {
+            Playback::Hls { pl, .. } => &pl.segments,
+            Playback::File { .. } => &[],
+        }
+    }
+
+    /// HLS 分片的数量，视频文件回放为 0
     pub fn len_segments(&self) -> usize {
-        self.pl.segments.len()
+        self.segments().len()
+    }
+
+    /// 视频文件回放的地址，HLS 回放为 `None`
+    pub fn file_url(&self) -> Option<&Url> {
+        match &self.playback {
+            Playback::Hls { .. } => None,
+            Playback::File { url } => Some(url),
+        }
+    }
+
+    /// 请求视频文件从 `offset` 开始的至多 `len` 字节。
+    ///
+    /// 服务器不支持 Range 请求时返回整个文件 (`offset` 为 0)，但文件超过 `len` 字节时返回错误；
+    /// `offset` 已到达文件末尾时返回空的数据。
+    pub async fn get_file_chunk(&self, offset: u64, len: u64) -> anyhow::Result<FileChunk> {
+        let url = self.file_url().context("not a video file replay")?;
+        let res = self
+            .client
+            .get_range_by_uri(url.as_str(), offset, len)
+            .await?;
+        let header = |name| res.headers().get(name).and_then(|v| v.to_str().ok());
+        let content_range =
+            header(http::header::CONTENT_RANGE).and_then(low_level::parse_content_range);
+        let validator = header(http::header::ETAG)
+            .or_else(|| header(http::header::LAST_MODIFIED))
+            .map(str::to_owned);
+
+        match res.status().as_u16() {
+            206 => {
+                let (start, total) = content_range.context("invalid content-range")?;
+                anyhow::ensure!(
+                    start == Some(offset),
+                    "server returned range starting at {start:?}, expected {offset}"
+                );
+                Ok(FileChunk {
+                    offset,
+                    data: res.bytes(),
+                    total,
+                    validator,
+                })
+            }
+            200 => {
+                let data = res.bytes();
+                Ok(FileChunk {
+                    offset: 0,
+                    total: Some(data.len() as u64),
+                    data,
+                    validator,
+                })
+            }
+            416 => {
+                let total = content_range.and_then(|(_, t)| t);
+                anyhow::ensure!(
+                    total == Some(offset),
+                    "range {offset}- not satisfiable, file length {total:?}"
+                );
+                Ok(FileChunk {
+                    offset,
+                    data: bytes::Bytes::new(),
+                    total,
+                    validator,
+                })
+            }
+            s => anyhow::bail!("unexpected status {s}"),
+        }
     }
 
     /// Refresh the key for the given segment index. You should call this method before getting the segment data referenced by the index.
@@ -1830,21 +2070,44 @@ impl CourseVideo {
         index: usize,
         key: Option<&'a m3u8_rs::Key>,
     ) -> Option<&'a m3u8_rs::Key> {
-        let seg = &self.pl.segments[index];
+        let seg = &self.segments()[index];
         fn fallback_keyformat(key: &m3u8_rs::Key) -> &str {
             key.keyformat.as_deref().unwrap_or("identity")
         }
 
-        if let Some(newkey) = &seg.key {
-            if key.is_none_or(|k| fallback_keyformat(k) == fallback_keyformat(newkey)) {
-                return Some(newkey);
-            }
+        if let Some(newkey) = &seg.key
+            && key.is_none_or(|k| fallback_keyformat(k) == fallback_keyformat(newkey))
+        {
+            return Some(newkey);
         }
         key
     }
 
+    /// 指向解密后分片的媒体播放列表：去掉所有 `EXT-X-KEY`，第 `i` 个分片的地址替换为 `uri(i)`。
+    ///
+    /// 视频文件回放，以及带有初始化分片 (`EXT-X-MAP`) 的播放列表会返回错误，后者的分片无法单独解码
+    pub fn local_playlist(&self, uri: impl Fn(usize) -> String) -> anyhow::Result<String> {
+        let Playback::Hls { pl, .. } = &self.playback else {
+            anyhow::bail!("not an HLS replay");
+        };
+        anyhow::ensure!(
+            pl.segments.iter().all(|seg| seg.map.is_none()),
+            "playlists with EXT-X-MAP initialization sections are not supported"
+        );
+        let mut pl = pl.as_ref().clone();
+        for (i, seg) in pl.segments.iter_mut().enumerate() {
+            seg.uri = uri(i);
+            seg.key = None;
+            // segments are served whole
+            seg.byte_range = None;
+        }
+        let mut out = Vec::new();
+        pl.write_to(&mut out).expect("write to vec");
+        Ok(String::from_utf8(out).expect("playlist is utf-8"))
+    }
+
     pub fn segment(&self, index: usize) -> &m3u8_rs::MediaSegment {
-        &self.pl.segments[index]
+        &self.segments()[index]
     }
 
     /// Fetch the segment data for the given index. If `key` is provided, the segment will be decrypted.
@@ -1852,6 +2115,25 @@ impl CourseVideo {
         &'a self,
         index: usize,
         key: Option<&'a m3u8_rs::Key>,
+    ) -> anyhow::Result<bytes::Bytes> {
+        self.segment_data(index, key, true).await
+    }
+
+    /// Same as [`Self::get_segment_data`], but bypasses the cache store. Used when the segments
+    /// are written straight into an output file, so that they are not kept twice on disk.
+    pub async fn fetch_segment_data<'a>(
+        &'a self,
+        index: usize,
+        key: Option<&'a m3u8_rs::Key>,
+    ) -> anyhow::Result<bytes::Bytes> {
+        self.segment_data(index, key, false).await
+    }
+
+    async fn segment_data(
+        &self,
+        index: usize,
+        key: Option<&m3u8_rs::Key>,
+        cached: bool,
     ) -> anyhow::Result<bytes::Bytes> {
         log::info!(
             "downloading segment {}/{} for video {}",
@@ -1860,22 +2142,30 @@ impl CourseVideo {
             self.meta.title()
         );
 
-        let seg = &self.pl.segments[index];
+        let Playback::Hls { pl_url, pl, .. } = &self.playback else {
+            anyhow::bail!("not an HLS replay");
+        };
+        let seg = &pl.segments[index];
 
         // fetch maybe encrypted segment data
-        let seg_url: String = self.pl_url.join(&seg.uri).context("join seg url")?.into();
-        let mut bytes = with_cache_bytes(
-            &format!("CourseVideo::download_segment_{}", seg_url),
-            self.client.download_artifact_ttl(),
-            self._download_segment(&seg_url),
-        )
-        .await
+        let seg_url: String = pl_url.join(&seg.uri).context("join seg url")?.into();
+        let mut bytes = if cached {
+            with_cache_bytes(
+                self.client.cache_store(),
+                &self.course.cache_key("video_segment").id(&seg_url),
+                self.client.download_artifact_ttl(),
+                self._download_segment(&seg_url),
+            )
+            .await
+        } else {
+            self._download_segment(&seg_url).await
+        }
         .context("download segment data")?;
 
         // decrypt it if needed
         if let Some(key) = key {
             // sequence number may be used to construct IV
-            let seq = (self.pl.media_sequence as usize + index) as u128;
+            let seq = pl.media_sequence + index as u64;
             bytes = self
                 .decrypt_segment(key, bytes, seq)
                 .await
@@ -1889,17 +2179,18 @@ impl CourseVideo {
         let res = self.client.get_by_uri(seg_url).await?;
         anyhow::ensure!(res.status().is_success(), "status not success");
 
-        let bytes = res.bytes().await?;
+        let bytes = res.bytes();
         Ok(bytes)
     }
 
     async fn get_aes128_key(&self, url: &str) -> anyhow::Result<[u8; 16]> {
         // fetch aes128 key from uri
         let r = with_cache_bytes(
-            &format!("CourseVideo::get_aes128_uri_{}", url),
+            self.client.cache_store(),
+            &self.course.cache_key("video_key").id(url),
             self.client.download_artifact_ttl(),
             async {
-                let r = self.client.get_by_uri(url).await?.bytes().await?;
+                let r = self.client.get_by_uri(url).await?.bytes();
                 Ok(r)
             },
         )
@@ -1916,47 +2207,26 @@ impl CourseVideo {
         Ok(key)
     }
 
+    /// 按 `key` 解密分片。无法解密的加密方式返回 [`UnsupportedEncryption`]
     async fn decrypt_segment(
         &self,
         key: &m3u8_rs::Key,
         bytes: bytes::Bytes,
-        seq: u128,
+        seq: u64,
     ) -> anyhow::Result<bytes::Bytes> {
-        use aes::cipher::{
-            BlockDecryptMut, KeyIvInit, block_padding::Pkcs7, generic_array::GenericArray,
-        };
         // ref: https://datatracker.ietf.org/doc/html/rfc8216#section-4.3.2.4
-        match &key.method {
-            // An encryption method of AES-128 signals that Media Segments are
-            // completely encrypted using [AES_128] with a 128-bit key, Cipher
-            // Block Chaining, and PKCS7 padding [RFC5652].  CBC is restarted
-            // on each segment boundary, using either the IV attribute value
-            // or the Media Sequence Number as the IV; see Section 5.2.  The
-            // URI attribute is REQUIRED for this METHOD.
-            m3u8_rs::KeyMethod::AES128 => {
-                let uri = key.uri.as_ref().context("key uri not found")?;
-                let iv = if let Some(iv) = &key.iv {
-                    let iv = iv.to_ascii_uppercase();
-                    let hx = iv.strip_prefix("0x").context("iv not start with 0x")?;
-                    u128::from_str_radix(hx, 16).context("parse iv failed")?
-                } else {
-                    seq
-                }
-                .to_be_bytes();
-
-                let aes_key = self.get_aes128_key(uri).await?;
-
-                let aes_key = GenericArray::from(aes_key);
-                let iv = GenericArray::from(iv);
-
-                let de = cbc::Decryptor::<aes::Aes128>::new(&aes_key, &iv)
-                    .decrypt_padded_vec_mut::<Pkcs7>(&bytes)
-                    .context("decrypt failed")?;
-
-                Ok(de.into())
-            }
-            r => unimplemented!("m3u8 key: {:?}", r),
-        }
+        let decrypt = match hls::Cipher::of(key)? {
+            hls::Cipher::None => return Ok(bytes),
+            // Media Segments are completely encrypted, CBC is restarted on each segment boundary.
+            hls::Cipher::Aes128 => hls::decrypt_aes128,
+            // only parts of the H.264 and AAC elementary streams are encrypted
+            hls::Cipher::SampleAes => hls::decrypt_sample_aes,
+        };
+        // The URI attribute is REQUIRED unless the METHOD is NONE.
+        let uri = key.uri.as_ref().context("key uri not found")?;
+        let iv = hls::segment_iv(key, seq)?;
+        let aes_key = self.get_aes128_key(uri).await?;
+        Ok(decrypt(&aes_key, &iv, &bytes)?.into())
     }
 }
 
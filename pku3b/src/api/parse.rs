@@ -0,0 +1,280 @@
+//! 教学网页面解析.
+//!
+//! 这里的函数只依赖传入的 [`Html`]，不发出任何请求，便于用保存下来的页面做回归测试。
+//! 测试用的页面位于 `tests/fixtures/html/<解析器>/<用例>.html`，期望输出为同名的 `.json`
+//! 文件。教学网改版导致解析结果变化时，测试会失败；确认新结果无误后，可以用
+//! `UPDATE_GOLDEN=1 cargo test` 重新生成期望输出。
+
+use super::{CourseContentData, CourseVideoMeta};
+use anyhow::Context as _;
+use scraper::{ElementRef, Html, Selector};
+use std::{collections::HashMap, sync::LazyLock};
+
+fn sel(s: &str) -> Selector {
+    Selector::parse(s).unwrap()
+}
+
+static COURSE_KEY: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"key=([\d_]+),").unwrap());
+
+/// 将连续的空白字符替换为一个空格
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// 教学网主页中的课程列表，返回 `(课程 ID, 课程全名, 是否为本学期课程)`
+pub fn courses(dom: &Html) -> anyhow::Result<Vec<(String, String, bool)>> {
+    let ul_sel = sel("ul.courseListing");
+    let a_sel = sel("li a");
+
+    let f = |a: ElementRef<'_>| {
+        let href = a.value().attr("href").context("course link not found")?;
+        let text = a.text().collect::<String>();
+        // course key is of form key=_80052_1
+        let key = COURSE_KEY
+            .captures(href)
+            .and_then(|s| s.get(1))
+            .context("course key not found")?
+            .as_str()
+            .to_owned();
+
+        anyhow::Ok((key, text))
+    };
+
+    // the first one contains the courses in the current semester,
+    // the second one contains the courses in the previous semesters
+    let mut uls = dom.select(&ul_sel);
+    let current = uls.next().context("courses not found")?;
+    let history = uls.next().context("courses not found")?;
+
+    let mut courses = Vec::new();
+    for (ul, is_current) in [(current, true), (history, false)] {
+        for a in ul.select(&a_sel) {
+            let (key, text) = f(a)?;
+            courses.push((key, text, is_current));
+        }
+    }
+    Ok(courses)
+}
+
+/// 课程主页左侧菜单，返回 菜单名 → 链接
+pub fn course_entries(dom: &Html) -> HashMap<String, String> {
+    dom.select(&sel("#courseMenuPalette_contents > li > a"))
+        .filter_map(|a| {
+            let text = a.text().collect::<String>();
+            let href = a.value().attr("href")?;
+            Some((text, href.to_owned()))
+        })
+        .collect()
+}
+
+/// 课程内容页中的条目。无法解析的条目会被跳过。
+pub fn contents(
+    dom: &Html,
+    parent_id: Option<&str>,
+    parent_title: Option<&str>,
+    depth: usize,
+    section_name: Option<&str>,
+) -> Vec<CourseContentData> {
+    dom.select(&sel("#content_listContainer > li"))
+        .filter_map(|li| {
+            CourseContentData::from_element(li, parent_id, parent_title, depth, section_name)
+                .inspect_err(|e| log::warn!("解析元素错误: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+/// 课程主页中的公告列表
+pub fn announcements(dom: &Html) -> anyhow::Result<Vec<CourseContentData>> {
+    dom.select(&sel("ul.announcementList > li"))
+        .map(CourseContentData::from_announcement_element)
+        .collect()
+}
+
+/// 回放列表页中的行数，包括 [`video_list`] 跳过的行，用于翻页
+pub fn video_list_rows(dom: &Html) -> usize {
+    dom.select(&sel("tbody#listContainer_databody > tr"))
+        .count()
+}
+
+/// 课程回放列表，链接相对于 `base` 解析。还没有链接 (转码中) 的回放会被跳过
+pub fn video_list(dom: &Html, base: &url::Url) -> anyhow::Result<Vec<CourseVideoMeta>> {
+    let value_sel = sel("span.table-data-cell-value");
+
+    dom.select(&sel("tbody#listContainer_databody > tr"))
+        .map(|tr| -> anyhow::Result<Option<CourseVideoMeta>> {
+            let title = tr
+                .child_elements()
+                .next()
+                .context("title not found")?
+                .text()
+                .collect::<String>();
+            let mut values = tr.select(&value_sel);
+            let time = values
+                .next()
+                .context("time not found")?
+                .text()
+                .collect::<String>();
+            let teacher = values
+                .next()
+                .context("teacher not found")?
+                .text()
+                .collect::<String>();
+            // a replay that is still transcoding ("转码中") has no link yet
+            let Some(link) = values
+                .next()
+                .and_then(|v| v.child_elements().next())
+                .and_then(|a| a.value().attr("href"))
+            else {
+                log::debug!("skip video without link: {title} {time}");
+                return Ok(None);
+            };
+
+            Ok(Some(CourseVideoMeta {
+                title,
+                time,
+                url: base.join(link)?.to_string(),
+                teacher: collapse_whitespace(&teacher),
+                duration: None,
+            }))
+        })
+        .filter_map(Result::transpose)
+        .collect()
+}
+
+/// 作业提交页中的截止时间
+pub fn assignment_deadline(dom: &Html) -> Option<String> {
+    dom.select(&sel("#assignMeta2 + div"))
+        .next()
+        .map(|e| collapse_whitespace(&e.text().collect::<String>()))
+}
+
+/// 作业查看页中最近一次提交的标签（包含提交时间）
+pub fn current_attempt(dom: &Html) -> Option<String> {
+    dom.select(&sel("h3#currentAttempt_label"))
+        .next()
+        .map(|e| collapse_whitespace(&e.text().collect::<String>()))
+}
+
+/// 作业提交表单中的隐藏字段
+pub fn submit_formfields(dom: &Html) -> HashMap<String, String> {
+    let extract_field = |input: ElementRef<'_>| {
+        let name = input.value().attr("name")?.to_owned();
+        let value = input.value().attr("value")?.to_owned();
+        Some((name, value))
+    };
+
+    dom.select(&sel("form#uploadAssignmentFormId input"))
+        .chain(dom.select(&sel("div.field input")))
+        .filter_map(extract_field)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datetime;
+    use serde_json::{Value, json};
+    use std::{collections::BTreeMap, path::Path};
+
+    fn result<T: serde::Serialize>(r: anyhow::Result<T>) -> Value {
+        match r {
+            Ok(v) => serde_json::to_value(v).unwrap(),
+            Err(e) => json!({ "error": e.to_string() }),
+        }
+    }
+
+    fn sorted(m: HashMap<String, String>) -> BTreeMap<String, String> {
+        m.into_iter().collect()
+    }
+
+    fn with_time(label: Option<String>) -> Value {
+        let time = label.as_deref().and_then(datetime::parse);
+        json!({ "label": label, "time": time })
+    }
+
+    fn run(parser: &str, dom: &Html) -> Value {
+        match parser {
+            "courses" => result(courses(dom)),
+            "course_entries" => json!(sorted(course_entries(dom))),
+            "contents" => json!(contents(
+                dom,
+                Some("_1_1"),
+                Some("课程内容"),
+                1,
+                Some("教学内容")
+            )),
+            "announcements" => result(announcements(dom)),
+            "video_list" => {
+                let base = url::Url::parse(super::super::low_level::VIDEO_LIST).unwrap();
+                let videos = video_list(dom, &base).map(|v| {
+                    v.into_iter()
+                        .map(|v| {
+                            let start = datetime::parse(v.time());
+                            json!({ "meta": v, "start_time": start })
+                        })
+                        .collect::<Vec<_>>()
+                });
+                result(videos)
+            }
+            "assignment_deadline" => with_time(assignment_deadline(dom)),
+            "current_attempt" => with_time(current_attempt(dom)),
+            "submit_formfields" => json!(sorted(submit_formfields(dom))),
+            _ => panic!("unknown parser: {parser}"),
+        }
+    }
+
+    #[test]
+    fn test_golden() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/html");
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+
+        let mut parsers = std::fs::read_dir(&root)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        parsers.sort();
+
+        let mut failures = Vec::new();
+        let mut cases = 0;
+        for dir in parsers {
+            let parser = dir.file_name().unwrap().to_str().unwrap().to_owned();
+            let mut htmls = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .filter(|p| p.extension().is_some_and(|e| e == "html"))
+                .collect::<Vec<_>>();
+            htmls.sort();
+
+            for html in htmls {
+                cases += 1;
+                let dom = Html::parse_document(&std::fs::read_to_string(&html).unwrap());
+                let got = run(&parser, &dom);
+                let golden = html.with_extension("json");
+                if update {
+                    let s = serde_json::to_string_pretty(&got).unwrap() + "\n";
+                    std::fs::write(&golden, s).unwrap();
+                    continue;
+                }
+                let expected = std::fs::read_to_string(&golden)
+                    .ok()
+                    .and_then(|s| serde_json::from_str::<Value>(&s).ok());
+                if expected.as_ref() != Some(&got) {
+                    failures.push(format!(
+                        "{}:\n{}",
+                        html.strip_prefix(&root).unwrap().display(),
+                        serde_json::to_string_pretty(&got).unwrap()
+                    ));
+                }
+            }
+        }
+
+        assert!(cases > 0, "no fixtures found in {}", root.display());
+        assert!(
+            failures.is_empty(),
+            "golden mismatch (rerun with UPDATE_GOLDEN=1 to accept):\n{}",
+            failures.join("\n\n")
+        );
+    }
+}
@@ -5,7 +5,7 @@ use super::{
     CourseAnnouncementHandle, CourseAssignmentHandle, CourseDocumentHandle, CourseVideoHandle,
 };
 /// 结点类型 —— 后续可扩充
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum NodeKind {
     Course,       // 课程根结点
     Entry,        // 左侧导航条
@@ -0,0 +1,422 @@
+//! 回放的视频流与清晰度选择.
+//!
+//! 一个回放可能有多路视频流 (sub_info 中的多个条目，或主播放列表中以 `EXT-X-MEDIA` 区分的
+//! 教师/屏幕画面)，每路视频流又可能有多个清晰度。每个组合对应一个媒体播放列表，称为变体。
+//! 少数回放不使用 HLS，而是直接给出视频文件的地址，这样的视频流只有一个变体。
+
+use anyhow::Context as _;
+use std::str::FromStr;
+use url::Url;
+
+/// 回放的播放方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackKind {
+    /// HLS 媒体播放列表
+    #[default]
+    Hls,
+    /// 可直接下载的视频文件 (如 mp4)
+    File,
+}
+
+impl PlaybackKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Hls => "hls",
+            Self::File => "file",
+        }
+    }
+}
+
+/// 回放的一个可下载的变体
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VideoVariant {
+    /// 视频流名称
+    pub stream: String,
+    #[serde(default)]
+    pub kind: PlaybackKind,
+    /// 峰值码率 (bit/s)，直接给出媒体播放列表时未知
+    pub bandwidth: Option<u64>,
+    /// 分辨率 (宽, 高)
+    pub resolution: Option<(u64, u64)>,
+    /// 媒体播放列表或视频文件的地址
+    pub url: String,
+}
+
+impl VideoVariant {
+    /// 清晰度名称，形如 `720p`
+    pub fn quality(&self) -> Option<String> {
+        self.resolution.map(|(_, h)| format!("{h}p"))
+    }
+
+    /// 区分不同变体下载目录的短 ID
+    pub fn cache_id(&self) -> String {
+        // names directories on disk, so it must not change between builds
+        let h = crate::cache::fnv1a(self.url.as_bytes());
+        format!("{:08x}", h as u32)
+    }
+
+    fn matches_quality(&self, name: &str) -> bool {
+        self.quality().is_some_and(|q| q.eq_ignore_ascii_case(name))
+            || self
+                .resolution
+                .is_some_and(|(w, h)| format!("{w}x{h}").eq_ignore_ascii_case(name))
+    }
+
+    /// 码率未知时按分辨率比较
+    fn rank(&self) -> (u64, u64) {
+        (
+            self.bandwidth.unwrap_or(0),
+            self.resolution.map_or(0, |(w, h)| w * h),
+        )
+    }
+}
+
+impl std::fmt::Display for VideoVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}]", self.stream)?;
+        if let Some(q) = self.quality() {
+            write!(f, " {q}")?;
+        }
+        if let Some(b) = self.bandwidth {
+            write!(f, " {:.1} Mbps", b as f64 / 1e6)?;
+        }
+        if self.kind == PlaybackKind::File {
+            f.write_str(" (file)")?;
+        }
+        Ok(())
+    }
+}
+
+/// 清晰度选择策略
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Quality {
+    /// 码率最高
+    #[default]
+    Best,
+    /// 码率最低
+    Smallest,
+    /// 指定清晰度，形如 `720p` 或 `1280x720`
+    Named(String),
+}
+
+impl FromStr for Quality {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "best" => Quality::Best,
+            "smallest" => Quality::Smallest,
+            _ => Quality::Named(s.to_owned()),
+        })
+    }
+}
+
+impl std::fmt::Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quality::Best => f.write_str("best"),
+            Quality::Smallest => f.write_str("smallest"),
+            Quality::Named(s) => f.write_str(s),
+        }
+    }
+}
+
+/// 从回放的变体中选出要下载的一个。缺省时选择第一路视频流中码率最高的变体。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VideoSelector {
+    /// 视频流名称 (不区分大小写，可以只给出一部分) 或序号 (从 0 开始)
+    pub stream: Option<String>,
+    pub quality: Quality,
+}
+
+impl VideoSelector {
+    /// 返回选中的变体在 `variants` 中的下标
+    pub fn select(&self, variants: &[VideoVariant]) -> anyhow::Result<usize> {
+        let mut streams = Vec::<&str>::new();
+        for v in variants {
+            if !streams.contains(&v.stream.as_str()) {
+                streams.push(&v.stream);
+            }
+        }
+        anyhow::ensure!(!streams.is_empty(), "no video stream available");
+
+        let stream = match &self.stream {
+            None => streams[0],
+            Some(s) => {
+                let lower = s.to_lowercase();
+                s.parse::<usize>()
+                    .ok()
+                    .and_then(|i| streams.get(i).copied())
+                    .or_else(|| streams.iter().copied().find(|n| n.to_lowercase() == lower))
+                    .or_else(|| {
+                        streams
+                            .iter()
+                            .copied()
+                            .find(|n| n.to_lowercase().contains(&lower))
+                    })
+                    .with_context(|| {
+                        format!("stream {s:?} not found, available: {}", streams.join(", "))
+                    })?
+            }
+        };
+
+        let candidates = variants
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.stream == stream);
+        let found = match &self.quality {
+            Quality::Best => candidates.max_by_key(|(i, v)| (v.rank(), std::cmp::Reverse(*i))),
+            Quality::Smallest => candidates.min_by_key(|(i, v)| (v.rank(), *i)),
+            Quality::Named(name) => candidates
+                .filter(|(_, v)| v.matches_quality(name))
+                .max_by_key(|(i, v)| (v.rank(), std::cmp::Reverse(*i))),
+        };
+        found.map(|(i, _)| i).with_context(|| {
+            let available = variants
+                .iter()
+                .filter(|v| v.stream == stream)
+                .filter_map(|v| v.quality())
+                .collect::<Vec<_>>();
+            format!(
+                "quality {} not found in stream {stream:?}, available: {}",
+                self.quality,
+                available.join(", ")
+            )
+        })
+    }
+}
+
+/// 解析 sub_info 中的每个条目，返回 (视频流名称, 播放方式, 地址)。
+///
+/// 只有一个条目或条目标题有重复时，以序号作为视频流名称。
+pub(super) fn sub_info_streams(
+    sub_info: &serde_json::Value,
+) -> anyhow::Result<Vec<(String, PlaybackKind, String)>> {
+    let list = sub_info
+        .get("list")
+        .context("sub_info.list not found")?
+        .as_array()
+        .context("sub_info.list not array")?;
+    anyhow::ensure!(!list.is_empty(), "sub_info.list empty");
+
+    let titles = list
+        .iter()
+        .map(|e| e.get("sub_title").and_then(|t| t.as_str()))
+        .collect::<Option<Vec<_>>>()
+        .filter(|t| {
+            t.len() > 1 && t.iter().collect::<std::collections::HashSet<_>>().len() == t.len()
+        });
+
+    let mut streams = Vec::new();
+    for (i, entry) in list.iter().enumerate() {
+        let name = titles
+            .as_ref()
+            .map_or_else(|| i.to_string(), |t| t[i].to_owned());
+        match playback_url(entry).with_context(|| format!("sub_info.list[{i}]")) {
+            Ok((kind, url)) => streams.push((name, kind, url)),
+            // keep the other streams usable
+            Err(e) if list.len() > 1 => log::warn!("skip video stream {name}: {e:#}"),
+            Err(e) => return Err(e),
+        }
+    }
+    anyhow::ensure!(!streams.is_empty(), "no playback found in sub_info");
+    Ok(streams)
+}
+
+fn playback_url(entry: &serde_json::Value) -> anyhow::Result<(PlaybackKind, String)> {
+    let sub_content = entry
+        .get("sub_content")
+        .context("sub_content not found")?
+        .as_str()
+        .context("sub_content not string")?;
+    let sub_content = serde_json::Value::from_str(sub_content)?;
+
+    let save_playback = sub_content
+        .get("save_playback")
+        .context("sub_content.save_playback not found")?
+        .as_object()
+        .context("sub_content.save_playback not object")?;
+
+    let is_m3u8 = save_playback
+        .get("is_m3u8")
+        .context("sub_content.save_playback.is_m3u8 not found")?
+        .as_str()
+        .context("sub_content.save_playback.is_m3u8 not string")?;
+    let kind = if is_m3u8 == "yes" {
+        PlaybackKind::Hls
+    } else {
+        PlaybackKind::File
+    };
+
+    let url = save_playback
+        .get("contents")
+        .context("save_playback.contents not found")?
+        .as_str()
+        .context("save_playback.contents not string")?;
+    Ok((kind, url.to_owned()))
+}
+
+/// 列出主播放列表中的变体。
+///
+/// 变体指定了 `VIDEO` 分组时，分组中的每个画面 (`EXT-X-MEDIA`) 作为一路视频流，以其 `NAME`
+/// 命名；否则属于名为 `stream` 的视频流。
+pub(super) fn master_variants(
+    pl: &m3u8_rs::MasterPlaylist,
+    base: &Url,
+    stream: &str,
+) -> anyhow::Result<Vec<VideoVariant>> {
+    let mut r = Vec::<VideoVariant>::new();
+    for v in pl.variants.iter().filter(|v| !v.is_i_frame) {
+        let renditions = pl
+            .alternatives
+            .iter()
+            .filter(|a| {
+                a.media_type == m3u8_rs::AlternativeMediaType::Video
+                    && v.video.as_deref() == Some(a.group_id.as_str())
+            })
+            .map(|a| (a.name.as_str(), a.uri.as_deref().unwrap_or(&v.uri)))
+            .collect::<Vec<_>>();
+        let renditions = if renditions.is_empty() {
+            vec![(stream, v.uri.as_str())]
+        } else {
+            renditions
+        };
+
+        for (name, uri) in renditions {
+            let url = base.join(uri).context("join variant url")?.to_string();
+            if r.iter().any(|x| x.url == url) {
+                continue;
+            }
+            r.push(VideoVariant {
+                stream: name.to_owned(),
+                kind: PlaybackKind::Hls,
+                bandwidth: Some(v.bandwidth),
+                resolution: v.resolution.map(|r| (r.width, r.height)),
+                url,
+            });
+        }
+    }
+    anyhow::ensure!(!r.is_empty(), "master playlist has no variant");
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(stream: &str, bandwidth: u64, height: u64) -> VideoVariant {
+        VideoVariant {
+            stream: stream.to_owned(),
+            kind: PlaybackKind::Hls,
+            bandwidth: Some(bandwidth),
+            resolution: Some((height * 16 / 9, height)),
+            url: format!("https://example.com/{stream}/{height}.m3u8"),
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let vs = [
+            variant("camera", 800_000, 360),
+            variant("camera", 2_500_000, 720),
+            variant("screen", 1_200_000, 720),
+            variant("screen", 4_000_000, 1080),
+        ];
+        let select = |stream: Option<&str>, quality: &str| {
+            VideoSelector {
+                stream: stream.map(str::to_owned),
+                quality: quality.parse().unwrap(),
+            }
+            .select(&vs)
+        };
+
+        assert_eq!(select(None, "best").unwrap(), 1);
+        assert_eq!(select(None, "smallest").unwrap(), 0);
+        assert_eq!(select(Some("screen"), "best").unwrap(), 3);
+        assert_eq!(select(Some("SCR"), "720P").unwrap(), 2);
+        assert_eq!(select(Some("1"), "1920x1080").unwrap(), 3);
+
+        let e = select(Some("slides"), "best").unwrap_err();
+        assert!(e.to_string().contains("available: camera, screen"));
+        let e = select(Some("camera"), "1080p").unwrap_err();
+        assert!(e.to_string().contains("available: 360p, 720p"));
+
+        // download dirs are named after it, so it must be stable across builds
+        assert_eq!(vs[1].cache_id(), "acff185a");
+    }
+
+    #[test]
+    fn test_master_variants() {
+        let raw = br#"#EXTM3U
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="hd",NAME="teacher",DEFAULT=YES
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="hd",NAME="screen",URI="screen/hd.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=2000000,RESOLUTION=1280x720,VIDEO="hd"
+teacher/hd.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=600000,RESOLUTION=640x360
+https://cdn.example.com/low.m3u8
+#EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=100000,URI="iframe.m3u8"
+"#;
+        let m3u8_rs::Playlist::MasterPlaylist(pl) = m3u8_rs::parse_playlist_res(raw).unwrap()
+        else {
+            panic!("not a master playlist");
+        };
+        let base = Url::parse("https://example.com/play/abc/index.m3u8").unwrap();
+        let vs = master_variants(&pl, &base, "0").unwrap();
+        let summary = vs
+            .iter()
+            .map(|v| (v.stream.as_str(), v.quality().unwrap(), v.url.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "teacher",
+                    "720p".to_owned(),
+                    "https://example.com/play/abc/teacher/hd.m3u8"
+                ),
+                (
+                    "screen",
+                    "720p".to_owned(),
+                    "https://example.com/play/abc/screen/hd.m3u8"
+                ),
+                ("0", "360p".to_owned(), "https://cdn.example.com/low.m3u8"),
+            ]
+        );
+        assert_eq!(vs[1].to_string(), "[screen] 720p 2.0 Mbps");
+    }
+
+    #[test]
+    fn test_sub_info_streams() {
+        let entry = |title: &str, is_m3u8: &str, url: &str| {
+            let content = serde_json::json!({
+                "save_playback": { "is_m3u8": is_m3u8, "contents": url }
+            });
+            serde_json::json!({ "sub_title": title, "sub_content": content.to_string() })
+        };
+        let info = serde_json::json!({ "list": [entry("lecture", "yes", "https://a/index.m3u8")] });
+        assert_eq!(
+            sub_info_streams(&info).unwrap(),
+            [(
+                "0".to_owned(),
+                PlaybackKind::Hls,
+                "https://a/index.m3u8".to_owned()
+            )]
+        );
+
+        let info = serde_json::json!({ "list": [
+            entry("教师", "yes", "https://a/1.m3u8"),
+            entry("屏幕", "no", "https://a/2.mp4"),
+            { "sub_title": "broken", "sub_content": "{}" },
+        ] });
+        let streams = sub_info_streams(&info).unwrap();
+        let summary = streams
+            .iter()
+            .map(|(n, k, _)| (n.as_str(), *k))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [("教师", PlaybackKind::Hls), ("屏幕", PlaybackKind::File)]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! 可序列化的视图模型
+//!
+//! 把课程、作业、回放、文档、公告转换为扁平的纯数据结构，供 JSON 输出、快照等使用。
+//! 时间统一按 [`crate::datetime`] 解析为带时区的时间（北京时间）。
+use super::{
+    CourseAnnouncement, CourseAnnouncementHandle, CourseAssignment, CourseContentData,
+    CourseDocumentHandle, CourseHandle, CourseMeta, CourseTreeNode, CourseVideoHandle, NodeKind,
+};
+use crate::datetime;
+use chrono::{DateTime, FixedOffset};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AttachmentView {
+    pub name: String,
+    pub uri: String,
+}
+
+impl AttachmentView {
+    fn from_pairs(pairs: &[(String, String)]) -> Vec<Self> {
+        pairs
+            .iter()
+            .map(|(name, uri)| Self {
+                name: name.to_owned(),
+                uri: uri.to_owned(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CourseView {
+    pub id: String,
+    pub name: String,
+    pub title: String,
+    pub is_current: bool,
+}
+
+impl From<&CourseMeta> for CourseView {
+    fn from(c: &CourseMeta) -> Self {
+        Self {
+            id: c.id.to_owned(),
+            name: c.name().to_owned(),
+            title: c.title().to_owned(),
+            is_current: c.is_current,
+        }
+    }
+}
+
+impl From<&CourseHandle> for CourseView {
+    fn from(c: &CourseHandle) -> Self {
+        c.meta.as_ref().into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AssignmentView {
+    /// 稳定 ID：<course_id>::<content_id>
+    pub id: String,
+    pub course_id: String,
+    pub course: String,
+    pub title: String,
+    pub section: Option<String>,
+    pub deadline: Option<DateTime<FixedOffset>>,
+    pub deadline_raw: Option<String>,
+    pub last_attempt: Option<String>,
+    pub descriptions: Vec<String>,
+    pub attachments: Vec<AttachmentView>,
+    pub url: String,
+}
+
+impl AssignmentView {
+    pub fn is_finished(&self) -> bool {
+        self.last_attempt.is_some()
+    }
+}
+
+impl From<&CourseAssignment> for AssignmentView {
+    fn from(a: &CourseAssignment) -> Self {
+        Self {
+            id: format!("{}::{}", a.course.id, a.content.id),
+            course_id: a.course.id.to_owned(),
+            course: a.course.name().to_owned(),
+            title: a.title().to_owned(),
+            section: a.content.section_name.clone(),
+            deadline: a.deadline_raw().and_then(datetime::parse),
+            deadline_raw: a.deadline_raw().map(ToOwned::to_owned),
+            last_attempt: a.last_attempt().map(ToOwned::to_owned),
+            descriptions: a.descriptions().to_vec(),
+            attachments: AttachmentView::from_pairs(a.attachments()),
+            url: a.url(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VideoView {
+    /// 稳定 ID：<course_id>::<sub_id>
+    pub id: String,
+    pub course_id: String,
+    pub course: String,
+    pub title: String,
+    pub time: String,
+    pub start_time: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    pub teacher: String,
+    pub url: String,
+}
+
+impl From<&CourseVideoHandle> for VideoView {
+    fn from(v: &CourseVideoHandle) -> Self {
+        Self {
+            id: v.id(),
+            course_id: v.course.id.to_owned(),
+            course: v.course.name().to_owned(),
+            title: v.title().to_owned(),
+            time: v.time().to_owned(),
+            start_time: datetime::parse(v.time()),
+            teacher: v.meta.teacher().to_owned(),
+            url: v.meta.url.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DocumentView {
+    pub id: String,
+    pub content_id: String,
+    pub course_id: String,
+    pub course: String,
+    pub title: String,
+    pub section: Option<String>,
+    pub parent: Option<String>,
+    pub descriptions: Vec<String>,
+    pub attachments: Vec<AttachmentView>,
+}
+
+impl From<&CourseDocumentHandle> for DocumentView {
+    fn from(d: &CourseDocumentHandle) -> Self {
+        let CourseContentData {
+            id,
+            title,
+            descriptions,
+            attachments,
+            parent_title,
+            section_name,
+            ..
+        } = d.content.as_ref();
+
+        Self {
+            id: d.id(),
+            content_id: id.to_owned(),
+            course_id: d.course.id.to_owned(),
+            course: d.course.name().to_owned(),
+            title: title.to_owned(),
+            section: section_name.clone(),
+            parent: parent_title.clone(),
+            descriptions: descriptions.clone(),
+            attachments: AttachmentView::from_pairs(attachments),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AnnouncementView {
+    pub id: String,
+    pub course_id: String,
+    pub course: String,
+    pub title: String,
+    pub creator: Option<String>,
+    pub time: Option<DateTime<FixedOffset>>,
+    pub time_raw: Option<String>,
+    /// 公告正文段落
+    pub descriptions: Vec<String>,
+    pub attachments: Vec<AttachmentView>,
+}
+
+impl From<&CourseAnnouncementHandle> for AnnouncementView {
+    fn from(a: &CourseAnnouncementHandle) -> Self {
+        // descriptions 的前两项是发布者和发布时间，见 `CourseContentData::from_announcement_element`
+        let desc = &a.content.descriptions;
+        let time_raw = desc.get(1).cloned();
+
+        Self {
+            id: a.id(),
+            course_id: a.course.id.to_owned(),
+            course: a.course.name().to_owned(),
+            title: a.title(),
+            creator: desc.first().cloned(),
+            time: time_raw.as_deref().and_then(datetime::parse),
+            time_raw,
+            descriptions: desc.iter().skip(2).cloned().collect(),
+            attachments: AttachmentView::from_pairs(&a.content.attachments),
+        }
+    }
+}
+
+impl From<&CourseAnnouncement> for AnnouncementView {
+    fn from(a: &CourseAnnouncement) -> Self {
+        let h = CourseAnnouncementHandle {
+            client: a.client.clone(),
+            course: a.course.clone(),
+            content: a.content.clone(),
+        };
+        (&h).into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TreeNodeView {
+    pub id: String,
+    pub title: String,
+    pub kind: NodeKind,
+    pub children: Vec<TreeNodeView>,
+}
+
+impl From<&CourseTreeNode> for TreeNodeView {
+    fn from(n: &CourseTreeNode) -> Self {
+        Self {
+            id: n.id.to_owned(),
+            title: n.title.to_owned(),
+            kind: n.kind,
+            children: n.children.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_course_view() {
+        let meta = CourseMeta {
+            id: "_80052_1".to_owned(),
+            long_title:
+                "24252-00048-04831110-0006162013-00-1: 数据结构与算法(2024-2025学年第2学期)"
+                    .to_owned(),
+            is_current: true,
+        };
+        let v = CourseView::from(&meta);
+        assert_eq!(v.name, "数据结构与算法");
+        assert_eq!(v.title, "数据结构与算法(2024-2025学年第2学期)");
+        assert!(v.is_current);
+    }
+
+    #[test]
+    fn test_view_json_roundtrip() {
+        let v = VideoView {
+            id: "_80052_1::abc".to_owned(),
+            course_id: "_80052_1".to_owned(),
+            course: "数据结构与算法".to_owned(),
+            title: "第一讲".to_owned(),
+            time: "2025-03-04 08:00:00".to_owned(),
+            start_time: datetime::parse("2025-03-04 08:00:00"),
+            teacher: "李四".to_owned(),
+            url: "https://course.pku.edu.cn/".to_owned(),
+        };
+        let s = serde_json::to_string(&v).unwrap();
+        assert!(s.contains(r#""start_time":"2025-03-04T08:00:00+08:00""#));
+        assert_eq!(serde_json::from_str::<VideoView>(&s).unwrap(), v);
+    }
+}
@@ -0,0 +1,706 @@
+//! 带索引、有容量上限的本地缓存.
+//!
+//! 每个缓存条目保存为缓存目录下 `data/` 中的一个文件，文件名由键的稳定哈希 (FNV-1a) 得到；
+//! 条目的键、类型、所属课程、大小、创建/访问时间和 TTL 记录在索引中。索引以追加写的日志
+//! (`index.jsonl`) 保存，加载时回放并在日志过长时压缩。读取条目时只在内存中更新访问时间，
+//! 随下一次写日志、压缩或 [`CacheStore::flush`] 批量写入，只读的命令不会每次命中都写盘。
+//!
+//! 缓存总大小超过上限时按照“已过期优先、最久未访问优先”的顺序淘汰条目。过期条目在容量
+//! 允许时会被保留，以便离线模式使用。
+
+use crate::utils;
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use compio::{buf::buf_try, fs};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// 默认的缓存容量上限 (2 GiB)
+pub const DEFAULT_SIZE_LIMIT: u64 = 2 << 30;
+
+const INDEX_NAME: &str = "index.jsonl";
+const DATA_DIR: &str = "data";
+/// 内存中积累的访问记录达到该数量时写入日志
+const TOUCH_BATCH: usize = 256;
+
+static GLOBAL: OnceLock<CacheStore> = OnceLock::new();
+static BASE_URL_STORES: OnceLock<Mutex<BTreeMap<String, &'static CacheStore>>> = OnceLock::new();
+
+/// 缓存条目的键：类型、所属课程和类型内的标识
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    kind: &'static str,
+    course: Option<(String, String)>,
+    id: String,
+}
+
+impl CacheKey {
+    pub fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            course: None,
+            id: String::new(),
+        }
+    }
+
+    /// 条目所属的课程 (课程 ID 与课程名)
+    pub fn course(mut self, id: impl Into<String>, title: impl Into<String>) -> Self {
+        self.course = Some((id.into(), title.into()));
+        self
+    }
+
+    /// 条目所属课程的 ID，用于不知道课程名的场合
+    pub fn course_id(self, id: impl Into<String>) -> Self {
+        self.course(id, "")
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    fn key(&self) -> String {
+        let course = self.course.as_ref().map(|(id, _)| id.as_str());
+        format!("{}:{}:{}", self.kind, course.unwrap_or(""), self.id)
+    }
+}
+
+/// 索引中的一个条目
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CacheEntry {
+    pub key: String,
+    pub kind: String,
+    pub course_id: Option<String>,
+    pub course: Option<String>,
+    /// 相对于缓存目录的路径，可以是文件或目录
+    pub path: String,
+    pub size: u64,
+    pub created: DateTime<Utc>,
+    pub accessed: DateTime<Utc>,
+    /// 写入时的有效期 (秒)
+    pub ttl: Option<u64>,
+}
+
+impl CacheEntry {
+    fn age(&self, now: DateTime<Utc>) -> Duration {
+        (now - self.created).to_std().unwrap_or_default()
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.ttl
+            .is_some_and(|ttl| self.age(now) >= Duration::from_secs(ttl))
+    }
+}
+
+/// 按课程、类型和创建时间筛选条目，未指定的条件视为匹配
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// 课程 ID 或课程名的一部分
+    pub course: Option<String>,
+    pub kind: Option<String>,
+    pub older_than: Option<Duration>,
+}
+
+impl Filter {
+    pub fn matches(&self, e: &CacheEntry, now: DateTime<Utc>) -> bool {
+        let course = self.course.as_deref().is_none_or(|q| {
+            e.course_id.as_deref() == Some(q)
+                || e.course
+                    .as_ref()
+                    .is_some_and(|t| t.to_lowercase().contains(&q.to_lowercase()))
+        });
+        let kind = self.kind.as_deref().is_none_or(|k| e.kind == k);
+        let older = self.older_than.is_none_or(|d| e.age(now) >= d);
+        course && kind && older
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Op {
+    Put(CacheEntry),
+    Touch {
+        key: String,
+        accessed: DateTime<Utc>,
+    },
+    Remove {
+        key: String,
+    },
+}
+
+#[derive(Default)]
+struct Index {
+    entries: BTreeMap<String, CacheEntry>,
+    total: u64,
+    /// 日志中的记录数
+    journal_len: usize,
+    /// 访问时间已更新但尚未写入日志的条目
+    touched: BTreeSet<String>,
+}
+
+impl Index {
+    fn apply(&mut self, op: Op) {
+        match op {
+            Op::Put(e) => {
+                self.total += e.size;
+                if let Some(old) = self.entries.insert(e.key.clone(), e) {
+                    self.total -= old.size;
+                }
+            }
+            Op::Touch { key, accessed } => {
+                if let Some(e) = self.entries.get_mut(&key) {
+                    e.accessed = accessed;
+                }
+            }
+            Op::Remove { key } => {
+                if let Some(old) = self.entries.remove(&key) {
+                    self.total -= old.size;
+                }
+            }
+        }
+    }
+}
+
+/// 带索引的缓存目录
+pub struct CacheStore {
+    dir: PathBuf,
+    limit: AtomicU64,
+    index: Mutex<Option<Index>>,
+}
+
+impl CacheStore {
+    pub fn new(dir: impl Into<PathBuf>, limit: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            limit: AtomicU64::new(limit),
+            index: Mutex::new(None),
+        }
+    }
+
+    /// 位于项目缓存目录的全局缓存
+    pub fn global() -> &'static CacheStore {
+        GLOBAL.get_or_init(|| CacheStore::new(utils::projectdir().cache_dir(), DEFAULT_SIZE_LIMIT))
+    }
+
+    /// 转发到 `base` 的客户端 (见 [`crate::api::Client::with_base_url`]) 使用的缓存，与全局缓存分开，
+    /// 避免模拟服务器的数据以真实地址为键混入全局缓存
+    pub fn for_base_url(base: &url::Url) -> &'static CacheStore {
+        let name = format!(
+            "{}-{}",
+            base.host_str().unwrap_or_default(),
+            base.port_or_known_default().unwrap_or_default()
+        )
+        .replace(
+            |c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-',
+            "_",
+        );
+        let mut stores = BASE_URL_STORES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap();
+        stores.entry(name).or_insert_with_key(|name| {
+            let dir = utils::projectdir().cache_dir().join("base-url").join(name);
+            Box::leak(Box::new(CacheStore::new(dir, DEFAULT_SIZE_LIMIT)))
+        })
+    }
+
+    /// 把所有已打开的全局缓存 ([`CacheStore::global`] 与 [`CacheStore::for_base_url`]) 中尚未写入的访问记录写入日志。
+    /// 静态变量不会被 drop，进程退出前应调用此函数
+    pub fn flush_all() {
+        if let Some(s) = GLOBAL.get() {
+            s.flush();
+        }
+        if let Some(stores) = BASE_URL_STORES.get() {
+            for s in stores.lock().unwrap().values() {
+                s.flush();
+            }
+        }
+    }
+
+    /// 把尚未写入的访问记录写入日志
+    pub fn flush(&self) {
+        let mut guard = self.index.lock().unwrap();
+        if let Some(index) = guard.as_mut() {
+            self.append(index, None);
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_limit(&self, limit: u64) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// 对索引进行操作，必要时先从日志加载索引
+    fn with_index<R>(&self, f: impl FnOnce(&mut Index) -> R) -> R {
+        let mut guard = self.index.lock().unwrap();
+        let index = guard.get_or_insert_with(|| self.load_index());
+        f(index)
+    }
+
+    fn load_index(&self) -> Index {
+        let path = self.dir.join(INDEX_NAME);
+        let mut index = Index::default();
+        match std::fs::read_to_string(&path) {
+            Ok(s) => {
+                for line in s.lines() {
+                    index.journal_len += 1;
+                    match serde_json::from_str(line) {
+                        Ok(op) => index.apply(op),
+                        Err(e) => log::warn!("skip corrupted cache index line: {e}"),
+                    }
+                }
+                if index.journal_len > 2 * index.entries.len() + 64
+                    && let Err(e) = self.compact(&mut index)
+                {
+                    log::warn!("compact cache index: {e:#}");
+                }
+            }
+            Err(_) => remove_legacy_files(&self.dir),
+        }
+        index
+    }
+
+    /// 用当前索引内容重写日志
+    fn compact(&self, index: &mut Index) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        for e in index.entries.values() {
+            serde_json::to_writer(&mut buf, &Op::Put(e.clone()))?;
+            buf.push(b'\n');
+        }
+        let path = self.dir.join(INDEX_NAME);
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, buf)?;
+        std::fs::rename(tmp, path)?;
+        index.journal_len = index.entries.len();
+        index.touched.clear();
+        Ok(())
+    }
+
+    /// 应用一条记录并追加到日志
+    fn record(&self, index: &mut Index, op: Op) {
+        self.append(index, Some(&op));
+        index.apply(op);
+    }
+
+    /// 把尚未写入的访问记录和 `op` 一起追加到日志
+    fn append(&self, index: &mut Index, op: Option<&Op>) {
+        let touches = std::mem::take(&mut index.touched)
+            .into_iter()
+            .filter_map(|key| {
+                let accessed = index.entries.get(&key)?.accessed;
+                Some(Op::Touch { key, accessed })
+            })
+            .collect::<Vec<_>>();
+        let ops = touches.iter().chain(op).collect::<Vec<_>>();
+        if ops.is_empty() {
+            return;
+        }
+        let r = (|| -> anyhow::Result<()> {
+            let mut buf = Vec::new();
+            for op in &ops {
+                serde_json::to_writer(&mut buf, op)?;
+                buf.push(b'\n');
+            }
+            std::fs::create_dir_all(&self.dir)?;
+            let mut f = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(INDEX_NAME))?;
+            f.write_all(&buf)?;
+            Ok(())
+        })();
+        if let Err(e) = r {
+            log::warn!("write cache index: {e:#}");
+        }
+        index.journal_len += ops.len();
+    }
+
+    /// 在内存中更新条目的访问时间，积累到一定数量后写入日志
+    fn touch(&self, index: &mut Index, key: String, accessed: DateTime<Utc>) {
+        let Some(e) = index.entries.get_mut(&key) else {
+            return;
+        };
+        e.accessed = accessed;
+        index.touched.insert(key);
+        if index.touched.len() >= TOUCH_BATCH {
+            self.append(index, None);
+        }
+    }
+
+    /// 所有条目，按访问时间从新到旧排序
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let mut r = self.with_index(|i| i.entries.values().cloned().collect::<Vec<_>>());
+        r.sort_by_key(|e| std::cmp::Reverse(e.accessed));
+        r
+    }
+
+    /// 所有条目的总大小
+    pub fn total_size(&self) -> u64 {
+        self.with_index(|i| i.total)
+    }
+
+    /// 读取未过期（相对于 `ttl`）的条目。`ttl` 为 `None` 时总是返回 `None`.
+    pub async fn get(&self, key: &CacheKey, ttl: Option<&Duration>) -> Option<Vec<u8>> {
+        let ttl = ttl?;
+        let (age, buf) = self.get_with_age(key).await?;
+        (age < *ttl).then_some(buf)
+    }
+
+    /// 读取条目（无论是否过期），同时返回条目的存在时长
+    pub async fn get_with_age(&self, key: &CacheKey) -> Option<(Duration, Vec<u8>)> {
+        let key = key.key();
+        let now = Utc::now();
+        let (age, path) = self.with_index(|i| {
+            let e = i.entries.get(&key)?;
+            Some((e.age(now), self.dir.join(&e.path)))
+        })?;
+
+        match fs::read(&path).await {
+            Ok(buf) => {
+                log::trace!("cache hit: {key}");
+                self.with_index(|i| self.touch(i, key, now));
+                Some((age, buf))
+            }
+            Err(_) => {
+                // the file is gone, drop the stale entry
+                self.with_index(|i| self.record(i, Op::Remove { key }));
+                None
+            }
+        }
+    }
+
+    /// 写入一个条目，然后按需淘汰旧条目
+    pub async fn put(
+        &self,
+        key: &CacheKey,
+        ttl: Option<&Duration>,
+        data: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let rel = format!("{DATA_DIR}/{:016x}", fnv1a(key.key().as_bytes()));
+        let path = self.dir.join(&rel);
+        fs::create_dir_all(path.parent().unwrap()).await?;
+
+        let size = data.len() as u64;
+        // fs::write is not atomic, so we write to a tmp file first. the same key may be
+        // written concurrently (e.g. a video key shared by all segments), so the tmp file
+        // must be unique to this write
+        static SEQ: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        let seq = SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}-{seq}.tmp", std::process::id()));
+        buf_try!(@try fs::write(&tmp, data).await);
+        fs::rename(&tmp, &path).await?;
+
+        self.insert(key, rel, size, ttl);
+        Ok(())
+    }
+
+    /// 把缓存目录下已有的文件或目录登记为一个条目，使其参与淘汰和 `cache rm`
+    pub fn track(&self, key: &CacheKey, path: &Path) -> anyhow::Result<()> {
+        let rel = path
+            .strip_prefix(&self.dir)
+            .context("path not inside the cache dir")?
+            .to_string_lossy()
+            .replace('\\', "/");
+        self.insert(key, rel, disk_usage(path), None);
+        Ok(())
+    }
+
+    fn insert(&self, key: &CacheKey, path: String, size: u64, ttl: Option<&Duration>) {
+        let now = Utc::now();
+        let (course_id, course) = key.course.clone().unzip();
+        let course = course.filter(|t| !t.is_empty());
+        let entry = CacheEntry {
+            key: key.key(),
+            kind: key.kind.to_owned(),
+            course_id,
+            course,
+            path,
+            size,
+            created: now,
+            accessed: now,
+            // an infinite ttl (offline mode) is not worth recording
+            ttl: ttl.map(|d| d.as_secs()).filter(|&s| s < u64::MAX),
+        };
+        let key = entry.key.clone();
+        self.with_index(|i| {
+            self.record(i, Op::Put(entry));
+            self.evict(i, &key);
+        });
+    }
+
+    /// 淘汰条目直到总大小不超过上限，不会淘汰 `keep`
+    fn evict(&self, index: &mut Index, keep: &str) {
+        let limit = self.limit();
+        if index.total <= limit {
+            return;
+        }
+
+        let now = Utc::now();
+        let mut victims = index
+            .entries
+            .values()
+            .filter(|e| e.key != keep)
+            .map(|e| (!e.is_expired(now), e.accessed, e.key.clone()))
+            .collect::<Vec<_>>();
+        victims.sort();
+
+        for (_, _, key) in victims {
+            if index.total <= limit {
+                break;
+            }
+            log::debug!("evict cache entry {key}");
+            self.remove_entry(index, &key);
+        }
+    }
+
+    fn remove_entry(&self, index: &mut Index, key: &str) {
+        let Some(e) = index.entries.get(key) else {
+            return;
+        };
+        let path = self.dir.join(&e.path);
+        let r = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        if let Err(e) = r
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            log::warn!("remove {}: {e}", path.display());
+        }
+        self.record(
+            index,
+            Op::Remove {
+                key: key.to_owned(),
+            },
+        );
+    }
+
+    /// 删除所有匹配的条目，返回被删除的条目
+    pub fn remove(&self, filter: &Filter) -> Vec<CacheEntry> {
+        let now = Utc::now();
+        self.with_index(|i| {
+            let removed = i
+                .entries
+                .values()
+                .filter(|e| filter.matches(e, now))
+                .cloned()
+                .collect::<Vec<_>>();
+            for e in &removed {
+                self.remove_entry(i, &e.key);
+            }
+            removed
+        })
+    }
+
+    /// 删除整个缓存目录
+    pub fn clear(&self) -> anyhow::Result<()> {
+        let mut guard = self.index.lock().unwrap();
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        *guard = None;
+        Ok(())
+    }
+}
+
+impl Drop for CacheStore {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// 旧版本以哈希命名的缓存文件不在索引中，首次建立索引时删除
+fn remove_legacy_files(dir: &Path) {
+    let Ok(d) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for e in d.flatten() {
+        let name = e.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("with_cache-") || name.starts_with("with_cache_bytes-") {
+            let _ = std::fs::remove_file(e.path());
+        }
+    }
+}
+
+/// 文件或目录占用的字节数
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    std::fs::read_dir(path)
+        .map(|d| d.flatten().map(|e| disk_usage(&e.path())).sum())
+        .unwrap_or(0)
+}
+
+/// 64 位 FNV-1a 哈希，结果不随编译器版本变化
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// 如果缓存存在且未过期，返回反序列化后的内容；否则执行 future，将结果写入缓存并返回.
+pub async fn with_cache<T, F>(
+    store: &CacheStore,
+    key: &CacheKey,
+    ttl: Option<&Duration>,
+    fut: F,
+) -> anyhow::Result<T>
+where
+    F: std::future::Future<Output = anyhow::Result<T>>,
+    T: serde::de::DeserializeOwned + serde::Serialize,
+{
+    if let Some(buf) = store.get(key, ttl).await
+        // ignore deserialization error
+        && let Ok(r) = serde_json::from_slice(&buf)
+    {
+        return Ok(r);
+    }
+
+    let r = fut.await?;
+    store.put(key, ttl, serde_json::to_vec(&r)?).await?;
+    Ok(r)
+}
+
+/// 与 [`with_cache`] 相同，但直接缓存字节.
+pub async fn with_cache_bytes<F>(
+    store: &CacheStore,
+    key: &CacheKey,
+    ttl: Option<&Duration>,
+    fut: F,
+) -> anyhow::Result<bytes::Bytes>
+where
+    F: std::future::Future<Output = anyhow::Result<bytes::Bytes>>,
+{
+    if let Some(buf) = store.get(key, ttl).await {
+        return Ok(buf.into());
+    }
+
+    let r = fut.await?;
+    store.put(key, ttl, r.to_vec()).await?;
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str, limit: u64) -> CacheStore {
+        let dir = std::env::temp_dir().join(format!("pku3b-cache-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        CacheStore::new(dir, limit)
+    }
+
+    fn key(course: &str, id: &str) -> CacheKey {
+        CacheKey::new("test")
+            .course(course, format!("课程{course}"))
+            .id(id)
+    }
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[compio::test]
+    async fn test_get_put() {
+        let s = store("get-put", DEFAULT_SIZE_LIMIT);
+        let k = key("_1", "a");
+        assert_eq!(s.get(&k, Some(&HOUR)).await, None);
+        s.put(&k, Some(&HOUR), b"hello".to_vec()).await.unwrap();
+        assert_eq!(s.get(&k, Some(&HOUR)).await.unwrap(), b"hello");
+        assert_eq!(s.get(&k, None).await, None);
+        assert_eq!(s.get(&k, Some(&Duration::ZERO)).await, None);
+
+        // the index survives a reload
+        let s2 = CacheStore::new(s.dir(), DEFAULT_SIZE_LIMIT);
+        let entries = s2.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].course.as_deref(), Some("课程_1"));
+        assert_eq!(entries[0].size, 5);
+        assert_eq!(entries[0].ttl, Some(3600));
+        assert_eq!(s2.get(&k, Some(&HOUR)).await.unwrap(), b"hello");
+
+        s.clear().unwrap();
+    }
+
+    #[compio::test]
+    async fn test_evict_and_remove() {
+        let s = store("evict", 12);
+        for id in ["a", "b", "c"] {
+            s.put(&key("_1", id), Some(&HOUR), vec![0; 4])
+                .await
+                .unwrap();
+        }
+        // touch "a" so that "b" is the least recently used
+        assert!(s.get(&key("_1", "a"), Some(&HOUR)).await.is_some());
+        s.put(&key("_2", "d"), Some(&HOUR), vec![0; 4])
+            .await
+            .unwrap();
+
+        let mut keys = s.entries().into_iter().map(|e| e.key).collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["test:_1:a", "test:_1:c", "test:_2:d"]);
+        assert_eq!(s.total_size(), 12);
+
+        let filter = Filter {
+            course: Some("课程_2".to_owned()),
+            ..Default::default()
+        };
+        let removed = s.remove(&filter);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(s.get(&key("_2", "d"), Some(&HOUR)).await, None);
+        assert_eq!(s.total_size(), 8);
+
+        s.clear().unwrap();
+    }
+
+    #[compio::test]
+    async fn test_touch_batched() {
+        let s = store("touch", DEFAULT_SIZE_LIMIT);
+        let k = key("_1", "a");
+        s.put(&k, Some(&HOUR), b"hello".to_vec()).await.unwrap();
+        let journal = || std::fs::read_to_string(s.dir().join(INDEX_NAME)).unwrap();
+        let before = journal();
+
+        // reads only update the access time in memory
+        for _ in 0..3 {
+            assert!(s.get(&k, Some(&HOUR)).await.is_some());
+        }
+        assert_eq!(journal(), before);
+        let accessed = s.entries()[0].accessed;
+
+        s.flush();
+        assert_eq!(journal().lines().count(), before.lines().count() + 1);
+        s.flush();
+        assert_eq!(journal().lines().count(), before.lines().count() + 1);
+        let s2 = CacheStore::new(s.dir(), DEFAULT_SIZE_LIMIT);
+        assert_eq!(s2.entries()[0].accessed, accessed);
+
+        // pending touches are written out when the store is dropped
+        assert!(s2.get(&k, Some(&HOUR)).await.is_some());
+        let accessed = s2.entries()[0].accessed;
+        drop(s2);
+        let s3 = CacheStore::new(s.dir(), DEFAULT_SIZE_LIMIT);
+        assert_eq!(s3.entries()[0].accessed, accessed);
+
+        s.clear().unwrap();
+    }
+}
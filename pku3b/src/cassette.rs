@@ -0,0 +1,447 @@
+//! 请求录制与回放.
+//!
+//! 录制模式下，经由 [`crate::api::LowLevelClient`] 发出的每个请求及其响应都会保存到目录中：
+//! `NNNN.json` 记录请求方法、URL、请求头和响应头，`NNNN.body` 为响应体。账号、密码、
+//! token、nonce、cookie 等敏感信息在写入前被替换为 [`SCRUBBED`]，包括 JSON 响应中的字段以及
+//! HTML 页面中隐藏表单字段的值和链接的查询参数。回放模式下按照方法和（脱敏后的）URL
+//! 从录制的文件中应答请求，不访问网络。
+//!
+//! 注意：页面正文（例如课程名、姓名、学号）不会被脱敏，录制的目录不应公开分享。
+
+use anyhow::Context as _;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// 敏感字段被替换成的内容
+pub const SCRUBBED: &str = "SCRUBBED";
+
+/// 需要脱敏的查询参数、表单字段和 JSON 字段（不区分大小写）
+const SENSITIVE_FIELDS: &[&str] = &[
+    "username",
+    "password",
+    "token",
+    "auth_data",
+    "_rand",
+    "smscode",
+    "otpcode",
+];
+
+/// 不会被录制的请求头和响应头
+const SENSITIVE_HEADERS: &[&str] = &["cookie", "set-cookie", "authorization"];
+
+fn is_sensitive(name: &str) -> bool {
+    SENSITIVE_FIELDS
+        .iter()
+        .any(|f| f.eq_ignore_ascii_case(name))
+        // e.g. blackboard.platform.security.NonceUtil.nonce
+        || name.to_ascii_lowercase().ends_with("nonce")
+}
+
+static RE_INPUT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<input\b[^>]*>").unwrap());
+
+static RE_INPUT_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\sname\s*=\s*["']?([^"'\s>]+)"#).unwrap());
+
+static RE_INPUT_VALUE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)(\svalue\s*=\s*)(?:"[^"]*"|'[^']*'|[^"'\s>]+)"#).unwrap());
+
+/// 链接中的查询参数，`&amp;` 以 `;` 结尾
+static RE_QUERY_PARAM: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([?&;])([\w.]+)=([^&"'#\s<>]*)"#).unwrap());
+
+/// 录制的一次请求
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    pub request_headers: BTreeMap<String, String>,
+    /// 表单请求的请求体，其他请求体不录制
+    pub request_body: Option<String>,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+/// 将 URL 中的敏感查询参数替换为 [`SCRUBBED`]
+pub fn scrub_url(url: &url::Url) -> String {
+    if url.query().is_none() {
+        return url.to_string();
+    }
+    let pairs = url
+        .query_pairs()
+        .map(|(k, v)| {
+            let v = if is_sensitive(&k) { SCRUBBED.into() } else { v };
+            (k.into_owned(), v.into_owned())
+        })
+        .collect::<Vec<_>>();
+    let mut url = url.clone();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    url.to_string()
+}
+
+/// 将 `application/x-www-form-urlencoded` 请求体中的敏感字段替换为 [`SCRUBBED`]
+pub fn scrub_form(body: &str) -> String {
+    let pairs = url::form_urlencoded::parse(body.as_bytes()).map(|(k, v)| {
+        let v = if is_sensitive(&k) { SCRUBBED.into() } else { v };
+        (k, v)
+    });
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
+
+/// 响应体为 JSON 时，将其中的敏感字段替换为 [`SCRUBBED`]
+pub fn scrub_body(body: &[u8]) -> Option<Vec<u8>> {
+    fn scrub(v: &mut serde_json::Value) {
+        match v {
+            serde_json::Value::Object(m) => {
+                for (k, v) in m.iter_mut() {
+                    if is_sensitive(k) {
+                        *v = SCRUBBED.into();
+                    } else {
+                        scrub(v);
+                    }
+                }
+            }
+            serde_json::Value::Array(a) => a.iter_mut().for_each(scrub),
+            _ => {}
+        }
+    }
+
+    let mut v = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+    if !v.is_object() && !v.is_array() {
+        return None;
+    }
+    scrub(&mut v);
+    serde_json::to_vec(&v).ok()
+}
+
+/// 将 HTML 页面中敏感表单字段的值和链接中的敏感查询参数替换为 [`SCRUBBED`]
+pub fn scrub_html(body: &str) -> String {
+    let body = RE_INPUT.replace_all(body, |c: &regex::Captures| {
+        let tag = &c[0];
+        match RE_INPUT_NAME.captures(tag) {
+            Some(name) if is_sensitive(&name[1]) => RE_INPUT_VALUE
+                .replace(tag, format!("${{1}}\"{SCRUBBED}\""))
+                .into_owned(),
+            _ => tag.to_owned(),
+        }
+    });
+    RE_QUERY_PARAM
+        .replace_all(&body, |c: &regex::Captures| {
+            if is_sensitive(&c[2]) {
+                format!("{}{}={SCRUBBED}", &c[1], &c[2])
+            } else {
+                c[0].to_owned()
+            }
+        })
+        .into_owned()
+}
+
+fn scrub_headers<'a>(
+    headers: impl Iterator<Item = (&'a http::HeaderName, &'a http::HeaderValue)>,
+) -> impl Iterator<Item = (String, String)> {
+    headers
+        .filter(|(k, _)| !SENSITIVE_HEADERS.contains(&k.as_str()))
+        .map(|(k, v)| {
+            let v = String::from_utf8_lossy(v.as_bytes());
+            // redirects to the sso login carry tokens in the query
+            let v = if k == http::header::LOCATION {
+                scrub_location(&v)
+            } else {
+                v.into_owned()
+            };
+            (k.to_string(), v)
+        })
+}
+
+/// 对 `Location` 的值执行 [`scrub_url`]，相对地址只处理查询参数
+fn scrub_location(value: &str) -> String {
+    match url::Url::parse(value) {
+        Ok(url) => scrub_url(&url),
+        Err(_) => match value.split_once('?') {
+            Some((path, query)) => format!("{path}?{}", scrub_form(query)),
+            None => value.to_owned(),
+        },
+    }
+}
+
+#[derive(Debug)]
+enum Mode {
+    Record(AtomicUsize),
+    Replay(Mutex<HashMap<(String, String), VecDeque<usize>>>),
+}
+
+/// 录制或回放请求的目录
+#[derive(Debug)]
+pub struct Cassette {
+    dir: PathBuf,
+    mode: Mode,
+}
+
+impl Cassette {
+    /// 录制到 `dir`，目录不存在时会被创建
+    pub fn record(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+        // continue after existing interactions
+        let next = Self::load(&dir)?.last().map_or(0, |(seq, _)| seq + 1);
+        Ok(Self {
+            dir,
+            mode: Mode::Record(AtomicUsize::new(next)),
+        })
+    }
+
+    /// 从 `dir` 回放
+    pub fn replay(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        let mut index = HashMap::<_, VecDeque<_>>::new();
+        for (seq, i) in Self::load(&dir)? {
+            index.entry((i.method, i.url)).or_default().push_back(seq);
+        }
+        anyhow::ensure!(!index.is_empty(), "no interactions in {}", dir.display());
+        Ok(Self {
+            dir,
+            mode: Mode::Replay(Mutex::new(index)),
+        })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self.mode, Mode::Replay(_))
+    }
+
+    /// 目录中所有录制的请求，按序号排序
+    pub fn load(dir: &Path) -> anyhow::Result<Vec<(usize, Interaction)>> {
+        let mut r = Vec::new();
+        for e in std::fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+            let path = e?.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let Some(seq) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse().ok())
+            else {
+                continue;
+            };
+            let buf = std::fs::read(&path)?;
+            let i = serde_json::from_slice(&buf)
+                .with_context(|| format!("parse {}", path.display()))?;
+            r.push((seq, i));
+        }
+        r.sort_by_key(|(seq, _)| *seq);
+        Ok(r)
+    }
+
+    fn paths(&self, seq: usize) -> (PathBuf, PathBuf) {
+        (
+            self.dir.join(format!("{seq:04}.json")),
+            self.dir.join(format!("{seq:04}.body")),
+        )
+    }
+
+    /// 为请求创建记录（尚无响应信息）
+    pub fn interaction(&self, req: &cyper::Request) -> Interaction {
+        let is_form = req
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .is_some_and(|v| {
+                v.as_bytes()
+                    .starts_with(b"application/x-www-form-urlencoded")
+            });
+        let request_body = is_form
+            .then(|| req.body().as_bytes())
+            .flatten()
+            .map(|b| scrub_form(&String::from_utf8_lossy(b)));
+
+        Interaction {
+            method: req.method().to_string(),
+            url: scrub_url(req.url()),
+            request_headers: scrub_headers(req.headers().iter()).collect(),
+            request_body,
+            status: 0,
+            headers: Vec::new(),
+        }
+    }
+
+    /// 保存一次请求及其响应
+    pub fn save(
+        &self,
+        mut interaction: Interaction,
+        status: http::StatusCode,
+        headers: &http::HeaderMap,
+        body: &[u8],
+    ) -> anyhow::Result<()> {
+        let Mode::Record(next) = &self.mode else {
+            anyhow::bail!("cassette is not recording");
+        };
+        interaction.status = status.as_u16();
+        interaction.headers = scrub_headers(headers.iter()).collect();
+
+        let seq = next.fetch_add(1, Ordering::Relaxed);
+        let (json, body_path) = self.paths(seq);
+        let is_html = headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("html"));
+        let body = match scrub_body(body) {
+            Some(body) => body,
+            None if is_html => scrub_html(&String::from_utf8_lossy(body)).into_bytes(),
+            None => body.to_vec(),
+        };
+        std::fs::write(body_path, body)?;
+        std::fs::write(json, serde_json::to_vec_pretty(&interaction)?)?;
+        Ok(())
+    }
+
+    /// 查找与请求匹配的录制响应。同一请求被录制多次时依次返回，用完后重复返回最后一次。
+    pub fn find(&self, req: &cyper::Request) -> anyhow::Result<(Interaction, Vec<u8>)> {
+        let Mode::Replay(index) = &self.mode else {
+            anyhow::bail!("cassette is not replaying");
+        };
+        let key = (req.method().to_string(), scrub_url(req.url()));
+        let seq = {
+            let mut index = index.lock().unwrap();
+            let q = index
+                .get_mut(&key)
+                .with_context(|| format!("replay: no recorded response for {} {}", key.0, key.1))?;
+            if q.len() > 1 {
+                q.pop_front().unwrap()
+            } else {
+                q[0]
+            }
+        };
+
+        let (json, body) = self.paths(seq);
+        let i = serde_json::from_slice(&std::fs::read(&json)?)
+            .with_context(|| format!("parse {}", json.display()))?;
+        let body = std::fs::read(&body).with_context(|| format!("read {}", body.display()))?;
+        Ok((i, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrub() {
+        let url = url::Url::parse("https://example.com/login?token=abc&_rand=0.1&course_id=_1_1")
+            .unwrap();
+        assert_eq!(
+            scrub_url(&url),
+            "https://example.com/login?token=SCRUBBED&_rand=SCRUBBED&course_id=_1_1"
+        );
+        assert_eq!(
+            scrub_form("appid=blackboard&userName=2100012345&password=p%40ss"),
+            "appid=blackboard&userName=SCRUBBED&password=SCRUBBED"
+        );
+        let body = scrub_body(br#"{"success":true,"token":"secret"}"#).unwrap();
+        assert_eq!(body, br#"{"success":true,"token":"SCRUBBED"}"#);
+        assert!(scrub_body(b"<html></html>").is_none());
+        assert_eq!(
+            scrub_location("https://example.com/login?token=abc&next=1"),
+            "https://example.com/login?token=SCRUBBED&next=1"
+        );
+        assert_eq!(
+            scrub_location("/webapps/login/?token=abc"),
+            "/webapps/login/?token=SCRUBBED"
+        );
+        assert_eq!(scrub_location("/webapps/portal"), "/webapps/portal");
+    }
+
+    #[test]
+    fn test_scrub_html() {
+        let page = r#"<form>
+<input type="hidden" name="blackboard.platform.security.NonceUtil.nonce" value="n-123">
+<input value='tok-456' type="hidden" name="token">
+<input type="hidden" name="course_id" value="_1_1">
+<a href="/webapps/blackboard/execute/launcher?type=Course&amp;token=tok-789&amp;id=_1_1">课程</a>
+<a href="https://example.com/login?userName=2100012345&next=1">登录</a>
+</form>"#;
+        let r = scrub_html(page);
+        for secret in ["n-123", "tok-456", "tok-789", "2100012345"] {
+            assert!(!r.contains(secret), "{secret} in {r}");
+        }
+        assert!(r.contains(r#"name="course_id" value="_1_1""#));
+        assert!(r.contains("type=Course&amp;token=SCRUBBED&amp;id=_1_1"));
+        assert!(r.contains("?userName=SCRUBBED&next=1"));
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let dir = std::env::temp_dir().join(format!("pku3b-cassette-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let req = |token: &str| {
+            let url = format!("https://example.com/page?course_id=_1_1&token={token}");
+            let mut req = cyper::Request::new(http::Method::GET, url::Url::parse(&url).unwrap());
+            req.headers_mut()
+                .insert(http::header::COOKIE, "s_session_id=1".parse().unwrap());
+            req
+        };
+
+        let c = Cassette::record(&dir).unwrap();
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::SET_COOKIE, "a=b".parse().unwrap());
+        headers.insert(
+            http::header::LOCATION,
+            "https://example.com/next?token=secret".parse().unwrap(),
+        );
+        for body in ["first", "second"] {
+            let i = c.interaction(&req("secret"));
+            assert!(i.request_headers.is_empty());
+            c.save(i, http::StatusCode::OK, &headers, body.as_bytes())
+                .unwrap();
+        }
+        let raw = std::fs::read_to_string(dir.join("0000.json")).unwrap();
+        assert!(!raw.contains("secret") && !raw.contains("session"));
+
+        // html pages are scrubbed as well
+        let mut html = http::HeaderMap::new();
+        html.insert(
+            http::header::CONTENT_TYPE,
+            "text/html; charset=utf-8".parse().unwrap(),
+        );
+        let page = r#"<input type="hidden" name="token" value="secret">"#;
+        let page_req = cyper::Request::new(
+            http::Method::GET,
+            url::Url::parse("https://example.com/form").unwrap(),
+        );
+        c.save(
+            c.interaction(&page_req),
+            http::StatusCode::OK,
+            &html,
+            page.as_bytes(),
+        )
+        .unwrap();
+        let raw = std::fs::read_to_string(dir.join("0002.body")).unwrap();
+        assert_eq!(
+            raw,
+            r#"<input type="hidden" name="token" value="SCRUBBED">"#
+        );
+
+        // a different token still matches since it is scrubbed
+        let c = Cassette::replay(&dir).unwrap();
+        let bodies = (0..3)
+            .map(|_| c.find(&req("other")).unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(bodies, [&b"first"[..], b"second", b"second"]);
+        assert!(
+            c.find(&cyper::Request::new(
+                http::Method::GET,
+                url::Url::parse("https://example.com/none").unwrap()
+            ))
+            .is_err()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::Context;
+
+use super::*;
+
+async fn fetch_announcements(
+    force: bool,
+    cur_term: bool,
+    course: Option<&str>,
+) -> anyhow::Result<Vec<api::CourseAnnouncement>> {
+    let courses = load_courses_matching(force, cur_term, course).await?;
+
+    let pb = pbar::new(courses.len() as u64);
+    let futs = courses.into_iter().map(async |c| -> anyhow::Result<_> {
+        let c = c.get().await.context("fetch course")?;
+        let hs = c
+            .list_announcements()
+            .await
+            .with_context(|| format!("fetch announcements of {}", c.meta().title()))?;
+        let r = try_join_all(hs.iter().map(|h| h.get())).await?;
+        pb.inc(1);
+        Ok(r)
+    });
+    let mut items = try_join_all(futs)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    pb.finish_and_clear();
+
+    // latest first
+    items.sort_by_cached_key(|a| std::cmp::Reverse(a.created_time()));
+    Ok(items)
+}
+
+fn find_or_select(
+    mut items: Vec<api::CourseAnnouncement>,
+    id: Option<&str>,
+    message: &str,
+) -> anyhow::Result<api::CourseAnnouncement> {
+    match id {
+        Some(id) => items
+            .into_iter()
+            .find(|a| a.id() == id)
+            .with_context(|| format!("announcement with id {id} not found")),
+        None => {
+            let options = items
+                .iter()
+                .map(|a| format!("{} > {}", a.course.name(), a.title()))
+                .collect();
+            let idx = select_index(message, options)?;
+            Ok(items.swap_remove(idx))
+        }
+    }
+}
+
+pub async fn list(
+    force: bool,
+    cur_term: bool,
+    course: Option<&str>,
+    format: output::OutputFormat,
+) -> anyhow::Result<()> {
+    let items = fetch_announcements(force, cur_term, course).await?;
+    let records = items
+        .iter()
+        .map(api::AnnouncementView::from)
+        .collect::<Vec<_>>();
+
+    output::write_records(format, &records, |outbuf| {
+        writeln!(
+            outbuf,
+            "{D}>{D:#} {B}课程公告 ({}){B:#} {D}<{D:#}\n",
+            records.len()
+        )?;
+
+        for a in &records {
+            write!(
+                outbuf,
+                "{BL}{B}{}{B:#}{BL:#} {D}>{D:#} {}",
+                a.course, a.title
+            )?;
+            if let Some(t) = &a.time_raw {
+                write!(outbuf, " ({t})")?;
+            }
+            writeln!(outbuf, " {D}{}{D:#}", a.id)?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+pub async fn show(
+    id: Option<&str>,
+    force: bool,
+    cur_term: bool,
+    course: Option<&str>,
+) -> anyhow::Result<()> {
+    let items = fetch_announcements(force, cur_term, course).await?;
+    let a = find_or_select(items, id, "请选择要查看的公告")?;
+    let v = api::AnnouncementView::from(&a);
+
+    let mut outbuf = Vec::new();
+    writeln!(
+        outbuf,
+        "{BL}{B}{}{B:#}{BL:#} {D}>{D:#} {B}{}{B:#}",
+        v.course, v.title
+    )?;
+    if let Some(creator) = &v.creator {
+        write!(outbuf, "{D}{creator}{D:#} ")?;
+    }
+    if let Some(t) = &v.time_raw {
+        write!(outbuf, "{D}{t}{D:#}")?;
+    }
+    writeln!(outbuf, "\n")?;
+    for p in &v.descriptions {
+        writeln!(outbuf, "{p}")?;
+    }
+    if !v.attachments.is_empty() {
+        writeln!(outbuf)?;
+        for att in &v.attachments {
+            writeln!(outbuf, "{D}[附件]{D:#} {UL}{}{UL:#}", att.name)?;
+        }
+    }
+
+    buf_try!(@try fs::stdout().write_all(outbuf).await);
+    Ok(())
+}
+
+pub async fn download(
+    id: Option<&str>,
+    dir: &std::path::Path,
+    force: bool,
+    cur_term: bool,
+    course: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut items = fetch_announcements(force, cur_term, course).await?;
+    items.retain(|a| !a.attachments().is_empty());
+    let a = find_or_select(items, id, "请选择要下载附件的公告")?;
+
+    if !dir.exists() {
+        compio::fs::create_dir_all(dir).await?;
+    }
+
+    let sp = pbar::new_spinner();
+    let atts = a.attachments();
+    let tot = atts.len();
+    for (id, (name, uri)) in atts.iter().enumerate() {
+        sp.set_message(format!(
+            "[{}/{tot}] downloading attachment '{name}'...",
+            id + 1
+        ));
+        a.download_attachment(uri, &dir.join(name))
+            .await
+            .with_context(|| format!("download attachment '{}'", name))?;
+    }
+
+    drop(sp);
+    println!("Done.");
+    Ok(())
+}
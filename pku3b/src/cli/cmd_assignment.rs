@@ -4,38 +4,30 @@ use anyhow::Context;
 
 use super::*;
 
-async fn get_contents(
+pub async fn get_contents(
     c: &api::Course,
     pb: indicatif::ProgressBar,
 ) -> anyhow::Result<Vec<api::CourseContent>> {
-    let fut = async {
-        let mut s = c.content_stream();
+    let mut s = c.content_stream();
 
-        // let pb = pbar::new(s.len() as u64).with_message("search contents");
-        pb.set_length(s.len() as u64);
-        pb.tick();
-
-        let mut contents = Vec::new();
-        while let Some(batch) = s.next_batch().await {
-            contents.extend(batch);
+    pb.set_length(s.len() as u64);
+    pb.tick();
 
-            pb.set_length(s.len() as u64);
-            pb.set_position(s.num_finished() as u64);
-            pb.tick();
-        }
+    let mut contents = Vec::new();
+    while let Some(batch) = s.next_batch().await {
+        contents.extend(batch);
 
-        pb.finish_with_message("done.");
-        Ok(contents)
-    };
+        pb.set_length(s.len() as u64);
+        pb.set_position(s.num_finished() as u64);
+        pb.tick();
+    }
 
-    let data = utils::with_cache(
-        &format!("get_course_contents_{}", c.meta().id()),
-        c.client().cache_ttl(),
-        fut,
-    )
-    .await?;
+    pb.finish_with_message("done.");
 
-    Ok(data.into_iter().map(|data| c.build_content(data)).collect())
+    Ok(contents
+        .into_iter()
+        .map(|data| c.build_content(data))
+        .collect())
 }
 
 async fn get_assignments(
@@ -50,7 +42,7 @@ async fn get_assignments(
     Ok(r)
 }
 
-async fn get_courses_and_assignments(
+pub async fn get_courses_and_assignments(
     force: bool,
     cur_term: bool,
 ) -> anyhow::Result<Vec<(api::Course, Vec<(String, api::CourseAssignment)>)>> {
@@ -89,7 +81,12 @@ async fn get_courses_and_assignments(
     Ok(courses)
 }
 
-pub async fn list(force: bool, all: bool, cur_term: bool) -> anyhow::Result<()> {
+pub async fn list(
+    force: bool,
+    all: bool,
+    cur_term: bool,
+    format: output::OutputFormat,
+) -> anyhow::Result<()> {
     let courses = get_courses_and_assignments(force, cur_term).await?;
 
     let mut all_assignments = courses
@@ -107,24 +104,139 @@ pub async fn list(force: bool, all: bool, cur_term: bool) -> anyhow::Result<()>
     log::debug!("sorting assignments...");
     all_assignments.sort_by_cached_key(|(_, _, a)| a.deadline());
 
-    // prepare output statements
-    let mut outbuf = Vec::new();
-    let title = if all {
-        "所有作业 (包括已完成)"
-    } else {
-        "未完成作业"
-    };
-    let total = all_assignments.len();
-    writeln!(outbuf, "{D}>{D:#} {B}{title} ({total}){B:#} {D}<{D:#}\n")?;
+    let records = all_assignments
+        .iter()
+        .map(|(_, _, a)| api::AssignmentView::from(*a))
+        .collect::<Vec<_>>();
+
+    output::write_records(format, &records, |outbuf| {
+        let title = if all {
+            "所有作业 (包括已完成)"
+        } else {
+            "未完成作业"
+        };
+        let total = all_assignments.len();
+        writeln!(outbuf, "{D}>{D:#} {B}{title} ({total}){B:#} {D}<{D:#}\n")?;
+
+        for (c, id, a) in all_assignments {
+            write_course_assignment(outbuf, &id, &c, a).context("io error")?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum DueFormat {
+    /// 每个作业一行
+    Plain,
+    /// 所有作业汇总为一行，适合状态栏
+    Oneline,
+    /// JSON 数组
+    Json,
+}
+
+#[derive(serde::Serialize)]
+struct DueItem {
+    id: String,
+    course: String,
+    title: String,
+    deadline: String,
+    remaining_secs: i64,
+    url: String,
+    #[serde(skip)]
+    plain_deadline: String,
+    #[serde(skip)]
+    remaining: String,
+}
 
-    for (c, id, a) in all_assignments {
-        write_course_assignment(&mut outbuf, &id, &c, a).context("io error")?;
+impl output::PlainRecord for DueItem {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{}\t{}",
+            self.plain_deadline, self.remaining, self.course, self.title, self.id
+        )
     }
+}
 
-    // write to stdout
-    buf_try!(@try fs::stdout().write_all(outbuf).await);
+/// Print unfinished assignments due within the given window.
+///
+/// Without `format`, the global `--output` is used, and plain text if that is not given either.
+/// Returns whether there is any such assignment.
+pub async fn due(
+    force: bool,
+    within: std::time::Duration,
+    course: Option<&str>,
+    format: Option<DueFormat>,
+    output: Option<output::OutputFormat>,
+    cur_term: bool,
+) -> anyhow::Result<bool> {
+    let items = fetch_assignments(force, false, cur_term).await?;
 
-    Ok(())
+    let now = chrono::Local::now();
+    let until = now + chrono::Duration::from_std(within).context("duration too large")?;
+    let items = items
+        .iter()
+        .filter(|(c, _, _)| course_matches(c.meta().id(), c.meta().title(), course))
+        .filter_map(|(c, id, a)| {
+            let t = a.deadline()?;
+            (now <= t && t <= until).then_some((c, id, a, t))
+        })
+        .collect::<Vec<_>>();
+
+    let format = match format {
+        Some(DueFormat::Plain) => output::OutputFormat::Plain,
+        Some(DueFormat::Json) => output::OutputFormat::Json,
+        Some(DueFormat::Oneline) => {
+            if !items.is_empty() {
+                let s = items
+                    .iter()
+                    .map(|(c, _, a, t)| {
+                        format!(
+                            "{}/{} ({})",
+                            c.meta().name(),
+                            a.title(),
+                            fmt_time_delta_plain(*t - now)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let line = format!("{} due: {}\n", items.len(), s);
+                buf_try!(@try fs::stdout().write_all(line.into_bytes()).await);
+            }
+            return Ok(!items.is_empty());
+        }
+        None => output.unwrap_or(output::OutputFormat::Plain),
+    };
+
+    let records = items
+        .iter()
+        .map(|(c, id, a, t)| DueItem {
+            id: id.to_string(),
+            course: c.meta().name().to_owned(),
+            title: a.title().to_owned(),
+            deadline: t.to_rfc3339(),
+            remaining_secs: (*t - now).num_seconds(),
+            url: a.url(),
+            plain_deadline: t.format("%Y-%m-%d %H:%M").to_string(),
+            remaining: fmt_time_delta_plain(*t - now),
+        })
+        .collect::<Vec<_>>();
+    output::write_records(format, &records, |outbuf| {
+        let total = items.len();
+        writeln!(
+            outbuf,
+            "{D}>{D:#} {B}即将截止的作业 ({total}){B:#} {D}<{D:#}\n"
+        )?;
+        for (c, id, a, _) in &items {
+            write_course_assignment(outbuf, id, c, a).context("io error")?;
+        }
+        Ok(())
+    })
+    .await?;
+
+    Ok(!items.is_empty())
 }
 
 type AssignmentListItem = (Arc<api::Course>, String, api::CourseAssignment);
@@ -354,7 +466,15 @@ pub fn fmt_time_delta(delta: chrono::TimeDelta) -> String {
         Style::new().fg_color(Some(AnsiColor::Red.into()))
     };
 
-    let mut delta = delta.to_std().unwrap();
+    format!("{s}{}{s:#}", fmt_time_delta_plain(delta))
+}
+
+/// Same as [`fmt_time_delta`] but without styles, e.g. `in 1d 2h 3m 4s`.
+pub fn fmt_time_delta_plain(delta: chrono::TimeDelta) -> String {
+    let Ok(mut delta) = delta.to_std() else {
+        return "due".to_owned();
+    };
+
     let mut res = String::new();
     res.push_str("in ");
     if delta.as_secs() >= 86400 {
@@ -370,5 +490,5 @@ pub fn fmt_time_delta(delta: chrono::TimeDelta) -> String {
         delta = std::time::Duration::from_secs(delta.as_secs() % 60);
     }
     res.push_str(&format!("{}s", delta.as_secs()));
-    format!("{s}{}{s:#}", res)
+    res
 }
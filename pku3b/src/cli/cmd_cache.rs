@@ -0,0 +1,107 @@
+use super::*;
+
+fn store() -> &'static cache::CacheStore {
+    super::cache_store()
+}
+
+pub async fn show() -> anyhow::Result<()> {
+    let store = store();
+    log::info!("Cache dir: '{}'", store.dir().display());
+
+    let entries = store.entries();
+    // the cache dir may also contain untracked files, e.g. unfinished video downloads
+    let total = cache::disk_usage(store.dir());
+
+    let mut kinds = std::collections::BTreeMap::<_, (usize, u64)>::new();
+    for e in &entries {
+        let (n, size) = kinds.entry(e.kind.as_str()).or_default();
+        *n += 1;
+        *size += e.size;
+    }
+
+    let mut outbuf = Vec::new();
+    writeln!(
+        outbuf,
+        "缓存大小: {B}{}{B:#} {D}(上限 {}){D:#}",
+        utils::format_size(total),
+        utils::format_size(store.limit())
+    )?;
+    writeln!(outbuf, "缓存目录: {}", store.dir().display())?;
+    if !kinds.is_empty() {
+        writeln!(outbuf)?;
+    }
+    for (kind, (n, size)) in kinds {
+        writeln!(
+            outbuf,
+            "{D}•{D:#} {kind}: {n} 项, {}",
+            utils::format_size(size)
+        )?;
+    }
+
+    buf_try!(@try fs::stdout().write_all(outbuf).await);
+    Ok(())
+}
+
+pub async fn clean() -> anyhow::Result<()> {
+    let store = store();
+    let total = cache::disk_usage(store.dir());
+    store.clear()?;
+    println!("缓存已清空 (释放 {B}{}{B:#})", utils::format_size(total));
+    Ok(())
+}
+
+pub async fn list(filter: &cache::Filter, format: output::OutputFormat) -> anyhow::Result<()> {
+    let now = chrono::Utc::now();
+    let records = store()
+        .entries()
+        .into_iter()
+        .filter(|e| filter.matches(e, now))
+        .collect::<Vec<_>>();
+
+    output::write_records(format, &records, |outbuf| {
+        let total = records.iter().map(|e| e.size).sum();
+        writeln!(
+            outbuf,
+            "{D}>{D:#} {B}缓存条目 ({}, {}){B:#} {D}<{D:#}\n",
+            records.len(),
+            utils::format_size(total)
+        )?;
+
+        for e in &records {
+            write!(outbuf, "{D}•{D:#} {BL}{}{BL:#}", e.kind)?;
+            if let Some(course) = &e.course {
+                write!(outbuf, " {course}")?;
+            }
+            write!(
+                outbuf,
+                " {B}{}{B:#} {D}{}{D:#}",
+                utils::format_size(e.size),
+                e.created
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+            )?;
+            if e.is_expired(now) {
+                write!(outbuf, " {RD}(已过期){RD:#}")?;
+            }
+            writeln!(outbuf, " {D}{}{D:#}", e.key)?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+pub async fn remove(filter: &cache::Filter) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        filter.course.is_some() || filter.kind.is_some() || filter.older_than.is_some(),
+        "specify at least one of --course, --kind and --older-than (use `pku3b cache clean` to remove everything)"
+    );
+
+    let removed = store().remove(filter);
+    let freed = removed.iter().map(|e| e.size).sum();
+    println!(
+        "已删除 {} 个缓存条目 (释放 {B}{}{B:#})",
+        removed.len(),
+        utils::format_size(freed)
+    );
+    Ok(())
+}
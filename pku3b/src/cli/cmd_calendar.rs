@@ -0,0 +1,88 @@
+use anyhow::Context;
+
+use super::*;
+use crate::ical;
+
+pub struct ExportOptions {
+    /// include finished assignments
+    pub all: bool,
+    pub videos: bool,
+    pub todo: bool,
+    pub alarms: Vec<std::time::Duration>,
+}
+
+pub async fn export(
+    force: bool,
+    output: Option<&std::path::Path>,
+    opts: ExportOptions,
+    cur_term: bool,
+) -> anyhow::Result<()> {
+    let courses = cmd_assignment::get_courses_and_assignments(force, cur_term).await?;
+
+    let mut cal = ical::CalendarBuilder::new()
+        .name("pku3b")
+        .deadline_kind(if opts.todo {
+            ical::DeadlineKind::Todo
+        } else {
+            ical::DeadlineKind::Event
+        });
+    for before in &opts.alarms {
+        cal = cal.alarm(chrono::Duration::from_std(*before).context("alarm too large")?)?;
+    }
+
+    let mut skipped = 0;
+    for (_, assignments) in &courses {
+        for (_, a) in assignments {
+            if !opts.all && a.last_attempt().is_some() {
+                continue;
+            }
+            if !cal.add_assignment(a) {
+                log::warn!("skip assignment without deadline: {}", a.title());
+                skipped += 1;
+            }
+        }
+    }
+
+    if opts.videos {
+        let pb = pbar::new(courses.len() as u64).with_prefix("videos");
+        let futs = courses.iter().map(async |(c, _)| -> anyhow::Result<_> {
+            let vs = c.get_video_list().await.context("fetch video list")?;
+            pb.inc(1);
+            Ok(vs)
+        });
+        let videos = try_join_all(futs).await?;
+        pb.finish_and_clear();
+
+        for v in videos.iter().flatten() {
+            if !cal.add_video(v) {
+                log::warn!("skip video without time: {}", v.title());
+            }
+        }
+    }
+
+    let content = cal.build();
+    match output {
+        Some(path) => {
+            if let Some(par) = path.parent()
+                && !par.as_os_str().is_empty()
+                && !par.exists()
+            {
+                fs::create_dir_all(par).await?;
+            }
+            buf_try!(@try fs::write(path, content).await);
+            println!(
+                "已导出 {B}{}{B:#} 个日历项至 {GR}{H2}{}{H2:#}{GR:#}",
+                cal.len(),
+                path.display()
+            );
+            if skipped > 0 {
+                println!("{D}({skipped} 个作业没有截止时间, 未导出){D:#}");
+            }
+        }
+        None => {
+            buf_try!(@try fs::stdout().write_all(content).await);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use anyhow::Context;
+
+use super::*;
+
+pub async fn list(force: bool, cur_term: bool, format: output::OutputFormat) -> anyhow::Result<()> {
+    let courses = load_courses(force, cur_term).await?;
+    let records = courses
+        .iter()
+        .map(api::CourseView::from)
+        .collect::<Vec<_>>();
+
+    output::write_records(format, &records, |outbuf| {
+        let title = if cur_term {
+            "本学期课程"
+        } else {
+            "所有学期课程"
+        };
+        writeln!(
+            outbuf,
+            "{D}>{D:#} {B}{title} ({}){B:#} {D}<{D:#}\n",
+            records.len()
+        )?;
+
+        for c in &records {
+            write!(outbuf, "{D}•{D:#} {BL}{B}{}{B:#}{BL:#}", c.title)?;
+            if !c.is_current {
+                write!(outbuf, " {D}(历史学期){D:#}")?;
+            }
+            writeln!(outbuf, " {D}{}{D:#}", c.id)?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Print the content tree of the course matching `course`, or of a course chosen interactively.
+pub async fn tree(
+    force: bool,
+    course: Option<&str>,
+    cur_term: bool,
+    format: output::OutputFormat,
+) -> anyhow::Result<()> {
+    let mut courses = load_courses_matching(force, cur_term, course).await?;
+    let c = if courses.len() == 1 {
+        courses.swap_remove(0)
+    } else {
+        let options = courses.iter().map(|c| c.title().to_owned()).collect();
+        let idx = select_index("请选择课程", options)?;
+        courses.swap_remove(idx)
+    };
+
+    let sp = pbar::new_spinner();
+    sp.set_message("fetching course contents...");
+    let c = c.get().await.context("fetch course")?;
+    let root = c
+        .build_tree()
+        .await
+        .with_context(|| format!("build content tree of {}", c.meta().title()))?;
+    drop(sp);
+
+    let records = [api::TreeNodeView::from(&root)];
+    output::write_records(format, &records, |outbuf| {
+        write_tree(outbuf, &records[0], "", true, true).context("io error")
+    })
+    .await
+}
+
+fn write_tree(
+    buf: &mut Vec<u8>,
+    node: &api::TreeNodeView,
+    indent: &str,
+    last: bool,
+    root: bool,
+) -> std::io::Result<()> {
+    let icon = match node.kind {
+        api::NodeKind::Document => "📄",
+        api::NodeKind::Assignment => "📝",
+        api::NodeKind::Video => "📺",
+        api::NodeKind::Announcement => "📢",
+        api::NodeKind::Folder => "📁",
+        _ => "📦",
+    };
+
+    let child_indent = if root {
+        writeln!(buf, "{icon} {BL}{B}{}{B:#}{BL:#}", node.title)?;
+        String::new()
+    } else {
+        let branch = if last { "└── " } else { "├── " };
+        write!(buf, "{D}{indent}{branch}{D:#}{icon} {}", node.title)?;
+        match node.kind {
+            api::NodeKind::Document | api::NodeKind::Assignment | api::NodeKind::Video => {
+                writeln!(buf, " {D}{}{D:#}", node.id)?
+            }
+            _ => writeln!(buf)?,
+        }
+        format!("{indent}{}", if last { "    " } else { "│   " })
+    };
+
+    let n = node.children.len();
+    for (i, child) in node.children.iter().enumerate() {
+        write_tree(buf, child, &child_indent, i + 1 == n, false)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use anyhow::Context;
+
+use super::*;
+
+type DocumentListItem = (std::sync::Arc<api::Course>, api::CourseDocumentHandle);
+
+async fn fetch_documents(
+    force: bool,
+    cur_term: bool,
+    course: Option<&str>,
+) -> anyhow::Result<Vec<DocumentListItem>> {
+    let courses = load_courses_matching(force, cur_term, course).await?;
+
+    // fetch each course concurrently
+    let m = indicatif::MultiProgress::new();
+    let futs = courses.into_iter().map(async |c| -> anyhow::Result<_> {
+        let c = c.get().await.context("fetch course")?;
+        let docs = cmd_assignment::get_contents(
+            &c,
+            m.add(pbar::new(0).with_prefix(c.meta().name().to_owned())),
+        )
+        .await
+        .with_context(|| format!("fetch contents of {}", c.meta().title()))?
+        .into_iter()
+        .filter_map(|c| c.into_document_opt())
+        .collect::<Vec<_>>();
+
+        let c = std::sync::Arc::new(c);
+        Ok(docs.into_iter().map(move |d| (c.clone(), d)))
+    });
+    let items = try_join_all(futs).await?.into_iter().flatten().collect();
+    m.clear().unwrap();
+
+    Ok(items)
+}
+
+pub async fn list(
+    force: bool,
+    cur_term: bool,
+    course: Option<&str>,
+    format: output::OutputFormat,
+) -> anyhow::Result<()> {
+    let items = fetch_documents(force, cur_term, course).await?;
+    let records = items
+        .iter()
+        .map(|(_, d)| api::DocumentView::from(d))
+        .collect::<Vec<_>>();
+
+    output::write_records(format, &records, |outbuf| {
+        writeln!(
+            outbuf,
+            "{D}>{D:#} {B}课程文档 ({}){B:#} {D}<{D:#}\n",
+            records.len()
+        )?;
+
+        let mut last_course = None;
+        for ((c, _), d) in items.iter().zip(&records) {
+            if last_course != Some(c.meta().id()) {
+                if last_course.is_some() {
+                    writeln!(outbuf)?;
+                }
+                writeln!(outbuf, "{BL}{H1}[{}]{H1:#}{BL:#}\n", c.meta().title())?;
+                last_course = Some(c.meta().id());
+            }
+            write_document_title_ln(outbuf, d).context("io error")?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn write_document_title_ln(buf: &mut Vec<u8>, d: &api::DocumentView) -> std::io::Result<()> {
+    write!(buf, "{D}•{D:#} ")?;
+    if let Some(section) = &d.section {
+        write!(buf, "{D}{section} >{D:#} ")?;
+    }
+    if let Some(parent) = &d.parent
+        && d.section.as_ref() != Some(parent)
+    {
+        write!(buf, "{D}{parent} >{D:#} ")?;
+    }
+    write!(buf, "{}", d.title)?;
+    if !d.attachments.is_empty() {
+        write!(buf, " ({} 个附件)", d.attachments.len())?;
+    }
+    writeln!(buf, " {D}{}{D:#}", d.id)
+}
+
+/// Download the attachments of the document with the given id (or a chosen one) into `dir`.
+pub async fn download(
+    id: Option<&str>,
+    dir: &std::path::Path,
+    force: bool,
+    cur_term: bool,
+    course: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut items = fetch_documents(force, cur_term, course).await?;
+    items.retain(|(_, d)| !d.attachments().is_empty());
+
+    let (_, d) = match id {
+        Some(id) => match items.into_iter().find(|(_, d)| d.id() == id) {
+            Some(r) => r,
+            None => anyhow::bail!("document with id {} not found", id),
+        },
+        None => {
+            let options = items
+                .iter()
+                .map(|(c, d)| format!("{} > {}", c.meta().name(), d.title()))
+                .collect();
+            let idx = select_index("请选择要下载的文档", options)?;
+            items.swap_remove(idx)
+        }
+    };
+
+    let d = d.get().await?;
+    if !dir.exists() {
+        compio::fs::create_dir_all(dir).await?;
+    }
+
+    let sp = pbar::new_spinner();
+    let atts = d.attachments();
+    let tot = atts.len();
+    for (id, (name, uri)) in atts.iter().enumerate() {
+        sp.set_message(format!(
+            "[{}/{tot}] downloading attachment '{name}'...",
+            id + 1
+        ));
+        d.download_attachment(uri, &dir.join(name))
+            .await
+            .with_context(|| format!("download attachment '{}'", name))?;
+    }
+
+    drop(sp);
+    println!("Done.");
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use anyhow::Context;
+
+use super::*;
+
+pub async fn run(
+    force: bool,
+    course: Option<&str>,
+    save: bool,
+    cur_term: bool,
+    format: output::OutputFormat,
+) -> anyhow::Result<()> {
+    let courses = load_courses_matching(force, cur_term, course).await?;
+    let store = snapshot::SnapshotStore::default();
+
+    let pb = pbar::new(courses.len() as u64);
+    let futs = courses.into_iter().map(async |c| -> anyhow::Result<_> {
+        let c = c.get().await.context("fetch course")?;
+        let events = store
+            .check(&c, save)
+            .await
+            .with_context(|| format!("check changes of {}", c.meta().title()))?;
+        pb.inc(1);
+        Ok((c, events))
+    });
+    let courses = try_join_all(futs).await?;
+    pb.finish_and_clear();
+
+    let records = courses
+        .iter()
+        .flat_map(|(_, events)| events.iter().flatten().cloned())
+        .collect::<Vec<_>>();
+
+    output::write_records(format, &records, |outbuf| {
+        writeln!(
+            outbuf,
+            "{D}>{D:#} {B}课程动态 ({}){B:#} {D}<{D:#}\n",
+            records.len()
+        )?;
+
+        let mut baseline = Vec::new();
+        for (c, events) in &courses {
+            let Some(events) = events else {
+                baseline.push(c.meta().name());
+                continue;
+            };
+            if events.is_empty() {
+                continue;
+            }
+
+            writeln!(outbuf, "{BL}{H1}[{}]{H1:#}{BL:#}\n", c.meta().title())?;
+            for e in events {
+                write_event(outbuf, e).context("io error")?;
+            }
+            writeln!(outbuf)?;
+        }
+
+        if !baseline.is_empty() {
+            writeln!(
+                outbuf,
+                "{EM:}首次记录以下课程的状态，下次运行时将报告变化: {}{EM:#}",
+                baseline.join(", ")
+            )?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn item_name(item: snapshot::ItemKind) -> &'static str {
+    match item {
+        snapshot::ItemKind::Content => "内容",
+        snapshot::ItemKind::Assignment => "作业",
+        snapshot::ItemKind::Announcement => "公告",
+        snapshot::ItemKind::Video => "回放",
+    }
+}
+
+pub fn write_event(buf: &mut Vec<u8>, e: &snapshot::Event) -> std::io::Result<()> {
+    let item = item_name(e.item);
+    let fmt = |t: Option<chrono::DateTime<chrono::FixedOffset>>| {
+        t.map_or("无".to_owned(), |t| t.format("%Y-%m-%d %H:%M").to_string())
+    };
+    match e.change {
+        snapshot::EventKind::Added => writeln!(buf, "{GR}+ [{item}] {}{GR:#}", e.title),
+        snapshot::EventKind::Removed => writeln!(buf, "{RD}- [{item}] {}{RD:#}", e.title),
+        snapshot::EventKind::Modified => writeln!(buf, "{MG}~ [{item}] {}{MG:#}", e.title),
+        snapshot::EventKind::DeadlineChanged => writeln!(
+            buf,
+            "{MG}~ [{item}] {}{MG:#} 截止时间: {D}{}{D:#} → {B}{}{B:#}",
+            e.title,
+            fmt(e.previous_deadline),
+            fmt(e.deadline)
+        ),
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::Context;
+
+use super::*;
+
+pub async fn run(
+    force: bool,
+    course: Option<&str>,
+    dir: &std::path::Path,
+    opts: sync::SyncOptions,
+    cur_term: bool,
+) -> anyhow::Result<()> {
+    let courses = load_courses_matching(force, cur_term, course).await?;
+
+    let mut failed = 0;
+    for c in courses {
+        let sp = pbar::new_spinner();
+        sp.set_message(format!("{}: fetching contents...", c.title()));
+        let c = c.get().await.context("fetch course")?;
+        let name = c.meta().name().to_owned();
+        let report = sync::sync_course(&c, dir, opts, |idx, tot, change| {
+            sp.set_message(format!("{name}: [{idx}/{tot}] {}", change.path));
+        })
+        .await
+        .with_context(|| format!("sync {}", c.meta().title()))?;
+        drop(sp);
+
+        let mut outbuf = Vec::new();
+        write_report(&mut outbuf, &c, &report, opts)?;
+        buf_try!(@try fs::stdout().write_all(outbuf).await);
+        failed += report.failed.len();
+    }
+
+    if opts.dry_run {
+        println!("{EM:}tips: 这是预览 (--dry-run)，没有写入任何文件{EM:#}");
+    }
+    anyhow::ensure!(failed == 0, "{failed} file(s) failed to sync");
+    Ok(())
+}
+
+fn write_report(
+    buf: &mut Vec<u8>,
+    c: &api::Course,
+    report: &sync::SyncReport,
+    opts: sync::SyncOptions,
+) -> std::io::Result<()> {
+    writeln!(
+        buf,
+        "{BL}{H1}[{}]{H1:#}{BL:#} {D}{}{D:#}",
+        c.meta().title(),
+        report.course_dir.display()
+    )?;
+
+    for change in &report.changes {
+        match change.kind {
+            sync::ChangeKind::Added => writeln!(buf, "{GR}+ {}{GR:#}", change.path)?,
+            sync::ChangeKind::Modified => writeln!(buf, "{MG}~ {}{MG:#}", change.path)?,
+            sync::ChangeKind::Removed if opts.prune => {
+                writeln!(buf, "{RD}- {}{RD:#}", change.path)?
+            }
+            sync::ChangeKind::Removed => writeln!(
+                buf,
+                "{D}- {} (远端已删除, 使用 --prune 删除本地文件){D:#}",
+                change.path
+            )?,
+        }
+    }
+    for (path, e) in &report.failed {
+        writeln!(buf, "{RD}! {path}: {e}{RD:#}")?;
+    }
+
+    let count = |kind| report.changes.iter().filter(|c| c.kind == kind).count();
+    writeln!(
+        buf,
+        "{D}新增 {}, 更新 {}, 删除 {}, 未变化 {}, 失败 {}{D:#}\n",
+        count(sync::ChangeKind::Added),
+        count(sync::ChangeKind::Modified),
+        count(sync::ChangeKind::Removed),
+        report.unchanged,
+        report.failed.len(),
+    )
+}
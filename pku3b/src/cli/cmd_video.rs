@@ -1,7 +1,7 @@
 use anyhow::Context;
 
 use super::*;
-pub async fn list(force: bool, cur_term: bool) -> anyhow::Result<()> {
+pub async fn list(force: bool, cur_term: bool, format: output::OutputFormat) -> anyhow::Result<()> {
     let courses = load_courses(force, cur_term).await?;
 
     let pb = pbar::new(courses.len() as u64);
@@ -14,36 +14,111 @@ pub async fn list(force: bool, cur_term: bool) -> anyhow::Result<()> {
     let courses = try_join_all(futs).await?;
     pb.finish_and_clear();
 
-    let mut outbuf = Vec::new();
-    let title = "课程回放";
+    let records = courses
+        .iter()
+        .flat_map(|(_, vs)| vs.iter().map(api::VideoView::from))
+        .collect::<Vec<_>>();
 
-    writeln!(outbuf, "{D}>{D:#} {B}{}{B:#} {D}<{D:#}\n", title)?;
+    output::write_records(format, &records, |outbuf| {
+        let title = "课程回放";
 
-    for (c, vs) in courses {
-        if vs.is_empty() {
-            continue;
+        writeln!(outbuf, "{D}>{D:#} {B}{}{B:#} {D}<{D:#}\n", title)?;
+
+        for (c, vs) in courses {
+            if vs.is_empty() {
+                continue;
+            }
+
+            writeln!(outbuf, "{BL}{H1}[{}]{H1:#}{BL:#}\n", c.meta().title())?;
+
+            for v in vs {
+                let teacher = match v.meta().teacher() {
+                    "" => String::new(),
+                    t => format!(" {t}"),
+                };
+                writeln!(
+                    outbuf,
+                    "{D}•{D:#} {} ({}){teacher} {D}{}{D:#}",
+                    v.meta().title(),
+                    v.meta().time(),
+                    v.id()
+                )?;
+            }
+
+            writeln!(outbuf)?;
         }
+        Ok(())
+    })
+    .await
+}
 
-        writeln!(outbuf, "{BL}{H1}[{}]{H1:#}{BL:#}\n", c.meta().title())?;
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum AudioFormat {
+    /// MP4 封装的 AAC 音频
+    M4a,
+    /// ADTS 格式的 AAC 裸流
+    Aac,
+}
 
-        for v in vs {
-            writeln!(
-                outbuf,
-                "{D}•{D:#} {} ({}) {D}{}{D:#}",
-                v.meta().title(),
-                v.meta().time(),
-                v.id()
-            )?;
+impl From<AudioFormat> for video::SaveFormat {
+    fn from(f: AudioFormat) -> Self {
+        match f {
+            AudioFormat::M4a => video::SaveFormat::M4a,
+            AudioFormat::Aac => video::SaveFormat::Aac,
         }
+    }
+}
 
-        writeln!(outbuf)?;
+/// `pku3b video download` 中与选择哪些回放无关的选项
+pub struct DownloadArgs {
+    pub opts: video::DownloadOptions,
+    pub selector: api::VideoSelector,
+    pub ffmpeg: bool,
+    pub audio: Option<AudioFormat>,
+    /// 导出为 HLS 目录
+    pub hls: bool,
+    /// 输出目录
+    pub dir: std::path::PathBuf,
+    /// 文件名模板
+    pub name: Option<String>,
+}
+
+impl DownloadArgs {
+    fn format(&self) -> video::SaveFormat {
+        match self.audio {
+            Some(f) => f.into(),
+            None if self.hls => video::SaveFormat::Hls,
+            None => video::SaveFormat::Mp4,
+        }
     }
+}
 
-    buf_try!(@try fs::stdout().write_all(outbuf).await);
-    Ok(())
+/// 回放的中间文件目录，每个变体一个子目录
+fn work_dir(id: &str, v: &api::CourseVideo) -> std::path::PathBuf {
+    video_download_dir(v.cache_store(), id).join(v.variant().cache_id())
+}
+
+fn video_download_dir(store: &cache::CacheStore, id: &str) -> std::path::PathBuf {
+    store.dir().join("video_download").join(id)
 }
 
-pub async fn download(force: bool, id: String, cur_term: bool) -> anyhow::Result<()> {
+/// let the download dir of a video take part in cache eviction
+fn track_download_dir(store: &cache::CacheStore, course: &api::CourseMeta, id: &str) {
+    let dir = video_download_dir(store, id);
+    if !dir.exists() {
+        return;
+    }
+    if let Err(e) = store.track(&course.cache_key("video_download").id(id), &dir) {
+        log::warn!("track video download dir: {e:#}");
+    }
+}
+
+/// 按 ID 查找课程回放
+async fn find_video(
+    force: bool,
+    id: &str,
+    cur_term: bool,
+) -> anyhow::Result<(api::CourseVideoHandle, pbar::AsyncSpinner)> {
     let (_, courses, sp) = load_client_courses(force, cur_term).await?;
 
     sp.set_message("finding video...");
@@ -67,121 +142,245 @@ pub async fn download(force: bool, id: String, cur_term: bool) -> anyhow::Result
     let Some(v) = target_video else {
         anyhow::bail!("video with id {} not found", id);
     };
+    Ok((v, sp))
+}
+
+pub async fn streams(
+    force: bool,
+    id: String,
+    cur_term: bool,
+    format: output::OutputFormat,
+) -> anyhow::Result<()> {
+    let (v, sp) = find_video(force, &id, cur_term).await?;
+    sp.set_message("fetch video streams...");
+    let variants = v.variants().await?;
+    drop(sp);
+
+    output::write_records(format, &variants, |outbuf| {
+        writeln!(
+            outbuf,
+            "{D}>{D:#} {B}{} ({}){B:#} {D}<{D:#}\n",
+            v.title(),
+            v.time()
+        )?;
+        for x in &variants {
+            write!(outbuf, "{D}•{D:#} {BL}{H1}[{}]{H1:#}{BL:#}", x.stream)?;
+            if let Some(q) = x.quality() {
+                write!(outbuf, " {q}")?;
+            }
+            if let Some(b) = x.bandwidth {
+                write!(outbuf, " {D}{:.1} Mbps{D:#}", b as f64 / 1e6)?;
+            }
+            writeln!(outbuf)?;
+        }
+        writeln!(outbuf)?;
+        Ok(())
+    })
+    .await
+}
+
+/// 下载课程回放。`args.audio` 不为空时只提取音频。
+pub async fn download(
+    force: bool,
+    id: String,
+    cur_term: bool,
+    args: DownloadArgs,
+) -> anyhow::Result<()> {
+    let (handle, sp) = find_video(force, &id, cur_term).await?;
 
     sp.set_message("fetch video metadata...");
-    let v = v.get().await?;
+    let v = handle.get_with(&args.selector).await?;
 
     drop(sp);
 
-    println!("下载课程回放：{} ({})", v.course_name(), v.meta().title());
-
-    // prepare download dir
-    let dir = utils::projectdir()
-        .cache_dir()
-        .join("video_download")
-        .join(&id);
-    fs::create_dir_all(&dir)
-        .await
-        .context("create dir failed")?;
-
-    let paths = download_segments(&v, &dir)
-        .await
-        .context("download ts segments")?;
-
-    let m3u8 = dir.join("playlist").with_extension("m3u8");
-    buf_try!(@try fs::write(&m3u8, v.m3u8_raw()).await);
-
-    // merge all segments into one file
-    let merged = dir.join("merged").with_extension("ts");
-    merge_segments(&merged, &paths).await?;
-    let dest = format!("{}_{}.mp4", v.course_name(), v.meta().title());
-    log::info!("Merged segments to {}", merged.display());
-    log::info!(
-        r#"You may execute `ffmpeg -i "{}" -c copy "{}"` to convert it to mp4"#,
-        merged.display(),
-        dest,
+    println!(
+        "下载课程回放：{} ({}) {D}{}{D:#}",
+        v.course_name(),
+        v.meta().title(),
+        v.variant()
     );
 
-    // convert the merged ts file to mp4. overwrite existing file
-    let sp = pbar::new_spinner();
-    sp.set_message("Converting to mp4 file...");
-    let c = compio::process::Command::new("ffmpeg")
-        .args(["-y", "-hide_banner", "-loglevel", "quiet"])
-        .args(["-i", merged.to_string_lossy().as_ref()])
-        .args(["-c", "copy"])
-        .arg(&dest)
-        .output()
-        .await
-        .context("execute ffmpeg")?;
-    drop(sp);
-
-    if c.status.success() {
-        println!("下载完成, 文件保存为: {GR}{H2}{}{H2:#}{GR:#}", dest);
+    let template = args.name.as_deref().unwrap_or(video::NAME_TEMPLATE);
+    let stem = args.dir.join(video::render_name(template, &handle)?);
+    let pb = if v.file_url().is_some() {
+        pbar::new_bytes(0)
     } else {
-        anyhow::bail!("ffmpeg failed with exit code {:?}", c.status.code());
+        pbar::new(v.len_segments() as u64)
     }
-
+    .with_prefix("download");
+    pb.tick();
+    let spinner = std::cell::RefCell::new(None);
+    let r = video::save(
+        &v,
+        &stem,
+        args.format(),
+        &work_dir(&id, &v),
+        args.opts,
+        args.ffmpeg,
+        |p| match p {
+            video::SaveProgress::Converting => {
+                pb.finish_and_clear();
+                let sp = pbar::new_spinner();
+                sp.set_message(if args.audio.is_some() {
+                    "Extracting audio..."
+                } else {
+                    "Converting to mp4 file..."
+                });
+                *spinner.borrow_mut() = Some(sp);
+            }
+            p => pbar::set_save_progress(&pb, p),
+        },
+    )
+    .await;
+    pb.finish_and_clear();
+    drop(spinner);
+    track_download_dir(v.cache_store(), v.course(), &id);
+
+    let dest = r?;
+    let what = match args.format() {
+        video::SaveFormat::Hls => "播放列表",
+        f if f.is_audio() => "音频",
+        _ => "文件",
+    };
+    println!(
+        "下载完成, {what}保存为: {GR}{H2}{}{H2:#}{GR:#}",
+        dest.display()
+    );
     Ok(())
 }
 
-async fn download_segments(
-    v: &api::CourseVideo,
-    dir: impl AsRef<std::path::Path>,
-) -> anyhow::Result<Vec<std::path::PathBuf>> {
-    let dir = dir.as_ref();
-    if !dir.exists() {
-        anyhow::bail!("dir {} not exists", dir.display());
-    }
-
-    let tot = v.len_segments();
-    let pb = pbar::new(tot as u64).with_prefix("download");
-    pb.tick();
+/// 在本地提供课程回放的 HLS 播放列表，直到进程被终止
+pub async fn serve(
+    force: bool,
+    id: String,
+    cur_term: bool,
+    port: u16,
+    opts: video::DownloadOptions,
+    selector: api::VideoSelector,
+) -> anyhow::Result<()> {
+    let (handle, sp) = find_video(force, &id, cur_term).await?;
 
-    let mut key = None;
-    let mut paths = Vec::new();
-    // faster than try_join_all
-    for i in 0..tot {
-        key = v.refresh_key(i, key);
-        let path = dir.join(&v.segment(i).uri).with_extension("ts");
-
-        if !path.exists() {
-            log::debug!("key: {:?}", key);
-            let seg = v
-                .get_segment_data(i, key)
-                .await
-                .with_context(|| format!("get segment #{i} with key {key:?}"))?;
-
-            // fs::write is not atomic, so we write to a tmp file first
-            let tmpath = path.with_extension("tmp");
-            buf_try!(@try fs::write(&tmpath, seg).await);
-            fs::rename(tmpath, &path).await.context("rename tmp file")?;
-        }
+    sp.set_message("fetch video metadata...");
+    let v = handle.get_with(&selector).await?;
+    drop(sp);
 
-        pb.inc(1);
-        paths.push(path);
-    }
-    pb.finish_and_clear();
+    println!(
+        "播放课程回放：{} ({}) {D}{}{D:#}",
+        v.course_name(),
+        v.meta().title(),
+        v.variant()
+    );
+    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    let proxy = serve::HlsProxy::bind(v, addr, opts).await?;
+    let url = proxy.playlist_url();
+    println!("在播放器中打开: {GR}{H2}{url}{H2:#}{GR:#}");
+    println!("{D}例如 `mpv {url}` 或 `vlc {url}`，按 Ctrl-C 退出{D:#}");
+    proxy.run().await
+}
 
-    Ok(paths)
+/// 批量下载课程回放时挑选回放的方式
+pub struct BatchSelect {
+    /// 课程 ID 或课程名的一部分
+    pub course: String,
+    pub filter: video::VideoFilter,
+    /// 是否下载全部回放
+    pub all: bool,
+    /// 同时下载的回放数量
+    pub parallel: usize,
 }
 
-async fn merge_segments(
-    dest: impl AsRef<std::path::Path>,
-    paths: &[std::path::PathBuf],
+/// 批量下载匹配的课程中符合条件的回放，跳过已经存在的文件
+pub async fn download_batch(
+    force: bool,
+    cur_term: bool,
+    select: BatchSelect,
+    args: DownloadArgs,
 ) -> anyhow::Result<()> {
-    let f = fs::File::create(&dest)
-        .await
-        .context("create merged file failed")?;
-    let mut f = std::io::Cursor::new(f);
+    anyhow::ensure!(
+        select.all || select.filter.since.is_some() || select.filter.latest.is_some(),
+        "specify --since, --latest or --all to choose the replays of {:?} to download",
+        select.course
+    );
 
-    let pb = pbar::new(paths.len() as u64).with_prefix("merge segments");
-    pb.tick();
-    for p in paths {
-        let data = fs::read(p).await.context("read segments failed")?;
-        buf_try!(@try f.write(data).await);
-        pb.inc(1);
-    }
-    pb.finish_and_clear();
+    let courses = load_courses_matching(force, cur_term, Some(&select.course)).await?;
+    let sp = pbar::new_spinner();
+    sp.set_message("fetching video lists...");
+    let futs = courses.into_iter().map(async |c| -> anyhow::Result<_> {
+        let c = c.get().await.context("fetch course")?;
+        c.get_video_list().await.context("fetch video list")
+    });
+    let videos = try_join_all(futs).await?.into_iter().flatten().collect();
+    let videos = select.filter.apply(videos);
+    drop(sp);
 
+    if videos.is_empty() {
+        println!("没有符合条件的课程回放");
+        return Ok(());
+    }
+    println!("下载 {} 个课程回放到 {}", videos.len(), args.dir.display());
+
+    let opts = video::BatchOptions {
+        parallel: select.parallel,
+        template: args
+            .name
+            .clone()
+            .unwrap_or_else(|| video::BATCH_NAME_TEMPLATE.to_owned()),
+        format: args.format(),
+        selector: args.selector.clone(),
+        ffmpeg: args.ffmpeg,
+        download: args.opts,
+    };
+    let names = video::batch_names(&opts.template, &videos)?;
+    let mp = indicatif::MultiProgress::new();
+    let bars = std::cell::RefCell::new(std::collections::HashMap::new());
+    let results = video::save_batch(
+        &videos,
+        &args.dir,
+        &opts,
+        |h, v| work_dir(&h.id(), v),
+        |i, p| {
+            let mut bars = bars.borrow_mut();
+            let pb = bars.entry(i).or_insert_with(|| {
+                let pb = match p {
+                    video::SaveProgress::Bytes(..) => pbar::new_bytes(0),
+                    _ => pbar::new(0),
+                };
+                mp.add(pb.with_prefix(names[i].clone()))
+            });
+            match p {
+                video::SaveProgress::Converting => {
+                    pb.set_prefix(format!("{} (converting)", names[i]))
+                }
+                p => pbar::set_save_progress(pb, p),
+            }
+        },
+        |i, r| {
+            if let Some(pb) = bars.borrow_mut().remove(&i) {
+                pb.finish_and_clear();
+                mp.remove(&pb);
+            }
+            let line = match r {
+                video::BatchStatus::Saved(p) => format!("{GR}✓{GR:#} {}", p.display()),
+                video::BatchStatus::Skipped(p) => format!("{D}- {} (已存在){D:#}", p.display()),
+                video::BatchStatus::Failed(e) => format!("{RD}✗ {}: {e:#}{RD:#}", names[i]),
+            };
+            // MultiProgress drops printed lines when it is hidden, e.g. not on a terminal
+            if mp.is_hidden() {
+                println!("{line}");
+            } else {
+                mp.println(line).ok();
+            }
+            let v = &videos[i];
+            track_download_dir(v.cache_store(), v.course(), &v.id());
+        },
+    )
+    .await?;
+
+    let count = |f: fn(&video::BatchStatus) -> bool| results.iter().filter(|r| f(r)).count();
+    let saved = count(|r| matches!(r, video::BatchStatus::Saved(_)));
+    let skipped = count(|r| matches!(r, video::BatchStatus::Skipped(_)));
+    let failed = count(|r| matches!(r, video::BatchStatus::Failed(_)));
+    println!("下载完成: {saved} 个已保存, {skipped} 个已存在, {failed} 个失败");
+    anyhow::ensure!(failed == 0, "{failed} of {} replays failed", results.len());
     Ok(())
 }
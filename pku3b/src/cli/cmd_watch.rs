@@ -0,0 +1,191 @@
+use anyhow::Context;
+
+use super::*;
+
+/// Delay before the first retry after a failed poll. Doubles on each consecutive failure.
+const RETRY_INITIAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+pub async fn run(
+    interval: std::time::Duration,
+    course: Option<&str>,
+    sinks: Vec<watch::Sink>,
+    once: bool,
+    cur_term: bool,
+) -> anyhow::Result<()> {
+    let store = snapshot::SnapshotStore::default();
+    let mut backoff = watch::Backoff::new(RETRY_INITIAL, interval.max(RETRY_INITIAL));
+    let mut session = None;
+
+    log::info!(
+        "watching with {} sink(s), interval {interval:?}",
+        sinks.len()
+    );
+    loop {
+        let delay = match poll(&mut session, &store, course, cur_term).await {
+            Ok(polled) => {
+                backoff.reset();
+                let failed = deliver(polled, &store, &sinks).await?;
+                if once && failed > 0 {
+                    anyhow::bail!("{failed} course(s) failed");
+                }
+                interval
+            }
+            Err(e) => {
+                // the session may have expired, log in again on the next attempt
+                session = None;
+                let delay = backoff.fail();
+                eprintln!(
+                    "{RD}[{}] poll failed ({} in a row): {e:#}{RD:#}",
+                    now(),
+                    backoff.failures()
+                );
+                if once {
+                    return Err(e);
+                }
+                eprintln!("{D}retry in {}s{D:#}", delay.as_secs());
+                delay
+            }
+        };
+
+        if once {
+            return Ok(());
+        }
+        compio::time::sleep(delay).await;
+    }
+}
+
+/// Send the events of each polled course, then save its snapshot. A course whose check or
+/// delivery failed keeps its old snapshot, so its changes are reported again on the next poll.
+///
+/// Returns the number of failed courses.
+async fn deliver(
+    polled: Vec<CoursePoll>,
+    store: &snapshot::SnapshotStore,
+    sinks: &[watch::Sink],
+) -> anyhow::Result<usize> {
+    let mut failed = 0;
+    let mut any_event = false;
+    for (title, r) in polled {
+        let (snapshot, events) = match r {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("{RD}[{}] check {title} failed: {e:#}{RD:#}", now());
+                failed += 1;
+                continue;
+            }
+        };
+        let Some(events) = events else {
+            log::info!("recorded baseline snapshot of {title}");
+            if let Err(e) = store.save(&snapshot).await {
+                eprintln!("{RD}[{}] save snapshot of {title}: {e:#}{RD:#}", now());
+            }
+            continue;
+        };
+        any_event |= !events.is_empty();
+        if !dispatch(&events, sinks).await? {
+            failed += 1;
+            continue;
+        }
+        if let Err(e) = store.save(&snapshot).await {
+            eprintln!("{RD}[{}] save snapshot of {title}: {e:#}{RD:#}", now());
+            failed += 1;
+        }
+    }
+    if !any_event {
+        println!("{D}[{}] no changes{D:#}", now());
+    }
+    Ok(failed)
+}
+
+fn now() -> String {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+async fn login() -> anyhow::Result<api::Blackboard> {
+    // always fetch fresh data; --offline is rejected before watch starts
+    let client = new_client(true)?;
+    let net = NET.get_or_init(Default::default);
+    let (username, password) = if net.cassette.as_ref().is_some_and(|c| c.is_replay()) {
+        // the cassette answers the login, no credentials are needed
+        Default::default()
+    } else {
+        let cfg = config::read_cfg(utils::default_config_path())
+            .await
+            .context("read config file")?;
+        (cfg.username, cfg.password)
+    };
+    client
+        .blackboard(&username, &password)
+        .await
+        .context("login to blackboard")
+}
+
+/// Title of a course and the result of comparing it with its saved snapshot.
+type CoursePoll = (
+    String,
+    anyhow::Result<(snapshot::CourseSnapshot, Option<Vec<snapshot::Event>>)>,
+);
+
+/// Check every course without saving its snapshot. Only logging in and listing courses fail the
+/// whole poll; a failed course is reported in its own entry.
+async fn poll(
+    session: &mut Option<api::Blackboard>,
+    store: &snapshot::SnapshotStore,
+    course: Option<&str>,
+    cur_term: bool,
+) -> anyhow::Result<Vec<CoursePoll>> {
+    let bb = match session {
+        Some(bb) => bb,
+        None => {
+            log::info!("logging in to blackboard...");
+            session.insert(login().await?)
+        }
+    };
+
+    let mut courses = bb
+        .get_courses(cur_term)
+        .await
+        .context("fetch course handles")?;
+    courses.retain(|c| course_matches(c.id(), c.title(), course));
+
+    let futs = courses.into_iter().map(async |c| {
+        let title = c.title().to_owned();
+        let r = async {
+            let c = c.get().await.context("fetch course")?;
+            store.compare(&c).await
+        }
+        .await;
+        (title, r)
+    });
+    let mut polled = futures_util::future::join_all(futs).await;
+    // every course failing usually means the session expired
+    if polled.iter().all(|(_, r)| r.is_err())
+        && let Some((title, Err(e))) = polled.drain(..).next()
+    {
+        return Err(e.context(format!("check {title}")));
+    }
+
+    Ok(polled)
+}
+
+/// Print the events and send them to every sink. Returns whether all sinks succeeded.
+async fn dispatch(events: &[snapshot::Event], sinks: &[watch::Sink]) -> anyhow::Result<bool> {
+    let mut outbuf = Vec::new();
+    for e in events {
+        write!(outbuf, "{D}[{}]{D:#} {BL}{}{BL:#} ", now(), e.course)?;
+        cmd_news::write_event(&mut outbuf, e)?;
+    }
+    buf_try!(@try fs::stdout().write_all(outbuf).await);
+
+    // a broken sink should not stop the daemon
+    let mut ok = true;
+    for e in events {
+        for sink in sinks {
+            if let Err(err) = sink.send(e).await {
+                eprintln!("{RD}[{}] sink {sink:?} failed: {err:#}{RD:#}", now());
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
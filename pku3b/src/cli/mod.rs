@@ -1,22 +1,40 @@
+mod cmd_announcement;
 mod cmd_assignment;
+mod cmd_cache;
+mod cmd_calendar;
+mod cmd_course;
+mod cmd_document;
+mod cmd_news;
+mod cmd_sync;
 mod cmd_video;
+mod cmd_watch;
+mod output;
 mod pbar;
 
-use crate::{api, build, config, utils, walkdir};
+use crate::{api, build, cache, cassette, config, serve, snapshot, sync, utils, video, watch};
 use anyhow::Context as _;
 use clap::{
     CommandFactory, Parser, Subcommand,
     builder::styling::{AnsiColor, Style},
 };
-use compio::{
-    buf::buf_try,
-    fs,
-    io::{AsyncWrite, AsyncWriteExt},
-};
-use futures_util::{StreamExt, future::try_join_all};
-use std::io::Write as _;
+use compio::{buf::buf_try, fs, io::AsyncWriteExt};
+use futures_util::future::try_join_all;
+use std::{io::Write as _, sync::OnceLock};
 use utils::style::*;
 
+/// Exit code of `pku3b assignment due` when some assignments are due in the window.
+pub const EXIT_DUE: i32 = 3;
+
+/// Set by the global `--offline`, `--record`, `--replay` and `--base-url` options.
+#[derive(Default)]
+struct NetOptions {
+    offline: bool,
+    cassette: Option<std::sync::Arc<cassette::Cassette>>,
+    base_url: Option<url::Url>,
+}
+
+static NET: OnceLock<NetOptions> = OnceLock::new();
+
 #[derive(Parser)]
 #[command(
     version,
@@ -32,6 +50,27 @@ use utils::style::*;
 pub struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// 列表类命令的输出格式 [默认: table]
+    #[arg(long, global = true, value_enum)]
+    output: Option<output::OutputFormat>,
+
+    /// 离线模式：不登录，所有数据从本地缓存读取（无论是否过期）
+    #[arg(long, global = true, default_value = "false")]
+    offline: bool,
+
+    /// 将所有请求和响应（已去除账号、密码、cookie 和 token）保存到目录中，用于报告问题
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<std::path::PathBuf>,
+
+    /// 使用 `--record` 保存的目录应答所有请求，不访问网络
+    #[arg(long, global = true, value_name = "DIR")]
+    replay: Option<std::path::PathBuf>,
+
+    /// 将发往教学网的请求转发到指定地址（例如本地的 fakebb 模拟服务器），用于测试。
+    /// http 地址只能指向本机
+    #[arg(long, global = true, value_name = "URL")]
+    base_url: Option<url::Url>,
 }
 
 #[derive(Subcommand)]
@@ -58,6 +97,130 @@ enum Commands {
         command: VideoCommands,
     },
 
+    /// 查看课程列表
+    #[command(visible_alias("c"), arg_required_else_help(true))]
+    Course {
+        /// 强制刷新
+        #[arg(short, long, default_value = "false")]
+        force: bool,
+
+        #[command(subcommand)]
+        command: CourseCommands,
+    },
+
+    /// 获取课程文档/下载文档附件
+    #[command(visible_alias("doc"), arg_required_else_help(true))]
+    Document {
+        /// 强制刷新
+        #[arg(short, long, default_value = "false")]
+        force: bool,
+
+        #[command(subcommand)]
+        command: DocumentCommands,
+    },
+
+    /// 获取课程公告/查看公告内容/下载公告附件
+    #[command(visible_alias("ann"), arg_required_else_help(true))]
+    Announcement {
+        /// 强制刷新
+        #[arg(short, long, default_value = "false")]
+        force: bool,
+
+        #[command(subcommand)]
+        command: AnnouncementCommands,
+    },
+
+    /// 查看课程内容树 (文档、作业、回放按栏目/文件夹组织)
+    ///
+    /// 如果没有指定课程，则会启用交互式模式，列出所有课程供用户选择
+    Tree {
+        /// 强制刷新
+        #[arg(short, long, default_value = "false")]
+        force: bool,
+        /// 课程 ID 或课程名的一部分
+        course: Option<String>,
+        /// 在所有学期的课程范围中查找
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+
+    /// 查看自上次运行以来课程的变化 (新增/删除/修改的内容、作业、公告、回放，以及截止时间变更)
+    News {
+        /// 强制刷新
+        #[arg(short, long, default_value = "false")]
+        force: bool,
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 只查看变化，不更新保存的快照
+        #[arg(long, default_value = "false")]
+        peek: bool,
+        /// 包括所有学期的课程
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+
+    /// 持续运行，定期检查课程变化并推送到本地命令、webhook 或事件日志
+    ///
+    /// 变化的检测方式与 `pku3b news` 相同，两者共享保存的课程快照
+    Watch {
+        /// 检查间隔 (形如 `15m`, `1h`)
+        #[arg(short, long, default_value = "15m", value_parser = utils::parse_duration)]
+        interval: std::time::Duration,
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 对每个事件执行的 shell 命令，事件 JSON 通过标准输入传入，可多次指定
+        #[arg(long)]
+        exec: Vec<String>,
+        /// 对每个事件 POST 事件 JSON 的 URL，可多次指定
+        #[arg(long)]
+        webhook: Vec<String>,
+        /// 以 JSONL 格式追加记录事件的文件，可多次指定
+        #[arg(long)]
+        log: Vec<std::path::PathBuf>,
+        /// 只检查一次后退出
+        #[arg(long, default_value = "false")]
+        once: bool,
+        /// 包括所有学期的课程
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+
+    /// 将课程内容 (文档、作业附件、说明、公告) 增量同步到本地目录
+    ///
+    /// 每门课程对应一个子目录，其中的清单文件记录已同步的内容，再次同步时只下载新增或变化的文件
+    Sync {
+        /// 强制刷新
+        #[arg(short, long, default_value = "false")]
+        force: bool,
+        /// 只同步指定课程 (课程 ID 或课程名的一部分)，缺省时同步所有课程
+        course: Option<String>,
+        /// 同步目录 (支持相对路径)
+        #[arg(short, long, default_value = ".")]
+        dir: std::path::PathBuf,
+        /// 删除远端已移除的文件
+        #[arg(long, default_value = "false")]
+        prune: bool,
+        /// 只显示将要进行的变化，不写入任何文件
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+        /// 同步所有学期的课程
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+
+    /// 导出作业截止时间 (以及课程回放时间) 为 iCalendar 日历文件
+    #[command(visible_alias("cal"), arg_required_else_help(true))]
+    Calendar {
+        /// 强制刷新
+        #[arg(short, long, default_value = "false")]
+        force: bool,
+
+        #[command(subcommand)]
+        command: CalendarCommands,
+    },
+
     /// (重新) 初始化配置选项
     Init,
 
@@ -69,7 +232,7 @@ enum Commands {
         value: Option<String>,
     },
 
-    /// 查看缓存大小/清除缓存
+    /// 查看/删除缓存
     Cache {
         #[command(subcommand)]
         command: Option<CacheCommands>,
@@ -91,9 +254,96 @@ enum VideoCommands {
     },
 
     /// 下载课程回放视频 (MP4 格式)，支持断点续传
+    ///
+    /// 指定 `--course` 时批量下载该课程中符合条件的回放，已经存在的文件会被跳过
     #[command(visible_alias("down"))]
     Download {
         /// 课程回放 ID (形如 `e780808c9eb81f61`, 可通过 `pku3b video list` 查看)
+        #[arg(required_unless_present = "course", conflicts_with = "course")]
+        id: Option<String>,
+        /// 批量下载指定课程的回放 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 只下载此后开始的回放 (形如 `2024-09-01`, 或 `2w` 表示最近两周)
+        #[arg(long, value_parser = utils::parse_since, requires = "course", value_name = "DATE")]
+        since: Option<chrono::DateTime<chrono::Local>>,
+        /// 只下载最近的 N 个回放
+        #[arg(long, requires = "course", value_name = "N")]
+        latest: Option<usize>,
+        /// 下载课程的全部回放
+        #[arg(long, default_value = "false", requires = "course")]
+        all: bool,
+        /// 批量下载时同时下载的回放数量
+        #[arg(
+            short,
+            long,
+            default_value = "2",
+            value_name = "N",
+            requires = "course"
+        )]
+        parallel: usize,
+        /// 文件保存目录 (支持相对路径)
+        #[arg(short, long, default_value = ".")]
+        dir: std::path::PathBuf,
+        /// 文件名模板 (不含扩展名)，可用 {course} {title} {teacher} {date} {time} {id}，
+        /// 缺省为 `{course}_{title}`，批量下载时为 `{course}_{title}_{date}`
+        #[arg(long, value_name = "TEMPLATE")]
+        name: Option<String>,
+        /// 在所有学期的课程回放范围中查找
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+        /// 同时下载的分片数量
+        #[arg(short, long, default_value = "4", value_name = "N")]
+        jobs: usize,
+        /// 每个分片下载失败后的最多重试次数
+        #[arg(long, default_value = "3", value_name = "N")]
+        retries: u32,
+        /// 使用 ffmpeg 而不是内置的转封装器生成 mp4
+        #[arg(long, default_value = "false")]
+        ffmpeg: bool,
+        /// 清晰度: best (码率最高), smallest (码率最低) 或形如 `720p`、`1280x720` 的名称
+        #[arg(short, long, default_value = "best", value_name = "QUALITY")]
+        quality: api::Quality,
+        /// 视频流名称 (如屏幕画面) 或序号, 可通过 `pku3b video streams` 查看，缺省为第一路
+        #[arg(short, long, value_name = "STREAM")]
+        stream: Option<String>,
+        /// 只提取音频 (例如用于语音转写)，不生成视频文件
+        #[arg(long, default_value = "false", conflicts_with = "ffmpeg")]
+        audio_only: bool,
+        /// 提取音频时的输出格式
+        #[arg(long, value_enum, default_value_t = cmd_video::AudioFormat::M4a, requires = "audio_only")]
+        audio_format: cmd_video::AudioFormat,
+        /// 导出为可离线播放的 HLS 目录 (解密后的分片和播放列表)，可直接用播放器打开并跳转，不转换为 mp4
+        #[arg(long, default_value = "false", conflicts_with_all = ["audio_only", "ffmpeg"])]
+        hls: bool,
+    },
+
+    /// 在本地启动 HLS 服务器，用任意播放器 (mpv、VLC 等) 边下边看课程回放
+    ///
+    /// 分片在播放器请求时下载并解密，已经下载过的分片会从缓存中读取
+    Serve {
+        /// 课程回放 ID (可通过 `pku3b video list` 查看)
+        id: String,
+        /// 在所有学期的课程回放范围中查找
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+        /// 监听的端口，缺省时由系统分配
+        #[arg(short, long, default_value = "0")]
+        port: u16,
+        /// 每个分片下载失败后的最多重试次数
+        #[arg(long, default_value = "3", value_name = "N")]
+        retries: u32,
+        /// 清晰度: best (码率最高), smallest (码率最低) 或形如 `720p`、`1280x720` 的名称
+        #[arg(short, long, default_value = "best", value_name = "QUALITY")]
+        quality: api::Quality,
+        /// 视频流名称 (如屏幕画面) 或序号, 可通过 `pku3b video streams` 查看，缺省为第一路
+        #[arg(short, long, value_name = "STREAM")]
+        stream: Option<String>,
+    },
+
+    /// 查看课程回放的视频流和清晰度
+    Streams {
+        /// 课程回放 ID (可通过 `pku3b video list` 查看)
         id: String,
         /// 在所有学期的课程回放范围中查找
         #[arg(long, default_value = "false")]
@@ -101,12 +351,148 @@ enum VideoCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum CourseCommands {
+    /// 查看课程列表
+    #[command(visible_alias("ls"))]
+    List {
+        /// 显示所有学期的课程
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DocumentCommands {
+    /// 查看课程文档列表
+    #[command(visible_alias("ls"))]
+    List {
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 显示所有学期的课程文档
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+    /// 下载文档附件到指定文件夹下
+    ///
+    /// 如果没有指定文档 ID，则会启用交互式模式，列出所有带附件的文档供用户选择
+    #[command(visible_alias("down"))]
+    Download {
+        /// (Optional) 文档 ID (可通过 `pku3b doc list` 查看)
+        id: Option<String>,
+        /// 文件下载目录 (支持相对路径)
+        #[arg(short, long, default_value = ".")]
+        dir: std::path::PathBuf,
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 在所有学期的课程文档范围中查找
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AnnouncementCommands {
+    /// 查看课程公告列表，按发布时间倒序排列
+    #[command(visible_alias("ls"))]
+    List {
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 显示所有学期的课程公告
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+    /// 查看公告内容
+    ///
+    /// 如果没有指定公告 ID，则会启用交互式模式，列出所有公告供用户选择
+    Show {
+        /// (Optional) 公告 ID (可通过 `pku3b ann list` 查看)
+        id: Option<String>,
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 在所有学期的课程公告范围中查找
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+    /// 下载公告附件到指定文件夹下
+    ///
+    /// 如果没有指定公告 ID，则会启用交互式模式，列出所有带附件的公告供用户选择
+    #[command(visible_alias("down"))]
+    Download {
+        /// (Optional) 公告 ID (可通过 `pku3b ann list` 查看)
+        id: Option<String>,
+        /// 文件下载目录 (支持相对路径)
+        #[arg(short, long, default_value = ".")]
+        dir: std::path::PathBuf,
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 在所有学期的课程公告范围中查找
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CalendarCommands {
+    /// 导出 .ics 日历文件，可导入 Apple/Google/Outlook 日历等
+    Export {
+        /// 输出文件路径，缺省时输出到标准输出
+        path: Option<std::path::PathBuf>,
+        /// 包括已完成的作业
+        #[arg(short, long, default_value = "false")]
+        all: bool,
+        /// 同时导出课程回放时间
+        #[arg(long, default_value = "false")]
+        videos: bool,
+        /// 以待办事项 (VTODO) 而非日程 (VEVENT) 导出作业
+        #[arg(long, default_value = "false")]
+        todo: bool,
+        /// 截止前提醒 (形如 `1d`, `2h`, `30m`)，可多次指定
+        #[arg(long, value_parser = utils::parse_duration)]
+        alarm: Vec<std::time::Duration>,
+        /// 导出所有学期的课程
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
+}
+
 #[derive(Subcommand)]
 enum CacheCommands {
     /// 查看缓存大小
     Show,
     /// 清除缓存
     Clean,
+    /// 列出缓存条目
+    #[command(visible_alias("ls"))]
+    List {
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 只看指定类型 (如 `video_segment`, `assignment`)
+        #[arg(short, long)]
+        kind: Option<String>,
+        /// 只看创建时间早于该时长之前的条目 (形如 `2d`, `1w`)
+        #[arg(long, value_parser = utils::parse_duration)]
+        older_than: Option<std::time::Duration>,
+    },
+    /// 删除匹配的缓存条目
+    #[command(visible_alias("rm"))]
+    Remove {
+        /// 删除指定课程的缓存 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 删除指定类型的缓存
+        #[arg(short, long)]
+        kind: Option<String>,
+        /// 删除创建时间早于该时长之前的条目 (形如 `2d`, `1w`)
+        #[arg(long, value_parser = utils::parse_duration)]
+        older_than: Option<std::time::Duration>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -121,6 +507,23 @@ enum AssignmentCommands {
         #[arg(long, default_value = "false")]
         all_term: bool,
     },
+    /// 列出指定时间内截止的未完成作业，供脚本/状态栏使用
+    ///
+    /// 存在这样的作业时以退出码 3 退出，否则以 0 退出
+    Due {
+        /// 时间窗口 (形如 `48h`, `2d`, `1w`)
+        #[arg(short, long, default_value = "48h", value_parser = utils::parse_duration)]
+        within: std::time::Duration,
+        /// 只看指定课程 (课程 ID 或课程名的一部分)
+        #[arg(short, long)]
+        course: Option<String>,
+        /// 输出格式，缺省时使用全局的 `--output`，都没有指定时为 plain
+        #[arg(long, value_enum)]
+        format: Option<cmd_assignment::DueFormat>,
+        /// 包括所有学期的作业
+        #[arg(long, default_value = "false")]
+        all_term: bool,
+    },
     /// 下载作业要求和附件到指定文件夹下
     ///
     /// 如果没有指定作业 ID，则会启用交互式模式，列出所有作业供用户选择
@@ -150,30 +553,67 @@ enum AssignmentCommands {
     },
 }
 
+/// The cache used by clients of this run, separate from the global one with `--base-url`.
+fn cache_store() -> &'static cache::CacheStore {
+    match &NET.get_or_init(Default::default).base_url {
+        Some(base) => cache::CacheStore::for_base_url(base),
+        None => cache::CacheStore::global(),
+    }
+}
+
+/// Build a client honoring `--force` and the global network options.
+fn new_client(force: bool) -> anyhow::Result<api::Client> {
+    let net = NET.get_or_init(Default::default);
+    anyhow::ensure!(
+        !(net.offline && force),
+        "--force cannot be used together with --offline"
+    );
+
+    let client = if net.offline {
+        api::Client::offline()
+    } else if force || net.cassette.is_some() {
+        // a cassette should capture every request instead of cached pages
+        api::Client::new_nocache()
+    } else {
+        api::Client::default()
+    };
+    let client = match &net.base_url {
+        Some(base) => client.with_base_url(base.clone())?,
+        None => client,
+    };
+    Ok(match &net.cassette {
+        Some(c) => client.with_cassette(c.clone()),
+        None => client,
+    })
+}
+
 /// Client, courses and spinner are returned. Spinner hasn't stopped.
 async fn load_client_courses(
     force: bool,
     only_current: bool,
 ) -> anyhow::Result<(api::Client, Vec<api::CourseHandle>, pbar::AsyncSpinner)> {
-    let client = if force {
-        api::Client::new_nocache()
-    } else {
-        api::Client::default()
-    };
+    let client = new_client(force)?;
 
     let sp = pbar::new_spinner();
 
-    sp.set_message("reading config...");
-    let cfg_path = utils::default_config_path();
-    let cfg = config::read_cfg(cfg_path)
-        .await
-        .context("read config file")?;
-
-    sp.set_message("logging in to blackboard...");
-    let blackboard = client
-        .blackboard(&cfg.username, &cfg.password)
-        .await
-        .context("login to blackboard")?;
+    let net = NET.get_or_init(Default::default);
+    let replay = net.cassette.as_ref().is_some_and(|c| c.is_replay());
+    let blackboard = if net.offline || replay {
+        // no credentials are needed: login is skipped offline and answered by the cassette on replay
+        client.blackboard("", "").await?
+    } else {
+        sp.set_message("reading config...");
+        let cfg_path = utils::default_config_path();
+        let cfg = config::read_cfg(cfg_path)
+            .await
+            .context("read config file")?;
+
+        sp.set_message("logging in to blackboard...");
+        client
+            .blackboard(&cfg.username, &cfg.password)
+            .await
+            .context("login to blackboard")?
+    };
 
     sp.set_message("fetching courses...");
     let courses = blackboard
@@ -189,6 +629,39 @@ async fn load_courses(force: bool, only_current: bool) -> anyhow::Result<Vec<api
     Ok(r)
 }
 
+/// Whether a course matches the `--course` filter, i.e. the course id or part of its title.
+fn course_matches(id: &str, title: &str, query: Option<&str>) -> bool {
+    query.is_none_or(|q| id == q || title.to_lowercase().contains(&q.to_lowercase()))
+}
+
+/// Load the handles of courses matching the `--course` filter.
+async fn load_courses_matching(
+    force: bool,
+    only_current: bool,
+    course: Option<&str>,
+) -> anyhow::Result<Vec<api::CourseHandle>> {
+    let mut courses = load_courses(force, only_current).await?;
+    courses.retain(|c| course_matches(c.id(), c.title(), course));
+    if let Some(q) = course
+        && courses.is_empty()
+    {
+        anyhow::bail!("course matching {q:?} not found");
+    }
+    Ok(courses)
+}
+
+/// Let the user choose one of the options interactively, returning its index.
+fn select_index(message: &str, options: Vec<String>) -> anyhow::Result<usize> {
+    anyhow::ensure!(!options.is_empty(), "nothing to select");
+    let options = options
+        .into_iter()
+        .enumerate()
+        .map(|(idx, s)| format!("[{}] {s}", idx + 1))
+        .collect();
+    let s = inquire::Select::new(message, options).raw_prompt()?;
+    Ok(s.index)
+}
+
 async fn command_config(
     attr: Option<config::ConfigAttrs>,
     value: Option<String>,
@@ -225,72 +698,90 @@ async fn command_init() -> anyhow::Result<()> {
     let username = inquire::Text::new("Enter PKU IAAA Username (ID):").prompt()?;
     let password = inquire::Password::new("Enter PKU IAAA Password:").prompt()?;
 
-    let cfg = config::Config { username, password };
+    let cfg = config::Config {
+        username,
+        password,
+        cache_limit: None,
+    };
     config::write_cfg(&cfg_path, &cfg).await?;
 
     println!("Configuration initialized.");
     Ok(())
 }
 
-async fn command_cache_clean(dry_run: bool) -> anyhow::Result<()> {
-    let dir = utils::projectdir();
-    log::info!("Cache dir: '{}'", dir.cache_dir().display());
-    let sp = pbar::new_spinner();
-    sp.set_message("scanning cache dir...");
-
-    let mut total_bytes = 0;
-    if dir.cache_dir().exists() {
-        let d = std::fs::read_dir(dir.cache_dir())?;
-
-        let mut s = walkdir::walkdir(d, false);
-        while let Some(e) = s.next().await {
-            let e = e?;
-            #[cfg(unix)]
-            let s = {
-                use std::os::unix::fs::MetadataExt;
-                e.metadata()?.size()
-            };
-            #[cfg(windows)]
-            let s = {
-                use std::os::windows::fs::MetadataExt;
-                e.metadata()?.file_size()
-            };
-            total_bytes += s;
-        }
-
-        if !dry_run {
-            std::fs::remove_dir_all(dir.cache_dir())?;
+pub async fn start(cli: Cli) -> anyhow::Result<()> {
+    let cli_output = cli.output;
+    let output = cli_output.unwrap_or_default();
+    let cassette = match (&cli.record, &cli.replay) {
+        (Some(dir), _) => Some(cassette::Cassette::record(dir)?),
+        (_, Some(dir)) => Some(cassette::Cassette::replay(dir)?),
+        _ => None,
+    };
+    let _ = NET.set(NetOptions {
+        offline: cli.offline,
+        cassette: cassette.map(Into::into),
+        base_url: cli.base_url,
+    });
+    if let Ok(cfg) = config::read_cfg(utils::default_config_path()).await {
+        match cfg.cache_limit() {
+            Ok(limit) => cache_store().set_limit(limit),
+            Err(e) => log::warn!("invalid cache_limit in config: {e:#}"),
         }
     }
-    drop(sp);
-
-    let sizenum = total_bytes as f64 / 1024.0f64.powi(3);
-    if dry_run {
-        println!("缓存大小: {B}{:.2}GB{B:#}", sizenum);
-    } else {
-        println!("缓存已清空 (释放 {B}{:.2}GB{B:#})", sizenum);
-    }
-    Ok(())
-}
-
-pub async fn start(cli: Cli) -> anyhow::Result<()> {
     if let Some(command) = cli.command {
         match command {
             Commands::Config { attr, value } => command_config(attr, value).await?,
             Commands::Init => command_init().await?,
-            Commands::Cache { command } => {
-                if let Some(command) = command {
-                    match command {
-                        CacheCommands::Clean => command_cache_clean(false).await?,
-                        CacheCommands::Show => command_cache_clean(true).await?,
-                    }
-                } else {
-                    command_cache_clean(true).await?
+            Commands::Cache { command } => match command.unwrap_or(CacheCommands::Show) {
+                CacheCommands::Show => cmd_cache::show().await?,
+                CacheCommands::Clean => cmd_cache::clean().await?,
+                CacheCommands::List {
+                    course,
+                    kind,
+                    older_than,
+                } => {
+                    let filter = cache::Filter {
+                        course,
+                        kind,
+                        older_than,
+                    };
+                    cmd_cache::list(&filter, output).await?
                 }
-            }
+                CacheCommands::Remove {
+                    course,
+                    kind,
+                    older_than,
+                } => {
+                    let filter = cache::Filter {
+                        course,
+                        kind,
+                        older_than,
+                    };
+                    cmd_cache::remove(&filter).await?
+                }
+            },
             Commands::Assignment { force, command } => match command {
                 AssignmentCommands::List { all, all_term } => {
-                    cmd_assignment::list(force, all || all_term, !all_term).await?
+                    cmd_assignment::list(force, all || all_term, !all_term, output).await?
+                }
+                AssignmentCommands::Due {
+                    within,
+                    course,
+                    format,
+                    all_term,
+                } => {
+                    let any = cmd_assignment::due(
+                        force,
+                        within,
+                        course.as_deref(),
+                        format,
+                        cli_output,
+                        !all_term,
+                    )
+                    .await?;
+                    if any {
+                        std::process::exit(EXIT_DUE);
+                    }
                 }
                 AssignmentCommands::Download { id, dir, all_term } => {
                     cmd_assignment::download(id.as_deref(), &dir, force, all_term, !all_term)
@@ -300,10 +791,177 @@ pub async fn start(cli: Cli) -> anyhow::Result<()> {
                     cmd_assignment::submit(id.as_deref(), path.as_deref()).await?
                 }
             },
+            Commands::Course { force, command } => match command {
+                CourseCommands::List { all_term } => {
+                    cmd_course::list(force, !all_term, output).await?
+                }
+            },
+            Commands::Document { force, command } => match command {
+                DocumentCommands::List { course, all_term } => {
+                    cmd_document::list(force, !all_term, course.as_deref(), output).await?
+                }
+                DocumentCommands::Download {
+                    id,
+                    dir,
+                    course,
+                    all_term,
+                } => {
+                    cmd_document::download(id.as_deref(), &dir, force, !all_term, course.as_deref())
+                        .await?
+                }
+            },
+            Commands::Announcement { force, command } => match command {
+                AnnouncementCommands::List { course, all_term } => {
+                    cmd_announcement::list(force, !all_term, course.as_deref(), output).await?
+                }
+                AnnouncementCommands::Show {
+                    id,
+                    course,
+                    all_term,
+                } => {
+                    cmd_announcement::show(id.as_deref(), force, !all_term, course.as_deref())
+                        .await?
+                }
+                AnnouncementCommands::Download {
+                    id,
+                    dir,
+                    course,
+                    all_term,
+                } => {
+                    cmd_announcement::download(
+                        id.as_deref(),
+                        &dir,
+                        force,
+                        !all_term,
+                        course.as_deref(),
+                    )
+                    .await?
+                }
+            },
+            Commands::Tree {
+                force,
+                course,
+                all_term,
+            } => cmd_course::tree(force, course.as_deref(), !all_term, output).await?,
+            Commands::News {
+                force,
+                course,
+                peek,
+                all_term,
+            } => cmd_news::run(force, course.as_deref(), !peek, !all_term, output).await?,
+            Commands::Watch {
+                interval,
+                course,
+                exec,
+                webhook,
+                log,
+                once,
+                all_term,
+            } => {
+                let sinks = (exec.into_iter().map(watch::Sink::Command))
+                    .chain(webhook.into_iter().map(watch::Sink::Webhook))
+                    .chain(log.into_iter().map(watch::Sink::Log))
+                    .collect();
+                // the cache never changes offline, so there would be nothing to watch
+                anyhow::ensure!(!cli.offline, "watch cannot run with --offline");
+                cmd_watch::run(interval, course.as_deref(), sinks, once, !all_term).await?
+            }
+            Commands::Sync {
+                force,
+                course,
+                dir,
+                prune,
+                dry_run,
+                all_term,
+            } => {
+                let opts = sync::SyncOptions { prune, dry_run };
+                cmd_sync::run(force, course.as_deref(), &dir, opts, !all_term).await?
+            }
+            Commands::Calendar { force, command } => match command {
+                CalendarCommands::Export {
+                    path,
+                    all,
+                    videos,
+                    todo,
+                    alarm,
+                    all_term,
+                } => {
+                    let opts = cmd_calendar::ExportOptions {
+                        all,
+                        videos,
+                        todo,
+                        alarms: alarm,
+                    };
+                    cmd_calendar::export(force, path.as_deref(), opts, !all_term).await?
+                }
+            },
             Commands::Video { force, command } => match command {
-                VideoCommands::List { all_term } => cmd_video::list(force, !all_term).await?,
-                VideoCommands::Download { id, all_term } => {
-                    cmd_video::download(force, id, !all_term).await?
+                VideoCommands::List { all_term } => {
+                    cmd_video::list(force, !all_term, output).await?
+                }
+                VideoCommands::Download {
+                    id,
+                    course,
+                    since,
+                    latest,
+                    all,
+                    parallel,
+                    dir,
+                    name,
+                    all_term,
+                    jobs,
+                    retries,
+                    ffmpeg,
+                    quality,
+                    stream,
+                    audio_only,
+                    audio_format,
+                    hls,
+                } => {
+                    let args = cmd_video::DownloadArgs {
+                        opts: video::DownloadOptions {
+                            jobs,
+                            retries,
+                            ..Default::default()
+                        },
+                        selector: api::VideoSelector { stream, quality },
+                        ffmpeg,
+                        audio: audio_only.then_some(audio_format),
+                        hls,
+                        dir,
+                        name,
+                    };
+                    match (id, course) {
+                        (Some(id), _) => cmd_video::download(force, id, !all_term, args).await?,
+                        (None, Some(course)) => {
+                            let select = cmd_video::BatchSelect {
+                                course,
+                                filter: video::VideoFilter { since, latest },
+                                all,
+                                parallel,
+                            };
+                            cmd_video::download_batch(force, !all_term, select, args).await?
+                        }
+                        (None, None) => unreachable!("id is required unless course is present"),
+                    }
+                }
+                VideoCommands::Serve {
+                    id,
+                    all_term,
+                    port,
+                    retries,
+                    quality,
+                    stream,
+                } => {
+                    let opts = video::DownloadOptions {
+                        retries,
+                        ..Default::default()
+                    };
+                    let selector = api::VideoSelector { stream, quality };
+                    cmd_video::serve(force, id, !all_term, port, opts, selector).await?
+                }
+                VideoCommands::Streams { id, all_term } => {
+                    cmd_video::streams(force, id, !all_term, output).await?
                 }
             },
 
@@ -0,0 +1,213 @@
+//! 列表类命令的输出格式
+//!
+//! 彩色的人类可读格式 (`table`) 由各个命令自行渲染，其余格式基于 [`api`] 中的视图模型统一输出。
+
+use super::*;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// 彩色的人类可读格式
+    #[default]
+    Table,
+    /// 无颜色，每条记录一行，字段以制表符分隔
+    Plain,
+    /// JSON 数组
+    Json,
+    /// 每行一个 JSON 对象
+    Ndjson,
+}
+
+/// 可以输出为一行纯文本的记录
+pub trait PlainRecord: serde::Serialize {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()>;
+}
+
+/// 按照指定格式将记录写到标准输出。`table` 格式使用 `table` 回调渲染.
+pub async fn write_records<T: PlainRecord>(
+    format: OutputFormat,
+    records: &[T],
+    table: impl FnOnce(&mut Vec<u8>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut outbuf = Vec::new();
+    match format {
+        OutputFormat::Table => table(&mut outbuf)?,
+        OutputFormat::Plain => {
+            for r in records {
+                r.write_plain(&mut outbuf)?;
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut outbuf, records)?;
+            writeln!(outbuf)?;
+        }
+        OutputFormat::Ndjson => {
+            for r in records {
+                serde_json::to_writer(&mut outbuf, r)?;
+                writeln!(outbuf)?;
+            }
+        }
+    }
+
+    buf_try!(@try fs::stdout().write_all(outbuf).await);
+    Ok(())
+}
+
+/// 纯文本字段中不能出现制表符和换行
+fn field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains(['\t', '\n', '\r']) {
+        s.replace(['\t', '\n', '\r'], " ").into()
+    } else {
+        s.into()
+    }
+}
+
+fn opt_time(t: Option<&chrono::DateTime<chrono::FixedOffset>>, raw: Option<&str>) -> String {
+    match (t, raw) {
+        (Some(t), _) => t.to_rfc3339(),
+        (None, Some(raw)) => field(raw).into_owned(),
+        (None, None) => "-".to_owned(),
+    }
+}
+
+impl PlainRecord for api::CourseView {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        let term = if self.is_current {
+            "current"
+        } else {
+            "history"
+        };
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{term}",
+            self.id,
+            field(&self.name),
+            field(&self.title)
+        )
+    }
+}
+
+impl PlainRecord for api::AssignmentView {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        let status = if self.is_finished() {
+            "finished"
+        } else {
+            "unfinished"
+        };
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{}\t{status}",
+            self.id,
+            field(&self.course),
+            field(&self.title),
+            opt_time(self.deadline.as_ref(), self.deadline_raw.as_deref()),
+        )
+    }
+}
+
+impl PlainRecord for api::VideoView {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{}\t{}",
+            self.id,
+            field(&self.course),
+            field(&self.title),
+            opt_time(self.start_time.as_ref(), Some(&self.time)),
+            field(&self.teacher),
+        )
+    }
+}
+
+impl PlainRecord for api::VideoVariant {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{}\t{}",
+            field(&self.stream),
+            self.kind.as_str(),
+            self.quality().as_deref().unwrap_or("-"),
+            self.bandwidth.map_or("-".to_owned(), |b| b.to_string()),
+            self.url,
+        )
+    }
+}
+
+impl PlainRecord for api::DocumentView {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{}\t{}",
+            self.id,
+            field(&self.course),
+            field(self.section.as_deref().unwrap_or("-")),
+            field(&self.title),
+            self.attachments.len(),
+        )
+    }
+}
+
+impl PlainRecord for api::AnnouncementView {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{}",
+            self.id,
+            field(&self.course),
+            opt_time(self.time.as_ref(), self.time_raw.as_deref()),
+            field(&self.title),
+        )
+    }
+}
+
+impl PlainRecord for api::TreeNodeView {
+    /// 每个结点一行：深度、类型、ID、标题
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        fn dfs(n: &api::TreeNodeView, depth: usize, buf: &mut Vec<u8>) -> std::io::Result<()> {
+            writeln!(buf, "{depth}\t{:?}\t{}\t{}", n.kind, n.id, field(&n.title))?;
+            for c in &n.children {
+                dfs(c, depth + 1, buf)?;
+            }
+            Ok(())
+        }
+        dfs(self, 0, buf)
+    }
+}
+
+impl PlainRecord for snapshot::Event {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        let change = match self.change {
+            snapshot::EventKind::Added => "added",
+            snapshot::EventKind::Removed => "removed",
+            snapshot::EventKind::Modified => "modified",
+            snapshot::EventKind::DeadlineChanged => "deadline_changed",
+        };
+        let item = match self.item {
+            snapshot::ItemKind::Content => "content",
+            snapshot::ItemKind::Assignment => "assignment",
+            snapshot::ItemKind::Announcement => "announcement",
+            snapshot::ItemKind::Video => "video",
+        };
+        writeln!(
+            buf,
+            "{change}\t{item}\t{}\t{}\t{}",
+            field(&self.course),
+            field(&self.title),
+            opt_time(self.deadline.as_ref(), None),
+        )
+    }
+}
+
+impl PlainRecord for cache::CacheEntry {
+    fn write_plain(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.kind,
+            self.course_id.as_deref().unwrap_or("-"),
+            field(self.course.as_deref().unwrap_or("-")),
+            self.size,
+            self.created.to_rfc3339(),
+            field(&self.key)
+        )
+    }
+}
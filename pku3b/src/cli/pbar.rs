@@ -46,9 +46,40 @@ pub fn new_spinner() -> AsyncSpinner {
     AsyncSpinner { pb, ticker }
 }
 
+/// Create a new progress bar counting bytes, the length may be set later
+pub fn new_bytes(pb_len: u64) -> ProgressBar {
+    let pb = ProgressBar::new(pb_len);
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{prefix} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ",
+        )
+        .unwrap()
+        .progress_chars("=> "),
+    );
+    pb
+}
+
 /// Create a new progress bar with a given length and a default style
 pub fn new(pb_len: u64) -> ProgressBar {
     let pb = ProgressBar::new(pb_len);
     pb.set_style(pb_style());
     pb
 }
+
+/// Update a bar created by [`new`] or [`new_bytes`] with the progress of saving a replay
+pub fn set_save_progress(pb: &ProgressBar, p: crate::video::SaveProgress) {
+    use crate::video::SaveProgress;
+    match p {
+        SaveProgress::Segments(done, total) => {
+            pb.set_length(total as u64);
+            pb.set_position(done as u64);
+        }
+        SaveProgress::Bytes(done, total) => {
+            if let Some(total) = total {
+                pb.set_length(total);
+            }
+            pb.set_position(done);
+        }
+        SaveProgress::Converting => {}
+    }
+}
@@ -4,6 +4,9 @@ use compio::fs;
 pub struct Config {
     pub username: String,
     pub password: String,
+    /// 缓存容量上限 (形如 `2G`, `512M`)，默认为 [`crate::cache::DEFAULT_SIZE_LIMIT`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_limit: Option<String>,
 }
 
 impl Config {
@@ -12,14 +15,29 @@ impl Config {
         match attr {
             ConfigAttrs::Username => writeln!(buf, "{}", self.username)?,
             ConfigAttrs::Password => writeln!(buf, "{}", self.password)?,
+            ConfigAttrs::CacheLimit => {
+                writeln!(buf, "{}", self.cache_limit.as_deref().unwrap_or(""))?
+            }
         };
         Ok(())
     }
 
+    /// 缓存容量上限 (字节)
+    pub fn cache_limit(&self) -> anyhow::Result<u64> {
+        match &self.cache_limit {
+            Some(s) => crate::utils::parse_size(s),
+            None => Ok(crate::cache::DEFAULT_SIZE_LIMIT),
+        }
+    }
+
     pub fn update(&mut self, attr: ConfigAttrs, value: String) -> anyhow::Result<()> {
         match attr {
             ConfigAttrs::Username => self.username = value,
             ConfigAttrs::Password => self.password = value,
+            ConfigAttrs::CacheLimit => {
+                crate::utils::parse_size(&value)?;
+                self.cache_limit = Some(value);
+            }
         }
 
         Ok(())
@@ -30,17 +48,19 @@ impl Config {
 pub enum ConfigAttrs {
     Username,
     Password,
+    CacheLimit,
 }
 
 impl clap::ValueEnum for ConfigAttrs {
     fn value_variants<'a>() -> &'a [Self] {
-        &[Self::Username, Self::Password]
+        &[Self::Username, Self::Password, Self::CacheLimit]
     }
 
     fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
         match self {
             Self::Username => Some(clap::builder::PossibleValue::new("username")),
             Self::Password => Some(clap::builder::PossibleValue::new("password")),
+            Self::CacheLimit => Some(clap::builder::PossibleValue::new("cache-limit")),
         }
     }
 }
@@ -72,10 +92,10 @@ pub async fn read_cfg(path: impl AsRef<std::path::Path>) -> anyhow::Result<Confi
 pub async fn write_cfg(path: impl AsRef<std::path::Path>, cfg: &Config) -> anyhow::Result<()> {
     let path = path.as_ref();
     // Create the parent directory if it does not exist
-    if let Some(par) = path.parent() {
-        if !par.exists() {
-            fs::create_dir_all(par).await?;
-        }
+    if let Some(par) = path.parent()
+        && !par.exists()
+    {
+        fs::create_dir_all(par).await?;
     }
 
     let content = toml::to_string(cfg)?;
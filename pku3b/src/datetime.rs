@@ -0,0 +1,275 @@
+//! 教学网时间字符串解析.
+//!
+//! 教学网上的时间（作业截止时间、公告发布时间、回放时间、提交时间等）格式五花八门：
+//! 中文/英文界面、12/24 小时制、有无星期、有无时区后缀。这里统一解析为带时区的
+//! [`DateTime`]。没有显式时区时，一律按北京时间 (Asia/Shanghai, UTC+8) 解释，
+//! 而不是本机时区。
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone as _};
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// 北京时间。自 1991 年起中国不再实行夏令时，固定偏移即可。
+pub const SHANGHAI: FixedOffset = match FixedOffset::east_opt(8 * 3600) {
+    Some(tz) => tz,
+    None => unreachable!(),
+};
+
+static RE_DATE_CJK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\d{4})\s*年\s*(\d{1,2})\s*月\s*(\d{1,2})\s*日").unwrap());
+
+static RE_DATE_NUM: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\d{4})[-/.](\d{1,2})[-/.](\d{1,2})").unwrap());
+
+/// `March 9, 2025` / `Mar 9 2025`
+static RE_DATE_EN_MDY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b([a-z]{3,9})\.?\s+(\d{1,2})(?:st|nd|rd|th)?,?\s+(\d{4})").unwrap()
+});
+
+/// `9 March 2025`
+static RE_DATE_EN_DMY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b(\d{1,2})\s+([a-z]{3,9})\.?,?\s+(\d{4})").unwrap());
+
+static RE_TIME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?ix)
+        (上午|下午|中午|晚上|凌晨)?\s*
+        (\d{1,2})\s*(?::|：|时)\s*(\d{1,2})
+        (?:\s*(?::|：|分)\s*(\d{1,2}))?
+        \s*(?:秒|分)?
+        (?:\s*(am|pm|a\.m\.|p\.m\.)(?:\W|$))?",
+    )
+    .unwrap()
+});
+
+static RE_OFFSET: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(?:GMT|UTC)(?:\s*([+-])\s*(\d{1,2})(?::?(\d{2}))?)?").unwrap()
+});
+
+fn month_from_name(name: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "january",
+        "february",
+        "march",
+        "april",
+        "may",
+        "june",
+        "july",
+        "august",
+        "september",
+        "october",
+        "november",
+        "december",
+    ];
+    let name = name.to_ascii_lowercase();
+    if name.len() < 3 {
+        return None;
+    }
+    MONTHS
+        .iter()
+        .position(|m| m.starts_with(&name) || (name == "sept" && *m == "september"))
+        .map(|i| i as u32 + 1)
+}
+
+/// 找到字符串中的第一个日期，返回日期以及其后剩余的部分.
+fn find_date(s: &str) -> Option<(NaiveDate, &str)> {
+    let mut found: Option<(usize, usize, NaiveDate)> = None;
+    let mut consider = |start: usize, end: usize, date: Option<NaiveDate>| {
+        if let Some(date) = date
+            && found.is_none_or(|(s, _, _)| start < s)
+        {
+            found = Some((start, end, date));
+        }
+    };
+
+    for re in [&*RE_DATE_CJK, &*RE_DATE_NUM] {
+        if let Some(c) = re.captures(s) {
+            let m = c.get(0).unwrap();
+            let date = (|| {
+                NaiveDate::from_ymd_opt(c[1].parse().ok()?, c[2].parse().ok()?, c[3].parse().ok()?)
+            })();
+            consider(m.start(), m.end(), date);
+        }
+    }
+    for c in RE_DATE_EN_MDY.captures_iter(s) {
+        let m = c.get(0).unwrap();
+        let date = (|| {
+            NaiveDate::from_ymd_opt(
+                c[3].parse().ok()?,
+                month_from_name(&c[1])?,
+                c[2].parse().ok()?,
+            )
+        })();
+        if date.is_some() {
+            consider(m.start(), m.end(), date);
+            break;
+        }
+    }
+    for c in RE_DATE_EN_DMY.captures_iter(s) {
+        let m = c.get(0).unwrap();
+        let date = (|| {
+            NaiveDate::from_ymd_opt(
+                c[3].parse().ok()?,
+                month_from_name(&c[2])?,
+                c[1].parse().ok()?,
+            )
+        })();
+        if date.is_some() {
+            consider(m.start(), m.end(), date);
+            break;
+        }
+    }
+
+    found.map(|(_, end, date)| (date, &s[end..]))
+}
+
+/// 解析日期之后的时间部分，返回时间以及其后剩余的部分.
+fn find_time(s: &str) -> Option<(NaiveTime, &str)> {
+    let c = RE_TIME.captures(s)?;
+    let mut hour: u32 = c[2].parse().ok()?;
+    let minute: u32 = c[3].parse().ok()?;
+    let second: u32 = c.get(4).map_or(Some(0), |m| m.as_str().parse().ok())?;
+
+    let cn = c.get(1).map(|m| m.as_str());
+    let en = c.get(5).map(|m| m.as_str().to_ascii_lowercase());
+    let pm = matches!(cn, Some("下午" | "晚上")) || matches!(en.as_deref(), Some("pm" | "p.m."));
+    let am = matches!(cn, Some("上午" | "凌晨")) || matches!(en.as_deref(), Some("am" | "a.m."));
+
+    if pm && hour < 12 {
+        hour += 12;
+    } else if am && hour == 12 {
+        hour = 0;
+    } else if cn == Some("中午") && hour < 11 {
+        // 中午 12:30 / 中午 1:00
+        hour += 12;
+    }
+
+    let time = NaiveTime::from_hms_opt(hour, minute, second)?;
+    Some((time, &s[c.get(0).unwrap().end()..]))
+}
+
+fn find_offset(s: &str) -> Option<FixedOffset> {
+    let c = RE_OFFSET.captures(s)?;
+    let Some(sign) = c.get(1) else {
+        return FixedOffset::east_opt(0);
+    };
+    let hours: i32 = c[2].parse().ok()?;
+    let minutes: i32 = c.get(3).map_or(Some(0), |m| m.as_str().parse().ok())?;
+    let secs = (hours * 60 + minutes) * 60;
+    FixedOffset::east_opt(if sign.as_str() == "-" { -secs } else { secs })
+}
+
+/// 解析教学网上的时间字符串.
+///
+/// 在字符串中查找第一个日期及其后的时间，忽略星期等其他文字。没有时间的日期按当天
+/// 零点处理；没有显式时区（`GMT+8`、`UTC-05:00` 等）时按北京时间处理。
+pub fn parse(s: &str) -> Option<DateTime<FixedOffset>> {
+    let (date, rest) = find_date(s)?;
+    let (time, rest) = find_time(rest).unwrap_or((NaiveTime::MIN, rest));
+    let tz = find_offset(rest).unwrap_or(SHANGHAI);
+
+    tz.from_local_datetime(&NaiveDateTime::new(date, time))
+        .single()
+}
+
+/// 与 [`parse`] 相同，但转换为本机时区.
+pub fn parse_local(s: &str) -> Option<DateTime<chrono::Local>> {
+    parse(s).map(|t| t.with_timezone(&chrono::Local))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> DateTime<FixedOffset> {
+        SHANGHAI.with_ymd_and_hms(y, m, d, h, min, s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_table() {
+        let table = [
+            // 中文界面，12 小时制
+            ("2025年3月9日 星期日 下午11:59", sh(2025, 3, 9, 23, 59, 0)),
+            ("2025年3月9日 星期日 上午9:05", sh(2025, 3, 9, 9, 5, 0)),
+            ("2025年3月9日 星期日 上午12:30", sh(2025, 3, 9, 0, 30, 0)),
+            ("2025年3月9日 星期日 下午12:30", sh(2025, 3, 9, 12, 30, 0)),
+            ("2025年3月9日 星期日 中午12:00", sh(2025, 3, 9, 12, 0, 0)),
+            ("2025年3月9日 星期日 晚上8:00", sh(2025, 3, 9, 20, 0, 0)),
+            ("2025年3月9日 凌晨1:00", sh(2025, 3, 9, 1, 0, 0)),
+            // 中文界面，24 小时制 / 无星期
+            ("2025年3月9日 星期日 23:59", sh(2025, 3, 9, 23, 59, 0)),
+            ("2025年3月9日 23:59", sh(2025, 3, 9, 23, 59, 0)),
+            ("2025年03月09日 23:59:30", sh(2025, 3, 9, 23, 59, 30)),
+            ("2025 年 3 月 9 日 23：59", sh(2025, 3, 9, 23, 59, 0)),
+            // 公告发布时间
+            (
+                "发帖时间: 2025年2月17日 星期一 上午10时29分04秒 CST",
+                sh(2025, 2, 17, 10, 29, 4),
+            ),
+            (
+                "2025年2月17日 星期一 下午3时05分",
+                sh(2025, 2, 17, 15, 5, 0),
+            ),
+            // 截止时间前后带额外文字
+            (
+                "到期日期 2025年3月9日 星期日 下午11:59",
+                sh(2025, 3, 9, 23, 59, 0),
+            ),
+            // 数字格式（回放时间、提交时间）
+            ("2025-03-04 08:00:00", sh(2025, 3, 4, 8, 0, 0)),
+            ("2025-03-04 08:00:00~09:50:00", sh(2025, 3, 4, 8, 0, 0)),
+            ("2025-3-4 下午3:26", sh(2025, 3, 4, 15, 26, 0)),
+            ("2025/3/4 15:26", sh(2025, 3, 4, 15, 26, 0)),
+            ("尝试 2025-3-4 下午3:26", sh(2025, 3, 4, 15, 26, 0)),
+            ("2025-03-04", sh(2025, 3, 4, 0, 0, 0)),
+            // 英文界面
+            (
+                "Sunday, March 9, 2025 11:59:00 PM CST",
+                sh(2025, 3, 9, 23, 59, 0),
+            ),
+            ("Mar 9, 2025 11:59 PM", sh(2025, 3, 9, 23, 59, 0)),
+            ("Mar. 9, 2025 12:15 AM", sh(2025, 3, 9, 0, 15, 0)),
+            ("Sept 1, 2025 9:00 a.m.", sh(2025, 9, 1, 9, 0, 0)),
+            ("Due Date: 9 March 2025 23:59", sh(2025, 3, 9, 23, 59, 0)),
+            ("Monday, February 17, 2025", sh(2025, 2, 17, 0, 0, 0)),
+        ];
+
+        for (s, expected) in table {
+            assert_eq!(parse(s), Some(expected), "parsing {s:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_explicit_offset() {
+        let t = parse("Mar 9, 2025 11:59 PM GMT+01:00").unwrap();
+        assert_eq!(t.offset().local_minus_utc(), 3600);
+        assert_eq!(t, sh(2025, 3, 10, 6, 59, 0));
+
+        let t = parse("2025-03-09 15:59 UTC").unwrap();
+        assert_eq!(t, sh(2025, 3, 9, 23, 59, 0));
+
+        let t = parse("2025-03-09 10:00 GMT-5").unwrap();
+        assert_eq!(t, sh(2025, 3, 9, 23, 0, 0));
+    }
+
+    #[test]
+    fn test_parse_is_timezone_independent() {
+        // the same wall-clock time in Beijing is the same instant everywhere
+        let t = parse("2025年3月9日 星期日 下午11:59").unwrap();
+        assert_eq!(t.timestamp(), 1741535940);
+        assert_eq!(parse_local("2025年3月9日 星期日 下午11:59").unwrap(), t);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in [
+            "",
+            "无截止时间",
+            "2025年13月1日 10:00",
+            "2025年2月30日",
+            "Foo 9, 2025 10:00",
+        ] {
+            assert_eq!(parse(s), None, "parsing {s:?}");
+        }
+    }
+}
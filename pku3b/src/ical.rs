@@ -0,0 +1,379 @@
+//! iCalendar (RFC 5545) 导出.
+//!
+//! 将作业截止时间（以及可选的课程回放时间）导出为 `.ics` 日历文件，可直接导入或订阅到
+//! 日历应用中。UID 由课程 ID 和内容 ID 生成，重复导出时日历应用会更新而不是重复添加。
+
+use crate::api::{CourseAssignment, CourseVideoHandle};
+use chrono::{DateTime, TimeZone, Utc};
+use std::fmt::Write as _;
+
+const PRODID: &str = "-//sshwy//pku3b//CN";
+
+/// 作业截止时间以何种日历组件导出
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeadlineKind {
+    /// `VEVENT`，日历中显示为截止时刻的事件
+    #[default]
+    Event,
+    /// `VTODO`，日历/提醒应用中显示为待办事项
+    Todo,
+}
+
+/// iCalendar 文件构造器
+#[derive(Debug, Clone, Default)]
+pub struct CalendarBuilder {
+    name: Option<String>,
+    kind: DeadlineKind,
+    alarms: Vec<chrono::Duration>,
+    components: Vec<String>,
+}
+
+impl CalendarBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置日历名称 (`X-WR-CALNAME`)
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// 设置作业截止时间的组件类型
+    pub fn deadline_kind(mut self, kind: DeadlineKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// 在截止时间前 `before` 提醒，可多次调用。`before` 不能为负
+    pub fn alarm(mut self, before: chrono::Duration) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            before >= chrono::Duration::zero(),
+            "alarm offset must not be negative"
+        );
+        self.alarms.push(before);
+        Ok(self)
+    }
+
+    /// 组件数量
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// 添加一个作业。没有（可解析的）截止时间的作业会被忽略，此时返回 `false`.
+    pub fn add_assignment(&mut self, a: &CourseAssignment) -> bool {
+        let Some(deadline) = a.deadline() else {
+            return false;
+        };
+
+        let mut description = a.descriptions().join("\n");
+        if !a.attachments().is_empty() {
+            description.push_str("\n\n附件:");
+            for (name, _) in a.attachments() {
+                description.push_str("\n- ");
+                description.push_str(name);
+            }
+        }
+        if let Some(att) = a.last_attempt() {
+            description.push_str("\n\n已完成: ");
+            description.push_str(att);
+        }
+
+        let mut c = Component::new(match self.kind {
+            DeadlineKind::Event => "VEVENT",
+            DeadlineKind::Todo => "VTODO",
+        });
+        c.prop("UID", &uid(a.course().id(), a.content_id()));
+        c.prop("DTSTAMP", &fmt_utc(&Utc::now()));
+        match self.kind {
+            DeadlineKind::Event => {
+                // a zero-length event; DTEND must be later than DTSTART
+                c.prop("DTSTART", &fmt_utc(&deadline));
+                c.prop("DURATION", "PT0S");
+                c.prop("TRANSP", "TRANSPARENT");
+            }
+            DeadlineKind::Todo => {
+                c.prop("DUE", &fmt_utc(&deadline));
+                if a.last_attempt().is_some() {
+                    c.prop("STATUS", "COMPLETED");
+                    c.prop("PERCENT-COMPLETE", "100");
+                } else {
+                    c.prop("STATUS", "NEEDS-ACTION");
+                }
+            }
+        }
+        c.text("SUMMARY", &format!("[{}] {}", a.course().name(), a.title()));
+        c.text("DESCRIPTION", description.trim());
+        c.text("CATEGORIES", a.course().name());
+        c.prop("URL", &a.url());
+
+        // no need to remind of finished assignments
+        if a.last_attempt().is_none() {
+            for before in &self.alarms {
+                c.alarm(self.kind, *before, &format!("{} 即将截止", a.title()));
+            }
+        }
+
+        self.components.push(c.finish());
+        true
+    }
+
+    /// 添加一个课程回放。没有（可解析的）回放时间时返回 `false`.
+    pub fn add_video(&mut self, v: &CourseVideoHandle) -> bool {
+        let Some(start) = v.meta().start_time() else {
+            return false;
+        };
+
+        let sub_id = v.id();
+        let sub_id = sub_id.rsplit("::").next().unwrap_or_default();
+
+        let mut c = Component::new("VEVENT");
+        c.prop("UID", &uid(v.course().id(), &format!("video-{sub_id}")));
+        c.prop("DTSTAMP", &fmt_utc(&Utc::now()));
+        c.prop("DTSTART", &fmt_utc(&start));
+        c.text(
+            "SUMMARY",
+            &format!("[{}] 课程回放: {}", v.course().name(), v.title()),
+        );
+        c.text("DESCRIPTION", &format!("{} ({})", v.title(), v.time()));
+        c.text("CATEGORIES", v.course().name());
+        c.prop("URL", v.meta().url());
+
+        self.components.push(c.finish());
+        true
+    }
+
+    /// 生成 `.ics` 文件内容
+    pub fn build(&self) -> String {
+        let mut c = Component::new("VCALENDAR");
+        c.prop("VERSION", "2.0");
+        c.prop("PRODID", PRODID);
+        c.prop("CALSCALE", "GREGORIAN");
+        c.prop("METHOD", "PUBLISH");
+        if let Some(name) = &self.name {
+            c.text("X-WR-CALNAME", name);
+        }
+        for comp in &self.components {
+            c.buf.push_str(comp);
+        }
+        c.finish()
+    }
+}
+
+/// 一个 `BEGIN:...` / `END:...` 组件，内容行已折行.
+struct Component {
+    name: &'static str,
+    buf: String,
+}
+
+impl Component {
+    fn new(name: &'static str) -> Self {
+        let mut buf = String::new();
+        push_line(&mut buf, &format!("BEGIN:{name}"));
+        Self { name, buf }
+    }
+
+    /// 写入原样的属性值（日期、URI 等）
+    fn prop(&mut self, name: &str, value: &str) {
+        push_line(&mut self.buf, &format!("{name}:{value}"));
+    }
+
+    /// 写入 TEXT 类型的属性值，会进行转义
+    fn text(&mut self, name: &str, value: &str) {
+        push_line(&mut self.buf, &format!("{name}:{}", escape_text(value)));
+    }
+
+    /// 在截止时间前 `before` 提醒。VEVENT 的截止时间是开始时间，VTODO 只有 DUE，需相对于结束时间
+    fn alarm(&mut self, kind: DeadlineKind, before: chrono::Duration, description: &str) {
+        let mut a = Component::new("VALARM");
+        a.prop("ACTION", "DISPLAY");
+        a.text("DESCRIPTION", description);
+        let trigger = fmt_duration(-before);
+        match kind {
+            DeadlineKind::Event => a.prop("TRIGGER", &trigger),
+            DeadlineKind::Todo => a.prop("TRIGGER;RELATED=END", &trigger),
+        }
+        self.buf.push_str(&a.finish());
+    }
+
+    fn finish(mut self) -> String {
+        push_line(&mut self.buf, &format!("END:{}", self.name));
+        self.buf
+    }
+}
+
+fn uid(course_id: &str, content_id: &str) -> String {
+    format!("{course_id}-{content_id}@pku3b")
+}
+
+fn fmt_utc<Tz: TimeZone>(t: &DateTime<Tz>) -> String {
+    t.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// RFC 5545 3.3.6 dur-value，例如 `P1DT2H`，负的时长为 `-P1DT2H`
+fn fmt_duration(d: chrono::Duration) -> String {
+    let secs = d.num_seconds().unsigned_abs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (hours, secs) = (secs / 3600, secs % 3600);
+    let (minutes, secs) = (secs / 60, secs % 60);
+
+    let mut s = String::from(if d < chrono::Duration::zero() {
+        "-P"
+    } else {
+        "P"
+    });
+    if days > 0 {
+        write!(s, "{days}D").unwrap();
+    }
+    if hours > 0 || minutes > 0 || secs > 0 || days == 0 {
+        s.push('T');
+        if hours > 0 {
+            write!(s, "{hours}H").unwrap();
+        }
+        if minutes > 0 {
+            write!(s, "{minutes}M").unwrap();
+        }
+        if secs > 0 || (hours == 0 && minutes == 0) {
+            write!(s, "{secs}S").unwrap();
+        }
+    }
+    s
+}
+
+/// RFC 5545 3.3.11 TEXT 转义
+fn escape_text(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => r.push_str("\\\\"),
+            ';' => r.push_str("\\;"),
+            ',' => r.push_str("\\,"),
+            '\n' => r.push_str("\\n"),
+            '\r' => {}
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+/// 写入一行内容，超过 75 字节时按 RFC 5545 3.1 折行（不拆分 UTF-8 字符）
+fn push_line(buf: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            buf.push_str("\r\n ");
+            width = 1;
+        }
+        buf.push(c);
+        width += c.len_utf8();
+    }
+    buf.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_text() {
+        assert_eq!(escape_text("a,b;c\\d\r\ne"), "a\\,b\\;c\\\\d\\ne");
+    }
+
+    #[test]
+    fn test_fmt_duration() {
+        assert_eq!(fmt_duration(chrono::Duration::days(1)), "P1D");
+        assert_eq!(fmt_duration(chrono::Duration::hours(2)), "PT2H");
+        assert_eq!(fmt_duration(chrono::Duration::minutes(90)), "PT1H30M");
+        assert_eq!(
+            fmt_duration(chrono::Duration::hours(26) + chrono::Duration::seconds(5)),
+            "P1DT2H5S"
+        );
+        assert_eq!(fmt_duration(chrono::Duration::zero()), "PT0S");
+        assert_eq!(fmt_duration(-chrono::Duration::minutes(15)), "-PT15M");
+    }
+
+    #[test]
+    fn test_push_line_folding() {
+        let mut buf = String::new();
+        let line = format!("DESCRIPTION:{}", "作业".repeat(30));
+        push_line(&mut buf, &line);
+
+        let lines = buf.split("\r\n").collect::<Vec<_>>();
+        assert!(lines.len() > 2);
+        assert!(lines.iter().all(|l| l.len() <= 75));
+        assert!(
+            lines[1..]
+                .iter()
+                .filter(|l| !l.is_empty())
+                .all(|l| l.starts_with(' '))
+        );
+
+        // unfolding restores the original line
+        assert_eq!(buf.replace("\r\n ", "").trim_end(), line);
+    }
+
+    fn build(kind: DeadlineKind, a: &CourseAssignment) -> String {
+        let mut cal = CalendarBuilder::new()
+            .deadline_kind(kind)
+            .alarm(chrono::Duration::hours(1))
+            .unwrap();
+        assert!(cal.add_assignment(a));
+        // unfold the content lines
+        cal.build().replace("\r\n ", "")
+    }
+
+    #[test]
+    fn test_assignment_event() {
+        let a = CourseAssignment::for_test("第一次作业", "2024年10月8日 星期二 下午11:59", None);
+        let event = build(DeadlineKind::Event, &a);
+        assert!(event.contains("BEGIN:VEVENT\r\n"));
+        assert!(event.contains("\r\nUID:_80167_1-_1500201_1@pku3b\r\n"));
+        assert!(event.contains("\r\nDTSTART:20241008T155900Z\r\nDURATION:PT0S\r\n"));
+        assert!(!event.contains("DTEND"));
+        assert!(event.contains("\r\nSUMMARY:[数据结构与算法] 第一次作业\r\n"));
+        assert!(event.contains("\r\nTRIGGER:-PT1H\r\n"));
+    }
+
+    #[test]
+    fn test_assignment_todo() {
+        let a = CourseAssignment::for_test("第一次作业", "2024年10月8日 星期二 下午11:59", None);
+        let todo = build(DeadlineKind::Todo, &a);
+        assert!(todo.contains("BEGIN:VTODO\r\n"));
+        assert!(todo.contains("\r\nDUE:20241008T155900Z\r\n"));
+        assert!(todo.contains("\r\nSTATUS:NEEDS-ACTION\r\n"));
+        assert!(!todo.contains("DTSTART"));
+        // a todo has no start, so the alarm is relative to the due time
+        assert!(todo.contains("\r\nTRIGGER;RELATED=END:-PT1H\r\n"));
+
+        // finished assignments are completed and not reminded of
+        let a = CourseAssignment::for_test(
+            "第一次作业",
+            "2024年10月8日 星期二 下午11:59",
+            Some("已提交 2024-10-07"),
+        );
+        let todo = build(DeadlineKind::Todo, &a);
+        assert!(todo.contains("\r\nSTATUS:COMPLETED\r\n"));
+        assert!(!todo.contains("VALARM"));
+    }
+
+    #[test]
+    fn test_negative_alarm() {
+        assert!(
+            CalendarBuilder::new()
+                .alarm(-chrono::Duration::hours(1))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_build_empty_calendar() {
+        let cal = CalendarBuilder::new().name("pku3b, 作业").build();
+        assert!(cal.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(cal.contains("X-WR-CALNAME:pku3b\\, 作业\r\n"));
+        assert!(cal.ends_with("END:VCALENDAR\r\n"));
+    }
+}
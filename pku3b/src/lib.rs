@@ -1,7 +1,18 @@
 // src/lib.rs
 pub mod api;
+pub mod cache;
+pub mod cassette;
 pub mod config;
+pub mod datetime;
+pub mod ical;
 pub mod multipart;
 pub mod qs;
+#[cfg(feature = "remux")]
+pub mod remux;
+pub mod serve;
+pub mod snapshot;
+pub mod sync;
 pub mod utils;
+pub mod video;
 pub mod walkdir;
+pub mod watch;
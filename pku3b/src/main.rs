@@ -1,12 +1,8 @@
 extern crate directories as dirs;
 
-mod api;
 mod cli;
-mod config;
-mod multipart;
-mod qs;
-mod utils;
-mod walkdir;
+
+use pku3b::{api, cache, cassette, config, ical, serve, snapshot, sync, utils, video, watch};
 
 use shadow_rs::shadow;
 shadow!(build);
@@ -37,7 +33,9 @@ async fn main() {
 
     let cli = cli::Cli::parse();
 
-    match cli::start(cli).await {
+    let r = cli::start(cli).await;
+    cache::CacheStore::flush_all();
+    match r {
         Ok(r) => r,
         Err(e) => {
             use utils::style::*;
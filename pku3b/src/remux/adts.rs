@@ -0,0 +1,122 @@
+//! AAC 的 ADTS 帧.
+
+use anyhow::Context as _;
+
+/// 每个 AAC 帧包含的采样数
+pub const SAMPLES_PER_FRAME: u32 = 1024;
+
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// ADTS 帧头中转封装需要的字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdtsHeader {
+    /// MPEG-4 Audio Object Type (AAC LC 为 2)
+    pub object_type: u8,
+    pub freq_index: u8,
+    pub channels: u8,
+    /// 帧头长度 (7 或带 CRC 时为 9)
+    pub header_len: usize,
+    /// 整帧长度 (含帧头)
+    pub frame_len: usize,
+}
+
+impl AdtsHeader {
+    /// 解析 `data` 开头的 ADTS 帧头，数据不足 7 字节时返回 `None`
+    pub fn parse(data: &[u8]) -> anyhow::Result<Option<Self>> {
+        let Some(h) = data.get(..7) else {
+            return Ok(None);
+        };
+        anyhow::ensure!(h[0] == 0xff && h[1] & 0xf0 == 0xf0, "invalid adts syncword");
+        let protection_absent = h[1] & 0x01 == 1;
+        let header = Self {
+            object_type: (h[2] >> 6) + 1,
+            freq_index: (h[2] >> 2) & 0x0f,
+            channels: ((h[2] & 0x01) << 2) | (h[3] >> 6),
+            header_len: if protection_absent { 7 } else { 9 },
+            frame_len: (((h[3] & 0x03) as usize) << 11)
+                | ((h[4] as usize) << 3)
+                | ((h[5] as usize) >> 5),
+        };
+        header.sample_rate()?;
+        anyhow::ensure!(
+            header.frame_len > header.header_len,
+            "invalid adts frame length {}",
+            header.frame_len
+        );
+        Ok(Some(header))
+    }
+
+    pub fn sample_rate(&self) -> anyhow::Result<u32> {
+        SAMPLE_RATES
+            .get(self.freq_index as usize)
+            .copied()
+            .with_context(|| format!("invalid sampling frequency index {}", self.freq_index))
+    }
+
+    /// 生成 AudioSpecificConfig
+    pub fn audio_specific_config(&self) -> [u8; 2] {
+        [
+            (self.object_type << 3) | (self.freq_index >> 1),
+            ((self.freq_index & 1) << 7) | (self.channels << 3),
+        ]
+    }
+}
+
+/// 从 `buf` 中取出所有完整的 ADTS 帧，返回 (帧头, 含帧头的整帧)。不完整的帧留在 `buf` 中。
+pub fn drain_frames(buf: &mut Vec<u8>) -> anyhow::Result<Vec<(AdtsHeader, Vec<u8>)>> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while let Some(h) = AdtsHeader::parse(&buf[pos..])? {
+        let Some(frame) = buf.get(pos..pos + h.frame_len) else {
+            break;
+        };
+        frames.push((h, frame.to_vec()));
+        pos += h.frame_len;
+    }
+    buf.drain(..pos);
+    Ok(frames)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// AAC LC, 48kHz, 双声道的 ADTS 帧
+    pub fn frame(payload: &[u8]) -> Vec<u8> {
+        let len = 7 + payload.len();
+        let mut r = vec![
+            0xff,
+            0xf1,
+            (1 << 6) | (3 << 2),
+            (2 << 6) | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 7) << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        r.extend_from_slice(payload);
+        r
+    }
+
+    #[test]
+    fn test_drain_frames() {
+        let mut buf = [frame(&[1, 2, 3]), frame(&[4; 10])].concat();
+        let partial = frame(&[5; 20]);
+        buf.extend_from_slice(&partial[..12]);
+
+        let frames = drain_frames(&mut buf).unwrap();
+        assert_eq!(frames.len(), 2);
+        let (h, data) = &frames[0];
+        assert_eq!((h.object_type, h.channels), (2, 2));
+        assert_eq!(h.sample_rate().unwrap(), 48000);
+        assert_eq!(h.audio_specific_config(), [0x11, 0x90]);
+        assert_eq!(&data[h.header_len..], &[1, 2, 3]);
+        assert_eq!(frames[1].1, frame(&[4; 10]));
+        assert_eq!(buf, partial[..12]);
+
+        buf.extend_from_slice(&partial[12..]);
+        assert_eq!(drain_frames(&mut buf).unwrap()[0].1, partial);
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+//! H.264 Annex B 码流：NAL 单元切分和 SPS 解析.
+
+use anyhow::Context as _;
+
+pub const NAL_SLICE_IDR: u8 = 5;
+pub const NAL_SPS: u8 = 7;
+pub const NAL_PPS: u8 = 8;
+pub const NAL_AUD: u8 = 9;
+
+pub fn nal_type(nal: &[u8]) -> u8 {
+    nal.first().map_or(0, |b| b & 0x1f)
+}
+
+/// 按起始码 (`00 00 01` 或 `00 00 00 01`) 切分 NAL 单元
+pub fn split_annexb(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let starts = memchr::memmem::find_iter(data, &[0, 0, 1])
+        .map(|i| i + 3)
+        .collect::<Vec<_>>();
+    let ends = starts
+        .iter()
+        .skip(1)
+        .map(|&s| s - 3)
+        .chain(std::iter::once(data.len()))
+        .collect::<Vec<_>>();
+    starts
+        .into_iter()
+        .zip(ends)
+        .map(|(s, e)| {
+            // zero bytes before the next start code belong to it
+            let nal = &data[s..e];
+            let len = nal.iter().rposition(|&b| b != 0).map_or(0, |p| p + 1);
+            &nal[..len]
+        })
+        .filter(|nal| !nal.is_empty())
+}
+
+/// 去掉防竞争字节 (`00 00 03` 中的 `03`)，得到 RBSP
+fn unescape(nal: &[u8]) -> Vec<u8> {
+    let mut r = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        r.push(b);
+    }
+    r
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> anyhow::Result<u32> {
+        let byte = self.data.get(self.pos / 8).context("sps truncated")?;
+        let b = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(b as u32)
+    }
+
+    fn bits(&mut self, n: u32) -> anyhow::Result<u32> {
+        (0..n).try_fold(0, |acc, _| Ok((acc << 1) | self.bit()?))
+    }
+
+    /// 无符号指数哥伦布码
+    fn ue(&mut self) -> anyhow::Result<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            anyhow::ensure!(zeros < 32, "invalid exp-golomb code");
+        }
+        Ok((1 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    /// 有符号指数哥伦布码
+    fn se(&mut self) -> anyhow::Result<i32> {
+        let k = self.ue()? as i64;
+        Ok(if k % 2 == 1 { (k + 1) / 2 } else { -(k / 2) } as i32)
+    }
+}
+
+/// SPS 中转封装需要的字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sps {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Sps {
+    /// 解析 SPS NAL 单元 (含 NAL 头)
+    pub fn parse(nal: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(nal_type(nal) == NAL_SPS, "not a sps nal unit");
+        let rbsp = unescape(&nal[1..]);
+        anyhow::ensure!(rbsp.len() >= 3, "sps truncated");
+        let (profile_idc, constraint_flags, level_idc) = (rbsp[0], rbsp[1], rbsp[2]);
+        let mut r = BitReader {
+            data: &rbsp[3..],
+            pos: 0,
+        };
+
+        r.ue()?; // seq_parameter_set_id
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        if matches!(
+            profile_idc,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        ) {
+            chroma_format_idc = r.ue()?;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = r.bit()? == 1;
+            }
+            r.ue()?; // bit_depth_luma_minus8
+            r.ue()?; // bit_depth_chroma_minus8
+            r.bit()?; // qpprime_y_zero_transform_bypass_flag
+            if r.bit()? == 1 {
+                // seq_scaling_matrix_present_flag
+                let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+                for i in 0..lists {
+                    if r.bit()? == 1 {
+                        skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+        r.ue()?; // log2_max_frame_num_minus4
+        match r.ue()? {
+            0 => {
+                r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+            }
+            1 => {
+                r.bit()?; // delta_pic_order_always_zero_flag
+                r.se()?; // offset_for_non_ref_pic
+                r.se()?; // offset_for_top_to_bottom_field
+                for _ in 0..r.ue()? {
+                    r.se()?; // offset_for_ref_frame
+                }
+            }
+            _ => {}
+        }
+        r.ue()?; // max_num_ref_frames
+        r.bit()?; // gaps_in_frame_num_value_allowed_flag
+        let width_mbs = r.ue()? + 1;
+        let height_map_units = r.ue()? + 1;
+        let frame_mbs_only = r.bit()?;
+        if frame_mbs_only == 0 {
+            r.bit()?; // mb_adaptive_frame_field_flag
+        }
+        r.bit()?; // direct_8x8_inference_flag
+
+        let mut width = width_mbs * 16;
+        let mut height = (2 - frame_mbs_only) * height_map_units * 16;
+        if r.bit()? == 1 {
+            // frame_cropping_flag
+            let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+            let (sub_w, sub_h) = match (chroma_format_idc, separate_colour_plane) {
+                (0, _) | (3, true) => (1, 1),
+                (1, _) => (2, 2),
+                (2, _) => (2, 1),
+                _ => (1, 1),
+            };
+            let crop_x = sub_w;
+            let crop_y = sub_h * (2 - frame_mbs_only);
+            width = width
+                .checked_sub(crop_x * (left + right))
+                .context("invalid sps cropping")?;
+            height = height
+                .checked_sub(crop_y * (top + bottom))
+                .context("invalid sps cropping")?;
+        }
+
+        Ok(Self {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            width,
+            height,
+        })
+    }
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> anyhow::Result<()> {
+    let (mut last, mut next) = (8i32, 8i32);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Ok(())
+}
+
+/// 生成 `avcC` (AVCDecoderConfigurationRecord) 的内容
+pub fn avc_config(sps: &[u8], pps: &[u8], info: &Sps) -> Vec<u8> {
+    let mut r = vec![
+        1,
+        info.profile_idc,
+        info.constraint_flags,
+        info.level_idc,
+        0xfc | 3, // lengthSizeMinusOne = 3
+        0xe0 | 1, // one sps
+    ];
+    r.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    r.extend_from_slice(sps);
+    r.push(1); // one pps
+    r.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    r.extend_from_slice(pps);
+    r
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Baseline profile, 640x360 (640x368 cropped by 8 rows)
+    pub const SPS_640X360: &[u8] = &[0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xbf, 0xe5, 0x40];
+    pub const PPS: &[u8] = &[0x68, 0xce, 0x38, 0x80];
+
+    #[test]
+    fn test_split_annexb() {
+        let data = [
+            0, 0, 0, 1, 9, 0xf0, 0, 0, 1, 0x67, 1, 2, 0, 0, 0, 0, 1, 0x65, 0, 0, 3, 1, 0,
+        ];
+        let nals = split_annexb(&data).collect::<Vec<_>>();
+        assert_eq!(
+            nals,
+            vec![&[9, 0xf0][..], &[0x67, 1, 2], &[0x65, 0, 0, 3, 1]]
+        );
+        assert_eq!(unescape(nals[2]), vec![0x65, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_parse_sps() {
+        let sps = Sps::parse(SPS_640X360).unwrap();
+        assert_eq!(
+            sps,
+            Sps {
+                profile_idc: 66,
+                constraint_flags: 0xc0,
+                level_idc: 30,
+                width: 640,
+                height: 360,
+            }
+        );
+
+        // High profile 1920x1080 from a common x264 encode
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0x84, 0x00, 0x00,
+            0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xf0, 0x3c, 0x60, 0xc6, 0x58,
+        ];
+        let sps = Sps::parse(&sps).unwrap();
+        assert_eq!((sps.profile_idc, sps.width, sps.height), (100, 1920, 1080));
+    }
+}
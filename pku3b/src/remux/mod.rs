@@ -0,0 +1,763 @@
+//! MPEG-TS 到 MP4 的转封装 (不重新编码).
+//!
+//! 从 TS 中解出 H.264 视频和 AAC (ADTS) 音频的基本流，按 PES 的时间戳写为普通的 MP4 文件，
+//! 用于在没有 ffmpeg 的环境中代替 `ffmpeg -c copy`。其他编码的流会被丢弃。
+//!
+//! 也可以只提取音频，输出为 M4A 或 ADTS 格式的 AAC 裸流 (见 [`Output`])。
+
+mod adts;
+mod h264;
+mod mp4;
+mod ts;
+
+use anyhow::Context as _;
+use std::{
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// PES 时间戳的时间刻度
+const TS_TIMESCALE: u32 = 90000;
+
+/// 无法从相邻帧推出时使用的视频帧时长 (25fps)
+const DEFAULT_FRAME_DURATION: u32 = TS_TIMESCALE / 25;
+
+const VIDEO_TRACK_ID: u32 = 1;
+const AUDIO_TRACK_ID: u32 = 2;
+
+/// 转封装结果的统计信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemuxStats {
+    pub video_samples: usize,
+    pub audio_samples: usize,
+    pub duration: Duration,
+}
+
+/// 转封装的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// 包含视频和音频的 MP4
+    Mp4,
+    /// 只包含音频的 MP4 (M4A)
+    M4a,
+    /// 保留 ADTS 帧头的 AAC 裸流 (`.aac`)
+    Adts,
+}
+
+impl Output {
+    /// 对应的文件扩展名
+    pub fn extension(self) -> &'static str {
+        match self {
+            Output::Mp4 => "mp4",
+            Output::M4a => "m4a",
+            Output::Adts => "aac",
+        }
+    }
+}
+
+#[derive(Default)]
+struct VideoState {
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    samples: Vec<mp4::Sample>,
+    skipped: usize,
+}
+
+#[derive(Default)]
+struct AudioState {
+    header: Option<adts::AdtsHeader>,
+    /// 尚未凑成完整帧的 ADTS 数据
+    pending: Vec<u8>,
+    first_pts: Option<u64>,
+    samples: Vec<mp4::Sample>,
+}
+
+/// 将 TS 流转封装为 MP4
+pub fn remux(input: impl Read, output: impl Write + Seek) -> anyhow::Result<RemuxStats> {
+    remux_to(input, output, Output::Mp4)
+}
+
+/// 将 TS 流转封装为指定格式。输出音频时视频流的数据不会被写入。
+pub fn remux_to(
+    input: impl Read,
+    output: impl Write + Seek,
+    format: Output,
+) -> anyhow::Result<RemuxStats> {
+    if format == Output::Adts {
+        return extract_adts(input, output);
+    }
+    let audio_only = format == Output::M4a;
+    let mut demux = ts::Demuxer::new(input);
+    let mut writer = mp4::Writer::new(output, audio_only).context("write mp4 header")?;
+    let mut video = VideoState::default();
+    let mut audio = AudioState::default();
+
+    while let Some(pes) = demux.next_pes()? {
+        match pes.kind {
+            ts::StreamKind::Video if audio_only => {}
+            ts::StreamKind::Video => push_video(&mut writer, &mut video, pes)?,
+            ts::StreamKind::Audio => push_audio(&mut writer, &mut audio, pes)?,
+        }
+    }
+    if video.skipped > 0 {
+        log::warn!(
+            "dropped {} video frames before the first keyframe",
+            video.skipped
+        );
+    }
+    if !audio.pending.is_empty() {
+        log::warn!(
+            "dropped {} bytes of incomplete audio at the end",
+            audio.pending.len()
+        );
+    }
+
+    let mut tracks = Vec::new();
+    if !video.samples.is_empty() {
+        let (sps, pps) = video
+            .sps
+            .as_ref()
+            .zip(video.pps.as_ref())
+            .context("sps/pps not found in video stream")?;
+        let info = h264::Sps::parse(sps).context("parse sps")?;
+        let last_duration = match video.samples.as_slice() {
+            // out-of-order timestamps say nothing about the frame rate
+            [.., a, b] if b.dts > a.dts => (b.dts - a.dts) as u32,
+            _ => DEFAULT_FRAME_DURATION,
+        };
+        tracks.push(mp4::Track {
+            id: VIDEO_TRACK_ID,
+            timescale: TS_TIMESCALE,
+            codec: mp4::Codec::Avc {
+                width: info.width,
+                height: info.height,
+                config: h264::avc_config(sps, pps, &info),
+            },
+            samples: video.samples,
+            last_duration,
+            start_delay: 0,
+        });
+    }
+    if let Some(h) = audio.header
+        && !audio.samples.is_empty()
+    {
+        tracks.push(mp4::Track {
+            id: AUDIO_TRACK_ID,
+            timescale: h.sample_rate()?,
+            codec: mp4::Codec::Aac {
+                sample_rate: h.sample_rate()?,
+                channels: h.channels as u16,
+                config: h.audio_specific_config().to_vec(),
+            },
+            samples: audio.samples,
+            last_duration: adts::SAMPLES_PER_FRAME,
+            start_delay: 0,
+        });
+    }
+    anyhow::ensure!(
+        !tracks.is_empty(),
+        if audio_only {
+            "no AAC stream found"
+        } else {
+            "no H.264 or AAC stream found"
+        }
+    );
+
+    // align the tracks by their first presentation time
+    let starts = tracks
+        .iter()
+        .map(|t| match t.codec {
+            mp4::Codec::Avc { .. } => t
+                .samples
+                .iter()
+                .map(|s| s.dts.saturating_add_signed(s.cts_offset as i64))
+                .min()
+                .unwrap_or(0),
+            mp4::Codec::Aac { .. } => audio.first_pts.unwrap_or(0),
+        })
+        .collect::<Vec<_>>();
+    let movie_start = starts.iter().copied().min().unwrap_or(0);
+    for (t, start) in tracks.iter_mut().zip(starts) {
+        t.start_delay = (start - movie_start) * t.timescale as u64 / TS_TIMESCALE as u64;
+    }
+
+    let stats = RemuxStats {
+        video_samples: tracks
+            .iter()
+            .find(|t| t.id == VIDEO_TRACK_ID)
+            .map_or(0, |t| t.samples.len()),
+        audio_samples: tracks
+            .iter()
+            .find(|t| t.id == AUDIO_TRACK_ID)
+            .map_or(0, |t| t.samples.len()),
+        duration: tracks
+            .iter()
+            .map(|t| {
+                Duration::from_secs_f64((t.start_delay + t.duration()) as f64 / t.timescale as f64)
+            })
+            .max()
+            .unwrap_or_default(),
+    };
+    writer.finish(&tracks).context("write mp4 index")?;
+    Ok(stats)
+}
+
+/// 将 TS 文件转封装为 MP4 文件。先写入临时文件，成功后再重命名为 `dst`。
+pub fn remux_file(src: &Path, dst: &Path) -> anyhow::Result<RemuxStats> {
+    remux_files(&[src.to_owned()], dst, Output::Mp4)
+}
+
+/// 将依次拼接的多个 TS 文件 (如 HLS 分片) 转封装为指定格式的文件，无需先合并分片。
+/// 先写入临时文件，成功后再重命名为 `dst`。
+pub fn remux_files(srcs: &[PathBuf], dst: &Path, format: Output) -> anyhow::Result<RemuxStats> {
+    let part = crate::utils::with_suffix(dst, ".part");
+    let output =
+        std::fs::File::create(&part).with_context(|| format!("create {}", part.display()))?;
+
+    let r = remux_to(
+        std::io::BufReader::with_capacity(1 << 20, ChainFiles::new(srcs)),
+        std::io::BufWriter::with_capacity(1 << 20, output),
+        format,
+    );
+    match r {
+        Ok(stats) => {
+            std::fs::rename(&part, dst)
+                .with_context(|| format!("rename {} file", format.extension()))?;
+            Ok(stats)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&part);
+            Err(e)
+        }
+    }
+}
+
+/// 依次读取多个文件，相当于读取它们拼接后的内容
+struct ChainFiles<'a> {
+    paths: std::slice::Iter<'a, PathBuf>,
+    cur: Option<std::fs::File>,
+}
+
+impl<'a> ChainFiles<'a> {
+    fn new(paths: &'a [PathBuf]) -> Self {
+        Self {
+            paths: paths.iter(),
+            cur: None,
+        }
+    }
+}
+
+impl Read for ChainFiles<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(f) = &mut self.cur {
+                let n = f.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+            }
+            let Some(p) = self.paths.next() else {
+                return Ok(0);
+            };
+            let f = std::fs::File::open(p)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("open {}: {e}", p.display())))?;
+            self.cur = Some(f);
+        }
+    }
+}
+
+/// 只提取 TS 中的 AAC 音频，原样写出 ADTS 帧
+fn extract_adts(input: impl Read, mut output: impl Write) -> anyhow::Result<RemuxStats> {
+    let mut demux = ts::Demuxer::new(input);
+    let mut header: Option<adts::AdtsHeader> = None;
+    let mut pending = Vec::new();
+    let mut frames = 0usize;
+
+    while let Some(pes) = demux.next_pes()? {
+        if pes.kind != ts::StreamKind::Audio {
+            continue;
+        }
+        pending.extend_from_slice(&pes.data);
+        for (h, frame) in adts::drain_frames(&mut pending).context("parse adts")? {
+            header.get_or_insert(h);
+            output.write_all(&frame).context("write aac frame")?;
+            frames += 1;
+        }
+    }
+    if !pending.is_empty() {
+        log::warn!(
+            "dropped {} bytes of incomplete audio at the end",
+            pending.len()
+        );
+    }
+    output.flush().context("write aac frame")?;
+
+    let h = header.context("no AAC stream found")?;
+    let samples = frames as u64 * adts::SAMPLES_PER_FRAME as u64;
+    Ok(RemuxStats {
+        video_samples: 0,
+        audio_samples: frames,
+        duration: Duration::from_secs_f64(samples as f64 / h.sample_rate()? as f64),
+    })
+}
+
+/// 将一个 PES (一个访问单元) 转为长度前缀格式的样本写入
+fn push_video<W: Write + Seek>(
+    writer: &mut mp4::Writer<W>,
+    st: &mut VideoState,
+    pes: ts::Pes,
+) -> anyhow::Result<()> {
+    let (Some(pts), Some(dts)) = (pes.pts, pes.dts) else {
+        log::warn!("video pes without timestamp, dropped");
+        return Ok(());
+    };
+
+    let mut sync = false;
+    let mut nals = Vec::new();
+    for nal in h264::split_annexb(&pes.data) {
+        match h264::nal_type(nal) {
+            h264::NAL_AUD => {}
+            // parameter sets go to the sample entry
+            h264::NAL_SPS => {
+                st.sps.get_or_insert_with(|| nal.to_vec());
+            }
+            h264::NAL_PPS => {
+                st.pps.get_or_insert_with(|| nal.to_vec());
+            }
+            t => {
+                sync |= t == h264::NAL_SLICE_IDR;
+                nals.push(nal);
+            }
+        }
+    }
+    if nals.is_empty() {
+        return Ok(());
+    }
+    if st.samples.is_empty() && !sync {
+        st.skipped += 1;
+        return Ok(());
+    }
+
+    let lens = nals
+        .iter()
+        .map(|n| (n.len() as u32).to_be_bytes())
+        .collect::<Vec<_>>();
+    let parts = lens
+        .iter()
+        .zip(&nals)
+        .flat_map(|(l, n)| [&l[..], n])
+        .collect::<Vec<_>>();
+    let size = parts.iter().map(|p| p.len()).sum::<usize>() as u32;
+    let offset = writer.write_sample(&parts).context("write video sample")?;
+
+    if let Some(last) = st.samples.last()
+        && dts <= last.dts
+    {
+        log::warn!("non-monotonic video dts {dts} after {}", last.dts);
+    }
+    st.samples.push(mp4::Sample {
+        offset,
+        size,
+        dts,
+        cts_offset: (pts as i64 - dts as i64) as i32,
+        sync,
+    });
+    Ok(())
+}
+
+/// 将 PES 中的 ADTS 帧去掉帧头后逐帧写入
+fn push_audio<W: Write + Seek>(
+    writer: &mut mp4::Writer<W>,
+    st: &mut AudioState,
+    pes: ts::Pes,
+) -> anyhow::Result<()> {
+    if st.first_pts.is_none() {
+        st.first_pts = pes.pts;
+    }
+    st.pending.extend_from_slice(&pes.data);
+    for (h, frame) in adts::drain_frames(&mut st.pending).context("parse adts")? {
+        let data = &frame[h.header_len..];
+        let first = *st.header.get_or_insert(h);
+        anyhow::ensure!(
+            (h.object_type, h.freq_index, h.channels)
+                == (first.object_type, first.freq_index, first.channels),
+            "audio format changed mid-stream"
+        );
+        let offset = writer.write_sample(&[data]).context("write audio sample")?;
+        st.samples.push(mp4::Sample {
+            offset,
+            size: data.len() as u32,
+            dts: st.samples.len() as u64 * adts::SAMPLES_PER_FRAME as u64,
+            cts_offset: 0,
+            sync: true,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// 生成测试用的 TS 流
+    struct TsBuilder {
+        out: Vec<u8>,
+        cc: std::collections::HashMap<u16, u8>,
+    }
+
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x100;
+    const AUDIO_PID: u16 = 0x101;
+
+    impl TsBuilder {
+        fn new() -> Self {
+            let mut b = Self {
+                out: Vec::new(),
+                cc: Default::default(),
+            };
+            b.psi(0, &[0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00]);
+            let pmt = [
+                0x02, 0xb0, 23, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0x00, // header, pcr pid
+                0x1b, 0xe1, 0x00, 0xf0, 0x00, // h264
+                0x0f, 0xe1, 0x01, 0xf0, 0x00, // aac
+            ];
+            b.psi(PMT_PID, &pmt);
+            b
+        }
+
+        fn packet(&mut self, pid: u16, pusi: bool, payload: &[u8]) -> usize {
+            let cc = self.cc.entry(pid).or_default();
+            let n = payload.len().min(184);
+            let mut p = vec![
+                0x47,
+                (pusi as u8) << 6 | (pid >> 8) as u8,
+                pid as u8,
+                0x10 | *cc,
+            ];
+            *cc = (*cc + 1) & 0xf;
+            if n < 184 {
+                // stuffing in the adaptation field
+                p[3] |= 0x20;
+                let af = 184 - n - 1;
+                p.push(af as u8);
+                if af > 0 {
+                    p.push(0);
+                    p.resize(p.len() + af - 1, 0xff);
+                }
+            }
+            p.extend_from_slice(&payload[..n]);
+            assert_eq!(p.len(), 188);
+            self.out.extend_from_slice(&p);
+            n
+        }
+
+        fn psi(&mut self, pid: u16, section: &[u8]) {
+            // pointer_field + section + dummy crc
+            let data = [&[0][..], section, &[0, 0, 0, 0]].concat();
+            self.packet(pid, true, &data);
+        }
+
+        fn pes(&mut self, pid: u16, stream_id: u8, pts: u64, dts: Option<u64>, data: &[u8]) {
+            let ts = |prefix: u8, t: u64| {
+                [
+                    prefix << 4 | ((t >> 29) as u8 & 0x0e) | 1,
+                    (t >> 22) as u8,
+                    ((t >> 14) as u8 & 0xfe) | 1,
+                    (t >> 7) as u8,
+                    ((t << 1) as u8 & 0xfe) | 1,
+                ]
+            };
+            let mut hdr = Vec::new();
+            match dts {
+                Some(d) => {
+                    hdr.extend(ts(3, pts));
+                    hdr.extend(ts(1, d));
+                }
+                None => hdr.extend(ts(2, pts)),
+            }
+            let flags = if dts.is_some() { 0xc0 } else { 0x80 };
+            let len = if stream_id == 0xe0 {
+                0
+            } else {
+                3 + hdr.len() + data.len()
+            };
+            let mut pes = vec![0, 0, 1, stream_id, (len >> 8) as u8, len as u8, 0x80, flags];
+            pes.push(hdr.len() as u8);
+            pes.extend(hdr);
+            pes.extend_from_slice(data);
+
+            let mut rest = &pes[..];
+            let mut first = true;
+            while !rest.is_empty() {
+                let n = self.packet(pid, first, rest);
+                rest = &rest[n..];
+                first = false;
+            }
+        }
+    }
+
+    fn frame(idr: bool, params: bool, size: usize) -> Vec<u8> {
+        let mut r = vec![0, 0, 0, 1, 0x09, 0xf0];
+        if params {
+            r.extend([0, 0, 0, 1]);
+            r.extend_from_slice(h264::tests::SPS_640X360);
+            r.extend([0, 0, 0, 1]);
+            r.extend_from_slice(h264::tests::PPS);
+        }
+        r.extend([0, 0, 1, if idr { 0x65 } else { 0x41 }]);
+        r.resize(r.len() + size, 0xab);
+        r
+    }
+
+    /// 读出 box 的 (类型, 内容)
+    fn boxes(data: &[u8]) -> Vec<(String, &[u8])> {
+        let mut r = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let mut size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind = String::from_utf8_lossy(&data[pos + 4..pos + 8]).into_owned();
+            let mut hdr = 8;
+            if size == 1 {
+                size = u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap()) as usize;
+                hdr = 16;
+            }
+            r.push((kind, &data[pos + hdr..pos + size]));
+            pos += size;
+        }
+        r
+    }
+
+    fn find<'a>(data: &'a [u8], path: &[&str]) -> &'a [u8] {
+        let (first, rest) = path.split_first().unwrap();
+        let b = boxes(data)
+            .into_iter()
+            .find(|(k, _)| k == first)
+            .unwrap_or_else(|| panic!("box {first} not found"))
+            .1;
+        if rest.is_empty() { b } else { find(b, rest) }
+    }
+
+    fn u32_at(b: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(b[pos..pos + 4].try_into().unwrap())
+    }
+
+    /// 10 帧视频和 10 帧音频，音频比视频晚 0.5s 开始
+    fn sample_ts() -> TsBuilder {
+        let mut ts = TsBuilder::new();
+        // a leading non-idr frame which must be dropped
+        ts.pes(
+            VIDEO_PID,
+            0xe0,
+            90000 - 3600,
+            Some(90000 - 7200),
+            &frame(false, false, 50),
+        );
+        for i in 0..10u64 {
+            // 25fps, pts one frame ahead of dts
+            let dts = 90000 + i * 3600;
+            ts.pes(
+                VIDEO_PID,
+                0xe0,
+                dts + 3600,
+                Some(dts),
+                &frame(i % 5 == 0, i == 0, 300),
+            );
+            if i % 2 == 0 {
+                // 48kHz audio starting 0.5s after the video
+                let frames = [
+                    adts::tests::frame(&[i as u8; 20]),
+                    adts::tests::frame(&[0; 30]),
+                ]
+                .concat();
+                ts.pes(
+                    AUDIO_PID,
+                    0xc0,
+                    90000 + 3600 + 45000 + i * 1920,
+                    None,
+                    &frames,
+                );
+            }
+        }
+        ts
+    }
+
+    /// 依次取出 `sample_ts` 中的 ADTS 帧
+    fn sample_adts() -> Vec<u8> {
+        (0..10u8)
+            .step_by(2)
+            .flat_map(|i| [adts::tests::frame(&[i; 20]), adts::tests::frame(&[0; 30])].concat())
+            .collect()
+    }
+
+    #[test]
+    fn test_remux() {
+        let ts = sample_ts();
+        let mut out = Cursor::new(Vec::new());
+        let stats = remux(Cursor::new(&ts.out), &mut out).unwrap();
+        assert_eq!(stats.video_samples, 10);
+        assert_eq!(stats.audio_samples, 10);
+        // video: 10 frames of 40ms; audio: 0.5s delay + 10 * 1024 / 48000
+        assert_eq!(
+            stats.duration,
+            Duration::from_secs_f64(0.5 + 10240.0 / 48000.0)
+        );
+
+        let mp4 = out.into_inner();
+        let top = boxes(&mp4).into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(top, ["ftyp", "mdat", "moov"]);
+
+        let moov = find(&mp4, &["moov"]);
+        let traks = boxes(moov)
+            .into_iter()
+            .filter(|(k, _)| k == "trak")
+            .map(|(_, b)| b)
+            .collect::<Vec<_>>();
+        assert_eq!(traks.len(), 2);
+
+        // video track
+        let v = traks[0];
+        let stsd = find(v, &["mdia", "minf", "stbl", "stsd"]);
+        let avc1 = find(&stsd[8..], &["avc1"]);
+        assert_eq!(
+            (
+                u16::from_be_bytes([avc1[24], avc1[25]]),
+                u16::from_be_bytes([avc1[26], avc1[27]])
+            ),
+            (640, 360)
+        );
+        let avcc = find(&avc1[78..], &["avcC"]);
+        assert_eq!(
+            &avcc[8..8 + h264::tests::SPS_640X360.len()],
+            h264::tests::SPS_640X360
+        );
+        let stbl = find(v, &["mdia", "minf", "stbl"]);
+        let stts = find(stbl, &["stts"]);
+        assert_eq!(
+            (u32_at(stts, 4), u32_at(stts, 8), u32_at(stts, 12)),
+            (1, 10, 3600)
+        );
+        let ctts = find(stbl, &["ctts"]);
+        assert_eq!(
+            (u32_at(ctts, 4), u32_at(ctts, 8), u32_at(ctts, 12)),
+            (1, 10, 3600)
+        );
+        let stss = find(stbl, &["stss"]);
+        assert_eq!(
+            (u32_at(stss, 4), u32_at(stss, 8), u32_at(stss, 12)),
+            (2, 1, 6)
+        );
+        let elst = find(v, &["edts", "elst"]);
+        // single edit skipping the composition offset
+        assert_eq!(u32_at(elst, 4), 1);
+        assert_eq!(u64::from_be_bytes(elst[16..24].try_into().unwrap()), 3600);
+
+        // first video sample is the idr frame in length-prefixed form
+        let stsz = find(stbl, &["stsz"]);
+        let co64 = find(stbl, &["co64"]);
+        let off = u64::from_be_bytes(co64[8..16].try_into().unwrap()) as usize;
+        let size = u32_at(stsz, 12) as usize;
+        assert_eq!(u32_at(&mp4, off) as usize, size - 4);
+        assert_eq!(mp4[off + 4], 0x65);
+
+        // audio track
+        let a = traks[1];
+        let mdhd = find(a, &["mdia", "mdhd"]);
+        assert_eq!(u32_at(mdhd, 20), 48000);
+        let elst = find(a, &["edts", "elst"]);
+        assert_eq!(u32_at(elst, 4), 2);
+        assert_eq!(u64::from_be_bytes(elst[8..16].try_into().unwrap()), 500);
+        let stbl = find(a, &["mdia", "minf", "stbl"]);
+        let stsz = find(stbl, &["stsz"]);
+        assert_eq!((u32_at(stsz, 12), u32_at(stsz, 16)), (20, 30));
+        let esds = find(&find(stbl, &["stsd"])[8..], &["mp4a"]);
+        assert!(esds.windows(4).any(|w| w == [0x05, 2, 0x11, 0x90]));
+    }
+
+    #[test]
+    fn test_remux_audio() {
+        let ts = sample_ts();
+        let audio_duration = Duration::from_secs_f64(10240.0 / 48000.0);
+
+        let mut out = Cursor::new(Vec::new());
+        let stats = remux_to(Cursor::new(&ts.out), &mut out, Output::M4a).unwrap();
+        assert_eq!((stats.video_samples, stats.audio_samples), (0, 10));
+        assert_eq!(stats.duration, audio_duration);
+        let m4a = out.into_inner();
+        assert_eq!(&find(&m4a, &["ftyp"])[..4], b"M4A ");
+        // the video frames are not written
+        assert_eq!(find(&m4a, &["mdat"]).len(), 5 * (20 + 30));
+        let moov = find(&m4a, &["moov"]);
+        let traks = boxes(moov)
+            .into_iter()
+            .filter(|(k, _)| k == "trak")
+            .collect::<Vec<_>>();
+        assert_eq!(traks.len(), 1);
+        assert_eq!(u32_at(find(traks[0].1, &["mdia", "mdhd"]), 20), 48000);
+        // no delay without the video track
+        assert_eq!(u32_at(find(traks[0].1, &["edts", "elst"]), 4), 1);
+
+        let mut out = Cursor::new(Vec::new());
+        let stats = remux_to(Cursor::new(&ts.out), &mut out, Output::Adts).unwrap();
+        assert_eq!((stats.video_samples, stats.audio_samples), (0, 10));
+        assert_eq!(stats.duration, audio_duration);
+        assert_eq!(out.into_inner(), sample_adts());
+    }
+
+    #[test]
+    fn test_remux_files() {
+        let dir = std::env::temp_dir().join(format!("pku3b-remux-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // split at an arbitrary packet boundary, as hls segments would be
+        let ts = sample_ts().out;
+        let mid = ts.len() / ts::PACKET_SIZE / 2 * ts::PACKET_SIZE;
+        let srcs = [&ts[..mid], &ts[mid..]]
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                let p = dir.join(format!("{i}.ts"));
+                std::fs::write(&p, data).unwrap();
+                p
+            })
+            .collect::<Vec<_>>();
+
+        let dst = dir.join("out.aac");
+        let stats = remux_files(&srcs, &dst, Output::Adts).unwrap();
+        assert_eq!(stats.audio_samples, 10);
+        assert_eq!(std::fs::read(&dst).unwrap(), sample_adts());
+
+        let e = remux_files(&[dir.join("missing.ts")], &dst, Output::M4a).unwrap_err();
+        assert!(format!("{e:#}").contains("missing.ts"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remux_out_of_order_dts() {
+        let mut ts = TsBuilder::new();
+        // the last frame goes back in time
+        for (i, dts) in [90000, 93600, 90000].into_iter().enumerate() {
+            ts.pes(VIDEO_PID, 0xe0, dts, Some(dts), &frame(i == 0, i == 0, 300));
+        }
+        let mut out = Cursor::new(Vec::new());
+        let stats = remux(Cursor::new(&ts.out), &mut out).unwrap();
+        assert_eq!(stats.video_samples, 3);
+        let mp4 = out.into_inner();
+        let stts = find(&mp4, &["moov", "trak", "mdia", "minf", "stbl", "stts"]);
+        // 3600, 0, then the default duration for the last frame
+        assert_eq!(u32_at(stts, 4), 3);
+        assert_eq!(
+            (u32_at(stts, 16), u32_at(stts, 20), u32_at(stts, 24)),
+            (1, 0, 1)
+        );
+        assert_eq!(u32_at(stts, 28), DEFAULT_FRAME_DURATION);
+    }
+
+    #[test]
+    fn test_remux_no_stream() {
+        let mut ts = TsBuilder::new();
+        ts.packet(0x1fff, false, &[0xff; 184]);
+        let e = remux(Cursor::new(&ts.out), Cursor::new(Vec::new())).unwrap_err();
+        assert!(e.to_string().contains("no H.264 or AAC stream"));
+    }
+}
@@ -0,0 +1,437 @@
+//! 普通 (非分片) MP4 的写入.
+//!
+//! 文件布局为 `ftyp` `mdat` `moov`：样本在解复用的同时顺序写入 `mdat`，结束后回填 `mdat`
+//! 的长度，再根据记录下的样本表写出 `moov`。
+
+use std::io::{Seek, SeekFrom, Write};
+
+/// `mvhd`/`tkhd`/`elst` 使用的时间刻度 (毫秒)
+const MOVIE_TIMESCALE: u32 = 1000;
+
+/// 一个样本在 `mdat` 中的位置和时间信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub offset: u64,
+    pub size: u32,
+    /// 解码时间 (轨道时间刻度)
+    pub dts: u64,
+    /// 显示时间与解码时间之差
+    pub cts_offset: i32,
+    pub sync: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Codec {
+    Avc {
+        width: u32,
+        height: u32,
+        /// AVCDecoderConfigurationRecord
+        config: Vec<u8>,
+    },
+    Aac {
+        sample_rate: u32,
+        channels: u16,
+        /// AudioSpecificConfig
+        config: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub id: u32,
+    pub timescale: u32,
+    pub codec: Codec,
+    pub samples: Vec<Sample>,
+    /// 最后一个样本的时长，无法从下一个样本推出
+    pub last_duration: u32,
+    /// 轨道开始播放的时刻相对于整个影片开始的延迟 (轨道时间刻度)
+    pub start_delay: u64,
+}
+
+impl Track {
+    fn durations(&self) -> Vec<u32> {
+        let mut r = self
+            .samples
+            .windows(2)
+            .map(|w| w[1].dts.saturating_sub(w[0].dts) as u32)
+            .collect::<Vec<_>>();
+        if !self.samples.is_empty() {
+            r.push(self.last_duration);
+        }
+        r
+    }
+
+    /// 媒体时长 (轨道时间刻度)
+    pub fn duration(&self) -> u64 {
+        self.durations().iter().map(|&d| d as u64).sum()
+    }
+
+    fn is_video(&self) -> bool {
+        matches!(self.codec, Codec::Avc { .. })
+    }
+}
+
+fn to_movie_time(t: u64, timescale: u32) -> u64 {
+    t * MOVIE_TIMESCALE as u64 / timescale as u64
+}
+
+/// 带长度前缀的 box 写入工具
+trait BoxBuf {
+    fn u8(&mut self, v: u8);
+    fn u16(&mut self, v: u16);
+    fn u32(&mut self, v: u32);
+    fn u64(&mut self, v: u64);
+    fn zeros(&mut self, n: usize);
+    fn bx(&mut self, kind: &[u8; 4], f: impl FnOnce(&mut Self));
+    fn full_box(&mut self, kind: &[u8; 4], version: u8, flags: u32, f: impl FnOnce(&mut Self));
+}
+
+impl BoxBuf for Vec<u8> {
+    fn u8(&mut self, v: u8) {
+        self.push(v);
+    }
+    fn u16(&mut self, v: u16) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+    fn u32(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+    fn u64(&mut self, v: u64) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+    fn zeros(&mut self, n: usize) {
+        self.resize(self.len() + n, 0);
+    }
+    fn bx(&mut self, kind: &[u8; 4], f: impl FnOnce(&mut Self)) {
+        let start = self.len();
+        self.u32(0);
+        self.extend_from_slice(kind);
+        f(self);
+        let size = (self.len() - start) as u32;
+        self[start..start + 4].copy_from_slice(&size.to_be_bytes());
+    }
+    fn full_box(&mut self, kind: &[u8; 4], version: u8, flags: u32, f: impl FnOnce(&mut Self)) {
+        self.bx(kind, |b| {
+            b.u32((version as u32) << 24 | flags);
+            f(b);
+        })
+    }
+}
+
+const MATRIX: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000];
+
+pub struct Writer<W> {
+    out: W,
+    mdat_start: u64,
+    pos: u64,
+}
+
+impl<W: Write + Seek> Writer<W> {
+    /// 写入 `ftyp` 和 `mdat` 的头部。`audio_only` 时使用 M4A 的文件类型。
+    pub fn new(mut out: W, audio_only: bool) -> std::io::Result<Self> {
+        let (major, compatible): (&[u8; 4], &[&[u8; 4]]) = if audio_only {
+            (b"M4A ", &[b"M4A ", b"isom", b"mp42"])
+        } else {
+            (b"isom", &[b"isom", b"iso2", b"avc1", b"mp41"])
+        };
+        let mut buf = Vec::new();
+        buf.bx(b"ftyp", |b| {
+            b.extend_from_slice(major);
+            b.u32(0x200);
+            for brand in compatible {
+                b.extend_from_slice(*brand);
+            }
+        });
+        let mdat_start = buf.len() as u64;
+        // 64-bit largesize, patched in finish
+        buf.u32(1);
+        buf.extend_from_slice(b"mdat");
+        buf.u64(0);
+        out.write_all(&buf)?;
+        Ok(Self {
+            out,
+            mdat_start,
+            pos: buf.len() as u64,
+        })
+    }
+
+    /// 写入一段样本数据，返回其在文件中的偏移
+    pub fn write_sample(&mut self, parts: &[&[u8]]) -> std::io::Result<u64> {
+        let offset = self.pos;
+        for p in parts {
+            self.out.write_all(p)?;
+            self.pos += p.len() as u64;
+        }
+        Ok(offset)
+    }
+
+    /// 回填 `mdat` 长度并写出 `moov`
+    pub fn finish(mut self, tracks: &[Track]) -> std::io::Result<W> {
+        self.out.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.out
+            .write_all(&(self.pos - self.mdat_start).to_be_bytes())?;
+        self.out.seek(SeekFrom::Start(self.pos))?;
+        self.out.write_all(&moov(tracks))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn moov(tracks: &[Track]) -> Vec<u8> {
+    let movie_duration = tracks
+        .iter()
+        .map(|t| to_movie_time(t.start_delay + t.duration(), t.timescale))
+        .max()
+        .unwrap_or(0);
+
+    let mut b = Vec::new();
+    b.bx(b"moov", |b| {
+        b.full_box(b"mvhd", 1, 0, |b| {
+            b.u64(0); // creation_time
+            b.u64(0); // modification_time
+            b.u32(MOVIE_TIMESCALE);
+            b.u64(movie_duration);
+            b.u32(0x10000); // rate 1.0
+            b.u16(0x100); // volume 1.0
+            b.zeros(10);
+            MATRIX.iter().for_each(|&m| b.u32(m));
+            b.zeros(24);
+            b.u32(tracks.iter().map(|t| t.id).max().unwrap_or(0) + 1);
+        });
+        for t in tracks {
+            trak(b, t);
+        }
+    });
+    b
+}
+
+fn trak(b: &mut Vec<u8>, t: &Track) {
+    let media_duration = t.duration();
+    let delay = to_movie_time(t.start_delay, t.timescale);
+    let (width, height) = match t.codec {
+        Codec::Avc { width, height, .. } => (width, height),
+        Codec::Aac { .. } => (0, 0),
+    };
+
+    b.bx(b"trak", |b| {
+        // enabled | in_movie | in_preview
+        b.full_box(b"tkhd", 1, 0x7, |b| {
+            b.u64(0);
+            b.u64(0);
+            b.u32(t.id);
+            b.u32(0);
+            b.u64(delay + to_movie_time(media_duration, t.timescale));
+            b.zeros(8);
+            b.u16(0); // layer
+            b.u16(0); // alternate_group
+            b.u16(if t.is_video() { 0 } else { 0x100 });
+            b.u16(0);
+            MATRIX.iter().for_each(|&m| b.u32(m));
+            b.u32(width << 16);
+            b.u32(height << 16);
+        });
+
+        // the first sample may be presented later than it is decoded (b-frames)
+        let media_time = t
+            .samples
+            .iter()
+            .map(|s| s.dts.saturating_add_signed(s.cts_offset as i64))
+            .min()
+            .zip(t.samples.first())
+            .map_or(0, |(pts, s)| pts.saturating_sub(s.dts));
+        b.bx(b"edts", |b| {
+            b.full_box(b"elst", 1, 0, |b| {
+                b.u32(if delay > 0 { 2 } else { 1 });
+                if delay > 0 {
+                    b.u64(delay);
+                    b.u64(u64::MAX); // empty edit (-1)
+                    b.u32(0x10000);
+                }
+                b.u64(to_movie_time(media_duration, t.timescale));
+                b.u64(media_time);
+                b.u32(0x10000);
+            });
+        });
+
+        b.bx(b"mdia", |b| {
+            b.full_box(b"mdhd", 1, 0, |b| {
+                b.u64(0);
+                b.u64(0);
+                b.u32(t.timescale);
+                b.u64(media_duration);
+                b.u16(0x55c4); // 'und'
+                b.u16(0);
+            });
+            let (handler, name): (&[u8; 4], &[u8]) = if t.is_video() {
+                (b"vide", b"VideoHandler\0")
+            } else {
+                (b"soun", b"SoundHandler\0")
+            };
+            b.full_box(b"hdlr", 0, 0, |b| {
+                b.u32(0);
+                b.extend_from_slice(handler);
+                b.zeros(12);
+                b.extend_from_slice(name);
+            });
+            b.bx(b"minf", |b| {
+                if t.is_video() {
+                    b.full_box(b"vmhd", 0, 1, |b| b.zeros(8));
+                } else {
+                    b.full_box(b"smhd", 0, 0, |b| b.zeros(4));
+                }
+                b.bx(b"dinf", |b| {
+                    b.full_box(b"dref", 0, 0, |b| {
+                        b.u32(1);
+                        // media data is in the same file
+                        b.full_box(b"url ", 0, 1, |_| {});
+                    });
+                });
+                stbl(b, t);
+            });
+        });
+    });
+}
+
+fn stbl(b: &mut Vec<u8>, t: &Track) {
+    b.bx(b"stbl", |b| {
+        b.full_box(b"stsd", 0, 0, |b| {
+            b.u32(1);
+            sample_entry(b, &t.codec);
+        });
+
+        // run-length encoded sample durations
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for d in t.durations() {
+            match runs.last_mut() {
+                Some((n, last)) if *last == d => *n += 1,
+                _ => runs.push((1, d)),
+            }
+        }
+        b.full_box(b"stts", 0, 0, |b| {
+            b.u32(runs.len() as u32);
+            runs.iter().for_each(|&(n, d)| {
+                b.u32(n);
+                b.u32(d);
+            });
+        });
+
+        if t.samples.iter().any(|s| s.cts_offset != 0) {
+            let mut runs: Vec<(u32, i32)> = Vec::new();
+            for s in &t.samples {
+                match runs.last_mut() {
+                    Some((n, last)) if *last == s.cts_offset => *n += 1,
+                    _ => runs.push((1, s.cts_offset)),
+                }
+            }
+            let version = if runs.iter().any(|&(_, o)| o < 0) {
+                1
+            } else {
+                0
+            };
+            b.full_box(b"ctts", version, 0, |b| {
+                b.u32(runs.len() as u32);
+                runs.iter().for_each(|&(n, o)| {
+                    b.u32(n);
+                    b.u32(o as u32);
+                });
+            });
+        }
+
+        if t.samples.iter().any(|s| !s.sync) {
+            let sync = t
+                .samples
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.sync)
+                .map(|(i, _)| i as u32 + 1)
+                .collect::<Vec<_>>();
+            b.full_box(b"stss", 0, 0, |b| {
+                b.u32(sync.len() as u32);
+                sync.iter().for_each(|&i| b.u32(i));
+            });
+        }
+
+        // one sample per chunk
+        b.full_box(b"stsc", 0, 0, |b| {
+            b.u32(1);
+            b.u32(1); // first_chunk
+            b.u32(1); // samples_per_chunk
+            b.u32(1); // sample_description_index
+        });
+        b.full_box(b"stsz", 0, 0, |b| {
+            b.u32(0);
+            b.u32(t.samples.len() as u32);
+            t.samples.iter().for_each(|s| b.u32(s.size));
+        });
+        b.full_box(b"co64", 0, 0, |b| {
+            b.u32(t.samples.len() as u32);
+            t.samples.iter().for_each(|s| b.u64(s.offset));
+        });
+    });
+}
+
+fn sample_entry(b: &mut Vec<u8>, codec: &Codec) {
+    match codec {
+        Codec::Avc {
+            width,
+            height,
+            config,
+        } => b.bx(b"avc1", |b| {
+            b.zeros(6);
+            b.u16(1); // data_reference_index
+            b.zeros(16);
+            b.u16(*width as u16);
+            b.u16(*height as u16);
+            b.u32(0x00480000); // 72 dpi
+            b.u32(0x00480000);
+            b.u32(0);
+            b.u16(1); // frame_count
+            b.zeros(32); // compressorname
+            b.u16(0x18); // depth
+            b.u16(0xffff); // pre_defined = -1
+            b.bx(b"avcC", |b| b.extend_from_slice(config));
+        }),
+        Codec::Aac {
+            sample_rate,
+            channels,
+            config,
+        } => b.bx(b"mp4a", |b| {
+            b.zeros(6);
+            b.u16(1); // data_reference_index
+            b.zeros(8);
+            b.u16(*channels);
+            b.u16(16); // samplesize
+            b.zeros(4);
+            b.u32(sample_rate << 16);
+            b.full_box(b"esds", 0, 0, |b| esds(b, config));
+        }),
+    }
+}
+
+/// ES_Descriptor，见 ISO/IEC 14496-1
+fn esds(b: &mut Vec<u8>, asc: &[u8]) {
+    fn descriptor(b: &mut Vec<u8>, tag: u8, body: &[u8]) {
+        b.u8(tag);
+        b.u8(body.len() as u8);
+        b.extend_from_slice(body);
+    }
+
+    let mut dsi = Vec::new();
+    descriptor(&mut dsi, 0x05, asc);
+
+    let mut dcd = Vec::new();
+    dcd.u8(0x40); // objectTypeIndication: MPEG-4 Audio
+    dcd.u8(0x15); // streamType: audio, upStream = 0, reserved = 1
+    dcd.extend_from_slice(&[0, 0, 0]); // bufferSizeDB
+    dcd.u32(0); // maxBitrate
+    dcd.u32(0); // avgBitrate
+    dcd.extend_from_slice(&dsi);
+
+    let mut es = Vec::new();
+    es.u16(0); // ES_ID
+    es.u8(0); // flags
+    descriptor(&mut es, 0x04, &dcd);
+    descriptor(&mut es, 0x06, &[0x02]); // SLConfigDescriptor: predefined = MP4
+
+    descriptor(b, 0x03, &es);
+}
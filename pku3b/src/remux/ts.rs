@@ -0,0 +1,333 @@
+//! MPEG-TS 解复用：解析 PAT/PMT，按 PID 重组 PES 包.
+
+use anyhow::Context as _;
+use std::{collections::HashMap, io::Read};
+
+/// TS 包的长度
+pub const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+/// PTS/DTS 为 33 位，超出后回绕
+const TS_WRAP: u64 = 1 << 33;
+
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_AAC_ADTS: u8 = 0x0f;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    /// H.264 (Annex B)
+    Video,
+    /// AAC (ADTS)
+    Audio,
+}
+
+/// 一个完整的 PES 包
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pes {
+    pub kind: StreamKind,
+    /// 展开回绕后的 PTS (90kHz)
+    pub pts: Option<u64>,
+    /// 展开回绕后的 DTS (90kHz)，缺省时等于 PTS
+    pub dts: Option<u64>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct PesBuf {
+    kind: Option<StreamKind>,
+    data: Vec<u8>,
+    /// 上一个时间戳 (已展开)，用于处理回绕
+    last_ts: Option<u64>,
+}
+
+/// 从 TS 字节流中依次读出 PES 包
+pub struct Demuxer<R> {
+    input: R,
+    pmt_pid: Option<u16>,
+    streams: HashMap<u16, PesBuf>,
+    /// 已完成、尚未取走的 PES 包
+    ready: std::collections::VecDeque<Pes>,
+    eof: bool,
+    /// 不支持的流类型，只警告一次
+    unsupported: Vec<u8>,
+}
+
+impl<R: Read> Demuxer<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            pmt_pid: None,
+            streams: HashMap::new(),
+            ready: Default::default(),
+            eof: false,
+            unsupported: Vec::new(),
+        }
+    }
+
+    /// 读出下一个完整的 PES 包，输入结束后返回 `None`
+    pub fn next_pes(&mut self) -> anyhow::Result<Option<Pes>> {
+        loop {
+            if let Some(p) = self.ready.pop_front() {
+                return Ok(Some(p));
+            }
+            if self.eof {
+                return Ok(None);
+            }
+            match self.read_packet()? {
+                Some(pkt) => self.push_packet(&pkt)?,
+                None => {
+                    self.eof = true;
+                    self.flush_all()?;
+                }
+            }
+        }
+    }
+
+    /// 读取一个 TS 包。若当前位置不是同步字节，向后查找下一个同步字节。
+    fn read_packet(&mut self) -> anyhow::Result<Option<[u8; PACKET_SIZE]>> {
+        let mut pkt = [0u8; PACKET_SIZE];
+        if !read_full(&mut self.input, &mut pkt[..1])? {
+            return Ok(None);
+        }
+        if pkt[0] != SYNC_BYTE {
+            let mut skipped = 0usize;
+            while pkt[0] != SYNC_BYTE {
+                if !read_full(&mut self.input, &mut pkt[..1])? {
+                    return Ok(None);
+                }
+                skipped += 1;
+            }
+            log::warn!("lost sync, skipped {skipped} bytes");
+        }
+        if !read_full(&mut self.input, &mut pkt[1..])? {
+            log::warn!("truncated ts packet at end of input");
+            return Ok(None);
+        }
+        Ok(Some(pkt))
+    }
+
+    fn push_packet(&mut self, pkt: &[u8; PACKET_SIZE]) -> anyhow::Result<()> {
+        let pusi = pkt[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([pkt[1] & 0x1f, pkt[2]]);
+        let afc = (pkt[3] >> 4) & 0x3;
+        if pkt[1] & 0x80 != 0 {
+            log::debug!("transport error indicator set on pid {pid:#x}");
+        }
+        if afc & 0x1 == 0 {
+            // no payload
+            return Ok(());
+        }
+        let mut start = 4;
+        if afc & 0x2 != 0 {
+            start += 1 + pkt[4] as usize;
+        }
+        let Some(payload) = pkt.get(start..) else {
+            log::warn!("invalid adaptation field on pid {pid:#x}");
+            return Ok(());
+        };
+
+        if pid == 0 {
+            if pusi && let Some(pmt) = parse_pat(payload) {
+                self.pmt_pid = Some(pmt);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if pusi {
+                self.parse_pmt(payload);
+            }
+        } else if let Some(buf) = self.streams.get_mut(&pid) {
+            if pusi {
+                if let Some(p) = take_pes(buf).with_context(|| format!("pes on pid {pid:#x}"))? {
+                    self.ready.push_back(p);
+                }
+            } else if buf.data.is_empty() {
+                // the start of this pes was lost
+                return Ok(());
+            }
+            buf.data.extend_from_slice(payload);
+        }
+        Ok(())
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let Some(sec) = psi_section(payload, 0x02) else {
+            return;
+        };
+        let Some(&[_, _, hi, lo]) = sec.get(5..9) else {
+            return;
+        };
+        let info_len = (u16::from_be_bytes([hi, lo]) & 0x0fff) as usize;
+        let mut es = sec.get(9 + info_len..).unwrap_or_default();
+        while let [stype, p0, p1, l0, l1, rest @ ..] = es {
+            let pid = u16::from_be_bytes([p0 & 0x1f, *p1]);
+            let len = (u16::from_be_bytes([*l0, *l1]) & 0x0fff) as usize;
+            let kind = match *stype {
+                STREAM_TYPE_H264 => Some(StreamKind::Video),
+                STREAM_TYPE_AAC_ADTS => Some(StreamKind::Audio),
+                t => {
+                    if !self.unsupported.contains(&t) {
+                        log::warn!("unsupported stream type {t:#04x} on pid {pid:#x}, dropped");
+                        self.unsupported.push(t);
+                    }
+                    None
+                }
+            };
+            if let Some(kind) = kind {
+                self.streams.entry(pid).or_default().kind = Some(kind);
+            }
+            es = rest.get(len..).unwrap_or_default();
+        }
+    }
+
+    fn flush_all(&mut self) -> anyhow::Result<()> {
+        let mut pids = self.streams.keys().copied().collect::<Vec<_>>();
+        pids.sort();
+        for pid in pids {
+            let buf = self.streams.get_mut(&pid).unwrap();
+            match take_pes(buf) {
+                Ok(Some(p)) => self.ready.push_back(p),
+                Ok(None) => {}
+                // the last pes is often truncated, keep what we have
+                Err(e) => log::warn!("drop last pes on pid {pid:#x}: {e:#}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 读满 `buf`。输入在开头即结束时返回 `false`，读到一半结束时同样返回 `false`。
+fn read_full(r: &mut impl Read, buf: &mut [u8]) -> anyhow::Result<bool> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => return Ok(false),
+            Ok(k) => n += k,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("read ts input"),
+        }
+    }
+    Ok(true)
+}
+
+/// 跳过 pointer_field，返回 table_id 匹配的 section (含表头，不含 CRC)
+fn psi_section(payload: &[u8], table_id: u8) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let sec = payload.get(1 + pointer..)?;
+    if *sec.first()? != table_id {
+        return None;
+    }
+    let len = (u16::from_be_bytes([*sec.get(1)?, *sec.get(2)?]) & 0x0fff) as usize;
+    // header (3 bytes) + section_length, minus CRC32
+    sec.get(3..(3 + len).checked_sub(4)?)
+}
+
+/// 返回 PAT 中第一个节目的 PMT PID
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    let sec = psi_section(payload, 0x00)?;
+    sec.get(5..)?.chunks_exact(4).find_map(|e| {
+        let program = u16::from_be_bytes([e[0], e[1]]);
+        (program != 0).then(|| u16::from_be_bytes([e[2] & 0x1f, e[3]]))
+    })
+}
+
+fn parse_timestamp(b: &[u8]) -> u64 {
+    ((b[0] as u64 >> 1) & 0x07) << 30
+        | (b[1] as u64) << 22
+        | (b[2] as u64 >> 1) << 15
+        | (b[3] as u64) << 7
+        | (b[4] as u64 >> 1)
+}
+
+/// 将 33 位的时间戳展开为与 `last` 最接近的单调值
+fn unwrap_ts(last: Option<u64>, ts: u64) -> u64 {
+    let Some(last) = last else {
+        return ts;
+    };
+    let base = last - last % TS_WRAP;
+    [base.saturating_sub(TS_WRAP), base, base + TS_WRAP]
+        .into_iter()
+        .map(|b| b + ts)
+        .min_by_key(|c| c.abs_diff(last))
+        .unwrap()
+}
+
+/// 取出缓冲区中已累积的 PES 包并解析其头部
+fn take_pes(buf: &mut PesBuf) -> anyhow::Result<Option<Pes>> {
+    if buf.data.is_empty() {
+        return Ok(None);
+    }
+    let data = std::mem::take(&mut buf.data);
+    let Some(kind) = buf.kind else {
+        return Ok(None);
+    };
+    anyhow::ensure!(
+        data.len() >= 9 && data[..3] == [0, 0, 1],
+        "invalid pes start code"
+    );
+    let flags = data[7] >> 6;
+    let header_len = data[8] as usize;
+    let hdr = data
+        .get(9..9 + header_len)
+        .context("pes header truncated")?;
+
+    let mut pts = None;
+    let mut dts = None;
+    if flags & 0x2 != 0 {
+        let raw = parse_timestamp(hdr.get(..5).context("pts truncated")?);
+        let v = unwrap_ts(buf.last_ts, raw);
+        pts = Some(v);
+        dts = Some(v);
+        if flags == 0x3 {
+            let raw = parse_timestamp(hdr.get(5..10).context("dts truncated")?);
+            dts = Some(unwrap_ts(Some(v), raw));
+        }
+        buf.last_ts = dts;
+    }
+
+    // PES_packet_length is 0 for unbounded video pes, otherwise drop stuffing beyond it
+    let pes_len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let end = if pes_len == 0 {
+        data.len()
+    } else {
+        (6 + pes_len).min(data.len())
+    };
+    let payload = data.get(9 + header_len..end).unwrap_or_default().to_vec();
+
+    Ok(Some(Pes {
+        kind,
+        pts,
+        dts,
+        data: payload,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unwrap_ts() {
+        assert_eq!(unwrap_ts(None, 100), 100);
+        assert_eq!(unwrap_ts(Some(100), 3700), 3700);
+        // wrap around
+        assert_eq!(unwrap_ts(Some(TS_WRAP - 1000), 2600), TS_WRAP + 2600);
+        // dts slightly earlier than pts across the wrap point
+        assert_eq!(
+            unwrap_ts(Some(TS_WRAP + 100), TS_WRAP - 3500),
+            TS_WRAP - 3500
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        // '0010' + pts[32..30] + '1', pts[29..15] + '1', pts[14..0] + '1'
+        let pts: u64 = 0x1_2345_6789;
+        let b = [
+            0x20 | ((pts >> 29) as u8 & 0x0e) | 1,
+            (pts >> 22) as u8,
+            ((pts >> 14) as u8 & 0xfe) | 1,
+            (pts >> 7) as u8,
+            ((pts << 1) as u8 & 0xfe) | 1,
+        ];
+        assert_eq!(parse_timestamp(&b), pts);
+    }
+}
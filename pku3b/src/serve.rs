@@ -0,0 +1,244 @@
+//! 课程回放的本地 HLS 代理.
+//!
+//! [`HlsProxy`] 在本机监听 HTTP 请求，提供一个去掉加密信息的媒体播放列表 ([`PLAYLIST_PATH`])。
+//! 播放器请求分片时，代理通过已登录的客户端下载并解密对应的分片 (经由缓存，重复观看不会重新下载)，
+//! 因此任何支持 HLS 的播放器 (mpv、VLC 等) 都可以直接观看回放，不必等待整个回放下载完成。
+
+use crate::{api::CourseVideo, video::DownloadOptions};
+use anyhow::Context as _;
+use compio::{
+    buf::{BufResult, buf_try},
+    io::{AsyncRead as _, AsyncWrite as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+use std::{net::SocketAddr, rc::Rc};
+
+/// 本地播放列表的路径
+pub const PLAYLIST_PATH: &str = "/index.m3u8";
+
+/// 请求头的大小上限
+const MAX_HEADER: usize = 16 << 10;
+
+/// 提供一个回放的本地 HLS 服务器
+pub struct HlsProxy {
+    listener: TcpListener,
+    addr: SocketAddr,
+    state: Rc<State>,
+}
+
+struct State {
+    video: CourseVideo,
+    /// 每个分片的密钥，见 [`crate::video::resolve_keys`]
+    keys: Vec<Option<m3u8_rs::Key>>,
+    playlist: String,
+    opts: DownloadOptions,
+}
+
+impl HlsProxy {
+    /// 在 `addr` 上监听，端口为 0 时由系统分配。`opts.retries` 和 `opts.retry_delay` 用于下载分片
+    pub async fn bind(
+        video: CourseVideo,
+        addr: SocketAddr,
+        opts: DownloadOptions,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            video.file_url().is_none(),
+            "not an HLS replay, the video file can be downloaded directly"
+        );
+        let playlist = video.local_playlist(|i| format!("seg/{i}.ts"))?;
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("listen on {addr}"))?;
+        let addr = listener.local_addr()?;
+        let keys = crate::video::resolve_keys(&video)
+            .into_iter()
+            .map(|k| k.cloned())
+            .collect();
+        let state = Rc::new(State {
+            video,
+            keys,
+            playlist,
+            opts,
+        });
+        Ok(Self {
+            listener,
+            addr,
+            state,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 供播放器打开的播放列表地址
+    pub fn playlist_url(&self) -> String {
+        format!("http://{}{PLAYLIST_PATH}", self.addr)
+    }
+
+    /// 持续处理请求，每个连接一个任务。只在监听出错时返回
+    pub async fn run(self) -> anyhow::Result<()> {
+        loop {
+            let (stream, peer) = self.listener.accept().await.context("accept")?;
+            let state = self.state.clone();
+            compio::runtime::spawn(async move {
+                if let Err(e) = handle(stream, &state).await {
+                    log::warn!("serve {peer}: {e:#}");
+                }
+            })
+            .detach();
+        }
+    }
+}
+
+/// 从请求头中取出方法和路径 (不含查询参数)
+fn parse_request(head: &str) -> anyhow::Result<(&str, &str)> {
+    let line = head.lines().next().context("empty request")?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().context("no method")?;
+    let target = parts.next().context("no request target")?;
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    Ok((method, path))
+}
+
+/// `/seg/{i}.ts` 中的分片下标
+fn segment_index(path: &str) -> Option<usize> {
+    path.strip_prefix("/seg/")?
+        .strip_suffix(".ts")?
+        .parse()
+        .ok()
+}
+
+async fn read_head(stream: &mut TcpStream) -> anyhow::Result<String> {
+    let mut head = Vec::new();
+    while memchr::memmem::find(&head, b"\r\n\r\n").is_none() {
+        anyhow::ensure!(head.len() < MAX_HEADER, "request header too large");
+        let BufResult(n, buf) = stream.read(Vec::with_capacity(4096)).await;
+        anyhow::ensure!(n? > 0, "connection closed before the request ended");
+        head.extend_from_slice(&buf);
+    }
+    String::from_utf8(head).context("request header is not utf-8")
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: bytes::Bytes,
+    /// 为 `false` 时不发送 `Content-Length`，用于长度未知的 HEAD 响应
+    known_length: bool,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: impl Into<bytes::Bytes>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+            known_length: true,
+        }
+    }
+
+    /// 不获取内容的 HEAD 响应
+    fn head(content_type: &'static str) -> Self {
+        Self {
+            known_length: false,
+            ..Self::new(200, content_type, bytes::Bytes::new())
+        }
+    }
+
+    fn text(status: u16, body: &'static str) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body)
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: &State) -> anyhow::Result<()> {
+    let head = read_head(&mut stream).await?;
+    let (method, path) = parse_request(&head)?;
+    log::debug!("{method} {path}");
+
+    let res = match (method, path) {
+        ("GET" | "HEAD", "/" | PLAYLIST_PATH) => {
+            Response::new(200, "application/vnd.apple.mpegurl", state.playlist.clone())
+        }
+        ("GET" | "HEAD", path) => match segment_index(path) {
+            // players probe segments with HEAD, which must not cost a download
+            Some(i) if i < state.video.len_segments() && method == "HEAD" => {
+                Response::head("video/mp2t")
+            }
+            Some(i) if i < state.video.len_segments() => segment(state, i).await,
+            _ => Response::text(404, "not found"),
+        },
+        _ => Response::text(405, "method not allowed"),
+    };
+
+    let mut header = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\n",
+        res.status,
+        reason(res.status),
+        res.content_type
+    );
+    if res.known_length {
+        header += &format!("Content-Length: {}\r\n", res.body.len());
+    }
+    header += "Connection: close\r\n\r\n";
+    buf_try!(@try stream.write_all(header.into_bytes()).await);
+    if method != "HEAD" {
+        buf_try!(@try stream.write_all(res.body).await);
+    }
+    stream.shutdown().await.ok();
+    Ok(())
+}
+
+async fn segment(state: &State, index: usize) -> Response {
+    let v = &state.video;
+    let key = state.keys[index].as_ref();
+    let name = format!("segment {index}");
+    match crate::video::with_retries(state.opts, &name, || v.get_segment_data(index, key)).await {
+        Ok(data) => Response::new(200, "video/mp2t", data),
+        Err(e) => {
+            log::error!("{e:#}");
+            if e.is::<crate::api::UnsupportedEncryption>() {
+                Response::text(501, "unsupported encryption")
+            } else {
+                Response::text(502, "failed to fetch segment")
+            }
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let head = "GET /seg/12.ts?t=1 HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n";
+        assert_eq!(parse_request(head).unwrap(), ("GET", "/seg/12.ts"));
+        assert_eq!(
+            parse_request("HEAD / HTTP/1.1\r\n\r\n").unwrap(),
+            ("HEAD", "/")
+        );
+        assert!(parse_request("\r\n\r\n").is_err());
+        assert!(parse_request("GET\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_segment_index() {
+        assert_eq!(segment_index("/seg/0.ts"), Some(0));
+        assert_eq!(segment_index("/seg/42.ts"), Some(42));
+        assert_eq!(segment_index("/seg/-1.ts"), None);
+        assert_eq!(segment_index("/seg/1.mp4"), None);
+        assert_eq!(segment_index("/index.m3u8"), None);
+    }
+}
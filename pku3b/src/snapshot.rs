@@ -0,0 +1,367 @@
+//! 课程快照与变化检测.
+//!
+//! 每次获取课程后把课程内容、作业（及截止时间）、公告、回放记录为一份快照，与上一次的快照
+//! 比较即可得到新增、删除和修改的条目，其中截止时间的变化单独报告。快照保存在数据目录中，
+//! 不会被 `pku3b cache clean` 清除。
+
+use crate::{
+    api::{Course, CourseContentKind},
+    datetime, utils,
+};
+use anyhow::Context as _;
+use chrono::{DateTime, FixedOffset, Utc};
+use compio::{buf::buf_try, fs};
+use futures_util::future::try_join_all;
+use std::{collections::BTreeMap, path::PathBuf};
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    /// 课程内容（文档等）
+    Content,
+    Assignment,
+    Announcement,
+    Video,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotItem {
+    pub kind: ItemKind,
+    pub id: String,
+    pub title: String,
+    /// 作业截止时间
+    pub deadline: Option<DateTime<FixedOffset>>,
+    /// 标题、正文和附件的哈希，用于判断内容是否变化
+    pub fingerprint: String,
+}
+
+impl SnapshotItem {
+    pub fn new(
+        kind: ItemKind,
+        id: impl Into<String>,
+        title: impl Into<String>,
+        deadline: Option<DateTime<FixedOffset>>,
+        descriptions: &[String],
+        attachments: &[(String, String)],
+    ) -> Self {
+        let title = title.into();
+        // snapshots are kept on disk, so the hash must not change between builds
+        let content = serde_json::to_vec(&(&title, descriptions, attachments)).expect("serialize");
+        let fingerprint = format!("{:016x}", crate::cache::fnv1a(&content));
+
+        Self {
+            kind,
+            id: id.into(),
+            title,
+            deadline,
+            fingerprint,
+        }
+    }
+
+    fn key(&self) -> (ItemKind, String) {
+        (self.kind, self.id.clone())
+    }
+}
+
+/// 一门课程在某一时刻的状态
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CourseSnapshot {
+    pub course_id: String,
+    pub course: String,
+    pub taken_at: DateTime<Utc>,
+    pub items: Vec<SnapshotItem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Added,
+    Removed,
+    Modified,
+    /// 作业截止时间变化
+    DeadlineChanged,
+}
+
+/// 两次快照之间的一个变化
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Event {
+    pub course_id: String,
+    pub course: String,
+    pub change: EventKind,
+    pub item: ItemKind,
+    pub id: String,
+    pub title: String,
+    pub deadline: Option<DateTime<FixedOffset>>,
+    pub previous_deadline: Option<DateTime<FixedOffset>>,
+}
+
+/// 比较两次快照，返回按条目类型排序的变化列表
+pub fn diff(old: &CourseSnapshot, new: &CourseSnapshot) -> Vec<Event> {
+    let old_items = old
+        .items
+        .iter()
+        .map(|i| (i.key(), i))
+        .collect::<BTreeMap<_, _>>();
+    let new_items = new
+        .items
+        .iter()
+        .map(|i| (i.key(), i))
+        .collect::<BTreeMap<_, _>>();
+
+    let event = |change, item: &SnapshotItem, previous_deadline| Event {
+        course_id: new.course_id.clone(),
+        course: new.course.clone(),
+        change,
+        item: item.kind,
+        id: item.id.clone(),
+        title: item.title.clone(),
+        deadline: item.deadline,
+        previous_deadline,
+    };
+
+    let mut events = Vec::new();
+    for (key, item) in &new_items {
+        match old_items.get(key) {
+            None => events.push(event(EventKind::Added, item, None)),
+            Some(prev) if prev.deadline != item.deadline => {
+                events.push(event(EventKind::DeadlineChanged, item, prev.deadline))
+            }
+            Some(prev) if prev.fingerprint != item.fingerprint => {
+                events.push(event(EventKind::Modified, item, None))
+            }
+            Some(_) => {}
+        }
+    }
+    for (key, item) in &old_items {
+        if !new_items.contains_key(key) {
+            events.push(event(EventKind::Removed, item, None));
+        }
+    }
+
+    events.sort_by_key(|e| e.item);
+    events
+}
+
+/// 获取课程当前的状态
+pub async fn capture(course: &Course) -> anyhow::Result<CourseSnapshot> {
+    let mut stream = course.content_stream();
+    let mut contents = Vec::new();
+    while let Some(batch) = stream.next_batch().await {
+        contents.extend(batch);
+    }
+
+    let mut items = Vec::new();
+    let mut assignments = Vec::new();
+    for data in contents {
+        match data.kind() {
+            CourseContentKind::Folder | CourseContentKind::Video => {}
+            CourseContentKind::Assignment => {
+                assignments.extend(course.build_content(data).into_assignment_opt())
+            }
+            _ => items.push(SnapshotItem::new(
+                ItemKind::Content,
+                data.id(),
+                data.title(),
+                None,
+                data.descriptions(),
+                data.attachments(),
+            )),
+        }
+    }
+
+    let assignments = try_join_all(assignments.iter().map(|h| h.get()))
+        .await
+        .context("fetch assignments")?;
+    items.extend(assignments.iter().map(|a| {
+        SnapshotItem::new(
+            ItemKind::Assignment,
+            a.content_id(),
+            a.title(),
+            a.deadline_raw().and_then(datetime::parse),
+            a.descriptions(),
+            a.attachments(),
+        )
+    }));
+
+    let announcements = course
+        .list_announcements()
+        .await
+        .context("list announcements")?;
+    items.extend(announcements.iter().map(|a| {
+        SnapshotItem::new(
+            ItemKind::Announcement,
+            a.id(),
+            a.title(),
+            None,
+            a.content.descriptions(),
+            a.content.attachments(),
+        )
+    }));
+
+    let videos = course.get_video_list().await.context("list videos")?;
+    items.extend(videos.iter().map(|v| {
+        SnapshotItem::new(
+            ItemKind::Video,
+            v.id(),
+            v.title(),
+            None,
+            &[v.time().to_owned()],
+            &[],
+        )
+    }));
+
+    Ok(CourseSnapshot {
+        course_id: course.meta().id().to_owned(),
+        course: course.meta().name().to_owned(),
+        taken_at: Utc::now(),
+        items,
+    })
+}
+
+/// 快照的存储位置，每门课程保存最近一次快照
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl Default for SnapshotStore {
+    fn default() -> Self {
+        Self::new(utils::projectdir().data_dir().join("snapshots"))
+    }
+}
+
+impl SnapshotStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, course_id: &str) -> PathBuf {
+        self.dir.join(format!("{course_id}.json"))
+    }
+
+    pub async fn load(&self, course_id: &str) -> anyhow::Result<Option<CourseSnapshot>> {
+        let path = self.path(course_id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let buf = fs::read(&path).await?;
+        let r = serde_json::from_slice(&buf)
+            .with_context(|| format!("parse snapshot {}", path.display()))?;
+        Ok(Some(r))
+    }
+
+    pub async fn save(&self, snapshot: &CourseSnapshot) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let buf = serde_json::to_vec(snapshot)?;
+        buf_try!(@try fs::write(self.path(&snapshot.course_id), buf).await);
+        Ok(())
+    }
+
+    /// 获取课程当前状态并与上次的快照比较。`save` 为真时用新快照替换旧快照。
+    ///
+    /// 没有旧快照（首次运行）时返回 `None`.
+    pub async fn check(&self, course: &Course, save: bool) -> anyhow::Result<Option<Vec<Event>>> {
+        let (snapshot, events) = self.compare(course).await?;
+        if save {
+            self.save(&snapshot).await?;
+        }
+        Ok(events)
+    }
+
+    /// 同 [`SnapshotStore::check`]，但不保存新快照而是将其返回，由调用者在处理完变化后 [`SnapshotStore::save`]
+    pub async fn compare(
+        &self,
+        course: &Course,
+    ) -> anyhow::Result<(CourseSnapshot, Option<Vec<Event>>)> {
+        let snapshot = capture(course).await?;
+        let events = self
+            .load(&snapshot.course_id)
+            .await?
+            .map(|old| diff(&old, &snapshot));
+        Ok((snapshot, events))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(items: Vec<SnapshotItem>) -> CourseSnapshot {
+        CourseSnapshot {
+            course_id: "_80052_1".to_owned(),
+            course: "数据结构与算法".to_owned(),
+            taken_at: Utc::now(),
+            items,
+        }
+    }
+
+    fn assignment(id: &str, deadline: &str, desc: &str) -> SnapshotItem {
+        SnapshotItem::new(
+            ItemKind::Assignment,
+            id,
+            format!("作业 {id}"),
+            datetime::parse(deadline),
+            &[desc.to_owned()],
+            &[],
+        )
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = snapshot(vec![
+            assignment("_1", "2025-03-10 23:59", "a"),
+            assignment("_2", "2025-03-17 23:59", "b"),
+            assignment("_3", "2025-03-24 23:59", "c"),
+            SnapshotItem::new(ItemKind::Content, "_4", "课件", None, &[], &[]),
+        ]);
+        let new = snapshot(vec![
+            assignment("_1", "2025-03-10 23:59", "a"),
+            assignment("_2", "2025-03-19 23:59", "b"),
+            assignment("_3", "2025-03-24 23:59", "c (updated)"),
+            SnapshotItem::new(ItemKind::Announcement, "_5", "停课通知", None, &[], &[]),
+        ]);
+
+        let events = diff(&old, &new);
+        let summary = events
+            .iter()
+            .map(|e| (e.change, e.item, e.id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (EventKind::Removed, ItemKind::Content, "_4"),
+                (EventKind::DeadlineChanged, ItemKind::Assignment, "_2"),
+                (EventKind::Modified, ItemKind::Assignment, "_3"),
+                (EventKind::Added, ItemKind::Announcement, "_5"),
+            ]
+        );
+        assert_eq!(
+            events[1].previous_deadline,
+            datetime::parse("2025-03-17 23:59")
+        );
+        assert_eq!(events[1].deadline, datetime::parse("2025-03-19 23:59"));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let item = |title: &str, desc: &[&str]| {
+            let desc = desc.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            SnapshotItem::new(ItemKind::Announcement, "_1", title, None, &desc, &[])
+        };
+        // stored in snapshots, so it must be stable across builds
+        assert_eq!(item("a", &["b"]).fingerprint, "00877165ce86de08");
+        assert_ne!(item("a", &["b"]).fingerprint, item("ab", &[]).fingerprint);
+        assert_ne!(
+            item("a", &["b", "c"]).fingerprint,
+            item("a", &["bc"]).fingerprint
+        );
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        let s = snapshot(vec![assignment("_1", "2025-03-10 23:59", "a")]);
+        assert!(diff(&s, &s.clone()).is_empty());
+    }
+}
@@ -0,0 +1,583 @@
+//! 课程内容镜像.
+//!
+//! 将课程内容树同步到本地目录：栏目和文件夹对应目录，文档/作业附件对应文件，说明文字写为
+//! Markdown，公告写为带日期的笔记。每个课程目录下的清单文件 ([`MANIFEST_NAME`]) 记录每个文件的
+//! 来源、大小和同步时间，再次同步时只获取新增或变化的内容，并可选择删除远端已移除的文件。
+
+use crate::{
+    api::{Course, CourseContentData, CourseContentKind},
+    datetime,
+};
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use compio::{buf::buf_try, fs};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+/// 课程目录下的清单文件名
+pub const MANIFEST_NAME: &str = ".pku3b-sync.json";
+
+/// 公告所在的目录名
+const ANNOUNCEMENT_DIR: &str = "课程公告";
+
+/// 同步清单，记录由 pku3b 管理的文件
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub course_id: String,
+    pub course_title: String,
+    /// 相对于课程目录的路径 (以 `/` 分隔) -> 文件信息
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ManifestEntry {
+    /// 来源内容的 ID
+    pub source_id: String,
+    /// 远端内容的指纹：附件为下载 URI，笔记为正文的哈希
+    pub fingerprint: String,
+    /// 写入时的文件大小
+    pub size: u64,
+    pub synced_at: DateTime<Utc>,
+}
+
+impl Manifest {
+    /// 读取课程目录下的清单，不存在时返回 `None`
+    pub async fn load(course_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = course_dir.join(MANIFEST_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let buf = fs::read(&path).await?;
+        let r = serde_json::from_slice(&buf)
+            .with_context(|| format!("parse manifest {}", path.display()))?;
+        Ok(Some(r))
+    }
+
+    pub async fn save(&self, course_dir: &Path) -> anyhow::Result<()> {
+        let buf = serde_json::to_vec_pretty(self)?;
+        buf_try!(@try fs::write(course_dir.join(MANIFEST_NAME), buf).await);
+        Ok(())
+    }
+}
+
+/// 本地的一个目标文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// 相对于课程目录的路径 (以 `/` 分隔)
+    pub path: String,
+    pub source_id: String,
+    pub body: TargetBody,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetBody {
+    /// 附件，从 URI 下载
+    Attachment(String),
+    /// Markdown 笔记
+    Note(String),
+}
+
+impl Target {
+    pub fn fingerprint(&self) -> String {
+        match &self.body {
+            TargetBody::Attachment(uri) => uri.to_owned(),
+            // stable across builds, since fingerprints are stored in the manifest
+            TargetBody::Note(text) => format!("{:016x}", crate::cache::fnv1a(text.as_bytes())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ChangeKind {
+    /// 本地尚未同步
+    Added,
+    /// 远端内容变化，或本地文件缺失/被修改
+    Modified,
+    /// 远端已删除
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyncOptions {
+    /// 删除远端已移除的文件
+    pub prune: bool,
+    /// 只计算变化，不写入任何文件
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub course_dir: PathBuf,
+    pub changes: Vec<Change>,
+    pub unchanged: usize,
+    /// 同步失败的文件及错误信息
+    pub failed: Vec<(String, String)>,
+}
+
+/// 将文件名中不能出现的字符替换为 `_`
+pub fn sanitize(name: &str) -> String {
+    let s = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let s = s.trim().trim_end_matches('.').trim_end();
+    if s.is_empty() {
+        "_".to_owned()
+    } else {
+        s.to_owned()
+    }
+}
+
+/// 课程在同步根目录下的目录名
+pub fn course_dir_name(course: &Course) -> String {
+    sanitize(course.meta().title())
+}
+
+/// 根据课程内容和公告计算本地应有的文件
+pub fn plan_targets(
+    contents: &[CourseContentData],
+    announcements: &[CourseContentData],
+) -> Vec<Target> {
+    let folders = contents
+        .iter()
+        .filter(|c| c.is_folder())
+        .map(|c| (c.id(), c))
+        .collect::<HashMap<_, _>>();
+
+    // the order of the content stream is not stable, so sort by id to make paths deterministic
+    let mut items = contents
+        .iter()
+        .filter(|c| !c.is_folder())
+        .collect::<Vec<_>>();
+    items.sort_by(|a, b| a.id().cmp(b.id()));
+
+    let mut used = HashSet::new();
+    let mut targets = Vec::new();
+    let mut push = |dir: &str, name: &str, source_id: &str, body: TargetBody| {
+        let path = unique_path(&mut used, dir, name);
+        targets.push(Target {
+            path,
+            source_id: source_id.to_owned(),
+            body,
+        });
+    };
+
+    for c in items {
+        if matches!(c.kind(), CourseContentKind::Video) {
+            continue;
+        }
+
+        let mut dirs = Vec::new();
+        let mut parent = c.parent_id();
+        while let Some(f) = parent.and_then(|id| folders.get(id)) {
+            dirs.push(sanitize(f.title()));
+            parent = f.parent_id();
+        }
+        dirs.push(sanitize(c.section_name.as_deref().unwrap_or("其他")));
+        dirs.reverse();
+        let dir = dirs.join("/");
+
+        if !c.descriptions().is_empty() || matches!(c.kind(), CourseContentKind::Assignment) {
+            push(
+                &dir,
+                &format!("{}.md", sanitize(c.title())),
+                c.id(),
+                TargetBody::Note(render_note(c)),
+            );
+        }
+        for (name, uri) in c.attachments() {
+            push(
+                &dir,
+                &sanitize(name),
+                c.id(),
+                TargetBody::Attachment(uri.to_owned()),
+            );
+        }
+    }
+
+    let mut announcements = announcements.iter().collect::<Vec<_>>();
+    announcements.sort_by(|a, b| a.id().cmp(b.id()));
+    for a in announcements {
+        let date = a
+            .descriptions()
+            .get(1)
+            .and_then(|s| datetime::parse(s))
+            .map(|t| format!("{} ", t.format("%Y-%m-%d")))
+            .unwrap_or_default();
+        push(
+            ANNOUNCEMENT_DIR,
+            &format!("{date}{}.md", sanitize(a.title())),
+            a.id(),
+            TargetBody::Note(render_announcement(a)),
+        );
+    }
+
+    targets
+}
+
+/// 同一目录下重名时追加序号，如 `a (2).pdf`
+fn unique_path(used: &mut HashSet<String>, dir: &str, name: &str) -> String {
+    let mut path = format!("{dir}/{name}");
+    let mut n = 2;
+    while used.contains(&path) {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+            _ => (name, String::new()),
+        };
+        path = format!("{dir}/{stem} ({n}){ext}");
+        n += 1;
+    }
+    used.insert(path.clone());
+    path
+}
+
+fn render_note(c: &CourseContentData) -> String {
+    let mut s = format!("# {}\n", c.title());
+    for p in c.descriptions() {
+        write!(s, "\n{p}\n").unwrap();
+    }
+    if !c.attachments().is_empty() {
+        s.push_str("\n## 附件\n\n");
+        for (name, _) in c.attachments() {
+            let name = sanitize(name);
+            writeln!(s, "- [{name}](<{name}>)").unwrap();
+        }
+    }
+    s
+}
+
+fn render_announcement(a: &CourseContentData) -> String {
+    // descriptions 的前两项是发布者和发布时间，见 `CourseContentData::from_announcement_element`
+    let desc = a.descriptions();
+    let mut s = format!("# {}\n", a.title());
+    if desc.len() >= 2 {
+        write!(s, "\n> {} · {}\n", desc[0], desc[1]).unwrap();
+    }
+    for p in desc.iter().skip(2) {
+        write!(s, "\n{p}\n").unwrap();
+    }
+    s
+}
+
+/// 比较清单与目标文件，得到需要执行的变化。
+///
+/// `local_size` 返回本地文件的大小，文件缺失或大小与清单不符时会重新获取。
+pub fn diff(
+    manifest: &Manifest,
+    targets: &[Target],
+    local_size: impl Fn(&str) -> Option<u64>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for t in targets {
+        let kind = match manifest.files.get(&t.path) {
+            None => ChangeKind::Added,
+            Some(e) if e.fingerprint != t.fingerprint() => ChangeKind::Modified,
+            Some(e) if local_size(&t.path) != Some(e.size) => ChangeKind::Modified,
+            Some(_) => continue,
+        };
+        changes.push(Change {
+            kind,
+            path: t.path.clone(),
+        });
+    }
+
+    let wanted = targets
+        .iter()
+        .map(|t| t.path.as_str())
+        .collect::<HashSet<_>>();
+    for path in manifest.files.keys() {
+        if !wanted.contains(path.as_str()) {
+            changes.push(Change {
+                kind: ChangeKind::Removed,
+                path: path.clone(),
+            });
+        }
+    }
+
+    changes
+}
+
+fn local_path(course_dir: &Path, rel: &str) -> PathBuf {
+    let mut p = course_dir.to_owned();
+    p.extend(rel.split('/'));
+    p
+}
+
+/// 获取课程内容，计算本地应有的文件
+pub async fn collect_targets(course: &Course) -> anyhow::Result<Vec<Target>> {
+    let mut stream = course.content_stream();
+    let mut contents = Vec::new();
+    while let Some(batch) = stream.next_batch().await {
+        contents.extend(batch);
+    }
+
+    let announcements = course
+        .list_announcements()
+        .await
+        .context("list announcements")?
+        .into_iter()
+        .map(|h| h.content.as_ref().clone())
+        .collect::<Vec<_>>();
+
+    Ok(plan_targets(&contents, &announcements))
+}
+
+/// 将课程同步到 `root` 下以课程名命名的目录中。
+///
+/// 每处理一个变化前调用 `progress(index, total, change)`。单个文件失败不会中止同步，
+/// 会记录在 [`SyncReport::failed`] 中。
+pub async fn sync_course(
+    course: &Course,
+    root: &Path,
+    opts: SyncOptions,
+    mut progress: impl FnMut(usize, usize, &Change),
+) -> anyhow::Result<SyncReport> {
+    let course_dir = root.join(course_dir_name(course));
+    let targets = collect_targets(course).await?;
+
+    let mut manifest = Manifest::load(&course_dir).await?.unwrap_or_default();
+    manifest.course_id = course.meta().id().to_owned();
+    manifest.course_title = course.meta().title().to_owned();
+
+    let changes = diff(&manifest, &targets, |rel| {
+        std::fs::metadata(local_path(&course_dir, rel))
+            .ok()
+            .map(|m| m.len())
+    });
+    let unchanged = targets.len()
+        - changes
+            .iter()
+            .filter(|c| c.kind != ChangeKind::Removed)
+            .count();
+
+    let mut report = SyncReport {
+        course_dir: course_dir.clone(),
+        changes: Vec::new(),
+        unchanged,
+        failed: Vec::new(),
+    };
+    if opts.dry_run {
+        report.changes = changes;
+        return Ok(report);
+    }
+
+    let targets = targets
+        .iter()
+        .map(|t| (t.path.as_str(), t))
+        .collect::<HashMap<_, _>>();
+    let total = changes.len();
+    for (idx, change) in changes.into_iter().enumerate() {
+        progress(idx + 1, total, &change);
+
+        let path = local_path(&course_dir, &change.path);
+        let r = match change.kind {
+            ChangeKind::Removed if !opts.prune => {
+                report.changes.push(change);
+                continue;
+            }
+            ChangeKind::Removed => remove_file(&course_dir, &path).await.map(|()| {
+                manifest.files.remove(&change.path);
+            }),
+            ChangeKind::Added | ChangeKind::Modified => {
+                let t = targets[change.path.as_str()];
+                write_target(course, t, &path).await.map(|size| {
+                    manifest.files.insert(
+                        change.path.clone(),
+                        ManifestEntry {
+                            source_id: t.source_id.clone(),
+                            fingerprint: t.fingerprint(),
+                            size,
+                            synced_at: Utc::now(),
+                        },
+                    );
+                })
+            }
+        };
+
+        match r {
+            Ok(()) => report.changes.push(change),
+            Err(e) => {
+                log::warn!("sync {} failed: {e:#}", change.path);
+                report.failed.push((change.path, format!("{e:#}")));
+            }
+        }
+    }
+
+    fs::create_dir_all(&course_dir).await?;
+    manifest.save(&course_dir).await?;
+
+    Ok(report)
+}
+
+/// 写入目标文件，返回文件大小。先写入临时文件再重命名，避免留下不完整的文件。
+async fn write_target(course: &Course, t: &Target, path: &Path) -> anyhow::Result<u64> {
+    let dir = path.parent().context("invalid path")?;
+    fs::create_dir_all(dir).await?;
+
+    let part = crate::utils::with_suffix(path, ".part");
+
+    match &t.body {
+        TargetBody::Attachment(uri) => course
+            .download_attachment(uri, &part)
+            .await
+            .with_context(|| format!("download {}", t.path))?,
+        TargetBody::Note(text) => {
+            buf_try!(@try fs::write(&part, text.clone().into_bytes()).await);
+        }
+    }
+    fs::rename(&part, path).await?;
+
+    Ok(std::fs::metadata(path)?.len())
+}
+
+/// 删除文件，并清理由此变空的目录
+async fn remove_file(course_dir: &Path, path: &Path) -> anyhow::Result<()> {
+    if path.exists() {
+        fs::remove_file(path).await?;
+    }
+    let mut dir = path.parent();
+    while let Some(d) = dir
+        && d != course_dir
+        && d.starts_with(course_dir)
+    {
+        if std::fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(
+        id: &str,
+        title: &str,
+        kind: &str,
+        parent_id: Option<&str>,
+        attachments: &[(&str, &str)],
+    ) -> CourseContentData {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "title": title,
+            "kind": kind,
+            "has_link": kind == "Folder",
+            "descriptions": if kind == "Assignment" { vec!["请按时提交"] } else { vec![] },
+            "attachments": attachments,
+            "parent_id": parent_id,
+            "parent_title": null,
+            "depth": 0,
+            "section_name": "课程课件",
+            "is_folder": kind == "Folder",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("第1讲: 绪论/导论?.pdf"), "第1讲_ 绪论_导论_.pdf");
+        assert_eq!(sanitize(" .. "), "_");
+        assert_eq!(sanitize("a\tb. "), "a_b");
+    }
+
+    #[test]
+    fn test_plan_targets() {
+        let contents = [
+            content("_2", "Week 1", "Folder", None, &[]),
+            content(
+                "_3",
+                "slides",
+                "Document",
+                Some("_2"),
+                &[("l1.pdf", "/u/1")],
+            ),
+            content(
+                "_4",
+                "slides",
+                "Document",
+                Some("_2"),
+                &[("l1.pdf", "/u/2")],
+            ),
+            content("_5", "HW1", "Assignment", None, &[("hw1.pdf", "/u/3")]),
+        ];
+        let targets = plan_targets(&contents, &[]);
+        let paths = targets.iter().map(|t| t.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "课程课件/Week 1/l1.pdf",
+                "课程课件/Week 1/l1 (2).pdf",
+                "课程课件/HW1.md",
+                "课程课件/hw1.pdf",
+            ]
+        );
+        assert_eq!(targets[0].fingerprint(), "/u/1");
+        assert!(matches!(&targets[2].body, TargetBody::Note(s) if s.contains("请按时提交")));
+        // notes are fingerprinted by a stable hash of their text
+        let note = Target {
+            body: TargetBody::Note("hello".to_owned()),
+            ..targets[2].clone()
+        };
+        assert_eq!(note.fingerprint(), "a430d84680aabd0b");
+    }
+
+    #[test]
+    fn test_diff() {
+        let target = |path: &str, uri: &str| Target {
+            path: path.to_owned(),
+            source_id: "_1".to_owned(),
+            body: TargetBody::Attachment(uri.to_owned()),
+        };
+        let entry = |uri: &str| ManifestEntry {
+            source_id: "_1".to_owned(),
+            fingerprint: uri.to_owned(),
+            size: 10,
+            synced_at: Utc::now(),
+        };
+        let manifest = Manifest {
+            files: [
+                ("same".to_owned(), entry("/a")),
+                ("replaced".to_owned(), entry("/b")),
+                ("missing".to_owned(), entry("/c")),
+                ("gone".to_owned(), entry("/d")),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let targets = [
+            target("same", "/a"),
+            target("replaced", "/b2"),
+            target("missing", "/c"),
+            target("new", "/e"),
+        ];
+        let changes = diff(&manifest, &targets, |p| (p != "missing").then_some(10));
+        let changes = changes
+            .iter()
+            .map(|c| (c.kind, c.path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [
+                (ChangeKind::Modified, "replaced"),
+                (ChangeKind::Modified, "missing"),
+                (ChangeKind::Added, "new"),
+                (ChangeKind::Removed, "gone"),
+            ]
+        );
+    }
+}
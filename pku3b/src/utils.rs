@@ -1,5 +1,3 @@
-use compio::{buf::buf_try, fs, io::AsyncReadAtExt};
-
 pub mod style {
     use clap::builder::styling::{AnsiColor, Color, Style};
 
@@ -24,83 +22,165 @@ pub fn default_config_path() -> std::path::PathBuf {
     crate::utils::projectdir().config_dir().join("cfg.toml")
 }
 
-/// If the cache file exists and is not expired, return the deserialized content.
-/// Otherwise, execute the future, serialize the result to the cache file, and return the result.
-pub async fn with_cache<T, F>(
-    name: &str,
-    ttl: Option<&std::time::Duration>,
-    fut: F,
-) -> anyhow::Result<T>
-where
-    F: std::future::Future<Output = anyhow::Result<T>>,
-    T: serde::de::DeserializeOwned + serde::Serialize + 'static,
-{
-    let name_hash = {
-        use std::hash::{Hash, Hasher};
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        name.hash(&mut hasher);
-        let type_id = std::any::TypeId::of::<T>();
-        type_id.hash(&mut hasher);
-        hasher.finish()
-    };
-    let name = format!("with_cache-{:x}", name_hash);
-
-    let path = &projectdir().cache_dir().join(&name);
-
-    if let Ok(f) = fs::File::open(path).await {
-        if let Some(ttl) = ttl {
-            if f.metadata().await?.modified()?.elapsed()? < *ttl {
-                let r = f.read_to_end_at(Vec::new(), 0).await;
-                let (_, buf) = buf_try!(@try r);
-                // ignore deserialization error
-                if let Ok(r) = serde_json::from_slice(&buf) {
-                    log::trace!("cache hit: {}", name);
-                    return Ok(r);
-                }
-            }
+/// Parse a human-friendly duration such as `90s`, `30m`, `48h`, `2d`, `1w` or `1d12h`.
+/// A bare number is interpreted as hours.
+pub fn parse_duration(s: &str) -> anyhow::Result<std::time::Duration> {
+    let s = s.trim();
+    anyhow::ensure!(!s.is_empty(), "empty duration");
+
+    if let Ok(h) = s.parse::<u64>() {
+        return Ok(std::time::Duration::from_secs(h * 3600));
+    }
+
+    let mut total = 0u64;
+    let mut num = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
         }
+        let n: u64 = num
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid duration {s:?}"))?;
+        num.clear();
+        total += n * match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 86400 * 7,
+            _ => anyhow::bail!("invalid duration unit {c:?} in {s:?}"),
+        };
     }
+    anyhow::ensure!(num.is_empty(), "missing unit in duration {s:?}");
 
-    let r = fut.await?;
-    fs::create_dir_all(path.parent().unwrap()).await?;
-    let buf = serde_json::to_vec(&r)?;
-    buf_try!(@try fs::write(path, buf).await);
+    Ok(std::time::Duration::from_secs(total))
+}
 
-    Ok(r)
+/// Parse the start of a time range: a date (and time) such as `2024-09-01` or `2024-09-01 08:00`
+/// in Beijing time, or a duration before now such as `2w` (see [`parse_duration`]).
+pub fn parse_since(s: &str) -> anyhow::Result<chrono::DateTime<chrono::Local>> {
+    if let Some(t) = crate::datetime::parse_local(s) {
+        return Ok(t);
+    }
+    let d = parse_duration(s).map_err(|_| anyhow::anyhow!("invalid date or duration {s:?}"))?;
+    Ok(chrono::Local::now() - d)
 }
 
-pub async fn with_cache_bytes<F>(
-    name: &str,
-    ttl: Option<&std::time::Duration>,
-    fut: F,
-) -> anyhow::Result<bytes::Bytes>
-where
-    F: std::future::Future<Output = anyhow::Result<bytes::Bytes>>,
-{
-    let name_hash = {
-        use std::hash::{Hash, Hasher};
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        name.hash(&mut hasher);
-        hasher.finish()
+/// Parse a human-friendly size such as `512M`, `2G` or `1.5GB`. A bare number is in bytes.
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let upper = s.to_ascii_uppercase();
+    let num = upper.trim_end_matches(['B', 'I']);
+    let (num, unit) = match num.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&num[..i], c),
+        _ => (num, ' '),
     };
-    let name = format!("with_cache_bytes-{:x}", name_hash);
-
-    let path = &projectdir().cache_dir().join(&name);
-
-    if let Ok(f) = fs::File::open(path).await {
-        if let Some(ttl) = ttl {
-            if f.metadata().await?.modified()?.elapsed()? < *ttl {
-                let r = f.read_to_end_at(Vec::new(), 0).await;
-                let (_, buf) = buf_try!(@try r);
-                log::trace!("cache hit: {}", name);
-                return Ok(bytes::Bytes::from(buf));
-            }
-        }
+    let shift = match unit {
+        ' ' => 0,
+        'K' => 10,
+        'M' => 20,
+        'G' => 30,
+        'T' => 40,
+        _ => anyhow::bail!("invalid size unit in {s:?}"),
+    };
+    let n: f64 = num
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid size {s:?}"))?;
+    anyhow::ensure!(n >= 0.0, "invalid size {s:?}");
+    Ok((n * (1u64 << shift) as f64) as u64)
+}
+
+/// Format a byte count like `1.50 GB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut n = bytes as f64;
+    let mut unit = 0;
+    while n >= 1024.0 && unit + 1 < UNITS.len() {
+        n /= 1024.0;
+        unit += 1;
     }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{n:.2} {}", UNITS[unit])
+    }
+}
+
+/// Append `suffix` to the file name without replacing its extension, e.g. `a.mp4` → `a.mp4.part`.
+pub fn with_suffix(path: &std::path::Path, suffix: &str) -> std::path::PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(suffix);
+    p.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
 
-    let r = fut.await?;
-    fs::create_dir_all(path.parent().unwrap()).await?;
-    let (_, r) = buf_try!(@try fs::write(path, r).await);
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(
+            parse_duration("48h").unwrap(),
+            Duration::from_secs(48 * 3600)
+        );
+        assert_eq!(
+            parse_duration("2d").unwrap(),
+            Duration::from_secs(2 * 86400)
+        );
+        assert_eq!(
+            parse_duration("1w").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert_eq!(
+            parse_duration("1d12h").unwrap(),
+            Duration::from_secs(36 * 3600)
+        );
+        assert_eq!(
+            parse_duration("24").unwrap(),
+            Duration::from_secs(24 * 3600)
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("3x").is_err());
+        assert!(parse_duration("1h30").is_err());
+    }
 
-    Ok(r)
+    #[test]
+    fn test_parse_since() {
+        // dates are in Beijing time
+        let t = parse_since("2024-09-01").unwrap();
+        assert_eq!(t.timestamp(), 1725120000);
+        let t = parse_since("2024-09-01 08:30").unwrap();
+        assert_eq!(t.timestamp(), 1725150600);
+        let ago = chrono::Local::now() - parse_since("2w").unwrap();
+        assert!((ago.num_seconds() - 14 * 86400).abs() < 60);
+        assert!(parse_since("last week").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("2G").unwrap(), 2 << 30);
+        assert_eq!(parse_size("2GB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1.5gib").unwrap(), 3 << 29);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("3X").is_err());
+        assert_eq!(format_size(1000), "1000 B");
+        assert_eq!(format_size(3 << 29), "1.50 GB");
+    }
+
+    #[test]
+    fn test_with_suffix() {
+        let p = std::path::Path::new("dir/a.b.mp4");
+        assert_eq!(
+            with_suffix(p, ".part"),
+            std::path::Path::new("dir/a.b.mp4.part")
+        );
+    }
 }
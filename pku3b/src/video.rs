@@ -0,0 +1,926 @@
+//! 课程回放的下载.
+//!
+//! [`download_segments`] 用固定数量的并发任务下载 HLS 回放的全部分片。单个分片失败时
+//! 按指数退避重试，结果按分片顺序返回。已经下载过的分片会被跳过，因此中断后可以继续下载。
+//! [`export_hls`] 在此基础上生成指向这些分片的播放列表，得到可以离线播放的 HLS 目录。
+//! [`download_ts`] 则把解密后的分片按顺序直接追加到一个 TS 文件中，不保留分片文件，
+//! 用检查点记录进度以便断点续传。
+//!
+//! [`save`] 在此基础上把一个回放保存为 MP4、TS 或音频文件，[`save_batch`] 则按文件名模板
+//! 并发保存多个回放，跳过已经存在的文件。
+//! 不使用 HLS 的回放由 [`download_file`] 分块下载，同样支持断点续传。
+
+use crate::{
+    api::{CourseVideo, CourseVideoHandle, VideoSelector},
+    utils::with_suffix,
+    watch::Backoff,
+};
+use anyhow::Context as _;
+use compio::{buf::buf_try, fs};
+use futures_util::StreamExt as _;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// 重试等待时间的上限
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadOptions {
+    /// 同时下载的分片数量
+    pub jobs: usize,
+    /// 每个分片失败后的最多重试次数
+    pub retries: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    pub retry_delay: Duration,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            jobs: 4,
+            retries: 3,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// 以最多 `opts.jobs` 个并发任务对 `0..total` 中的每个下标执行 `task`，失败的任务最多重试
+/// `opts.retries` 次。
+///
+/// 结果按下标顺序返回。每完成一个任务调用一次 `progress(done, total)`。任一任务重试后仍然失败时，
+/// 取消其余任务并返回该错误。
+pub async fn run_pool<T, F, Fut>(
+    total: usize,
+    opts: DownloadOptions,
+    task: F,
+    mut progress: impl FnMut(usize, usize),
+) -> anyhow::Result<Vec<T>>
+where
+    F: Fn(usize) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let task = &task;
+    let attempt = |i: usize| async move {
+        (
+            i,
+            with_retries(opts, &format!("task #{i}"), || task(i)).await,
+        )
+    };
+
+    let mut slots = (0..total).map(|_| None).collect::<Vec<_>>();
+    let mut results = futures_util::stream::iter(0..total)
+        .map(attempt)
+        .buffer_unordered(opts.jobs.max(1));
+    let mut done = 0;
+    while let Some((i, r)) = results.next().await {
+        slots[i] = Some(r?);
+        done += 1;
+        progress(done, total);
+    }
+
+    Ok(slots
+        .into_iter()
+        .map(|r| r.expect("every task yields a result"))
+        .collect())
+}
+
+/// 执行 `task`，失败后按指数退避最多重试 `opts.retries` 次
+pub(crate) async fn with_retries<T, Fut>(
+    opts: DownloadOptions,
+    name: &str,
+    task: impl Fn() -> Fut,
+) -> anyhow::Result<T>
+where
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut backoff = Backoff::new(opts.retry_delay, MAX_RETRY_DELAY);
+    loop {
+        match task().await {
+            Ok(r) => return Ok(r),
+            // the replay can never be decrypted, retrying is pointless
+            Err(e) if e.is::<crate::api::UnsupportedEncryption>() => return Err(e),
+            Err(e) if backoff.failures() < opts.retries => {
+                let delay = backoff.fail();
+                log::warn!("{name} failed, retry in {delay:?}: {e:#}");
+                compio::time::sleep(delay).await;
+            }
+            Err(e) => {
+                return Err(e.context(format!(
+                    "{name} failed after {} retries",
+                    backoff.failures()
+                )));
+            }
+        }
+    }
+}
+
+/// 分片在下载目录中的文件名
+pub fn segment_filename(index: usize) -> String {
+    format!("{index:05}.ts")
+}
+
+/// 依次求出每个分片使用的密钥 (见 [`CourseVideo::refresh_key`])。
+///
+/// 一个分片的密钥取决于它之前的分片，因此在并发下载之前一次性求出
+pub fn resolve_keys(v: &CourseVideo) -> Vec<Option<&m3u8_rs::Key>> {
+    let mut key = None;
+    (0..v.len_segments())
+        .map(|i| {
+            key = v.refresh_key(i, key);
+            key
+        })
+        .collect()
+}
+
+/// 将回放的全部分片 (已解密) 下载到 `dir`，按顺序返回分片文件的路径。
+///
+/// `dir` 中已存在的分片不会重新下载，因此分片不再经过缓存。每完成一个分片调用一次
+/// `progress(done, total)`。
+pub async fn download_segments(
+    v: &CourseVideo,
+    dir: &Path,
+    opts: DownloadOptions,
+    progress: impl FnMut(usize, usize),
+) -> anyhow::Result<Vec<PathBuf>> {
+    anyhow::ensure!(dir.exists(), "dir {} not exists", dir.display());
+
+    let keys = resolve_keys(v);
+
+    run_pool(
+        keys.len(),
+        opts,
+        |i| {
+            let key = keys[i];
+            async move {
+                let path = dir.join(segment_filename(i));
+                if path.exists() {
+                    return Ok(path);
+                }
+
+                log::debug!("segment #{i} key: {key:?}");
+                let seg = v
+                    .fetch_segment_data(i, key)
+                    .await
+                    .with_context(|| format!("get segment #{i} with key {key:?}"))?;
+
+                // fs::write is not atomic, so we write to a tmp file first
+                let tmpath = path.with_extension("tmp");
+                buf_try!(@try fs::write(&tmpath, seg).await);
+                fs::rename(&tmpath, &path)
+                    .await
+                    .context("rename tmp file")?;
+                Ok(path)
+            }
+        },
+        progress,
+    )
+    .await
+}
+
+/// [`export_hls`] 生成的播放列表的文件名
+pub const HLS_PLAYLIST: &str = "index.m3u8";
+
+/// 将 HLS 回放导出为可以离线播放的目录 `dir`，返回其中播放列表的路径。
+///
+/// 目录中包含解密后的分片 (文件名见 [`segment_filename`]) 和播放列表 [`HLS_PLAYLIST`]，后者以相对
+/// 路径指向各分片且不含 `EXT-X-KEY`，播放器可以直接打开并任意跳转，无需先合并分片。分片的下载方式见
+/// [`download_segments`]，中断后再次调用会继续下载。播放列表在全部分片完成后才写入，因此它存在即表示
+/// 导出完成。`dir` 应当只用于一个回放。
+pub async fn export_hls(
+    v: &CourseVideo,
+    dir: &Path,
+    opts: DownloadOptions,
+    progress: impl FnMut(usize, usize),
+) -> anyhow::Result<PathBuf> {
+    let playlist = v.local_playlist(segment_filename)?;
+    fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("create dir {}", dir.display()))?;
+    download_segments(v, dir, opts, progress).await?;
+
+    let path = dir.join(HLS_PLAYLIST);
+    let tmp = with_suffix(&path, ".tmp");
+    buf_try!(@try fs::write(&tmp, playlist.into_bytes()).await);
+    fs::rename(&tmp, &path)
+        .await
+        .context("rename tmp playlist")?;
+    Ok(path)
+}
+
+/// [`download_ts`] 的进度，保存在输出文件旁的 `.ckpt` 文件中
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct Checkpoint {
+    /// 回放的分片总数，与当前播放列表不符时从头下载
+    total: usize,
+    /// 已写入的分片数量
+    segments: usize,
+    /// 已写入的字节数
+    offset: u64,
+}
+
+/// 检查分片是否为完整的 MPEG-TS：长度为 188 的整数倍，且每个包以同步字节开头
+pub fn check_ts(data: &[u8]) -> anyhow::Result<()> {
+    const PACKET: usize = 188;
+    anyhow::ensure!(!data.is_empty(), "empty segment");
+    anyhow::ensure!(
+        data.len().is_multiple_of(PACKET),
+        "length {} is not a multiple of {PACKET}",
+        data.len()
+    );
+    if let Some(i) = data.chunks_exact(PACKET).position(|p| p[0] != 0x47) {
+        anyhow::bail!("sync byte missing at packet {i}");
+    }
+    Ok(())
+}
+
+/// 将 HLS 回放解密后的全部分片按顺序写入一个 TS 文件 `dst`，不保留单独的分片文件。
+///
+/// 分片以最多 `opts.jobs` 个并发任务下载 (不经过缓存)，检查 TS 包结构后依次追加到 `dst.part`，
+/// 每写完一个分片更新检查点 `dst.ckpt` (已完成的分片数和字节数)。中断后再次调用会截去检查点之后
+/// 的数据并从下一个分片继续，完成后重命名为 `dst` 并删除检查点。每写完一个分片调用一次
+/// `progress(done, total)`。
+pub async fn download_ts(
+    v: &CourseVideo,
+    dst: &Path,
+    opts: DownloadOptions,
+    mut progress: impl FnMut(usize, usize),
+) -> anyhow::Result<()> {
+    anyhow::ensure!(v.file_url().is_none(), "not an HLS replay");
+    let total = v.len_segments();
+    let (part, ckpt) = (with_suffix(dst, ".part"), with_suffix(dst, ".ckpt"));
+
+    let saved = fs::read(&ckpt)
+        .await
+        .ok()
+        .and_then(|b| serde_json::from_slice::<Checkpoint>(&b).ok());
+    let part_len = fs::metadata(&part).await.map_or(0, |m| m.len());
+    let mut cp = match saved {
+        Some(cp) if cp.total == total && cp.segments <= total && cp.offset <= part_len => cp,
+        _ => Checkpoint {
+            total,
+            segments: 0,
+            offset: 0,
+        },
+    };
+    if cp.segments > 0 {
+        log::info!(
+            "resume {} from segment {}/{total}, byte {}",
+            dst.display(),
+            cp.segments,
+            cp.offset
+        );
+    }
+    if part_len > cp.offset {
+        // drop whatever was written after the last checkpoint
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&part)
+            .and_then(|f| f.set_len(cp.offset))
+            .with_context(|| format!("truncate {}", part.display()))?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(&part)
+        .await
+        .with_context(|| format!("open {}", part.display()))?;
+
+    let keys = resolve_keys(v);
+
+    // segments are fetched concurrently but written in order
+    let fetch = |i: usize| {
+        let key = keys[i];
+        let task = move || async move {
+            let data = v
+                .fetch_segment_data(i, key)
+                .await
+                .with_context(|| format!("get segment #{i} with key {key:?}"))?;
+            check_ts(&data).with_context(|| format!("segment #{i} is not a valid MPEG-TS"))?;
+            Ok(data)
+        };
+        async move { with_retries(opts, &format!("segment #{i}"), task).await }
+    };
+    let mut segments = futures_util::stream::iter(cp.segments..total)
+        .map(fetch)
+        .buffered(opts.jobs.max(1));
+    progress(cp.segments, total);
+    while let Some(data) = segments.next().await {
+        let data = data?;
+        let n = data.len() as u64;
+        buf_try!(@try compio::io::AsyncWriteAtExt::write_all_at(&mut file, data, cp.offset).await);
+        cp.segments += 1;
+        cp.offset += n;
+
+        // the checkpoint is replaced atomically, and never runs ahead of the data
+        file.sync_data().await.context("sync part file")?;
+        let tmp = with_suffix(&ckpt, ".tmp");
+        buf_try!(@try fs::write(&tmp, serde_json::to_vec(&cp)?).await);
+        fs::rename(&tmp, &ckpt).await.context("rename checkpoint")?;
+        progress(cp.segments, total);
+    }
+
+    file.close().await.context("close part file")?;
+    fs::rename(&part, dst)
+        .await
+        .with_context(|| format!("rename {} file", dst.display()))?;
+    fs::remove_file(&ckpt).await.ok();
+    Ok(())
+}
+
+/// 从下载的分片中提取音频，直接读取各个分片而不合并。
+///
+/// `dst` 的扩展名为 `aac` 时输出 ADTS 格式的 AAC 裸流，否则输出 M4A。已存在的 `dst` 会被覆盖。
+#[cfg(feature = "remux")]
+pub async fn extract_audio(
+    paths: &[PathBuf],
+    dst: &Path,
+) -> anyhow::Result<crate::remux::RemuxStats> {
+    use crate::remux::Output;
+
+    let format = match dst.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("aac") => Output::Adts,
+        _ => Output::M4a,
+    };
+    let (paths, dst) = (paths.to_vec(), dst.to_owned());
+    let stats =
+        compio::runtime::spawn_blocking(move || crate::remux::remux_files(&paths, &dst, format))
+            .await
+            .map_err(|_| anyhow::anyhow!("remux panicked"))??;
+    log::info!(
+        "extracted {} audio samples, duration {:?}",
+        stats.audio_samples,
+        stats.duration
+    );
+    Ok(stats)
+}
+
+/// 视频文件回放每次请求的字节数
+pub const FILE_CHUNK_SIZE: u64 = 8 << 20;
+
+/// 视频文件回放的扩展名，取自地址的路径，缺省为 `mp4`
+pub fn file_extension(v: &CourseVideo) -> String {
+    v.file_url()
+        .and_then(|u| u.path_segments()?.next_back().map(ToOwned::to_owned))
+        .and_then(|name| Some(name.rsplit_once('.')?.1.to_ascii_lowercase()))
+        .filter(|ext| !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or_else(|| "mp4".to_owned())
+}
+
+/// [`download_file`] 下载的远端文件，保存在输出文件旁的 `.ckpt` 文件中
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct RemoteFile {
+    total: Option<u64>,
+    /// 见 [`crate::api::FileChunk::validator`]
+    validator: Option<String>,
+}
+
+/// 将视频文件回放下载到 `dst`，支持断点续传。
+///
+/// 数据按 [`FILE_CHUNK_SIZE`] 分块用 Range 请求写入 `dst` 旁的 `.part` 文件，中断后再次调用会从
+/// 已下载的位置继续，完成后重命名为 `dst`。远端文件的长度和 `ETag` (或 `Last-Modified`) 记录在
+/// `dst.ckpt` 中，与服务器返回的不一致 (文件已经变化) 或没有记录时从头下载。每块失败后最多重试
+/// `opts.retries` 次，每完成一块调用一次 `progress(已下载的字节数, 总字节数)`。
+pub async fn download_file(
+    v: &CourseVideo,
+    dst: &Path,
+    opts: DownloadOptions,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> anyhow::Result<()> {
+    anyhow::ensure!(v.file_url().is_some(), "not a video file replay");
+
+    let (part, ckpt) = (with_suffix(dst, ".part"), with_suffix(dst, ".ckpt"));
+    let mut saved = fs::read(&ckpt)
+        .await
+        .ok()
+        .and_then(|b| serde_json::from_slice::<RemoteFile>(&b).ok());
+    // a part file without a checkpoint cannot be verified
+    let mut offset = match saved {
+        Some(_) => fs::metadata(&part).await.map_or(0, |m| m.len()),
+        None => 0,
+    };
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(offset == 0)
+        .open(&part)
+        .await
+        .with_context(|| format!("open {}", part.display()))?;
+    if offset > 0 {
+        log::info!("resume {} from byte {offset}", dst.display());
+    }
+
+    loop {
+        let chunk = with_retries(opts, &format!("download bytes from {offset}"), || {
+            v.get_file_chunk(offset, FILE_CHUNK_SIZE)
+        })
+        .await?;
+        let remote = RemoteFile {
+            total: chunk.total,
+            validator: chunk.validator.clone(),
+        };
+        match &saved {
+            Some(s) if *s != remote => {
+                log::warn!("the file changed on the server, restart from the beginning");
+                file = fs::File::create(&part)
+                    .await
+                    .with_context(|| format!("create {}", part.display()))?;
+                (offset, saved) = (0, None);
+                continue;
+            }
+            Some(_) => {}
+            None => {
+                let tmp = with_suffix(&ckpt, ".tmp");
+                buf_try!(@try fs::write(&tmp, serde_json::to_vec(&remote)?).await);
+                fs::rename(&tmp, &ckpt).await.context("rename checkpoint")?;
+                saved = Some(remote);
+            }
+        }
+        if chunk.offset != offset {
+            // the server ignored the range request and sent the whole file
+            log::warn!("server does not support range requests, restart from the beginning");
+            file = fs::File::create(&part)
+                .await
+                .with_context(|| format!("create {}", part.display()))?;
+        }
+
+        let n = chunk.data.len() as u64;
+        if n > 0 {
+            buf_try!(@try compio::io::AsyncWriteAtExt::write_all_at(&mut file, chunk.data, chunk.offset).await);
+        }
+        offset = chunk.offset + n;
+        progress(offset, chunk.total);
+
+        match chunk.total {
+            Some(total) if offset >= total => break,
+            Some(total) => anyhow::ensure!(n > 0, "empty response at byte {offset} of {total}"),
+            // without a known length, a short chunk ends the file
+            None if n < FILE_CHUNK_SIZE => break,
+            None => {}
+        }
+    }
+    file.close().await.context("close part file")?;
+    fs::rename(&part, dst)
+        .await
+        .with_context(|| format!("rename {} file", dst.display()))?;
+    fs::remove_file(&ckpt).await.ok();
+    Ok(())
+}
+
+/// 将合并后的 TS 文件转为 MP4，不重新编码。
+///
+/// 启用 `remux` feature 且 `ffmpeg` 为 `false` 时使用内置的 [`crate::remux`]，否则调用
+/// `ffmpeg -c copy`。已存在的 `dst` 会被覆盖。
+pub async fn convert_to_mp4(src: &Path, dst: &Path, ffmpeg: bool) -> anyhow::Result<()> {
+    #[cfg(feature = "remux")]
+    if !ffmpeg {
+        let (src, dst) = (src.to_owned(), dst.to_owned());
+        let stats = compio::runtime::spawn_blocking(move || crate::remux::remux_file(&src, &dst))
+            .await
+            .map_err(|_| anyhow::anyhow!("remux panicked"))??;
+        log::info!(
+            "remuxed {} video and {} audio samples, duration {:?}",
+            stats.video_samples,
+            stats.audio_samples,
+            stats.duration
+        );
+        return Ok(());
+    }
+    #[cfg(not(feature = "remux"))]
+    let _ = ffmpeg;
+
+    let c = compio::process::Command::new("ffmpeg")
+        .args(["-y", "-hide_banner", "-loglevel", "quiet"])
+        .arg("-i")
+        .arg(src)
+        .args(["-c", "copy"])
+        .arg(dst)
+        .output()
+        .await
+        .context("execute ffmpeg")?;
+    anyhow::ensure!(
+        c.status.success(),
+        "ffmpeg failed with exit code {:?}",
+        c.status.code()
+    );
+    Ok(())
+}
+
+/// 回放的保存格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// 转封装为 MP4
+    Mp4,
+    /// 解密后合并的 MPEG-TS
+    Ts,
+    /// 只提取音频，MP4 封装
+    M4a,
+    /// 只提取音频，ADTS 格式的 AAC 裸流
+    Aac,
+    /// 可以离线播放的 HLS 目录，见 [`export_hls`]
+    Hls,
+}
+
+impl SaveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Ts => "ts",
+            Self::M4a => "m4a",
+            Self::Aac => "aac",
+            Self::Hls => "m3u8",
+        }
+    }
+
+    pub fn is_audio(self) -> bool {
+        matches!(self, Self::M4a | Self::Aac)
+    }
+}
+
+/// [`save`] 的进度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveProgress {
+    /// 已写入的分片数和分片总数
+    Segments(usize, usize),
+    /// 视频文件回放已下载的字节数和总字节数
+    Bytes(u64, Option<u64>),
+    /// 下载完成，正在转换格式
+    Converting,
+}
+
+/// 回放以 `format` 保存到 `{stem}.{扩展名}` 时的路径。视频文件回放除提取音频外保持原扩展名，
+/// HLS 目录为 `{stem}/index.m3u8`
+pub fn output_path(v: &CourseVideo, stem: &Path, format: SaveFormat) -> PathBuf {
+    if v.file_url().is_some() && !format.is_audio() && format != SaveFormat::Hls {
+        return with_suffix(stem, &format!(".{}", file_extension(v)));
+    }
+    format_output_path(stem, format)
+}
+
+/// 只由 `format` 决定的输出路径，即 HLS 回放的 [`output_path`]，不需要先获取回放
+fn format_output_path(stem: &Path, format: SaveFormat) -> PathBuf {
+    match format {
+        SaveFormat::Hls => stem.join(HLS_PLAYLIST),
+        f => with_suffix(stem, &format!(".{}", f.extension())),
+    }
+}
+
+/// 下载回放并以 `format` 保存到 [`output_path`]，返回该路径。
+///
+/// 需要转换格式时，解密后的 TS 文件先写入 `work_dir`，成功后删除整个 `work_dir`；失败时保留，
+/// 再次调用会从中断处继续。直接保存 (TS 或视频文件回放) 时断点续传所需的文件位于输出文件旁，
+/// 导出 HLS 目录时则是目录中已下载的分片。
+/// `ffmpeg` 含义同 [`convert_to_mp4`]。
+pub async fn save(
+    v: &CourseVideo,
+    stem: &Path,
+    format: SaveFormat,
+    work_dir: &Path,
+    opts: DownloadOptions,
+    ffmpeg: bool,
+    mut progress: impl FnMut(SaveProgress),
+) -> anyhow::Result<PathBuf> {
+    let dst = output_path(v, stem, format);
+    let is_file = v.file_url().is_some();
+    if format == SaveFormat::Hls {
+        anyhow::ensure!(
+            !is_file,
+            "HLS export is not supported for video file replays"
+        );
+        return export_hls(v, stem, opts, |done, total| {
+            progress(SaveProgress::Segments(done, total))
+        })
+        .await;
+    }
+    if format.is_audio() {
+        anyhow::ensure!(
+            cfg!(feature = "remux"),
+            "audio extraction requires pku3b to be built with the `remux` feature"
+        );
+        let ext = file_extension(v);
+        anyhow::ensure!(
+            !is_file || ext == "ts",
+            "audio extraction is not supported for .{ext} video files"
+        );
+    }
+    if let Some(dir) = dst.parent() {
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("create dir {}", dir.display()))?;
+    }
+
+    // saved as is, without intermediate files
+    let direct = format == SaveFormat::Ts || (is_file && !format.is_audio());
+    let ts = if direct {
+        dst.clone()
+    } else {
+        fs::create_dir_all(work_dir)
+            .await
+            .with_context(|| format!("create dir {}", work_dir.display()))?;
+        work_dir.join("merged.ts")
+    };
+    if is_file {
+        download_file(v, &ts, opts, |done, total| {
+            progress(SaveProgress::Bytes(done, total))
+        })
+        .await
+        .context("download video file")?;
+    } else {
+        download_ts(v, &ts, opts, |done, total| {
+            progress(SaveProgress::Segments(done, total))
+        })
+        .await
+        .context("download ts segments")?;
+    }
+    if direct {
+        return Ok(dst);
+    }
+
+    progress(SaveProgress::Converting);
+    match format {
+        #[cfg(feature = "remux")]
+        SaveFormat::M4a | SaveFormat::Aac => {
+            extract_audio(&[ts], &dst).await.context("extract audio")?;
+        }
+        _ => {
+            let r = convert_to_mp4(&ts, &dst, ffmpeg).await;
+            if cfg!(feature = "remux") && !ffmpeg {
+                r.context("remux to mp4 (use ffmpeg to convert instead)")?;
+            } else {
+                r?;
+            }
+        }
+    }
+    if let Err(e) = std::fs::remove_dir_all(work_dir) {
+        log::warn!("remove {}: {e}", work_dir.display());
+    }
+    Ok(dst)
+}
+
+/// 单个回放的默认文件名模板
+pub const NAME_TEMPLATE: &str = "{course}_{title}";
+/// 批量下载的默认文件名模板，同一课程的回放通常标题相同，因此加上日期
+pub const BATCH_NAME_TEMPLATE: &str = "{course}_{title}_{date}";
+
+/// 按模板生成回放的文件名 (不含扩展名)。
+///
+/// 可用的占位符为 `{course}` (课程名)、`{title}`、`{teacher}`、`{date}` (如 `2024-09-10`)、`{time}`
+/// (如 `0800`) 和 `{id}`。替换的内容中不能用于文件名的字符会被替换为 `_`，模板本身可以包含 `/`
+/// 以使用子目录。
+pub fn render_name(template: &str, v: &CourseVideoHandle) -> anyhow::Result<String> {
+    let start = v.meta().start_time();
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find('{') {
+        out.push_str(&rest[..i]);
+        let end = rest[i..]
+            .find('}')
+            .with_context(|| format!("unclosed placeholder in {template:?}"))?;
+        let value = match &rest[i + 1..i + end] {
+            "course" => v.course().name().to_owned(),
+            "title" => v.title().to_owned(),
+            "teacher" => v.meta().teacher().to_owned(),
+            "date" => {
+                start.map_or_else(|| v.time().to_owned(), |t| t.format("%Y-%m-%d").to_string())
+            }
+            "time" => start.map_or_else(String::new, |t| t.format("%H%M").to_string()),
+            "id" => v.id(),
+            p => anyhow::bail!(
+                "unknown placeholder {{{p}}} in {template:?}, expected course, title, teacher, date, time or id"
+            ),
+        };
+        out.push_str(&crate::sync::sanitize(&value));
+        rest = &rest[i + end + 1..];
+    }
+    out.push_str(rest);
+    anyhow::ensure!(!out.trim().is_empty(), "empty file name from {template:?}");
+    Ok(out)
+}
+
+/// 批量下载时挑选回放的条件
+#[derive(Debug, Clone, Default)]
+pub struct VideoFilter {
+    /// 只保留开始时间不早于此的回放
+    pub since: Option<chrono::DateTime<chrono::Local>>,
+    /// 只保留最近的若干个回放
+    pub latest: Option<usize>,
+}
+
+impl VideoFilter {
+    /// 按开始时间从早到晚排序并筛选回放，开始时间未知的排在最前
+    pub fn apply(&self, mut videos: Vec<CourseVideoHandle>) -> Vec<CourseVideoHandle> {
+        videos.sort_by_key(|v| v.meta().start_time());
+        if let Some(since) = self.since {
+            videos.retain(|v| v.meta().start_time().is_some_and(|t| t >= since));
+        }
+        if let Some(n) = self.latest {
+            videos.drain(..videos.len().saturating_sub(n));
+        }
+        videos
+    }
+}
+
+/// [`save_batch`] 的选项
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// 同时下载的回放数量
+    pub parallel: usize,
+    /// 文件名模板，见 [`render_name`]
+    pub template: String,
+    pub format: SaveFormat,
+    pub selector: VideoSelector,
+    pub ffmpeg: bool,
+    /// 每个回放的分片下载选项
+    pub download: DownloadOptions,
+}
+
+/// 批量下载中一个回放的结果
+#[derive(Debug)]
+pub enum BatchStatus {
+    Saved(PathBuf),
+    /// 目标文件已经存在
+    Skipped(PathBuf),
+    Failed(anyhow::Error),
+}
+
+/// 按文件名模板生成 `videos` 的文件名 (不含扩展名)，重名的都加上回放的 `sub_id` 作为后缀，
+/// 因此同一个回放的文件名不随列表的顺序或筛选条件变化
+pub fn batch_names(template: &str, videos: &[CourseVideoHandle]) -> anyhow::Result<Vec<String>> {
+    let names = videos
+        .iter()
+        .map(|v| render_name(template, v))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut count = std::collections::HashMap::<&str, usize>::new();
+    for name in &names {
+        *count.entry(name).or_default() += 1;
+    }
+    Ok(names
+        .iter()
+        .zip(videos)
+        .map(|(name, v)| match count[name.as_str()] {
+            1 => name.clone(),
+            // ids are `{course}::{sub_id}`, and the course is usually in the name already
+            _ => {
+                let id = v.id();
+                let sub_id = id.rsplit("::").next().unwrap_or(&id);
+                format!("{name}_{}", crate::sync::sanitize(sub_id))
+            }
+        })
+        .collect())
+}
+
+/// 并发保存 `videos` 到 `dir` 下 (最多 `opts.parallel` 个同时进行)，按 `videos` 的顺序返回结果。
+///
+/// 文件名由 [`batch_names`] 生成，已经存在的文件会被跳过 (除保持原扩展名的视频文件回放外不需要获取回放)。`work_dir(handle, v)` 给出回放的中间文件目录，
+/// 见 [`save`]。下载过程中调用 `progress(下标, 进度)`，每个回放结束时调用 `done(下标, 结果)`。
+/// 单个回放失败不影响其他回放。
+pub async fn save_batch(
+    videos: &[CourseVideoHandle],
+    dir: &Path,
+    opts: &BatchOptions,
+    work_dir: impl Fn(&CourseVideoHandle, &CourseVideo) -> PathBuf,
+    progress: impl Fn(usize, SaveProgress),
+    done: impl Fn(usize, &BatchStatus),
+) -> anyhow::Result<Vec<BatchStatus>> {
+    let names = batch_names(&opts.template, videos)?;
+    let (work_dir, progress, done) = (&work_dir, &progress, &done);
+    let task = async |i: usize| -> anyhow::Result<BatchStatus> {
+        let stem = dir.join(&names[i]);
+        // the path only depends on the replay for video files, check it without fetching first
+        let dst = format_output_path(&stem, opts.format);
+        if fs::metadata(&dst).await.is_ok() {
+            return Ok(BatchStatus::Skipped(dst));
+        }
+        let v = videos[i]
+            .get_with(&opts.selector)
+            .await
+            .with_context(|| format!("fetch video {}", videos[i].id()))?;
+        let dst = output_path(&v, &stem, opts.format);
+        if fs::metadata(&dst).await.is_ok() {
+            return Ok(BatchStatus::Skipped(dst));
+        }
+        let dst = save(
+            &v,
+            &stem,
+            opts.format,
+            &work_dir(&videos[i], &v),
+            opts.download,
+            opts.ffmpeg,
+            |p| progress(i, p),
+        )
+        .await?;
+        Ok(BatchStatus::Saved(dst))
+    };
+
+    let mut results = futures_util::stream::iter(0..videos.len())
+        .map(|i| async move {
+            let r = task(i).await.unwrap_or_else(BatchStatus::Failed);
+            done(i, &r);
+            r
+        })
+        .buffered(opts.parallel.max(1));
+    let mut out = Vec::with_capacity(videos.len());
+    while let Some(r) = results.next().await {
+        out.push(r);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    fn opts(jobs: usize, retries: u32) -> DownloadOptions {
+        DownloadOptions {
+            jobs,
+            retries,
+            retry_delay: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_check_ts() {
+        let mut ts = vec![0xff; 188 * 3];
+        for p in ts.chunks_exact_mut(188) {
+            p[0] = 0x47;
+        }
+        check_ts(&ts).unwrap();
+        assert!(check_ts(&[]).is_err());
+        assert!(check_ts(&ts[..188 * 2 + 100]).is_err());
+        ts[188] = 0;
+        let e = check_ts(&ts).unwrap_err();
+        assert_eq!(e.to_string(), "sync byte missing at packet 1");
+    }
+
+    #[compio::test]
+    async fn test_run_pool_order_and_concurrency() {
+        let running = Cell::new(0);
+        let peak = Cell::new(0);
+        let mut reported = Vec::new();
+        let r = run_pool(
+            10,
+            opts(3, 0),
+            |i| {
+                let (running, peak) = (&running, &peak);
+                async move {
+                    running.set(running.get() + 1);
+                    peak.set(peak.get().max(running.get()));
+                    // later tasks finish first
+                    compio::time::sleep(Duration::from_millis(20 - 2 * i as u64)).await;
+                    running.set(running.get() - 1);
+                    Ok(i * i)
+                }
+            },
+            |done, tot| reported.push((done, tot)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(r, (0..10).map(|i| i * i).collect::<Vec<_>>());
+        assert_eq!(peak.get(), 3);
+        assert_eq!(reported, (1..=10).map(|d| (d, 10)).collect::<Vec<_>>());
+    }
+
+    #[compio::test]
+    async fn test_run_pool_retry() {
+        let attempts = RefCell::new(vec![0; 4]);
+        let r = run_pool(
+            4,
+            opts(2, 2),
+            |i| {
+                let attempts = &attempts;
+                async move {
+                    let n = {
+                        let mut a = attempts.borrow_mut();
+                        a[i] += 1;
+                        a[i]
+                    };
+                    // segment 1 fails twice, then succeeds
+                    anyhow::ensure!(i != 1 || n > 2, "flaky");
+                    Ok(i)
+                }
+            },
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(r, vec![0, 1, 2, 3]);
+        assert_eq!(*attempts.borrow(), vec![1, 3, 1, 1]);
+
+        let e = run_pool(
+            3,
+            opts(2, 1),
+            |i| async move {
+                anyhow::ensure!(i != 2, "always fails");
+                Ok(i)
+            },
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{e:#}").contains("task #2 failed after 1 retries"));
+    }
+}
@@ -0,0 +1,207 @@
+//! 轮询模式的事件分发.
+//!
+//! `pku3b watch` 周期性地检查课程变化（见 [`crate::snapshot`]），并把得到的事件分发到若干
+//! [`Sink`]：本地命令、JSON webhook 或 JSONL 事件日志。
+
+use crate::snapshot::Event;
+use anyhow::Context as _;
+use std::{io::Write as _, path::PathBuf, time::Duration};
+
+/// 事件的去处
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sink {
+    /// 对每个事件执行一次 shell 命令。事件 JSON 通过标准输入传入，常用字段同时放在
+    /// `PKU3B_CHANGE`, `PKU3B_ITEM`, `PKU3B_COURSE`, `PKU3B_TITLE` 环境变量中
+    Command(String),
+    /// 对每个事件向 URL 发送一次 POST 请求，请求体为事件 JSON
+    Webhook(String),
+    /// 每个事件追加一行 JSON 到文件中
+    Log(PathBuf),
+}
+
+impl Sink {
+    pub async fn send(&self, event: &Event) -> anyhow::Result<()> {
+        match self {
+            Sink::Command(cmd) => run_command(cmd, event).await,
+            Sink::Webhook(url) => post_webhook(url, event).await,
+            Sink::Log(path) => append_log(path, event),
+        }
+    }
+}
+
+async fn run_command(cmd: &str, event: &Event) -> anyhow::Result<()> {
+    let body = serde_json::to_vec(event)?;
+
+    let mut c = if cfg!(windows) {
+        let mut c = compio::process::Command::new("cmd");
+        c.args(["/C", cmd]);
+        c
+    } else {
+        let mut c = compio::process::Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    };
+    c.env("PKU3B_CHANGE", enum_str(&event.change)?)
+        .env("PKU3B_ITEM", enum_str(&event.item)?)
+        .env("PKU3B_COURSE", &event.course)
+        .env("PKU3B_TITLE", &event.title);
+    c.stdin(std::process::Stdio::piped())
+        .map_err(|_| anyhow::anyhow!("pipe stdin"))?;
+
+    let mut child = c.spawn().with_context(|| format!("spawn {cmd:?}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        use compio::io::AsyncWriteExt;
+        compio::buf::buf_try!(@try stdin.write_all(body).await);
+    }
+    let status = child.wait().await?;
+    anyhow::ensure!(status.success(), "{cmd:?} exited with {status}");
+    Ok(())
+}
+
+async fn post_webhook(url: &str, event: &Event) -> anyhow::Result<()> {
+    let res = cyper::Client::new()
+        .post(url)?
+        .header(http::header::CONTENT_TYPE, "application/json")?
+        .body(serde_json::to_vec(event)?)
+        .send()
+        .await
+        .with_context(|| format!("POST {url}"))?;
+    anyhow::ensure!(
+        res.status().is_success(),
+        "POST {url}: status {}",
+        res.status()
+    );
+    Ok(())
+}
+
+fn append_log(path: &std::path::Path, event: &Event) -> anyhow::Result<()> {
+    #[derive(serde::Serialize)]
+    struct Line<'a> {
+        time: chrono::DateTime<chrono::Utc>,
+        #[serde(flatten)]
+        event: &'a Event,
+    }
+
+    let mut line = serde_json::to_vec(&Line {
+        time: chrono::Utc::now(),
+        event,
+    })?;
+    line.push(b'\n');
+
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open {}", path.display()))?;
+    f.write_all(&line)?;
+    Ok(())
+}
+
+/// `snake_case` name of a unit enum variant
+fn enum_str<T: serde::Serialize>(v: &T) -> anyhow::Result<String> {
+    match serde_json::to_value(v)? {
+        serde_json::Value::String(s) => Ok(s),
+        v => anyhow::bail!("not a unit variant: {v}"),
+    }
+}
+
+/// 失败后的指数退避
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            failures: 0,
+        }
+    }
+
+    /// 记录一次失败，返回下次重试前的等待时间
+    pub fn fail(&mut self) -> Duration {
+        let d = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(self.max);
+        self.failures = self.failures.saturating_add(1);
+        d
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{EventKind, ItemKind};
+
+    #[test]
+    fn test_backoff() {
+        let mut b = Backoff::new(Duration::from_secs(30), Duration::from_secs(300));
+        let delays = (0..6).map(|_| b.fail().as_secs()).collect::<Vec<_>>();
+        assert_eq!(delays, [30, 60, 120, 240, 300, 300]);
+        b.reset();
+        assert_eq!(b.fail().as_secs(), 30);
+    }
+
+    fn event() -> Event {
+        Event {
+            course_id: "_80052_1".to_owned(),
+            course: "数据结构与算法".to_owned(),
+            change: EventKind::DeadlineChanged,
+            item: ItemKind::Assignment,
+            id: "_1".to_owned(),
+            title: "作业 1".to_owned(),
+            deadline: None,
+            previous_deadline: None,
+        }
+    }
+
+    #[test]
+    fn test_append_log() {
+        let path = std::env::temp_dir().join(format!("pku3b-watch-{}.jsonl", std::process::id()));
+        let event = event();
+        append_log(&path, &event).unwrap();
+        append_log(&path, &event).unwrap();
+
+        let s = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = s.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let v: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(v["change"], "deadline_changed");
+        assert_eq!(v["item"], "assignment");
+        assert!(v["time"].is_string());
+        assert_eq!(enum_str(&event.change).unwrap(), "deadline_changed");
+    }
+
+    #[cfg(unix)]
+    #[compio::test]
+    async fn test_run_command() {
+        let path = std::env::temp_dir().join(format!("pku3b-watch-{}.cmd", std::process::id()));
+        let cmd = format!(
+            r#"printf '%s ' "$PKU3B_ITEM" > '{0}'; cat >> '{0}'"#,
+            path.display()
+        );
+        run_command(&cmd, &event()).await.unwrap();
+
+        let s = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (item, json) = s.split_once(' ').unwrap();
+        assert_eq!(item, "assignment");
+        assert_eq!(serde_json::from_str::<Event>(json).unwrap(), event());
+
+        assert!(run_command("exit 1", &event()).await.is_err());
+    }
+}
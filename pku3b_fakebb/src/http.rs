@@ -0,0 +1,243 @@
+//! 最小的 HTTP/1.1 实现：每个连接只处理一个请求.
+
+use anyhow::Context as _;
+use std::io::{BufRead as _, BufReader, Read as _, Write as _};
+
+/// 请求体的大小上限
+const MAX_BODY: usize = 64 << 20;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn read(stream: &mut impl std::io::Read) -> anyhow::Result<Self> {
+        let mut r = BufReader::new(stream);
+        let mut line = String::new();
+        r.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().context("empty request")?.to_owned();
+        let target = parts.next().context("no request target")?;
+        let url = url::Url::parse("http://localhost")?.join(target)?;
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            r.read_line(&mut line)?;
+            let l = line.trim_end();
+            if l.is_empty() {
+                break;
+            }
+            let (k, v) = l.split_once(':').context("invalid header")?;
+            headers.push((k.trim().to_ascii_lowercase(), v.trim().to_owned()));
+        }
+
+        let mut req = Self {
+            method,
+            path: url.path().to_owned(),
+            query: url.query_pairs().into_owned().collect(),
+            headers,
+            body: Vec::new(),
+        };
+        if let Some(len) = req.header("content-length") {
+            let len: usize = len.parse().context("invalid content-length")?;
+            anyhow::ensure!(len <= MAX_BODY, "request body too large");
+            req.body.resize(len, 0);
+            r.read_exact(&mut req.body)?;
+        }
+        Ok(req)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.header("cookie")?.split(';').find_map(|kv| {
+            let (k, v) = kv.trim().split_once('=')?;
+            (k == name).then_some(v)
+        })
+    }
+
+    /// `application/x-www-form-urlencoded` 请求体
+    pub fn form(&self) -> Vec<(String, String)> {
+        url::form_urlencoded::parse(&self.body)
+            .into_owned()
+            .collect()
+    }
+
+    /// `multipart/form-data` 请求体
+    pub fn multipart(&self) -> anyhow::Result<Vec<Part>> {
+        let ct = self.header("content-type").context("no content-type")?;
+        let boundary = ct
+            .split(';')
+            .find_map(|s| s.trim().strip_prefix("boundary="))
+            .context("no boundary")?;
+        let delim = format!("--{boundary}");
+
+        let mut parts = Vec::new();
+        let mut rest = &self.body[..];
+        rest = &rest[find(rest, delim.as_bytes()).context("no first boundary")? + delim.len()..];
+        while !rest.starts_with(b"--") {
+            rest = rest.strip_prefix(b"\r\n").context("invalid boundary")?;
+            let end = find(rest, format!("\r\n{delim}").as_bytes()).context("unterminated part")?;
+            parts.push(Part::parse(&rest[..end])?);
+            rest = &rest[end + 2 + delim.len()..];
+        }
+        Ok(parts)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[derive(Debug)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    fn parse(raw: &[u8]) -> anyhow::Result<Self> {
+        let sep = find(raw, b"\r\n\r\n").context("part has no header")?;
+        let headers = String::from_utf8_lossy(&raw[..sep]);
+        let disposition = headers
+            .lines()
+            .find_map(|l| {
+                let (k, v) = l.split_once(':')?;
+                k.eq_ignore_ascii_case("content-disposition").then_some(v)
+            })
+            .context("part has no content-disposition")?;
+
+        let param = |key: &str| {
+            disposition.split(';').find_map(|s| {
+                let v = s.trim().strip_prefix(key)?.strip_prefix('=')?;
+                Some(v.trim_matches('"').to_owned())
+            })
+        };
+        Ok(Self {
+            name: param("name").context("part has no name")?,
+            filename: param("filename"),
+            data: raw[sep + 4..].to_vec(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".into(), content_type.into())],
+            body: body.into(),
+        }
+    }
+
+    pub fn html(body: impl Into<String>) -> Self {
+        Self::new(200, "text/html; charset=utf-8", body.into())
+    }
+
+    pub fn json(value: &serde_json::Value) -> Self {
+        Self::new(200, "application/json; charset=utf-8", value.to_string())
+    }
+
+    pub fn bytes(content_type: &str, body: Vec<u8>) -> Self {
+        Self::new(200, content_type, body)
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Self::new(302, "text/html; charset=utf-8", "").header("Location", location)
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(404, "text/plain; charset=utf-8", "not found")
+    }
+
+    pub fn header(mut self, k: &str, v: impl Into<String>) -> Self {
+        self.headers.push((k.into(), v.into()));
+        self
+    }
+
+    pub fn write(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        let mut buf = Vec::with_capacity(self.body.len() + 256);
+        write!(buf, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        for (k, v) in &self.headers {
+            write!(buf, "{k}: {v}\r\n")?;
+        }
+        write!(
+            buf,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        )?;
+        buf.extend_from_slice(&self.body);
+        w.write_all(&buf)?;
+        w.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        416 => "Range Not Satisfiable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_multipart() {
+        let body = "------b\r\nContent-Disposition: form-data; name=\"mode\"\r\n\r\nview\r\n\
+                    ------b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"a.txt\"\r\n\
+                    Content-Type: text/plain\r\n\r\nline1\r\nline2\r\n------b--\r\n";
+        let raw = format!(
+            "POST /up?action=submit HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=----b\r\n\
+             Cookie: a=1; s_session_id=xyz\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let req = Request::read(&mut raw.as_bytes()).unwrap();
+        assert_eq!(req.path, "/up");
+        assert_eq!(req.query("action"), Some("submit"));
+        assert_eq!(req.cookie("s_session_id"), Some("xyz"));
+
+        let parts = req.multipart().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(
+            (parts[0].name.as_str(), &parts[0].data[..]),
+            ("mode", &b"view"[..])
+        );
+        assert_eq!(parts[1].filename.as_deref(), Some("a.txt"));
+        assert_eq!(parts[1].data, b"line1\r\nline2");
+    }
+}
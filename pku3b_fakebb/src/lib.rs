@@ -0,0 +1,1392 @@
+//! 用于端到端测试的模拟教学网服务器.
+//!
+//! 服务器根据场景文件（见 [`scenario`]）模拟 `pku3b::api` 访问的所有接口：IAAA 登录、
+//! 教学网 SSO、主页课程列表、课程通知、内容列表、作业提交（包括 multipart 上传）、
+//! 回放列表、回放 sub-info 以及 AES-128 加密的 m3u8 回放。
+//!
+//! pku3b 通过 `--base-url`（或 `PyClient(base_url=...)`）将所有发往 `*.pku.edu.cn`
+//! 的请求转发到本服务器，因此页面中的链接仍使用教学网的真实域名。
+
+mod http;
+mod pages;
+pub mod scenario;
+pub mod video;
+
+use http::{Request, Response};
+use scenario::{ContentKind, Course, Scenario};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+const LOGIN_PAGE: &str = "https://course.pku.edu.cn/webapps/login/";
+const SESSION_COOKIE: &str = "s_session_id";
+const NONCE_FIELD: &str = "blackboard.platform.security.NonceUtil.nonce";
+
+/// 一次作业提交
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Submission {
+    pub course_id: String,
+    pub content_id: String,
+    pub filename: String,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+    pub size: usize,
+    pub time: chrono::DateTime<chrono::FixedOffset>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// IAAA 签发、尚未使用的 token
+    tokens: HashSet<String>,
+    sessions: HashSet<String>,
+    /// 回放播放器的 auth_data
+    auth_data: HashSet<String>,
+    /// (course_id, content_id) → 作业提交表单中的 nonce
+    nonces: HashMap<(String, String), String>,
+    submissions: Vec<Submission>,
+    /// (sub_id 或 `sub_id/v<变体下标>`, 分片下标) → 已收到的请求次数
+    segment_hits: HashMap<(String, usize), usize>,
+    /// 对视频文件的请求的 Range 头，没有 Range 头时为空字符串
+    file_ranges: Vec<String>,
+    /// 因 `If-None-Match` 返回 304 的次数
+    not_modified: usize,
+    /// 回放 sub-info 的请求次数
+    sub_info_hits: usize,
+}
+
+#[derive(Debug)]
+struct Shared {
+    scenario: Scenario,
+    submissions_dir: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+pub struct Server {
+    listener: TcpListener,
+    shared: Arc<Shared>,
+}
+
+/// 在后台线程中运行的服务器
+#[derive(Clone)]
+pub struct Handle {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    pub fn bind(scenario: Scenario, addr: impl std::net::ToSocketAddrs) -> anyhow::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            shared: Arc::new(Shared {
+                scenario,
+                submissions_dir: None,
+                state: Default::default(),
+            }),
+        })
+    }
+
+    /// 将提交的文件保存到 `dir/<course_id>/<content_id>/<文件名>`
+    pub fn with_submissions_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        Arc::get_mut(&mut self.shared)
+            .expect("server not started")
+            .submissions_dir = Some(dir.into());
+        self
+    }
+
+    pub fn addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// 处理请求，不会返回
+    pub fn run(&self) -> anyhow::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    log::warn!("accept: {e}");
+                    continue;
+                }
+            };
+            let shared = self.shared.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = serve(&shared, stream) {
+                    log::warn!("{e:#}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// 在后台线程中运行
+    pub fn spawn(self) -> anyhow::Result<Handle> {
+        let handle = Handle {
+            addr: self.addr()?,
+            shared: self.shared.clone(),
+        };
+        std::thread::spawn(move || self.run());
+        Ok(handle)
+    }
+}
+
+impl Handle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 供 `--base-url` 使用的地址
+    pub fn url(&self) -> url::Url {
+        url::Url::parse(&format!("http://{}", self.addr)).unwrap()
+    }
+
+    pub fn submissions(&self) -> Vec<Submission> {
+        self.shared.state.lock().unwrap().submissions.clone()
+    }
+}
+
+fn serve(shared: &Shared, mut stream: TcpStream) -> anyhow::Result<()> {
+    let req = Request::read(&mut stream)?;
+    let res = revalidate(shared, &req, route(shared, &req));
+    log::info!("{} {} -> {}", req.method, req.path, res.status);
+    res.write(&mut stream)?;
+    Ok(())
+}
+
+/// 为 HTML 页面加上 `ETag`，与请求的 `If-None-Match` 相同时返回 304
+fn revalidate(shared: &Shared, req: &Request, res: Response) -> Response {
+    let html = res
+        .headers
+        .iter()
+        .any(|(k, v)| k == "Content-Type" && v.starts_with("text/html"));
+    if req.method != "GET" || res.status != 200 || !html {
+        return res;
+    }
+
+    use std::hash::{Hash, Hasher};
+    let mut h = std::hash::DefaultHasher::new();
+    res.body.hash(&mut h);
+    let etag = format!("\"{:016x}\"", h.finish());
+    if req.header("if-none-match") == Some(etag.as_str()) {
+        shared.state.lock().unwrap().not_modified += 1;
+        return Response::new(304, "text/html; charset=utf-8", "").header("ETag", etag);
+    }
+    res.header("ETag", etag)
+}
+
+fn random_hex() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+fn now() -> chrono::DateTime<chrono::FixedOffset> {
+    let tz = chrono::FixedOffset::east_opt(8 * 3600).unwrap();
+    chrono::Utc::now().with_timezone(&tz)
+}
+
+fn route(shared: &Shared, req: &Request) -> Response {
+    let s = &shared.scenario;
+    let path = req.path.as_str();
+    match (req.method.as_str(), path) {
+        ("POST", "/iaaa/oauthlogin.do") => return oauth_login(shared, req),
+        ("GET", "/webapps/bb-sso-BBLEARN/execute/authValidate/campusLogin") => {
+            return sso_login(shared, req);
+        }
+        ("GET", "/webapps/login/") => return Response::html(pages::login()),
+        ("GET", "/__fakebb/submissions") => {
+            let subs = shared.state.lock().unwrap().submissions.clone();
+            return Response::json(&serde_json::to_value(subs).unwrap());
+        }
+        _ => {}
+    }
+
+    // playlist, key and segments are served by a CDN without login
+    if let Some(rest) = path.strip_prefix("/play/") {
+        return play(shared, req, rest).unwrap_or_else(Response::not_found);
+    }
+    if path == "/courseapi/v2/schedule/get-sub-info-by-auth-data" {
+        return sub_info(shared, req);
+    }
+
+    let logged_in = req
+        .cookie(SESSION_COOKIE)
+        .is_some_and(|sid| shared.state.lock().unwrap().sessions.contains(sid));
+    if !logged_in {
+        return match s.inline_login_page {
+            true => Response::html(pages::login()),
+            false => Response::redirect(LOGIN_PAGE),
+        };
+    }
+
+    if path == "/webapps/portal/execute/tabs/tabAction" {
+        return Response::html(pages::homepage(s));
+    }
+    if let Some(rest) = path.strip_prefix("/bbcswebdav/") {
+        return attachment(s, rest).unwrap_or_else(Response::not_found);
+    }
+    if (req.method.as_str(), path) == ("POST", "/webapps/assignment/uploadAssignment") {
+        // course_id and content_id are sent in the form
+        return submit(shared, req);
+    }
+
+    let Some(course) = req.query("course_id").and_then(|id| s.course(id)) else {
+        return Response::not_found();
+    };
+    match (req.method.as_str(), path) {
+        ("GET", "/webapps/blackboard/execute/announcement") => {
+            Response::html(pages::coursepage(course))
+        }
+        ("GET", "/webapps/blackboard/content/listContent.jsp") => {
+            let Some(parent) = req.query("content_id") else {
+                return Response::not_found();
+            };
+            Response::html(pages::list_content(course, parent))
+        }
+        ("GET", "/webapps/assignment/uploadAssignment") => assignment_page(shared, course, req),
+        ("GET", "/webapps/bb-streammedia-hqy-BBLEARN/videoList.action") => {
+            let param = |name| req.query(name).and_then(|v| v.parse().ok());
+            let start = param("startIndex").unwrap_or(0);
+            let num = param("numResults").unwrap_or(usize::MAX);
+            Response::html(pages::video_list(course, start, num))
+        }
+        ("GET", "/webapps/bb-streammedia-hqy-BBLEARN/videoPlay.action") => {
+            match req.query("sub_id").filter(|id| course.video(id).is_some()) {
+                Some(sub_id) => Response::html(pages::video_play(&course.id, sub_id)),
+                None => Response::not_found(),
+            }
+        }
+        ("GET", "/webapps/bb-streammedia-hqy-BBLEARN/playVideo.action") => {
+            let Some(sub_id) = req.query("sub_id").filter(|id| course.video(id).is_some()) else {
+                return Response::not_found();
+            };
+            let auth = random_hex();
+            shared.state.lock().unwrap().auth_data.insert(auth.clone());
+            Response::redirect(&format!(
+                "https://onlineroomse.pku.edu.cn/player?course_id={}&sub_id={sub_id}&app_id=4&auth_data={auth}",
+                course.id
+            ))
+        }
+        _ => Response::not_found(),
+    }
+}
+
+fn oauth_login(shared: &Shared, req: &Request) -> Response {
+    let form = req.form();
+    let field = |k: &str| form.iter().find(|(n, _)| n == k).map(|(_, v)| v.as_str());
+    let s = &shared.scenario;
+    if field("userName") != Some(&s.username) || field("password") != Some(&s.password) {
+        return Response::json(&serde_json::json!({
+            "success": false,
+            "errors": { "code": "E01", "msg": "用户名或密码错误" },
+        }));
+    }
+    let token = random_hex();
+    shared.state.lock().unwrap().tokens.insert(token.clone());
+    Response::json(&serde_json::json!({ "success": true, "token": token }))
+}
+
+fn sso_login(shared: &Shared, req: &Request) -> Response {
+    let mut state = shared.state.lock().unwrap();
+    if !req.query("token").is_some_and(|t| state.tokens.remove(t)) {
+        return Response::new(401, "text/html; charset=utf-8", pages::login());
+    }
+    let sid = random_hex();
+    state.sessions.insert(sid.clone());
+    Response::html(pages::sso_success()).header(
+        "Set-Cookie",
+        format!("{SESSION_COOKIE}={sid}; Path=/; HttpOnly"),
+    )
+}
+
+fn assignment_page(shared: &Shared, course: &Course, req: &Request) -> Response {
+    let Some(x) = req
+        .query("content_id")
+        .and_then(|id| course.content(id))
+        .filter(|x| x.kind == ContentKind::Assignment)
+    else {
+        return Response::not_found();
+    };
+    let key = (course.id.clone(), x.id.clone());
+    let mut state = shared.state.lock().unwrap();
+
+    if req.query("action") != Some("newAttempt") {
+        let attempt = state
+            .submissions
+            .iter()
+            .rfind(|s| s.course_id == course.id && s.content_id == x.id)
+            .map(|s| s.time.format("%Y-%m-%d %H:%M:%S").to_string());
+        return Response::html(pages::view_assignment(x, attempt.as_deref()));
+    }
+
+    // every visit issues a new one-time nonce
+    let nonce = random_hex();
+    state.nonces.insert(key, nonce.clone());
+    let recall = format!(
+        "/webapps/blackboard/content/listContent.jsp?course_id={}&content_id={}",
+        course.id, x.parent
+    );
+    let fields = [
+        ("attempt_id", String::new()),
+        (NONCE_FIELD, nonce),
+        (
+            "blackboard.platform.security.NonceUtil.nonce.ajax",
+            random_hex(),
+        ),
+        ("content_id", x.id.clone()),
+        ("course_id", course.id.clone()),
+        ("isAjaxSubmit", "false".into()),
+        ("lu_link_id", String::new()),
+        ("mode", "view".into()),
+        ("recallUrl", recall),
+        ("remove_file_id", String::new()),
+        ("studentSubmission.text_f", String::new()),
+        ("studentSubmission.text_w", String::new()),
+        ("studentSubmission.type", "H".into()),
+        ("student_commentstext_f", String::new()),
+        ("student_commentstext_w", String::new()),
+        ("student_commentstype", "H".into()),
+        ("textbox_prefix", "studentSubmission.text".into()),
+    ];
+    Response::html(pages::upload_assignment(x, &fields))
+}
+
+fn submit(shared: &Shared, req: &Request) -> Response {
+    let error = |msg: &str| {
+        log::warn!("submit: {msg}");
+        Response::new(
+            500,
+            "text/html; charset=utf-8",
+            "<html><body>尝试呈现错误页面时发生严重的内部错误</body></html>",
+        )
+    };
+    let parts = match req.multipart() {
+        Ok(p) => p,
+        Err(e) => return error(&format!("{e:#}")),
+    };
+    let field = |k: &str| {
+        parts
+            .iter()
+            .find(|p| p.name == k)
+            .map(|p| String::from_utf8_lossy(&p.data).into_owned())
+    };
+    let Some(course) = field("course_id").and_then(|id| shared.scenario.course(&id)) else {
+        return error("no course_id");
+    };
+    let Some(content_id) = field("content_id") else {
+        return error("no content_id");
+    };
+    let Some(file) = parts
+        .iter()
+        .find(|p| p.name == "newFile_LocalFile0" && p.filename.is_some())
+    else {
+        return error("no file");
+    };
+
+    let mut state = shared.state.lock().unwrap();
+    let key = (course.id.clone(), content_id.clone());
+    if state.nonces.get(&key) != field(NONCE_FIELD).as_ref() {
+        return error("invalid nonce");
+    }
+    state.nonces.remove(&key);
+
+    let sub = Submission {
+        course_id: course.id.clone(),
+        content_id,
+        filename: file.filename.clone().unwrap(),
+        size: file.data.len(),
+        data: file.data.clone(),
+        time: now(),
+    };
+    if let Some(dir) = &shared.submissions_dir {
+        let dir = dir.join(&sub.course_id).join(&sub.content_id);
+        let name = std::path::Path::new(&sub.filename)
+            .file_name()
+            .unwrap_or("upload".as_ref());
+        if let Err(e) =
+            std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(dir.join(name), &sub.data))
+        {
+            log::warn!("save submission: {e}");
+        }
+    }
+    state.submissions.push(sub);
+    Response::html("<html><body>提交成功</body></html>")
+}
+
+fn sub_info(shared: &Shared, req: &Request) -> Response {
+    shared.state.lock().unwrap().sub_info_hits += 1;
+    let authorized = req
+        .query("auth_data")
+        .is_some_and(|a| shared.state.lock().unwrap().auth_data.contains(a));
+    let video = req
+        .query("course_id")
+        .and_then(|id| shared.scenario.course(id))
+        .zip(req.query("sub_id"))
+        .and_then(|(c, id)| c.video(id));
+    let (true, Some(v)) = (authorized, video) else {
+        return Response::json(
+            &serde_json::json!({ "code": 401, "msg": "auth failed", "list": [] }),
+        );
+    };
+
+    let (is_m3u8, file) = match &v.file {
+        Some(file) => ("no", file.as_str()),
+        None => ("yes", "index.m3u8"),
+    };
+    let content = serde_json::json!({
+        "save_playback": {
+            "is_m3u8": is_m3u8,
+            "contents": format!("{}/{file}", video::base(v)),
+        }
+    });
+    Response::json(&serde_json::json!({
+        "code": 0,
+        "msg": "success",
+        "list": [{
+            "sub_id": v.sub_id,
+            "sub_title": v.title,
+            "sub_content": content.to_string(),
+        }],
+    }))
+}
+
+/// `/play/<sub_id>/{index.m3u8,key,seg-N.ts}`，有变体时为 `/play/<sub_id>/v<i>/{index.m3u8,seg-N.ts}`，
+/// 视频文件回放为 `/play/<sub_id>/<文件名>`
+fn play(shared: &Shared, req: &Request, rest: &str) -> Option<Response> {
+    let (sub_id, file) = rest.split_once('/')?;
+    let v = shared
+        .scenario
+        .courses
+        .iter()
+        .find_map(|c| c.video(sub_id))?;
+    if v.file.as_deref() == Some(file) {
+        let range = req.header("range");
+        let mut st = shared.state.lock().unwrap();
+        st.file_ranges.push(range.unwrap_or_default().to_owned());
+        drop(st);
+        return Some(video::file_response(v, range.filter(|_| !v.ignore_range)));
+    }
+    // segment hits are counted per variant
+    let (hit_key, file) = match file.split_once('/') {
+        Some((variant, file)) => {
+            let i: usize = variant.strip_prefix('v')?.parse().ok()?;
+            v.variants.get(i)?;
+            (format!("{sub_id}/{variant}"), file)
+        }
+        None if file == "index.m3u8" && !v.variants.is_empty() => {
+            return Some(Response::bytes(
+                "application/vnd.apple.mpegurl",
+                video::master_playlist(v).into_bytes(),
+            ));
+        }
+        None => (sub_id.to_owned(), file),
+    };
+    if file == "index.m3u8" {
+        return Some(Response::bytes(
+            "application/vnd.apple.mpegurl",
+            video::playlist(v).into_bytes(),
+        ));
+    }
+    if file == "key" && v.encrypted {
+        return Some(Response::bytes(
+            "application/octet-stream",
+            v.key().ok()?.to_vec(),
+        ));
+    }
+    let index: usize = file
+        .strip_prefix("seg-")?
+        .strip_suffix(".ts")?
+        .parse()
+        .ok()?;
+    if index >= v.segments {
+        return None;
+    }
+    let mut st = shared.state.lock().unwrap();
+    let hits = st.segment_hits.entry((hit_key, index)).or_default();
+    *hits += 1;
+    if *hits <= v.flaky {
+        return Some(Response::new(503, "text/plain", "Service Unavailable"));
+    }
+    drop(st);
+    Some(Response::bytes(
+        "video/mp2t",
+        video::segment(v, index).ok()?,
+    ))
+}
+
+/// `/bbcswebdav/pid-<content>-dt-content-rid-<i>_1/xid-<i>_1` 跳转到
+/// `/bbcswebdav/courses/<course>/<content>/<i>/<文件名>`
+fn attachment(s: &Scenario, rest: &str) -> Option<Response> {
+    if let Some(rest) = rest.strip_prefix("courses/") {
+        let mut it = rest.splitn(4, '/');
+        let (course, content, index) = (it.next()?, it.next()?, it.next()?);
+        let x = s.course(course)?.content(content)?;
+        let a = x.attachments.get(index.parse::<usize>().ok()?)?;
+        return Some(Response::bytes("application/octet-stream", a.bytes()));
+    }
+
+    let (pid, _) = rest.split_once('/')?;
+    let (content, index) = pid.strip_prefix("pid-")?.split_once("-dt-content-rid-")?;
+    let index: usize = index.strip_suffix("_1")?.parse().ok()?;
+    s.courses.iter().find_map(|c| {
+        let x = c.content(content).filter(|x| index < x.attachments.len())?;
+        Some(Response::redirect(&pages::attachment_path(c, x, index)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pku3b::{api, cache::CacheStore};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pku3b-fakebb-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn start() -> (Handle, api::Client) {
+        start_with(|_| {})
+    }
+
+    /// 以修改后的示例场景启动服务器，每次使用独立的缓存目录
+    fn start_with(f: impl FnOnce(&mut Scenario)) -> (Handle, api::Client) {
+        static SEQ: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("scenario.example.toml");
+        let mut scenario = Scenario::load(&path).unwrap();
+        f(&mut scenario);
+        let server = Server::bind(scenario, "127.0.0.1:0")
+            .unwrap()
+            .spawn()
+            .unwrap();
+        // keep the user's cache untouched
+        let seq = SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let store = Box::leak(Box::new(CacheStore::new(
+            temp_dir(&format!("cache-{seq}")),
+            u64::MAX,
+        )));
+        let client = api::Client::new(None, None)
+            .with_base_url(server.url())
+            .unwrap()
+            .with_cache_store(store);
+        (server, client)
+    }
+
+    /// 登录并获取第一门本学期课程
+    async fn first_course(client: &api::Client) -> api::Course {
+        let bb = client
+            .blackboard("2100012345", "fakebb-password")
+            .await
+            .unwrap();
+        bb.get_courses(true)
+            .await
+            .unwrap()
+            .remove(0)
+            .get()
+            .await
+            .unwrap()
+    }
+
+    #[compio::test]
+    async fn test_login() {
+        let (_server, client) = start();
+        assert!(client.blackboard("2100012345", "wrong").await.is_err());
+
+        let bb = client
+            .blackboard("2100012345", "fakebb-password")
+            .await
+            .unwrap();
+        let courses = bb.get_courses(false).await.unwrap();
+        let names = courses.iter().map(|c| c.title()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "数据结构与算法 (24-25学年第1学期)",
+                "计算概论 (A)(23-24学年第2学期)"
+            ]
+        );
+        assert_eq!(bb.get_courses(true).await.unwrap().len(), 1);
+
+        // another client has no session
+        let (_server, other) = start();
+        let other = other.blackboard("", "").await;
+        assert!(other.is_err());
+    }
+
+    #[compio::test]
+    async fn test_revalidate() {
+        let (server, client) = start();
+        let bb = client
+            .blackboard("2100012345", "fakebb-password")
+            .await
+            .unwrap();
+        bb.get_courses(false).await.unwrap();
+
+        // tamper with the cached homepage, so that the body served on 304 can be told apart
+        let store = client.cache_store();
+        let entry = store
+            .entries()
+            .into_iter()
+            .find(|e| e.kind == "http" && e.key.contains("tabAction"))
+            .unwrap();
+        let path = store.dir().join(&entry.path);
+        let mut cached: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert!(cached["etag"].is_string(), "{cached}");
+        let body = cached["body"]
+            .as_str()
+            .unwrap()
+            .replace("计算概论", "缓存的课程");
+        cached["body"] = body.into();
+        std::fs::write(&path, serde_json::to_vec(&cached).unwrap()).unwrap();
+
+        let not_modified = || server.shared.state.lock().unwrap().not_modified;
+        let before = not_modified();
+        let courses = bb.get_courses(false).await.unwrap();
+        assert_eq!(not_modified(), before + 1);
+        assert_eq!(courses[1].title(), "缓存的课程 (A)(23-24学年第2学期)");
+    }
+
+    #[compio::test]
+    async fn test_expired_session() {
+        let (server, client) = start_with(|s| s.inline_login_page = true);
+        let bb = client
+            .blackboard("2100012345", "fakebb-password")
+            .await
+            .unwrap();
+        assert_eq!(bb.get_courses(false).await.unwrap().len(), 2);
+        let store = client.cache_store();
+        let cached = || {
+            let e = store
+                .entries()
+                .into_iter()
+                .find(|e| e.kind == "http" && e.key.contains("tabAction"))
+                .unwrap();
+            std::fs::read(store.dir().join(e.path)).unwrap()
+        };
+        let before = cached();
+
+        // the login page is served with 200 once the session is gone
+        server.shared.state.lock().unwrap().sessions.clear();
+        let e = bb.get_courses(false).await.unwrap_err();
+        assert!(format!("{e:#}").contains("login page"), "{e:#}");
+        assert_eq!(cached(), before);
+    }
+
+    #[compio::test]
+    async fn test_course_flow() {
+        let (server, client) = start();
+        let course = first_course(&client).await;
+
+        let announcements = course.list_announcements().await.unwrap();
+        assert_eq!(announcements.len(), 1);
+        assert_eq!(announcements[0].title(), "期中考试安排");
+
+        // contents inside folders are found as well
+        let mut stream = course.content_stream();
+        let mut titles = Vec::new();
+        while let Some(batch) = stream.next_batch().await {
+            titles.extend(batch.iter().map(|d| d.title().to_owned()));
+        }
+        titles.sort();
+        assert_eq!(
+            titles,
+            ["2023 期中.pdf", "往年试题", "第一次作业", "第一讲 课程介绍"]
+        );
+
+        // attachments are served through a redirect
+        let dir = temp_dir("flow");
+        let assignments = course.list_assignments().await.unwrap();
+        let assignment = assignments[0].get().await.unwrap();
+        assert!(assignment.deadline().is_some());
+        assert!(assignment.last_attempt().is_none());
+        let (name, uri) = &assignment.attachments()[0];
+        assignment
+            .download_attachment(uri, &dir.join(name))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join(name)).unwrap(),
+            "实现一个栈。"
+        );
+
+        let file = dir.join("answer.txt");
+        std::fs::write(&file, "push, pop").unwrap();
+        assignment.submit_file(&file).await.unwrap();
+        let subs = server.submissions();
+        assert_eq!(subs.len(), 1);
+        assert_eq!(
+            (subs[0].filename.as_str(), &subs[0].data[..]),
+            ("answer.txt", &b"push, pop"[..])
+        );
+
+        let assignment = assignments[0].get().await.unwrap();
+        assert!(assignment.last_attempt_time().is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[compio::test]
+    async fn test_video() {
+        let (server, client) = start();
+        let course = first_course(&client).await;
+        let handles = course.get_video_list().await.unwrap();
+        assert_eq!(handles.len(), 2);
+
+        let scenario = &server.shared.scenario.courses[0];
+        for (handle, expected) in handles.iter().zip(&scenario.videos) {
+            assert_eq!(handle.meta().teacher(), expected.teacher);
+            assert!(handle.meta().duration().is_none());
+            let v = handle.get().await.unwrap();
+            assert_eq!(v.len_segments(), expected.segments);
+            // 25 fps
+            let secs = (expected.segments * expected.frames) as f64 / 25.0;
+            let duration = v.meta().duration().unwrap().as_secs_f64();
+            assert!((duration - secs).abs() < 0.01, "{duration} != {secs}");
+            let start = handle.meta().start_time().unwrap();
+            let end = v.meta().end_time().unwrap();
+            assert_eq!(end, start + v.meta().duration().unwrap());
+            let mut key = None;
+            for i in 0..v.len_segments() {
+                key = v.refresh_key(i, key);
+                let data = v.get_segment_data(i, key).await.unwrap();
+                assert_eq!(data, video::plaintext(expected, i));
+            }
+        }
+    }
+
+    #[compio::test]
+    async fn test_video_list_pages() {
+        // an exact multiple of the page size ends with an empty page
+        for n in [100, 230] {
+            let (_server, client) = start_with(|s| {
+                let videos = &mut s.courses[0].videos;
+                let v = videos[0].clone();
+                *videos = (0..n)
+                    .map(|i| scenario::Video {
+                        sub_id: format!("sub{i}"),
+                        ..v.clone()
+                    })
+                    .collect();
+            });
+            let course = first_course(&client).await;
+            let handles = course.get_video_list().await.unwrap();
+            let ids = handles
+                .iter()
+                .map(|h| h.id().rsplit("::").next().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, (0..n).map(|i| format!("sub{i}")).collect::<Vec<_>>());
+        }
+    }
+
+    #[compio::test]
+    async fn test_video_list_transcoding() {
+        // rows still transcoding are skipped, and a short parsed page does not end the list
+        let transcoding = |i: usize| i % 7 == 3;
+        let (_server, client) = start_with(|s| {
+            let videos = &mut s.courses[0].videos;
+            let v = videos[0].clone();
+            *videos = (0..230)
+                .map(|i| scenario::Video {
+                    sub_id: format!("sub{i}"),
+                    transcoding: transcoding(i),
+                    ..v.clone()
+                })
+                .collect();
+        });
+        let course = first_course(&client).await;
+        let ids = course
+            .get_video_list()
+            .await
+            .unwrap()
+            .iter()
+            .map(|h| h.id().rsplit("::").next().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        let expected = (0..230)
+            .filter(|&i| !transcoding(i))
+            .map(|i| format!("sub{i}"))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, expected);
+    }
+
+    #[compio::test]
+    async fn test_video_key_methods() {
+        for (tag, supported) in [
+            ("METHOD=NONE", true),
+            ("METHOD=SAMPLE-AES-CTR,URI=\"key\"", false),
+            (
+                "METHOD=SAMPLE-AES,URI=\"skd://key\",KEYFORMAT=\"com.apple.streamingkeydelivery\"",
+                false,
+            ),
+        ] {
+            let (server, client) = start_with(|s| {
+                s.courses[0].videos[0].key_tag = Some(tag.to_owned());
+            });
+            let course = first_course(&client).await;
+            let v = course.get_video_list().await.unwrap()[0]
+                .get()
+                .await
+                .unwrap();
+            let expected = &server.shared.scenario.courses[0].videos[0];
+            let key = v.refresh_key(0, None);
+            assert!(key.is_some());
+            let r = v.get_segment_data(0, key).await;
+            if supported {
+                assert_eq!(r.unwrap(), video::plaintext(expected, 0));
+                continue;
+            }
+            let e = r.unwrap_err();
+            assert!(e.is::<api::UnsupportedEncryption>(), "{e:#}");
+
+            // the download fails at once instead of retrying every segment
+            let dir = temp_dir("key-methods");
+            let opts = pku3b::video::DownloadOptions {
+                retry_delay: std::time::Duration::from_secs(60),
+                ..Default::default()
+            };
+            let e = pku3b::video::download_segments(&v, &dir, opts, |_, _| {})
+                .await
+                .unwrap_err();
+            assert!(e.is::<api::UnsupportedEncryption>(), "{e:#}");
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[compio::test]
+    async fn test_video_variants() {
+        let (server, client) = start();
+        let course = first_course(&client).await;
+        let handle = course.get_video_list().await.unwrap().remove(1);
+        let expected = &server.shared.scenario.courses[0].videos[1];
+
+        let variants = handle.variants().await.unwrap();
+        let summary = variants
+            .iter()
+            .map(|v| (v.stream.as_str(), v.quality().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("teacher", "360p".to_owned()),
+                ("teacher", "720p".to_owned()),
+                ("screen", "720p".to_owned()),
+            ]
+        );
+
+        // the best variant of the first stream by default
+        let v = handle.get().await.unwrap();
+        assert_eq!(v.variant(), &variants[1]);
+
+        let selector = api::VideoSelector {
+            stream: Some("screen".to_owned()),
+            quality: api::Quality::Smallest,
+        };
+        let v = handle.get_with(&selector).await.unwrap();
+        assert_eq!(v.variant(), &variants[2]);
+        let key = v.refresh_key(0, None);
+        let data = v.get_segment_data(0, key).await.unwrap();
+        assert_eq!(data, video::plaintext(expected, 0));
+        let hits = server.shared.state.lock().unwrap().segment_hits.clone();
+        assert_eq!(
+            hits.keys().collect::<Vec<_>>(),
+            [&("ef34gh/v2".to_owned(), 0)]
+        );
+
+        let selector = api::VideoSelector {
+            stream: Some("teacher".to_owned()),
+            quality: "1080p".parse().unwrap(),
+        };
+        let e = handle.get_with(&selector).await.unwrap_err();
+        assert!(format!("{e:#}").contains("available: 360p, 720p"));
+    }
+
+    #[compio::test]
+    async fn test_download_segments() {
+        let (server, client) = start_with(|s| {
+            let v = &mut s.courses[0].videos[0];
+            v.segments = 12;
+            v.flaky = 1;
+        });
+        let course = first_course(&client).await;
+        let v = course.get_video_list().await.unwrap()[0]
+            .get()
+            .await
+            .unwrap();
+
+        let dir = temp_dir("segments");
+        let opts = pku3b::video::DownloadOptions {
+            jobs: 4,
+            retries: 1,
+            retry_delay: std::time::Duration::ZERO,
+        };
+        let mut reported = 0;
+        let paths = pku3b::video::download_segments(&v, &dir, opts, |done, tot| {
+            assert_eq!(tot, 12);
+            reported = done;
+        })
+        .await
+        .unwrap();
+        assert_eq!(reported, 12);
+
+        let expected = &server.shared.scenario.courses[0].videos[0];
+        for (i, p) in paths.iter().enumerate() {
+            assert_eq!(std::fs::read(p).unwrap(), video::plaintext(expected, i));
+        }
+        let hits = server.shared.state.lock().unwrap().segment_hits.clone();
+        assert!((0..12).all(|i| hits[&(expected.sub_id.clone(), i)] == 2));
+
+        // the merged segments remux into a continuous mp4
+        let merged = dir.join("merged.ts");
+        pku3b::video::download_ts(&v, &merged, opts, |_, _| {})
+            .await
+            .unwrap();
+        let stats = pku3b::remux::remux_file(&merged, &dir.join("out.mp4")).unwrap();
+        assert_eq!(stats.video_samples, 12 * expected.frames);
+        let frames = (12 * expected.frames) as u64;
+        assert_eq!(
+            stats.audio_samples as u64,
+            frames * video::FRAME_TICKS / video::AUDIO_FRAME_TICKS
+        );
+        let duration = std::time::Duration::from_millis(frames * video::FRAME_TICKS / 90);
+        assert_eq!(stats.duration, duration);
+
+        // the audio track is extracted from the segments without merging them
+        let audio_frames = stats.audio_samples;
+        let m4a = pku3b::video::extract_audio(&paths, &dir.join("out.m4a"))
+            .await
+            .unwrap();
+        assert_eq!((m4a.video_samples, m4a.audio_samples), (0, audio_frames));
+        assert_eq!(m4a.duration, duration);
+        let aac = pku3b::video::extract_audio(&paths, &dir.join("out.aac"))
+            .await
+            .unwrap();
+        assert_eq!(aac, m4a);
+        // 7-byte adts header and 48 bytes of payload per frame
+        let size = std::fs::metadata(dir.join("out.aac")).unwrap().len();
+        assert_eq!(size, audio_frames as u64 * (7 + 48));
+    }
+
+    #[compio::test]
+    async fn test_download_ts() {
+        let (server, client) = start_with(|s| {
+            let v = &mut s.courses[0].videos[0];
+            v.segments = 6;
+            v.flaky = 1;
+        });
+        let course = first_course(&client).await;
+        let v = course.get_video_list().await.unwrap()[0]
+            .get()
+            .await
+            .unwrap();
+        let expected = &server.shared.scenario.courses[0].videos[0];
+        let plain = (0..6)
+            .map(|i| video::plaintext(expected, i))
+            .collect::<Vec<_>>();
+
+        let dir = temp_dir("ts");
+        let opts = pku3b::video::DownloadOptions {
+            jobs: 3,
+            retries: 1,
+            retry_delay: std::time::Duration::ZERO,
+        };
+        let dst = dir.join("out.ts");
+        let mut reported = Vec::new();
+        pku3b::video::download_ts(&v, &dst, opts, |done, tot| reported.push((done, tot)))
+            .await
+            .unwrap();
+        assert_eq!(reported, (0..=6).map(|d| (d, 6)).collect::<Vec<_>>());
+        assert_eq!(std::fs::read(&dst).unwrap(), plain.concat());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        let hits = server.shared.state.lock().unwrap().segment_hits.clone();
+        assert!((0..6).all(|i| hits[&(expected.sub_id.clone(), i)] == 2));
+
+        // resume after two segments, with a partly written third one
+        let dst = dir.join("resumed.ts");
+        let done = plain[..2].concat();
+        let part = [&done[..], &plain[2][..100]].concat();
+        std::fs::write(dir.join("resumed.ts.part"), part).unwrap();
+        let ckpt = format!(r#"{{"total":6,"segments":2,"offset":{}}}"#, done.len());
+        std::fs::write(dir.join("resumed.ts.ckpt"), ckpt).unwrap();
+        let mut reported = Vec::new();
+        pku3b::video::download_ts(&v, &dst, opts, |done, _| reported.push(done))
+            .await
+            .unwrap();
+        assert_eq!(reported, [2, 3, 4, 5, 6]);
+        assert_eq!(std::fs::read(&dst).unwrap(), plain.concat());
+        assert!(!dir.join("resumed.ts.ckpt").exists());
+        let hits = server.shared.state.lock().unwrap().segment_hits.clone();
+        assert_eq!(hits[&(expected.sub_id.clone(), 0)], 2);
+        assert_eq!(hits[&(expected.sub_id.clone(), 5)], 3);
+
+        // a checkpoint of another playlist is ignored
+        let dst = dir.join("stale.ts");
+        std::fs::write(dir.join("stale.ts.part"), b"garbage").unwrap();
+        let ckpt = r#"{"total":9,"segments":1,"offset":3}"#;
+        std::fs::write(dir.join("stale.ts.ckpt"), ckpt).unwrap();
+        pku3b::video::download_ts(&v, &dst, opts, |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), plain.concat());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[compio::test]
+    async fn test_save_batch() {
+        let (server, client) = start();
+        let course = first_course(&client).await;
+        let videos = course.get_video_list().await.unwrap();
+        let name = course.meta().name().to_owned();
+        let expected = &server.shared.scenario.courses[0].videos;
+
+        // both replays share a title, the default batch template tells them apart by date
+        let names = pku3b::video::batch_names("{title}", &videos).unwrap();
+        let sub_ids = expected.iter().map(|v| &v.sub_id);
+        assert_eq!(
+            names,
+            sub_ids
+                .map(|id| format!("数据结构与算法_{id}"))
+                .collect::<Vec<_>>()
+        );
+        // the names do not depend on the order of the list
+        let reversed = videos.iter().rev().cloned().collect::<Vec<_>>();
+        let mut names_rev = pku3b::video::batch_names("{title}", &reversed).unwrap();
+        names_rev.reverse();
+        assert_eq!(names_rev, names);
+        assert!(pku3b::video::batch_names("{speaker}", &videos).is_err());
+
+        let filter = |since: Option<&str>, latest| pku3b::video::VideoFilter {
+            since: since.map(|s| pku3b::utils::parse_since(s).unwrap()),
+            latest,
+        };
+        let ids = |vs: Vec<api::CourseVideoHandle>| vs.iter().map(|v| v.id()).collect::<Vec<_>>();
+        let second = format!("{}::{}", course.meta().id(), expected[1].sub_id);
+        assert_eq!(
+            ids(filter(None, Some(1)).apply(videos.clone())),
+            [second.as_str()]
+        );
+        assert_eq!(
+            ids(filter(Some("2024-09-15"), None).apply(videos.clone())),
+            [second.as_str()]
+        );
+        assert_eq!(filter(None, None).apply(videos.clone()).len(), 2);
+
+        let dir = temp_dir("batch");
+        let opts = pku3b::video::BatchOptions {
+            parallel: 2,
+            template: pku3b::video::BATCH_NAME_TEMPLATE.to_owned(),
+            format: pku3b::video::SaveFormat::Ts,
+            selector: Default::default(),
+            ffmpeg: false,
+            download: pku3b::video::DownloadOptions {
+                retry_delay: std::time::Duration::ZERO,
+                ..Default::default()
+            },
+        };
+        let work =
+            |h: &api::CourseVideoHandle, _: &api::CourseVideo| dir.join("work").join(h.title());
+        let finished = std::cell::RefCell::new(Vec::new());
+        let results = pku3b::video::save_batch(
+            &videos,
+            &dir,
+            &opts,
+            work,
+            |_, _| {},
+            |i, _| finished.borrow_mut().push(i),
+        )
+        .await
+        .unwrap();
+        assert_eq!(finished.borrow().len(), 2);
+        for (i, date) in ["2024-09-10", "2024-09-17"].into_iter().enumerate() {
+            let path = dir.join(format!("{name}_数据结构与算法_{date}.ts"));
+            assert!(matches!(&results[i], pku3b::video::BatchStatus::Saved(p) if *p == path));
+            let plain = (0..expected[i].segments)
+                .map(|s| video::plaintext(&expected[i], s))
+                .collect::<Vec<_>>();
+            assert_eq!(std::fs::read(&path).unwrap(), plain.concat());
+        }
+
+        // existing files are skipped without fetching the replays
+        let (hits, sub_info_hits) = {
+            let st = server.shared.state.lock().unwrap();
+            (st.segment_hits.clone(), st.sub_info_hits)
+        };
+        let results = pku3b::video::save_batch(&videos, &dir, &opts, work, |_, _| {}, |_, _| {})
+            .await
+            .unwrap();
+        assert!(
+            results
+                .iter()
+                .all(|r| matches!(r, pku3b::video::BatchStatus::Skipped(_)))
+        );
+        let st = server.shared.state.lock().unwrap();
+        assert_eq!(st.segment_hits, hits);
+        assert_eq!(st.sub_info_hits, sub_info_hits);
+        drop(st);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[compio::test]
+    async fn test_export_hls() {
+        let (server, client) = start();
+        let course = first_course(&client).await;
+        // the replay with a non-zero media sequence
+        let handle = course.get_video_list().await.unwrap().remove(1);
+        let v = handle.get().await.unwrap();
+        let expected = &server.shared.scenario.courses[0].videos[1];
+        let opts = pku3b::video::DownloadOptions {
+            retry_delay: std::time::Duration::ZERO,
+            ..Default::default()
+        };
+
+        let dir = temp_dir("hls");
+        let out = dir.join("lecture");
+        let path = pku3b::video::save(
+            &v,
+            &out,
+            pku3b::video::SaveFormat::Hls,
+            &dir.join("work"),
+            opts,
+            false,
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(path, out.join("index.m3u8"));
+
+        let playlist = std::fs::read_to_string(&path).unwrap();
+        assert!(!playlist.contains("EXT-X-KEY"), "{playlist}");
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:100"));
+        assert!(playlist.contains("#EXT-X-ENDLIST"));
+        let uris = playlist
+            .lines()
+            .filter(|l| !l.starts_with('#') && !l.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(uris, ["00000.ts", "00001.ts"]);
+        for (i, uri) in uris.iter().enumerate() {
+            let data = std::fs::read(out.join(uri)).unwrap();
+            assert_eq!(data, video::plaintext(expected, i));
+        }
+        assert!(!dir.join("work").exists());
+
+        // an interrupted export only fetches the missing segments
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(out.join("00001.ts")).unwrap();
+        let hits = server.shared.state.lock().unwrap().segment_hits.clone();
+        let path = pku3b::video::export_hls(&v, &out, opts, |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), playlist);
+        let after = server.shared.state.lock().unwrap().segment_hits.clone();
+        let key = |i| (expected.sub_id.clone() + "/v1", i);
+        assert_eq!(after[&key(0)], hits[&key(0)]);
+        assert_eq!(after[&key(1)], hits[&key(1)] + 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[compio::test]
+    async fn test_init_map_rejected() {
+        let (_server, client) =
+            start_with(|s| s.courses[0].videos[0].map = Some("init.mp4".to_owned()));
+        let course = first_course(&client).await;
+        let v = course.get_video_list().await.unwrap()[0]
+            .get()
+            .await
+            .unwrap();
+
+        // segments after an initialization section cannot be played on their own
+        let dir = temp_dir("hls-map").join("lecture");
+        let e = pku3b::video::export_hls(&v, &dir, Default::default(), |_, _| {})
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("EXT-X-MAP"), "{e:#}");
+        assert!(!dir.exists());
+        let e = pku3b::serve::HlsProxy::bind(v, "127.0.0.1:0".parse().unwrap(), Default::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("EXT-X-MAP"), "{e:#}");
+    }
+
+    /// 向 `addr` 发送一个请求，返回状态码和响应体
+    async fn http_get(addr: std::net::SocketAddr, method: &str, path: &str) -> (u16, Vec<u8>) {
+        use compio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+        let mut stream = compio::net::TcpStream::connect(addr).await.unwrap();
+        let req = format!("{method} {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+        stream.write_all(req.into_bytes()).await.0.unwrap();
+        let (r, res) = stream.read_to_end(Vec::new()).await.into();
+        r.unwrap();
+        let sep = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = std::str::from_utf8(&res[..sep]).unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, res[sep + 4..].to_vec())
+    }
+
+    #[compio::test]
+    async fn test_serve() {
+        let (server, client) = start_with(|s| s.courses[0].videos[0].flaky = 1);
+        let course = first_course(&client).await;
+        let v = course.get_video_list().await.unwrap()[0]
+            .get()
+            .await
+            .unwrap();
+        let expected = &server.shared.scenario.courses[0].videos[0];
+
+        let opts = pku3b::video::DownloadOptions {
+            retry_delay: std::time::Duration::ZERO,
+            ..Default::default()
+        };
+        let proxy = pku3b::serve::HlsProxy::bind(v, "127.0.0.1:0".parse().unwrap(), opts)
+            .await
+            .unwrap();
+        let addr = proxy.local_addr();
+        assert_eq!(proxy.playlist_url(), format!("http://{addr}/index.m3u8"));
+        compio::runtime::spawn(proxy.run()).detach();
+
+        let (status, body) = http_get(addr, "GET", "/index.m3u8").await;
+        assert_eq!(status, 200);
+        let playlist = String::from_utf8(body).unwrap();
+        assert!(!playlist.contains("EXT-X-KEY"), "{playlist}");
+        assert!(playlist.contains("#EXT-X-ENDLIST"));
+        let uris = playlist
+            .lines()
+            .filter(|l| !l.starts_with('#') && !l.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uris,
+            (0..expected.segments)
+                .map(|i| format!("seg/{i}.ts"))
+                .collect::<Vec<_>>()
+        );
+
+        // segments are decrypted, failed requests are retried
+        for i in 0..expected.segments {
+            let (status, body) = http_get(addr, "GET", &format!("/seg/{i}.ts")).await;
+            assert_eq!(status, 200);
+            assert_eq!(body, video::plaintext(expected, i));
+        }
+        let hits = server.shared.state.lock().unwrap().segment_hits.clone();
+        assert!((0..expected.segments).all(|i| hits[&(expected.sub_id.clone(), i)] == 2));
+
+        let (status, body) = http_get(addr, "HEAD", "/index.m3u8").await;
+        assert_eq!((status, body.len()), (200, 0));
+        // HEAD does not fetch the segment
+        let (status, body) = http_get(addr, "HEAD", "/seg/0.ts").await;
+        assert_eq!((status, body.len()), (200, 0));
+        assert_eq!(server.shared.state.lock().unwrap().segment_hits, hits);
+        let seg = format!("/seg/{}.ts", expected.segments);
+        assert_eq!(http_get(addr, "GET", &seg).await.0, 404);
+        assert_eq!(http_get(addr, "POST", "/index.m3u8").await.0, 405);
+    }
+
+    #[compio::test]
+    async fn test_download_file() {
+        let (server, client) = start_with(|s| {
+            let v = &mut s.courses[0].videos[0];
+            v.segments = 4;
+            v.file = Some("lecture.ts".to_owned());
+        });
+        let course = first_course(&client).await;
+        let handle = course.get_video_list().await.unwrap().remove(0);
+        let variants = handle.variants().await.unwrap();
+        assert_eq!(variants.len(), 1);
+        assert_eq!(variants[0].kind, api::PlaybackKind::File);
+
+        let v = handle.get().await.unwrap();
+        assert_eq!(v.len_segments(), 0);
+        assert_eq!(pku3b::video::file_extension(&v), "ts");
+        assert!(v.get_segment_data(0, None).await.is_err());
+
+        // resume from a partial download of the same remote file
+        let expected = video::file_data(&server.shared.scenario.courses[0].videos[0]);
+        let len = expected.len() as u64;
+        let etag = v.get_file_chunk(0, 1).await.unwrap().validator;
+        assert!(etag.is_some());
+        let checkpoint = |validator: &Option<String>| {
+            serde_json::json!({ "total": len, "validator": validator }).to_string()
+        };
+        let dir = temp_dir("file");
+        let dst = dir.join("lecture.ts");
+        let (part, ckpt) = (dir.join("lecture.ts.part"), dir.join("lecture.ts.ckpt"));
+        std::fs::write(&part, &expected[..1000]).unwrap();
+        std::fs::write(&ckpt, checkpoint(&etag)).unwrap();
+        server.shared.state.lock().unwrap().file_ranges.clear();
+        let mut reported = (0, None);
+        pku3b::video::download_file(&v, &dst, Default::default(), |done, total| {
+            reported = (done, total);
+        })
+        .await
+        .unwrap();
+        assert_eq!(reported, (len, Some(len)));
+        assert_eq!(std::fs::read(&dst).unwrap(), expected);
+        assert!(!part.exists());
+        assert!(!ckpt.exists());
+        let chunk = pku3b::video::FILE_CHUNK_SIZE;
+        assert_eq!(
+            std::mem::take(&mut server.shared.state.lock().unwrap().file_ranges),
+            [format!("bytes=1000-{}", 1000 + chunk - 1)]
+        );
+
+        // a complete part file is only renamed
+        std::fs::rename(&dst, &part).unwrap();
+        std::fs::write(&ckpt, checkpoint(&etag)).unwrap();
+        pku3b::video::download_file(&v, &dst, Default::default(), |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), expected);
+        assert_eq!(
+            std::mem::take(&mut server.shared.state.lock().unwrap().file_ranges),
+            [format!("bytes={len}-{}", len + chunk - 1)]
+        );
+
+        // a part file of another version, or without a checkpoint, is downloaded again
+        let stale = Some("\"stale\"".to_owned());
+        for ckpt_content in [Some(checkpoint(&stale)), None] {
+            std::fs::write(&part, vec![0xff; 1000]).unwrap();
+            match &ckpt_content {
+                Some(c) => std::fs::write(&ckpt, c).unwrap(),
+                None => std::fs::remove_file(&ckpt).unwrap_or_default(),
+            }
+            pku3b::video::download_file(&v, &dst, Default::default(), |_, _| {})
+                .await
+                .unwrap();
+            assert_eq!(std::fs::read(&dst).unwrap(), expected);
+            assert!(!ckpt.exists());
+            let ranges = std::mem::take(&mut server.shared.state.lock().unwrap().file_ranges);
+            assert_eq!(ranges.last().unwrap(), &format!("bytes=0-{}", chunk - 1));
+        }
+
+        // the downloaded file is a plain transport stream
+        let stats = pku3b::video::extract_audio(&[dst], &dir.join("out.m4a"))
+            .await
+            .unwrap();
+        assert_eq!(stats.video_samples, 0);
+        assert!(stats.audio_samples > 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[compio::test]
+    async fn test_download_file_ignored_range() {
+        let (server, client) = start_with(|s| {
+            let v = &mut s.courses[0].videos[0];
+            v.segments = 4;
+            v.file = Some("lecture.ts".to_owned());
+            v.ignore_range = true;
+        });
+        let course = first_course(&client).await;
+        let v = course.get_video_list().await.unwrap().remove(0);
+        let v = v.get().await.unwrap();
+        let expected = video::file_data(&server.shared.scenario.courses[0].videos[0]);
+        let len = expected.len() as u64;
+
+        // a whole file that fits in the chunk is accepted
+        let chunk = v.get_file_chunk(0, len).await.unwrap();
+        assert_eq!((chunk.offset, chunk.total), (0, Some(len)));
+        assert_eq!(chunk.data, expected);
+        // a larger one is refused instead of being buffered
+        let err = v.get_file_chunk(0, len - 1).await.unwrap_err();
+        assert!(
+            format!("{err:#}").contains("ignored the range request"),
+            "{err:#}"
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use clap::Parser;
+use pku3b_fakebb::{Server, scenario::Scenario};
+
+/// 用于测试 pku3b 的模拟教学网服务器
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// 场景文件 (TOML)
+    scenario: std::path::PathBuf,
+
+    /// 监听地址
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// 将提交的作业保存到该目录
+    #[arg(long, value_name = "DIR")]
+    submissions: Option<std::path::PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let cli = Cli::parse();
+    let scenario = Scenario::load(&cli.scenario)?;
+    let mut server = Server::bind(scenario, &cli.listen)?;
+    if let Some(dir) = cli.submissions {
+        server = server.with_submissions_dir(dir);
+    }
+
+    let addr = server.addr()?;
+    println!("fakebb listening on http://{addr}");
+    println!("run pku3b with --base-url http://{addr}");
+    server.run()
+}
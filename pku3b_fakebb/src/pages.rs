@@ -0,0 +1,294 @@
+//! 模拟教学网页面，结构与 `pku3b/tests/fixtures/html` 中保存的真实页面一致.
+
+use crate::scenario::{Content, ContentKind, Course, Scenario};
+
+/// 回放栏目链接中的工具 ID，pku3b 据此识别回放栏目
+const VIDEO_TOOL_ID: &str = "_1761_1";
+
+pub fn escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => r.push_str("&amp;"),
+            '<' => r.push_str("&lt;"),
+            '>' => r.push_str("&gt;"),
+            '"' => r.push_str("&quot;"),
+            '\'' => r.push_str("&#39;"),
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n\
+         <body>\n{body}\n</body>\n</html>\n",
+        escape(title)
+    )
+}
+
+pub fn login() -> String {
+    page(
+        "北京大学教学网",
+        "<div id=\"loginBox\"><a href=\"https://iaaa.pku.edu.cn/iaaa/oauth.jsp?appID=blackboard\">校园卡用户登录</a></div>",
+    )
+}
+
+pub fn sso_success() -> String {
+    page(
+        "北京大学教学网",
+        "<script>location.href = '/webapps/portal/execute/tabs/tabAction';</script>",
+    )
+}
+
+pub fn homepage(s: &Scenario) -> String {
+    let list = |current: bool| {
+        s.courses
+            .iter()
+            .filter(|c| c.current == current)
+            .map(|c| {
+                format!(
+                    "      <li><img alt=\"\" src=\"/images/ci/icons/bookopen_li.gif\" width=\"12\" height=\"12\" />\
+                     <a href=\" /webapps/blackboard/execute/launcher?type=Course&amp;id=PkId{{key={}, dataType=blackboard.data.course.Course, container=blackboard.persist.DatabaseContainer@1}}&amp;url=\" target=\"_top\">{}</a></li>\n",
+                    escape(&c.id),
+                    escape(&c.title)
+                )
+            })
+            .collect::<String>()
+    };
+    let module = |id: &str, title: &str, current: bool| {
+        format!(
+            "<div id=\"module:{id}\" class=\"portlet\">\n  <h2 class=\"moduleTitle\">{title}</h2>\n  \
+             <div class=\"collapsible\">\n    <ul class=\"portletList-img courseListing coursefakeclass \">\n{}    </ul>\n  </div>\n</div>",
+            list(current)
+        )
+    };
+    page(
+        &format!("欢迎, {}", s.username),
+        &format!(
+            "{}\n{}",
+            module("_4_1", "当前学期课程", true),
+            module("_5_1", "历史课程", false)
+        ),
+    )
+}
+
+/// 课程主页：左侧菜单和课程通知
+pub fn coursepage(c: &Course) -> String {
+    let id = escape(&c.id);
+    let mut menu = format!(
+        "    <li class=\"clearfix\"><a href=\"/webapps/blackboard/execute/announcement?method=search&amp;context=course_entry&amp;course_id={id}&amp;handle=announcements_entry&amp;mode=view\" target=\"_self\"><span title=\"课程通知\">课程通知</span></a></li>\n"
+    );
+    for s in &c.sections {
+        menu += &format!(
+            "    <li class=\"clearfix\"><a href=\"/webapps/blackboard/content/listContent.jsp?course_id={id}&amp;content_id={}&amp;mode=reset\" target=\"_self\"><span title=\"{name}\">{name}</span></a></li>\n",
+            escape(&s.id),
+            name = escape(&s.name)
+        );
+    }
+    if !c.videos.is_empty() {
+        menu += &format!(
+            "    <li class=\"clearfix\"><a href=\"/webapps/blackboard/execute/blti/launchPlacement?blti_placement_id=_1_1&amp;course_id={id}&amp;tool_id={VIDEO_TOOL_ID}\" target=\"_self\"><span title=\"课堂实录\">课堂实录</span></a></li>\n"
+        );
+    }
+
+    let announcements = c
+        .announcements
+        .iter()
+        .map(|a| {
+            let body = a
+                .body
+                .iter()
+                .map(|p| format!("<p>{}</p>", escape(p)))
+                .collect::<String>();
+            format!(
+                "  <li class=\"clearfix\" id=\"{}\">\n    <h3 class=\"item\">{}</h3>\n    <div class=\"details\">\n      \
+                 <p><span>发帖时间: {}</span></p>\n      <div class=\"vtbegenerated\">{body}</div>\n    </div>\n    \
+                 <div class=\"announcementInfo\"><p><span class=\"creator\">发帖者: {}</span></p></div>\n  </li>\n",
+                escape(&a.id),
+                escape(&a.title),
+                escape(&a.time),
+                escape(&a.author)
+            )
+        })
+        .collect::<String>();
+
+    page(
+        &c.title,
+        &format!(
+            "<div id=\"courseMenuPalette\">\n  <ul id=\"courseMenuPalette_contents\" class=\"courseMenu\">\n{menu}  </ul>\n</div>\n\
+             <div id=\"content\">\n<ul id=\"announcementList\" class=\"announcementList announcementList-read\">\n{announcements}</ul>\n</div>"
+        ),
+    )
+}
+
+/// 内容条目的附件链接，指向 [`attachment_path`] 的跳转地址
+pub fn attachment_href(content: &Content, index: usize) -> String {
+    format!(
+        "/bbcswebdav/pid-{}-dt-content-rid-{index}_1/xid-{index}_1",
+        content.id
+    )
+}
+
+/// 附件的实际地址
+pub fn attachment_path(course: &Course, content: &Content, index: usize) -> String {
+    format!(
+        "/bbcswebdav/courses/{}/{}/{index}/{}",
+        course.id,
+        content.id,
+        url::form_urlencoded::byte_serialize(content.attachments[index].name.as_bytes())
+            .collect::<String>()
+    )
+}
+
+fn content_item(c: &Course, x: &Content) -> String {
+    let alt = match x.kind {
+        ContentKind::Document => "项目",
+        ContentKind::File => "文件",
+        ContentKind::Folder => "内容文件夹",
+        ContentKind::Assignment => "作业",
+    };
+    let title = escape(&x.title);
+    let title = match x.kind {
+        ContentKind::Folder if x.available => format!(
+            "<a href=\"/webapps/blackboard/content/listContent.jsp?course_id={}&amp;content_id={}\"><span>{title}</span></a>",
+            escape(&c.id),
+            escape(&x.id)
+        ),
+        ContentKind::Assignment => format!(
+            "<a href=\"/webapps/assignment/uploadAssignment?content_id={}&amp;course_id={}&amp;group_id=&amp;mode=view\"><span>{title}</span></a>",
+            escape(&x.id),
+            escape(&c.id)
+        ),
+        _ => format!("<span>{title}</span>"),
+    };
+    let description = x
+        .description
+        .iter()
+        .map(|p| format!("<p>{}</p>", escape(p)))
+        .collect::<String>();
+    let attachments = x
+        .attachments
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            format!(
+                "<li><a href=\"{}\" target=\"_blank\">&nbsp;{}</a></li>",
+                attachment_href(x, i),
+                escape(&a.name)
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        "  <li id=\"contentListItem:{id}\" class=\"clearfix liItem read\">\n    \
+         <img src=\"/images/ci/sets/set12/document_on.gif\" alt=\"{alt}\" class=\"item_icon\">\n    \
+         <div class=\"item clearfix\" id=\"{id}\"><h3>{title}</h3></div>\n    \
+         <div class=\"details\">\n      <div class=\"vtbegenerated\">{description}</div>\n      \
+         <div class=\"contextItemDetailsHeaders clearfix\"><ul class=\"attachments clearfix\">{attachments}</ul></div>\n    </div>\n  </li>\n",
+        id = escape(&x.id)
+    )
+}
+
+/// 栏目或文件夹的内容列表
+pub fn list_content(c: &Course, parent: &str) -> String {
+    let items = c
+        .children(parent)
+        .map(|x| content_item(c, x))
+        .collect::<String>();
+    page(
+        &c.title,
+        &format!("<ul id=\"content_listContainer\" class=\"contentList\">\n{items}</ul>"),
+    )
+}
+
+/// 作业提交页面，`fields` 为提交表单中的隐藏字段
+pub fn upload_assignment(x: &Content, fields: &[(&str, String)]) -> String {
+    let deadline = x
+        .deadline
+        .as_ref()
+        .map(|d| {
+            format!(
+                "<div id=\"assignMeta2\" class=\"metaLabel\">到期日期</div>\n<div class=\"metaField\">\n  {}\n</div>\n",
+                escape(d)
+            )
+        })
+        .unwrap_or_default();
+    let inputs = fields
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "  <input type=\"hidden\" name=\"{}\" value=\"{}\">\n",
+                escape(k),
+                escape(v)
+            )
+        })
+        .collect::<String>();
+    page(
+        &format!("上载作业: {}", x.title),
+        &format!(
+            "{deadline}<form id=\"uploadAssignmentFormId\" method=\"post\" enctype=\"multipart/form-data\" action=\"/webapps/assignment/uploadAssignment?action=submit\">\n{inputs}  \
+             <input type=\"submit\" name=\"bottom_提交\" value=\"提交\">\n</form>"
+        ),
+    )
+}
+
+/// 作业提交历史页面，`attempt` 为最近一次提交的时间
+pub fn view_assignment(x: &Content, attempt: Option<&str>) -> String {
+    let body = match attempt {
+        Some(t) => format!(
+            "<div id=\"currentAttempt\" class=\"container\">\n  <h3 id=\"currentAttempt_label\">\n    尝试\n    {}\n  </h3>\n</div>",
+            escape(t)
+        ),
+        None => "<div class=\"noItems\">没有提交</div>".to_owned(),
+    };
+    page(&format!("复查提交历史记录: {}", x.title), &body)
+}
+
+/// 课程回放列表中从第 `start` 条开始的至多 `num` 条
+pub fn video_list(c: &Course, start: usize, num: usize) -> String {
+    let rows = c
+        .videos
+        .iter()
+        .skip(start)
+        .take(num)
+        .map(|v| {
+            let link = if v.transcoding {
+                "转码中".to_owned()
+            } else {
+                format!(
+                    "<a href=\"videoPlay.action?course_id={}&amp;sub_id={}&amp;app_id=4\">查看</a>",
+                    escape(&c.id),
+                    escape(&v.sub_id)
+                )
+            };
+            format!(
+                "    <tr>\n      <th scope=\"row\">{}</th>\n      <td><span class=\"table-data-cell-value\">{}</span></td>\n      \
+                 <td><span class=\"table-data-cell-value\">{}</span></td>\n      \
+                 <td><span class=\"table-data-cell-value\">{link}</span></td>\n    </tr>\n",
+                escape(&v.title),
+                escape(&v.time),
+                escape(&v.teacher),
+            )
+        })
+        .collect::<String>();
+    page(
+        "课堂实录",
+        &format!(
+            "<table id=\"listContainer_datatable\" class=\"inventory sortable\">\n  <tbody id=\"listContainer_databody\">\n{rows}  </tbody>\n</table>"
+        ),
+    )
+}
+
+/// 回放播放页面，其中的 iframe 会跳转到带有 auth_data 的播放器地址
+pub fn video_play(course_id: &str, sub_id: &str) -> String {
+    page(
+        "课堂实录",
+        &format!(
+            "<div id=\"content\"><iframe src=\"https://course.pku.edu.cn/webapps/bb-streammedia-hqy-BBLEARN/playVideo.action?course_id={}&amp;sub_id={}&amp;app_id=4\"></iframe></div>",
+            escape(course_id),
+            escape(sub_id)
+        ),
+    )
+}
@@ -0,0 +1,259 @@
+//! 场景文件：模拟服务器上的账号、课程、内容和回放.
+//!
+//! 场景文件为 TOML 格式，示例见 `scenario.example.toml`。
+
+use anyhow::Context as _;
+use std::path::Path;
+
+/// 回放默认使用的 AES-128 密钥
+pub const DEFAULT_KEY: &[u8; 16] = b"pku3b-fakebb-key";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// 学号
+    pub username: String,
+    pub password: String,
+    /// 为 `true` 时未登录的页面请求以 200 直接返回登录页面，而不是重定向到登录页面
+    #[serde(default)]
+    pub inline_login_page: bool,
+    #[serde(default)]
+    pub courses: Vec<Course>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Course {
+    /// 课程 ID，形如 `_80167_1`
+    pub id: String,
+    /// 课程全名，形如 `24251-00048-04834520-0006171090-00-1: 数据结构与算法 (24-25学年第1学期)`
+    pub title: String,
+    /// 是否为本学期课程
+    #[serde(default = "default_true")]
+    pub current: bool,
+    /// 左侧菜单中的内容栏目
+    #[serde(default)]
+    pub sections: Vec<Section>,
+    #[serde(default)]
+    pub contents: Vec<Content>,
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    #[serde(default)]
+    pub videos: Vec<Video>,
+}
+
+/// 内容栏目，对应 `listContent.jsp?content_id=...`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Section {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentKind {
+    Document,
+    File,
+    Folder,
+    Assignment,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Content {
+    pub id: String,
+    /// 所在栏目或文件夹的 ID
+    pub parent: String,
+    pub kind: ContentKind,
+    pub title: String,
+    #[serde(default)]
+    pub description: Vec<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// 作业的截止时间，原样显示在作业页面上
+    pub deadline: Option<String>,
+    /// 文件夹是否可以打开
+    #[serde(default = "default_true")]
+    pub available: bool,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Attachment {
+    pub name: String,
+    /// 文件内容，缺省时使用文件名
+    pub content: Option<String>,
+}
+
+impl Attachment {
+    pub fn bytes(&self) -> Vec<u8> {
+        self.content
+            .as_ref()
+            .unwrap_or(&self.name)
+            .as_bytes()
+            .to_vec()
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Announcement {
+    pub id: String,
+    pub title: String,
+    pub author: String,
+    /// 发布时间，原样显示在页面上
+    pub time: String,
+    #[serde(default)]
+    pub body: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Video {
+    pub sub_id: String,
+    pub title: String,
+    /// 开始时间，原样显示在回放列表中
+    pub time: String,
+    pub teacher: String,
+    /// 分片数量
+    #[serde(default = "default_segments")]
+    pub segments: usize,
+    /// 每个分片包含的视频帧数 (25fps)
+    #[serde(default = "default_frames")]
+    pub frames: usize,
+    #[serde(default)]
+    pub media_sequence: u64,
+    /// 是否使用 AES-128 加密分片
+    #[serde(default = "default_true")]
+    pub encrypted: bool,
+    /// 给出时以此替换 `EXT-X-KEY` 的属性 (如 `METHOD=NONE`)，分片不加密，用于测试其他加密方式
+    pub key_tag: Option<String>,
+    /// 给出时在分片之前加上指向该文件的 `EXT-X-MAP` (初始化分片)，用于测试不支持的播放列表
+    pub map: Option<String>,
+    /// 十六进制表示的密钥，缺省为 [`DEFAULT_KEY`]
+    pub key: Option<String>,
+    /// 每个分片的前若干次请求返回 503，用于测试重试
+    #[serde(default)]
+    pub flaky: usize,
+    /// 非空时以主播放列表给出这些变体，每个变体的分片内容相同
+    #[serde(default)]
+    pub variants: Vec<Variant>,
+    /// 给出时不使用 HLS，而是以该文件名提供整个视频文件 (各分片明文的拼接)，支持 Range 请求
+    pub file: Option<String>,
+    /// 为 `true` 时视频文件忽略 Range 请求，总是返回整个文件
+    #[serde(default)]
+    pub ignore_range: bool,
+    /// 为 `true` 时回放仍在转码，列表中显示“转码中”而没有链接
+    #[serde(default)]
+    pub transcoding: bool,
+}
+
+/// 主播放列表中的一个变体
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Variant {
+    /// 视频流名称 (`EXT-X-MEDIA` 的 `NAME`)
+    pub stream: String,
+    pub width: u64,
+    pub height: u64,
+    pub bandwidth: u64,
+}
+
+impl Video {
+    pub fn key(&self) -> anyhow::Result<[u8; 16]> {
+        let Some(hex) = &self.key else {
+            return Ok(*DEFAULT_KEY);
+        };
+        anyhow::ensure!(hex.len() == 32, "key of {} is not 16 bytes", self.sub_id);
+        let mut key = [0; 16];
+        for (i, b) in key.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .with_context(|| format!("invalid key of {}", self.sub_id))?;
+        }
+        Ok(key)
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_segments() -> usize {
+    3
+}
+
+fn default_frames() -> usize {
+    8
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("read scenario {}", path.display()))?;
+        s.parse()
+            .with_context(|| format!("parse scenario {}", path.display()))
+    }
+
+    pub fn course(&self, id: &str) -> Option<&Course> {
+        self.courses.iter().find(|c| c.id == id)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for c in &self.courses {
+            anyhow::ensure!(c.title.contains(':'), "title of {} has no ':'", c.id);
+            for x in &c.contents {
+                let parent_ok = c.sections.iter().any(|s| s.id == x.parent)
+                    || c.contents
+                        .iter()
+                        .any(|p| p.id == x.parent && p.kind == ContentKind::Folder);
+                anyhow::ensure!(parent_ok, "parent {} of {} not found", x.parent, x.id);
+            }
+            for v in &c.videos {
+                v.key()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Scenario {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let scenario: Self = toml::from_str(s)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+}
+
+impl Course {
+    pub fn content(&self, id: &str) -> Option<&Content> {
+        self.contents.iter().find(|c| c.id == id)
+    }
+
+    pub fn children<'a>(&'a self, parent: &'a str) -> impl Iterator<Item = &'a Content> {
+        self.contents.iter().filter(move |c| c.parent == parent)
+    }
+
+    pub fn video(&self, sub_id: &str) -> Option<&Video> {
+        self.videos.iter().find(|v| v.sub_id == sub_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenario.example.toml");
+        let s = Scenario::load(&path).unwrap();
+        assert!(!s.courses.is_empty());
+
+        let err = "username = 'a'\npassword = 'b'\n[[courses]]\nid = '_1_1'\ntitle = 'x: y'\n\
+                   [[courses.contents]]\nid = '_2_1'\nparent = '_3_1'\nkind = 'file'\ntitle = 'z'"
+            .parse::<Scenario>()
+            .unwrap_err();
+        assert!(err.to_string().contains("parent _3_1"), "{err}");
+    }
+}
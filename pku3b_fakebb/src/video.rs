@@ -0,0 +1,296 @@
+//! 回放的 m3u8 播放列表和分片.
+//!
+//! 分片是真实结构的 MPEG-TS：PAT/PMT、H.264 视频 (640x360, 25fps) 和 AAC 音频 (48kHz 双声道)。
+//! 帧的内容是填充字节，无法解码，但足以检验下载、解密和转封装。
+
+use crate::{http::Response, scenario::Video};
+
+/// MPEG-TS 包的长度
+pub const TS_PACKET: usize = 188;
+
+/// 回放资源所在的域名，请求会经由 pku3b 的转发地址到达模拟服务器
+pub const RESOURCE_HOST: &str = "https://resourcese.pku.edu.cn";
+
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x100;
+const AUDIO_PID: u16 = 0x101;
+
+/// 视频帧时长 (90kHz)
+pub const FRAME_TICKS: u64 = 3600;
+/// AAC 帧时长 (1024 个采样 @ 48kHz，以 90kHz 计)
+pub const AUDIO_FRAME_TICKS: u64 = 1920;
+/// 第一帧的解码时间
+const START_TICKS: u64 = 126000;
+
+/// Baseline profile 640x360 的 SPS 和 PPS
+const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xbf, 0xe5, 0x40];
+const PPS: &[u8] = &[0x68, 0xce, 0x38, 0x80];
+
+pub fn base(video: &Video) -> String {
+    format!("{RESOURCE_HOST}/play/{}", video.sub_id)
+}
+
+/// 列出变体的主播放列表，第 i 个变体的媒体播放列表位于 `v{i}/index.m3u8`
+pub fn master_playlist(video: &Video) -> String {
+    let mut s = String::from("#EXTM3U\n");
+    for (i, v) in video.variants.iter().enumerate() {
+        s += &format!(
+            "#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID=\"v{i}\",NAME=\"{}\",DEFAULT=YES\n",
+            v.stream
+        );
+        s += &format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},VIDEO=\"v{i}\"\nv{i}/index.m3u8\n",
+            v.bandwidth, v.width, v.height
+        );
+    }
+    s
+}
+
+pub fn playlist(video: &Video) -> String {
+    let mut s = String::from("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:10\n");
+    s += &format!("#EXT-X-MEDIA-SEQUENCE:{}\n", video.media_sequence);
+    if let Some(tag) = &video.key_tag {
+        s += &format!("#EXT-X-KEY:{tag}\n");
+    } else if video.encrypted {
+        s += &format!("#EXT-X-KEY:METHOD=AES-128,URI=\"{}/key\"\n", base(video));
+    }
+    if let Some(map) = &video.map {
+        s += &format!("#EXT-X-MAP:URI=\"{map}\"\n");
+    }
+    let duration = (video.frames as u64 * FRAME_TICKS) as f64 / 90000.0;
+    for i in 0..video.segments {
+        s += &format!("#EXTINF:{duration:.3},\nseg-{i}.ts\n");
+    }
+    s += "#EXT-X-ENDLIST\n";
+    s
+}
+
+/// 视频文件回放的内容，即各分片明文的拼接
+pub fn file_data(video: &Video) -> Vec<u8> {
+    (0..video.segments)
+        .flat_map(|i| plaintext(video, i))
+        .collect()
+}
+
+/// 按 `Range: bytes=a-b` 请求头返回视频文件的一部分，不支持多段范围
+pub(crate) fn file_response(video: &Video, range: Option<&str>) -> Response {
+    use std::hash::{Hash, Hasher};
+
+    let data = file_data(video);
+    let total = data.len() as u64;
+    let mut h = std::hash::DefaultHasher::new();
+    data.hash(&mut h);
+    let etag = format!("\"{:016x}\"", h.finish());
+    let Some(range) = range else {
+        return Response::bytes("video/mp2t", data).header("ETag", etag);
+    };
+    let parsed = range.strip_prefix("bytes=").and_then(|r| {
+        let (a, b) = r.split_once('-')?;
+        let a: u64 = a.parse().ok()?;
+        let b = match b {
+            "" => total.saturating_sub(1),
+            b => b.parse::<u64>().ok()?.min(total.saturating_sub(1)),
+        };
+        Some((a, b))
+    });
+    match parsed {
+        Some((a, b)) if a < total && a <= b => {
+            Response::new(206, "video/mp2t", &data[a as usize..=b as usize])
+                .header("Content-Range", format!("bytes {a}-{b}/{total}"))
+                .header("ETag", etag)
+        }
+        _ => Response::new(416, "text/plain", "")
+            .header("Content-Range", format!("bytes */{total}"))
+            .header("ETag", etag),
+    }
+}
+
+/// 未加密的分片内容
+pub fn plaintext(video: &Video, index: usize) -> Vec<u8> {
+    let mut ts = TsWriter::default();
+    let program = (0xe000 | PMT_PID).to_be_bytes();
+    ts.psi(
+        0,
+        &[&[0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1][..], &program].concat(),
+    );
+    ts.psi(
+        PMT_PID,
+        &[
+            0x02, 0xb0, 23, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0x00, // pcr on the video pid
+            0x1b, 0xe1, 0x00, 0xf0, 0x00, // h264
+            0x0f, 0xe1, 0x01, 0xf0, 0x00, // aac adts
+        ],
+    );
+
+    let frames = video.frames as u64;
+    let first = index as u64 * frames;
+    // audio frames are numbered globally so that consecutive segments are continuous
+    let audio_at = |frame: u64| frame * FRAME_TICKS / AUDIO_FRAME_TICKS;
+    let mut next_audio = audio_at(first);
+    for f in first..first + frames {
+        let dts = START_TICKS + f * FRAME_TICKS;
+        let fill = 0x80 | (f as u8 & 0x7f);
+        let mut es = vec![0, 0, 0, 1, 0x09, 0xf0];
+        if f == first {
+            // every segment starts with a keyframe
+            for nal in [SPS, PPS] {
+                es.extend_from_slice(&[0, 0, 0, 1]);
+                es.extend_from_slice(nal);
+            }
+            es.extend_from_slice(&[0, 0, 1, 0x65]);
+        } else {
+            es.extend_from_slice(&[0, 0, 1, 0x41]);
+        }
+        es.resize(es.len() + 400, fill);
+        ts.pes(VIDEO_PID, 0xe0, dts + FRAME_TICKS, Some(dts), &es);
+
+        let end = audio_at(f + 1);
+        if next_audio < end {
+            let pts = START_TICKS + FRAME_TICKS + next_audio * AUDIO_FRAME_TICKS;
+            let es = (next_audio..end)
+                .flat_map(|a| adts_frame(&[0x80 | (a as u8 & 0x7f); 48]))
+                .collect::<Vec<_>>();
+            ts.pes(AUDIO_PID, 0xc0, pts, None, &es);
+            next_audio = end;
+        }
+    }
+    ts.out
+}
+
+/// 服务器返回的分片内容，按 RFC 8216 使用媒体序列号作为 IV 加密
+pub fn segment(video: &Video, index: usize) -> anyhow::Result<Vec<u8>> {
+    use aes::cipher::{BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+
+    let data = plaintext(video, index);
+    if !video.encrypted || video.key_tag.is_some() {
+        return Ok(data);
+    }
+    let iv = (video.media_sequence as u128 + index as u128).to_be_bytes();
+    let key = video.key()?;
+    Ok(cbc::Encryptor::<aes::Aes128>::new(&key.into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(&data))
+}
+
+/// AAC LC, 48kHz, 双声道的 ADTS 帧
+fn adts_frame(payload: &[u8]) -> Vec<u8> {
+    let len = 7 + payload.len();
+    let mut r = vec![
+        0xff,
+        0xf1,
+        (1 << 6) | (3 << 2),
+        (2 << 6) | (len >> 11) as u8,
+        (len >> 3) as u8,
+        ((len & 7) << 5) as u8 | 0x1f,
+        0xfc,
+    ];
+    r.extend_from_slice(payload);
+    r
+}
+
+/// CRC-32/MPEG-2
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0xffff_ffff, |mut crc, &b| {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[derive(Default)]
+struct TsWriter {
+    out: Vec<u8>,
+    cc: std::collections::HashMap<u16, u8>,
+}
+
+impl TsWriter {
+    /// 写入一个 TS 包，负载不足 184 字节时用适配字段填充，返回写入的负载长度
+    fn packet(&mut self, pid: u16, pusi: bool, payload: &[u8]) -> usize {
+        let cc = self.cc.entry(pid).or_default();
+        let n = payload.len().min(TS_PACKET - 4);
+        let mut p = vec![
+            0x47,
+            (pusi as u8) << 6 | (pid >> 8) as u8,
+            pid as u8,
+            0x10 | *cc,
+        ];
+        *cc = (*cc + 1) & 0xf;
+        if n < TS_PACKET - 4 {
+            p[3] |= 0x20;
+            let af = TS_PACKET - 4 - n - 1;
+            p.push(af as u8);
+            if af > 0 {
+                p.push(0);
+                p.resize(p.len() + af - 1, 0xff);
+            }
+        }
+        p.extend_from_slice(&payload[..n]);
+        self.out.extend_from_slice(&p);
+        n
+    }
+
+    fn psi(&mut self, pid: u16, section: &[u8]) {
+        let mut data = vec![0];
+        data.extend_from_slice(section);
+        data.extend_from_slice(&crc32(section).to_be_bytes());
+        self.packet(pid, true, &data);
+    }
+
+    fn pes(&mut self, pid: u16, stream_id: u8, pts: u64, dts: Option<u64>, es: &[u8]) {
+        let ts = |prefix: u8, t: u64| {
+            [
+                prefix << 4 | ((t >> 29) as u8 & 0x0e) | 1,
+                (t >> 22) as u8,
+                ((t >> 14) as u8 & 0xfe) | 1,
+                (t >> 7) as u8,
+                ((t << 1) as u8 & 0xfe) | 1,
+            ]
+        };
+        let mut hdr = Vec::new();
+        let flags = match dts {
+            Some(d) => {
+                hdr.extend(ts(3, pts));
+                hdr.extend(ts(1, d));
+                0xc0
+            }
+            None => {
+                hdr.extend(ts(2, pts));
+                0x80
+            }
+        };
+        // video pes are unbounded
+        let len = if stream_id == 0xe0 {
+            0
+        } else {
+            3 + hdr.len() + es.len()
+        };
+        let mut pes = vec![0, 0, 1, stream_id, (len >> 8) as u8, len as u8, 0x80, flags];
+        pes.push(hdr.len() as u8);
+        pes.extend(hdr);
+        pes.extend_from_slice(es);
+
+        let mut rest = &pes[..];
+        let mut first = true;
+        while !rest.is_empty() {
+            let n = self.packet(pid, first, rest);
+            rest = &rest[n..];
+            first = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        // check value of CRC-32/MPEG-2
+        assert_eq!(crc32(b"123456789"), 0x0376_e6e7);
+    }
+}
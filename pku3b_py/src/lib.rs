@@ -10,7 +10,7 @@ use pku3b::api::{
     CourseAssignment, CourseAssignmentHandle, CourseDocument, CourseDocumentHandle, CourseHandle,
     CourseTreeNode, CourseVideo, CourseVideoHandle,
 };
-use pku3b::utils;
+use pku3b::{cache, ical, snapshot, sync, utils, video};
 
 // ───────────── ① 每线程唯一的 Compio Runtime ─────────────
 thread_local! {
@@ -30,6 +30,10 @@ where
 }
 /// anyhow -> PyErr 简化
 fn anyhow_to_py(e: Error) -> PyErr {
+    // retrying or another quality will not help, let callers tell it apart
+    if e.is::<pku3b::api::UnsupportedEncryption>() {
+        return pyo3::exceptions::PyNotImplementedError::new_err(format!("{e:?}"));
+    }
     pyo3::exceptions::PyRuntimeError::new_err(format!("{e:?}"))
 }
 
@@ -40,12 +44,35 @@ pub struct PyClient {
     inner: Client,
 }
 
+impl Drop for PyClient {
+    fn drop(&mut self) {
+        self.inner.cache_store().flush();
+    }
+}
+
 #[pymethods]
 impl PyClient {
+    /// `base_url`：将发往教学网的请求转发到该地址（例如本地的 fakebb 模拟服务器），
+    /// http 地址只能指向本机
     #[new]
-    fn new() -> Self {
+    #[pyo3(signature = (base_url=None))]
+    fn new(base_url: Option<String>) -> PyResult<Self> {
+        let mut inner = Client::new(None, None);
+        if let Some(base) = base_url {
+            let invalid = |e: &dyn std::fmt::Display| {
+                pyo3::exceptions::PyValueError::new_err(format!("invalid base_url: {e}"))
+            };
+            let base = url::Url::parse(&base).map_err(|e| invalid(&e))?;
+            inner = inner.with_base_url(base).map_err(|e| invalid(&e))?;
+        }
+        Ok(Self { inner })
+    }
+
+    /// 离线客户端：不登录，所有数据从本地缓存读取
+    #[staticmethod]
+    fn offline() -> Self {
         Self {
-            inner: Client::new(None, None),
+            inner: Client::offline(),
         }
     }
 
@@ -57,8 +84,9 @@ impl PyClient {
 
     /// 返回当前平台下 pku3b 的缓存目录绝对路径
     fn cache_dir(&self) -> String {
-        utils::projectdir()
-            .cache_dir()
+        self.inner
+            .cache_store()
+            .dir()
             .to_string_lossy()
             .into_owned()
     }
@@ -118,6 +146,55 @@ impl PyBlackboard {
         Ok(PyCourse { inner: course })
     }
 
+    /// 导出作业截止时间（以及可选的回放时间）为 iCalendar 文本；指定 `path` 时同时写入文件
+    #[pyo3(signature = (path=None, include_videos=false, as_todo=false, include_finished=false, alarm_minutes=vec![]))]
+    fn export_calendar(
+        &self,
+        path: Option<String>,
+        include_videos: bool,
+        as_todo: bool,
+        include_finished: bool,
+        alarm_minutes: Vec<i64>,
+    ) -> PyResult<String> {
+        let mut cal = ical::CalendarBuilder::new()
+            .name("pku3b")
+            .deadline_kind(if as_todo {
+                ical::DeadlineKind::Todo
+            } else {
+                ical::DeadlineKind::Event
+            });
+        for m in alarm_minutes {
+            cal = cal
+                .alarm(chrono::Duration::minutes(m))
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        }
+
+        let fut = async {
+            for h in self.inner.get_courses(true).await? {
+                let c = h.get().await?;
+                for ah in c.list_assignments().await? {
+                    let a = ah.get().await?;
+                    if include_finished || a.last_attempt().is_none() {
+                        cal.add_assignment(&a);
+                    }
+                }
+                if include_videos {
+                    for v in c.get_video_list().await? {
+                        cal.add_video(&v);
+                    }
+                }
+            }
+            anyhow::Ok(())
+        };
+        with_rt(|rt| rt.block_on(fut)).map_err(anyhow_to_py)?;
+
+        let content = cal.build();
+        if let Some(path) = path {
+            fs::write(path, &content).map_err(|e| anyhow_to_py(e.into()))?;
+        }
+        Ok(content)
+    }
+
     /// **可选**：仅课程标题，给 UI 快速渲染用
     #[allow(dead_code)]
     fn course_titles(&self) -> PyResult<Vec<String>> {
@@ -163,6 +240,138 @@ impl PyCourse {
             .filter(|h| h.title().contains(&query))
             .collect())
     }
+    /// 批量下载课程回放到 `dst` 目录，已经存在的文件会被跳过。
+    /// 必须给出 `since` (如 "2024-09-01" 或表示最近两周的 "2w")、`latest` (最近的若干个) 或 `all=True`；
+    /// `format` 为 "mp4"、"ts"、"m4a"、"aac" 或 "hls" (见 `PyVideo.export_hls`)；`parallel` 为同时下载的回放数量，`template` 为文件名模板
+    /// (可用 {course} {title} {teacher} {date} {time} {id}，缺省为 "{course}_{title}_{date}")；
+    /// `progress` 若给出，则下载过程中调用 `progress(index, done, total)`。其余参数同 `PyVideo.download`。
+    /// 单个回放失败不会抛出异常，返回值中每项是含 id、title、status (saved/skipped/failed)、
+    /// path、error 的字典
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (dst, since=None, latest=None, all=false, format="mp4", quality=None, stream=None, parallel=2, jobs=4, retries=3, ffmpeg=false, template=None, progress=None))]
+    fn download_videos(
+        &self,
+        py: Python<'_>,
+        dst: PathBuf,
+        since: Option<&str>,
+        latest: Option<usize>,
+        all: bool,
+        format: &str,
+        quality: Option<String>,
+        stream: Option<String>,
+        parallel: usize,
+        jobs: usize,
+        retries: u32,
+        ffmpeg: bool,
+        template: Option<String>,
+        progress: Option<PyObject>,
+    ) -> PyResult<Vec<HashMap<String, Option<String>>>> {
+        if !all && since.is_none() && latest.is_none() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "specify since, latest or all=True",
+            ));
+        }
+        let format = match format {
+            "mp4" => video::SaveFormat::Mp4,
+            "ts" => video::SaveFormat::Ts,
+            "m4a" => video::SaveFormat::M4a,
+            "aac" => video::SaveFormat::Aac,
+            "hls" => video::SaveFormat::Hls,
+            f => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "unsupported format: {f} (expected mp4, ts, m4a, aac or hls)"
+                )))
+            }
+        };
+        let filter = video::VideoFilter {
+            since: since
+                .map(utils::parse_since)
+                .transpose()
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{e:#}")))?,
+            latest,
+        };
+        let quality = match quality {
+            Some(q) => {
+                let Ok(q) = q.parse();
+                q
+            }
+            None => Default::default(),
+        };
+        let opts = video::BatchOptions {
+            parallel,
+            template: template.unwrap_or_else(|| video::BATCH_NAME_TEMPLATE.to_owned()),
+            format,
+            selector: pku3b::api::VideoSelector { stream, quality },
+            ffmpeg,
+            download: video::DownloadOptions {
+                jobs,
+                retries,
+                ..Default::default()
+            },
+        };
+
+        let videos = with_rt(|rt| rt.block_on(self.inner.list_videos())).map_err(anyhow_to_py)?;
+        let videos = filter.apply(videos);
+        let cb_err = RefCell::new(None);
+        let results = with_rt(|rt| {
+            rt.block_on(video::save_batch(
+                &videos,
+                &dst,
+                &opts,
+                |h, v| batch_cache_dir(h).join(v.variant().cache_id()),
+                |i, p| {
+                    let (done, total) = match p {
+                        video::SaveProgress::Segments(done, total) => (done as u64, total as u64),
+                        video::SaveProgress::Bytes(done, total) => (done, total.unwrap_or(0)),
+                        video::SaveProgress::Converting => return,
+                    };
+                    let mut cb_err = cb_err.borrow_mut();
+                    if let (Some(cb), None) = (&progress, &*cb_err) {
+                        *cb_err = cb.call1(py, (i, done, total)).err();
+                    }
+                },
+                |i, r| {
+                    // keep partial downloads resumable, and let them take part in cache eviction
+                    let dir = batch_cache_dir(&videos[i]);
+                    if !matches!(r, video::BatchStatus::Failed(_)) || !dir.exists() {
+                        return;
+                    }
+                    let key = videos[i]
+                        .course()
+                        .cache_key("video_download")
+                        .id(videos[i].id());
+                    videos[i].cache_store().track(&key, &dir).ok();
+                },
+            ))
+        })
+        .map_err(anyhow_to_py)?;
+        if let Some(e) = cb_err.into_inner() {
+            return Err(e);
+        }
+
+        Ok(videos
+            .iter()
+            .zip(results)
+            .map(|(h, r)| {
+                let (status, path, error) = match r {
+                    video::BatchStatus::Saved(p) => ("saved", Some(p), None),
+                    video::BatchStatus::Skipped(p) => ("skipped", Some(p), None),
+                    video::BatchStatus::Failed(e) => ("failed", None, Some(format!("{e:#}"))),
+                };
+                HashMap::from([
+                    ("id".to_owned(), Some(h.id())),
+                    ("title".to_owned(), Some(h.title().to_owned())),
+                    ("status".to_owned(), Some(status.to_owned())),
+                    (
+                        "path".to_owned(),
+                        path.map(|p| p.to_string_lossy().into_owned()),
+                    ),
+                    ("error".to_owned(), error),
+                ])
+            })
+            .collect())
+    }
+
     /*—— 公告 ——*/
     pub fn list_announcements(&self) -> PyResult<Vec<PyAnnouncementHandle>> {
         let handles =
@@ -247,6 +456,49 @@ impl PyCourse {
 
         Ok(PyCourseTreeNode { inner: root })
     }
+
+    /// 与上次保存的快照比较，返回课程的变化（新增/删除/修改，以及作业截止时间变更）。
+    /// 每个变化是一个字典，键与 `pku3b --output json news` 的输出相同；首次调用时返回空列表。
+    #[pyo3(signature = (save=true))]
+    fn news(&self, save: bool) -> PyResult<Vec<HashMap<String, Option<String>>>> {
+        let store = snapshot::SnapshotStore::default();
+        let events = with_rt(|rt| rt.block_on(store.check(&self.inner, save)))
+            .map_err(anyhow_to_py)?
+            .unwrap_or_default();
+
+        events
+            .iter()
+            .map(|e| {
+                let v = serde_json::to_value(e).map_err(|e| anyhow_to_py(e.into()))?;
+                let obj = v.as_object().cloned().unwrap_or_default();
+                Ok(obj
+                    .into_iter()
+                    .map(|(k, v)| (k, v.as_str().map(ToOwned::to_owned)))
+                    .collect())
+            })
+            .collect()
+    }
+
+    /// 将课程内容增量同步到 `dir` 下以课程名命名的目录，返回 `(变化类型, 相对路径)` 列表
+    #[pyo3(signature = (dir, prune=false, dry_run=false))]
+    fn sync(&self, dir: PathBuf, prune: bool, dry_run: bool) -> PyResult<Vec<(String, String)>> {
+        let opts = sync::SyncOptions { prune, dry_run };
+        let report =
+            with_rt(|rt| rt.block_on(sync::sync_course(&self.inner, &dir, opts, |_, _, _| {})))
+                .map_err(anyhow_to_py)?;
+        if let Some((path, e)) = report.failed.first() {
+            return Err(pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "{} file(s) failed to sync, e.g. {path}: {e}",
+                report.failed.len()
+            )));
+        }
+
+        Ok(report
+            .changes
+            .into_iter()
+            .map(|c| (format!("{:?}", c.kind), c.path))
+            .collect())
+    }
     // ====== Entry 左侧菜单项 ======
     fn list_entry_titles(&self) -> Vec<String> {
         self.entries().keys().cloned().collect()
@@ -372,6 +624,11 @@ impl PyAssignment {
     fn deadline_raw(&self) -> Option<String> {
         self.inner.deadline_raw().map(|s| s.to_string())
     }
+
+    /// 截止时间（RFC 3339 格式，本机时区）
+    fn deadline(&self) -> Option<String> {
+        self.inner.deadline().map(|t| t.to_rfc3339())
+    }
 }
 /*━━━━━━━━━━━━━━━━━━━━━━ ⑤ PyVideoHandler ━━━━━━━━━━━━━━━━━━━━*/
 #[pyclass]
@@ -394,9 +651,44 @@ impl PyVideoHandle {
         self.handle.time().to_string()
     }
 
-    fn get(&self) -> PyResult<PyVideo> {
-        let v = with_rt(|rt| rt.block_on(self.handle.get())).map_err(anyhow_to_py)?;
-        Ok(PyVideo { inner: v })
+    /// 开始时间（RFC 3339 格式，本机时区）
+    fn start_time(&self) -> Option<String> {
+        self.handle.meta().start_time().map(|t| t.to_rfc3339())
+    }
+
+    fn teacher(&self) -> String {
+        self.handle.meta().teacher().to_string()
+    }
+
+    /// 获取回放。`quality` 为 "best"、"smallest" 或形如 "720p" 的清晰度，`stream` 为视频流名称或序号
+    #[pyo3(signature = (quality=None, stream=None))]
+    fn get(&self, quality: Option<String>, stream: Option<String>) -> PyResult<PyVideo> {
+        let v = get_video(&self.handle, quality, stream)?;
+        Ok(PyVideo {
+            inner: v,
+            handle: self.handle.clone(),
+        })
+    }
+
+    /// 列出回放的所有视频流和清晰度，每项是含 stream、quality、bandwidth、resolution、url 的字典
+    fn variants(&self) -> PyResult<Vec<HashMap<String, Option<String>>>> {
+        let vs = with_rt(|rt| rt.block_on(self.handle.variants())).map_err(anyhow_to_py)?;
+        Ok(vs
+            .into_iter()
+            .map(|v| {
+                HashMap::from([
+                    ("stream".to_owned(), Some(v.stream.clone())),
+                    ("kind".to_owned(), Some(v.kind.as_str().to_owned())),
+                    ("quality".to_owned(), v.quality()),
+                    ("bandwidth".to_owned(), v.bandwidth.map(|b| b.to_string())),
+                    (
+                        "resolution".to_owned(),
+                        v.resolution.map(|(w, h)| format!("{w}x{h}")),
+                    ),
+                    ("url".to_owned(), Some(v.url)),
+                ])
+            })
+            .collect())
     }
     fn summary(&self) -> String {
         format!("[{}] {} ({})", self.id(), self.title(), self.time())
@@ -406,6 +698,7 @@ impl PyVideoHandle {
 #[pyclass]
 pub struct PyVideo {
     inner: CourseVideo,
+    handle: CourseVideoHandle,
 }
 
 #[pymethods]
@@ -419,80 +712,248 @@ impl PyVideo {
     fn len(&self) -> usize {
         self.inner.len_segments()
     }
-
-    #[pyo3(name = "download")]
-    fn download(&self, dst: String, to_mp4: Option<bool>) -> PyResult<String> {
+    /// 回放时长 (秒)，由播放列表中各分片的时长累加得到；视频文件回放为 None
+    fn duration(&self) -> Option<f64> {
+        self.inner.meta().duration().map(|d| d.as_secs_f64())
+    }
+    /// 结束时间（RFC 3339 格式，本机时区），即开始时间加上时长
+    fn end_time(&self) -> Option<String> {
+        self.inner.meta().end_time().map(|t| t.to_rfc3339())
+    }
+
+    /// 下载回放到 `dst`。`jobs` 为同时下载的分片数量，`retries` 为每个分片失败后的最多重试次数；
+    /// `progress` 若给出，则每下载完一个分片调用一次 `progress(done, total)`；
+    /// `ffmpeg` 为 True 时调用 ffmpeg 转为 mp4，否则使用内置的转封装器；
+    /// 给出 `quality` 或 `stream` 时改为下载按其选出的变体 (含义同 `PyVideoHandle.get`)。
+    /// 分片解密后按顺序直接写入输出文件 (`to_mp4` 时先写入缓存目录，转换后删除)，中断后再次调用
+    /// 会从检查点继续。不使用 HLS 的回放直接保存为视频文件 (忽略 `to_mp4`)，`progress` 以字节计
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(name = "download", signature = (dst, to_mp4=None, jobs=4, retries=3, progress=None, ffmpeg=false, quality=None, stream=None))]
+    fn download(
+        &self,
+        py: Python<'_>,
+        dst: String,
+        to_mp4: Option<bool>,
+        jobs: usize,
+        retries: u32,
+        progress: Option<PyObject>,
+        ffmpeg: bool,
+        quality: Option<String>,
+        stream: Option<String>,
+    ) -> PyResult<String> {
         let dst = PathBuf::from(dst);
         if !dst.exists() {
             std::fs::create_dir_all(&dst).map_err(|e| anyhow_to_py(e.into()))?;
         }
 
-        /* ---------- 1. 准备缓存工作目录 ---------- */
-        let cache_dir = utils::projectdir()
-            .cache_dir()
-            .join("video_download")
-            .join(self.inner.meta().title()); // stable-id 更好
-        std::fs::create_dir_all(&cache_dir).ok();
-
-        /* ---------- 2. 如果缺少片段才下载 ---------- */
-        let mut key = None;
-        let mut paths = Vec::<PathBuf>::new();
-        for i in 0..self.inner.len_segments() {
-            key = self.inner.refresh_key(i, key);
-            let seg_path = cache_dir.join(format!("{:05}.ts", i));
-
-            if !seg_path.exists() {
-                let data = with_rt(|rt| rt.block_on(self.inner.get_segment_data(i, key)))
-                    .map_err(anyhow_to_py)?;
-                std::fs::write(&seg_path, data).map_err(|e| anyhow_to_py(e.into()))?;
-            }
-            paths.push(seg_path);
+        let selected;
+        let v = if quality.is_some() || stream.is_some() {
+            selected = get_video(&self.handle, quality, stream)?;
+            &selected
+        } else {
+            &self.inner
+        };
+
+        if v.file_url().is_some() {
+            let out = dst.join(format!("{}.{}", v.meta().title(), video::file_extension(v)));
+            fetch_file(v, &out, py, retries, progress)?;
+            return Ok(out.to_string_lossy().into_owned());
+        }
+
+        if !to_mp4.unwrap_or(false) {
+            // 仅 TS 模式：分片直接写入 课程标题.ts
+            let ts = dst.join(format!("{}.ts", v.meta().title()));
+            fetch_ts(v, &ts, py, jobs, retries, progress)?;
+            return Ok(ts.to_string_lossy().into_owned());
         }
 
-        /* ---------- 3. 合并 ---------- */
+        // 分片先写入缓存目录中的 merged.ts，转为 课程标题.mp4 后删除
+        let cache_dir = cache_dir(v);
         let merged = cache_dir.join("merged.ts");
-        {
-            let mut out = std::fs::File::create(&merged).map_err(|e| anyhow_to_py(e.into()))?;
-            for p in &paths {
-                let data = std::fs::read(p).map_err(|e| anyhow_to_py(e.into()))?;
-                std::io::Write::write_all(&mut out, &data).map_err(|e| anyhow_to_py(e.into()))?;
-            }
+        fetch_ts(v, &merged, py, jobs, retries, progress)?;
+        let mp4 = dst.join(format!("{}.mp4", v.meta().title()));
+        let r = with_rt(|rt| rt.block_on(video::convert_to_mp4(&merged, &mp4, ffmpeg)));
+        finish_cache_dir(v, &cache_dir, r.is_ok());
+        r.map_err(anyhow_to_py)?;
+        Ok(mp4.to_string_lossy().into_owned())
+    }
+
+    /// 把回放导出为可离线播放的 HLS 目录 `dst`：解密后的分片和以相对路径指向它们的 index.m3u8
+    /// (不含 EXT-X-KEY)，可直接用播放器打开并跳转。返回播放列表路径；中断后再次调用会继续下载，
+    /// `progress(done, total)` 在每个分片完成后调用。视频文件回放不支持导出
+    #[pyo3(signature = (dst, jobs=4, retries=3, progress=None))]
+    fn export_hls(
+        &self,
+        py: Python<'_>,
+        dst: PathBuf,
+        jobs: usize,
+        retries: u32,
+        progress: Option<PyObject>,
+    ) -> PyResult<String> {
+        let opts = video::DownloadOptions {
+            jobs,
+            retries,
+            ..Default::default()
+        };
+        let mut cb_err = None;
+        let path = with_rt(|rt| {
+            rt.block_on(video::export_hls(&self.inner, &dst, opts, |done, tot| {
+                if let (Some(cb), None) = (&progress, &cb_err) {
+                    cb_err = cb.call1(py, (done, tot)).err();
+                }
+            }))
+        })
+        .map_err(anyhow_to_py)?;
+        match cb_err {
+            Some(e) => Err(e),
+            None => Ok(path.to_string_lossy().into_owned()),
         }
+    }
 
-        // ---------- 4. （可选）转 mp4 ----------
-        let need_mp4 = to_mp4.unwrap_or(false);
-
-        let final_path = if need_mp4 {
-            // 保持现有：课程标题.mp4
-            let mp4 = dst.join(format!("{}.mp4", self.inner.meta().title()));
-            let status = std::process::Command::new("ffmpeg")
-                .args([
-                    "-y",
-                    "-hide_banner",
-                    "-loglevel",
-                    "quiet",
-                    "-i",
-                    merged.to_str().unwrap(),
-                    "-c",
-                    "copy",
-                ])
-                .arg(&mp4)
-                .status()
-                .map_err(|e| anyhow_to_py(e.into()))?;
+    /// 只提取回放的音频到 `dst` 目录，不需要 ffmpeg，返回生成的文件路径。
+    /// `format` 为 "m4a" 或 "aac" (ADTS 裸流)；其余参数同 `download`
+    #[pyo3(signature = (dst, format="m4a", jobs=4, retries=3, progress=None))]
+    fn download_audio(
+        &self,
+        py: Python<'_>,
+        dst: String,
+        format: &str,
+        jobs: usize,
+        retries: u32,
+        progress: Option<PyObject>,
+    ) -> PyResult<String> {
+        if !matches!(format, "m4a" | "aac") {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "unsupported audio format: {format} (expected m4a or aac)"
+            )));
+        }
+        let dst = PathBuf::from(dst);
+        std::fs::create_dir_all(&dst).map_err(|e| anyhow_to_py(e.into()))?;
+
+        let v = &self.inner;
+        let ext = video::file_extension(v);
+        if v.file_url().is_some() && ext != "ts" {
+            return Err(anyhow_to_py(anyhow::anyhow!(
+                "audio extraction is not supported for .{ext} video files"
+            )));
+        }
+        let cache_dir = cache_dir(v);
+        let ts = cache_dir.join("merged.ts");
+        if v.file_url().is_some() {
+            fetch_file(v, &ts, py, retries, progress)?;
+        } else {
+            fetch_ts(v, &ts, py, jobs, retries, progress)?;
+        }
+        let out = dst.join(format!("{}.{format}", v.meta().title()));
+        let r = with_rt(|rt| rt.block_on(video::extract_audio(&[ts], &out)));
+        finish_cache_dir(v, &cache_dir, r.is_ok());
+        r.map_err(anyhow_to_py)?;
+
+        Ok(out.to_string_lossy().into_owned())
+    }
+}
+
+fn get_video(
+    handle: &CourseVideoHandle,
+    quality: Option<String>,
+    stream: Option<String>,
+) -> PyResult<CourseVideo> {
+    let quality = match quality {
+        Some(q) => {
+            let Ok(q) = q.parse();
+            q
+        }
+        None => Default::default(),
+    };
+    let selector = pku3b::api::VideoSelector { stream, quality };
+    with_rt(|rt| rt.block_on(handle.get_with(&selector))).map_err(anyhow_to_py)
+}
+
+/// 回放的缓存目录 (每个变体一个子目录)
+fn cache_dir(v: &CourseVideo) -> PathBuf {
+    let dir = v
+        .cache_store()
+        .dir()
+        .join("video_download")
+        .join(v.meta().title()) // stable-id 更好
+        .join(v.variant().cache_id());
+    std::fs::create_dir_all(&dir).ok();
+    dir
+}
 
-            if !status.success() {
-                return Err(anyhow_to_py(anyhow::anyhow!("ffmpeg failed")));
+/// 批量下载时回放的缓存目录，按回放 ID 区分同名的回放
+fn batch_cache_dir(h: &CourseVideoHandle) -> PathBuf {
+    h.cache_store()
+        .dir()
+        .join("video_download")
+        .join(sync::sanitize(&h.id()))
+}
+
+/// 断点续传地下载视频文件回放到 `dst`，`progress(已下载字节, 总字节)` 中总字节未知时为 0
+fn fetch_file(
+    v: &CourseVideo,
+    dst: &Path,
+    py: Python<'_>,
+    retries: u32,
+    progress: Option<PyObject>,
+) -> PyResult<()> {
+    let opts = video::DownloadOptions {
+        retries,
+        ..Default::default()
+    };
+    let mut cb_err = None;
+    with_rt(|rt| {
+        rt.block_on(video::download_file(v, dst, opts, |done, total| {
+            if let (Some(cb), None) = (&progress, &cb_err) {
+                cb_err = cb.call1(py, (done, total.unwrap_or(0))).err();
             }
-            mp4
-        } else {
-            // 仅 TS 模式：课程标题.ts
-            let ts = dst.join(format!("{}.ts", self.inner.meta().title()));
-            std::fs::copy(&merged, &ts).map_err(|e| anyhow_to_py(e.into()))?;
-            ts
-        };
+        }))
+    })
+    .map_err(anyhow_to_py)?;
+    match cb_err {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
 
-        Ok(final_path.to_string_lossy().into_owned())
+/// 并发下载全部分片，解密后按顺序写入 `dst`，中断后再次调用会从检查点继续
+fn fetch_ts(
+    v: &CourseVideo,
+    dst: &Path,
+    py: Python<'_>,
+    jobs: usize,
+    retries: u32,
+    progress: Option<PyObject>,
+) -> PyResult<()> {
+    let opts = video::DownloadOptions {
+        jobs,
+        retries,
+        ..Default::default()
+    };
+    let mut cb_err = None;
+    with_rt(|rt| {
+        rt.block_on(video::download_ts(v, dst, opts, |done, tot| {
+            if let (Some(cb), None) = (&progress, &cb_err) {
+                cb_err = cb.call1(py, (done, tot)).err();
+            }
+        }))
+    })
+    .map_err(anyhow_to_py)?;
+    match cb_err {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// 成功时删除缓存目录，否则保留以便继续下载，并让它参与缓存淘汰
+fn finish_cache_dir(v: &CourseVideo, cache_dir: &Path, ok: bool) {
+    if ok {
+        fs::remove_dir_all(cache_dir).ok();
+        return;
     }
+    let key = v.course().cache_key("video_download").id(v.meta().title());
+    let dir = cache_dir.parent().unwrap_or(cache_dir);
+    v.cache_store().track(&key, dir).ok();
 }
 
 // ─────────────  递归计算目录大小 (同步)  ─────────────
@@ -521,9 +982,7 @@ fn cache_size_gb() -> PyResult<f64> {
 fn cache_clean() -> PyResult<f64> {
     let dir = utils::projectdir().cache_dir().to_path_buf();
     let freed = cache_size_gb()?; // 先记下大小
-    if dir.exists() {
-        fs::remove_dir_all(&dir).map_err(|e| anyhow_to_py(e.into()))?;
-    }
+    cache::CacheStore::global().clear().map_err(anyhow_to_py)?;
     fs::create_dir_all(&dir).ok(); // 重建空目录
     Ok(freed)
 }